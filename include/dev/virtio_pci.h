@@ -170,6 +170,8 @@ int virtio_pci_ack_device(struct virtio_pci_dev *dev);
 int virtio_pci_read_features(struct virtio_pci_dev *dev);
 int virtio_pci_write_features(struct virtio_pci_dev *dev, uint64_t features);
 int virtio_pci_start_device(struct virtio_pci_dev *dev);
+// stop the device, after which it uses none of its virtqueues
+int virtio_pci_reset_device(struct virtio_pci_dev *dev);
 
 // allocate a single descriptor
 int virtio_pci_desc_alloc(struct virtio_pci_dev *dev, uint16_t qidx, uint16_t *desc_idx);
@@ -216,5 +216,32 @@ uint64_t nk_sched_get_runtime(struct nk_thread *t);
 // what are the threads scheduling constraints
 int nk_sched_thread_get_constraints(struct nk_thread *t, struct nk_sched_constraints *c);
 
+// accounting, for tools outside the scheduler
+struct nk_sched_cpu_stats {
+    uint64_t pending;         // periodic/sporadic threads not yet arrived
+    uint64_t runnable;        // periodic/sporadic threads that have arrived
+    uint64_t aperiodic;       // runnable aperiodic threads
+    uint64_t num_thefts;      // threads stolen from other cpus
+    uint64_t reinject_count;  // timer/kick interrupts reinjected
+    uint64_t interrupt_count;
+    uint64_t current_tid;     // thread running now
+};
+
+int nk_sched_get_cpu_stats(int cpu, struct nk_sched_cpu_stats *s);
+
+// reset when the thread's constraints change
+struct nk_sched_thread_stats {
+    nk_sched_constraint_type_t type;
+    uint64_t run_time;          // ns
+    uint64_t arrival_count;
+    uint64_t resched_count;
+    uint64_t resched_long_count;
+    uint64_t switch_in_count;
+    uint64_t miss_count;        // deadline misses
+    uint64_t miss_time_sum;     // ns
+};
+
+int nk_sched_get_thread_stats(struct nk_thread *t, struct nk_sched_thread_stats *s);
+
 struct nk_thread *nk_find_thread_by_tid(uint64_t tid);
 #endif /* _SCHEDULER_H */
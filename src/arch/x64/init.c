@@ -329,6 +329,10 @@ init (unsigned long mbd,
 
 #ifdef NAUT_CONFIG_ENABLE_REMOTE_DEBUGGING 
     nk_gdb_init();
+#ifdef NAUT_CONFIG_RUST_SUPPORT
+    extern int nk_rust_gdb_init(void);
+    nk_rust_gdb_init();
+#endif
 #endif
 
 
@@ -365,6 +369,26 @@ init (unsigned long mbd,
     /* setup the main kernel memory allocator */
     nk_kmem_init();
 
+#ifdef NAUT_CONFIG_RUST_HEAP_POISON
+    extern int nk_rust_heap_poison_init(void);
+    nk_rust_heap_poison_init();
+#endif
+
+#ifdef NAUT_CONFIG_RUST_FAULT_INJECTION
+    extern int nk_rust_fault_init(void);
+    nk_rust_fault_init();
+#endif
+
+#ifdef NAUT_CONFIG_RUST_HEAP_ARENA
+    extern int nk_rust_heap_init(uint64_t size);
+    nk_rust_heap_init(NAUT_CONFIG_RUST_HEAP_ARENA_SIZE_MB * 1024ULL * 1024ULL);
+#endif
+
+#ifdef NAUT_CONFIG_RUST_LEAK_TRACKING
+    extern int nk_rust_leaks_init(void);
+    nk_rust_leaks_init();
+#endif
+
     // setup per-core area for BSP
     msr_write(MSR_GS_BASE, (uint64_t)naut->sys.cpus[0]);
 
@@ -495,6 +519,15 @@ init (unsigned long mbd,
 
     nk_vc_init();
 
+#ifdef NAUT_CONFIG_RUST_SUPPORT
+    extern int nk_rust_log_init(void);
+    nk_rust_log_init();
+    extern int nk_rust_tsc_init(void);
+    nk_rust_tsc_init();
+    // the Rust init calls' core and subsys levels (see nk_init)
+    extern int nk_rust_init_level(int level);
+    nk_rust_init_level(1);
+#endif
     
 #ifdef NAUT_CONFIG_VIRTUAL_CONSOLE_CHARDEV_CONSOLE
     nk_vc_start_chardev_console(NAUT_CONFIG_VIRTUAL_CONSOLE_CHARDEV_CONSOLE_NAME);
@@ -539,6 +572,11 @@ init (unsigned long mbd,
     nk_net_ethernet_collective_init();
 #endif
     
+#ifdef NAUT_CONFIG_RUST_SUPPORT
+    extern int nk_rust_init_level(int level);
+    nk_rust_init_level(2); // device
+#endif
+
     nk_fs_init();
 
 #ifdef NAUT_CONFIG_EXT2_FILESYSTEM_DRIVER
@@ -567,10 +605,22 @@ init (unsigned long mbd,
 
     nk_cmdline_dispatch(naut);
 
+#ifdef NAUT_CONFIG_RUST_SUPPORT
+    extern int nk_rust_init_level(int level);
+    nk_rust_init_level(3); // late
+#endif
+
 #ifdef NAUT_CONFIG_RUN_TESTS_AT_BOOT
     nk_run_tests(naut);
 #endif
 
+#ifdef NAUT_CONFIG_RUST_BOOT_TESTS
+    // the tests block, so they get a thread of their own rather than
+    // this one, which goes on to be this CPU's idle thread
+    extern void nk_rust_run_boot_tests(void *in, void **out);
+    nk_thread_start(nk_rust_run_boot_tests, NULL, 0, 1, TSTACK_DEFAULT, NULL, -1);
+#endif
+
 #ifdef NAUT_CONFIG_WATCHDOG
     nk_watchdog_init(NAUT_CONFIG_WATCHDOG_DEFAULT_TIME_MS * 1000000UL);
 #endif
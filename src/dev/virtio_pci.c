@@ -284,6 +284,10 @@ static int modern_discover(struct virtio_pci_dev *vdev)
 	DEBUG("GPU Device\n");
 	vdev->type = VIRTIO_PCI_GPU;
 	break;
+    case 0x1040+18 :
+	DEBUG("Input Device\n");
+	vdev->type = VIRTIO_PCI_INPUT;
+	break;
     default:
 	DEBUG("Unknown device\n");
 	vdev->type = VIRTIO_PCI_UNKNOWN;
@@ -502,7 +506,8 @@ int discover_devices(struct pci_info *pci)
 		    INFO("Found modern virtio %s device: bus=%u dev=%u func=%u: common=%p dev_spec=%p notif_mult=%u\n",
 			 vdev->type==VIRTIO_PCI_BLOCK ? "block" :
 			 vdev->type==VIRTIO_PCI_NET ? "net" :
-			 vdev->type==VIRTIO_PCI_GPU ? "gpu" : "other",
+			 vdev->type==VIRTIO_PCI_GPU ? "gpu" :
+			 vdev->type==VIRTIO_PCI_INPUT ? "input" : "other",
 			 bus->num, pdev->num, 0,
 			 vdev->common, vdev->device_specific, vdev->notify_off_multiplier);
 		}
@@ -713,6 +718,9 @@ static int virtqueue_init_modern(struct virtio_pci_dev *dev)
 	    virtio_pci_atomic_store(&dev->common->queue_msix_vector,i);
 	}
 
+	// and only now may the device use it
+	virtio_pci_atomic_store(&dev->common->queue_enable,1);
+
         dev->num_virtqs++;
   }
   
@@ -740,8 +748,16 @@ static int virtqueue_deinit_legacy(struct virtio_pci_dev *dev)
 
 static int virtqueue_deinit_modern(struct virtio_pci_dev *dev)
 {
-    ERROR("unimplemented\n");
-    return -1;
+    uint16_t i;
+
+    // the device must let go of the rings before we free them
+    virtio_pci_atomic_store(&dev->common->device_status,DEV_STATUS_RESET);
+
+    for (i=0;i<dev->num_virtqs;i++) {
+        free(dev->virtq[i].data);
+        dev->virtq[i].data=0;
+    }
+    return 0;
 }
 
 static int ack_device_legacy(struct virtio_pci_dev *dev)
@@ -836,8 +852,27 @@ static int start_device_legacy(struct virtio_pci_dev *dev)
 
 static int start_device_modern(struct virtio_pci_dev *dev)
 {
-    ERROR("unimplemented\n");
-    return -1;
+    uint8_t ds = virtio_pci_atomic_load(&dev->common->device_status);
+
+    ds |= DEV_STATUS_DRIVER_OK;
+
+    virtio_pci_atomic_store(&dev->common->device_status,ds);
+
+    return 0;
+}
+
+static int reset_device_legacy(struct virtio_pci_dev *dev)
+{
+    virtio_pci_write_regb(dev,DEVICE_STATUS,DEV_STATUS_RESET);
+
+    return 0;
+}
+
+static int reset_device_modern(struct virtio_pci_dev *dev)
+{
+    virtio_pci_atomic_store(&dev->common->device_status,DEV_STATUS_RESET);
+
+    return 0;
 }
 
 
@@ -876,6 +911,11 @@ int virtio_pci_start_device(struct virtio_pci_dev *dev)
 {
     DISPATCH_RET(dev,start_device);
 }	
+
+int virtio_pci_reset_device(struct virtio_pci_dev *dev)
+{
+    DISPATCH_RET(dev,reset_device);
+}
      
 int virtio_pci_read_features(struct virtio_pci_dev *dev)
 {
@@ -1061,11 +1101,46 @@ static int bringup_device(struct virtio_pci_dev *dev)
 	return virtio_net_init(dev);
 	break;
 #endif
+#ifdef NAUT_CONFIG_RUST_VIRTIO_NET
+    case VIRTIO_PCI_NET: {
+	extern int nk_rust_virtio_net_init(struct virtio_pci_dev *dev);
+	return nk_rust_virtio_net_init(dev);
+	break;
+    }
+#endif
 #ifdef NAUT_CONFIG_VIRTIO_BLK
     case VIRTIO_PCI_BLOCK:
 	return virtio_blk_init(dev);
 	break;
 #endif
+#ifdef NAUT_CONFIG_RUST_VIRTIO_BLK
+    case VIRTIO_PCI_BLOCK: {
+	extern int nk_rust_virtio_blk_init(struct virtio_pci_dev *dev);
+	return nk_rust_virtio_blk_init(dev);
+	break;
+    }
+#endif
+#ifdef NAUT_CONFIG_RUST_VIRTIO_CONSOLE
+    case VIRTIO_PCI_CONSOLE: {
+	extern int nk_rust_virtio_console_init(struct virtio_pci_dev *dev);
+	return nk_rust_virtio_console_init(dev);
+	break;
+    }
+#endif
+#ifdef NAUT_CONFIG_RUST_VIRTIO_RNG
+    case VIRTIO_PCI_ENTROPY: {
+	extern int nk_rust_virtio_rng_init(struct virtio_pci_dev *dev);
+	return nk_rust_virtio_rng_init(dev);
+	break;
+    }
+#endif
+#ifdef NAUT_CONFIG_RUST_VIRTIO_INPUT
+    case VIRTIO_PCI_INPUT: {
+	extern int nk_rust_virtio_input_init(struct virtio_pci_dev *dev);
+	return nk_rust_virtio_input_init(dev);
+	break;
+    }
+#endif
 #ifdef NAUT_CONFIG_VIRTIO_GPU
     case VIRTIO_PCI_GPU:
 	return virtio_gpu_init(dev);
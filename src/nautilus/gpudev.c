@@ -79,7 +79,7 @@ struct nk_gpu_dev * nk_gpu_dev_find(char *name)
 {
     DEBUG("find %s\n",name);
     struct nk_dev *d = nk_dev_find(name);
-    if (d->type!=NK_DEV_GRAPHICS) {
+    if (!d || d->type!=NK_DEV_GRAPHICS) {
 	DEBUG("%s not found\n",name);
 	return 0;
     } else {
@@ -1009,6 +1009,11 @@ handle_meminfo (char * buf, void * priv)
 
     free(s);
 
+#ifdef NAUT_CONFIG_RUST_SUPPORT
+    extern void nk_rust_meminfo(void);
+    nk_rust_meminfo();
+#endif
+
     return 0;
 }
 
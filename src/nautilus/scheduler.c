@@ -3660,6 +3660,46 @@ int nk_sched_thread_get_constraints(struct nk_thread *t, struct nk_sched_constra
     return 0;
 }
 
+int nk_sched_get_cpu_stats(int cpu, struct nk_sched_cpu_stats *stats)
+{
+    LOCAL_LOCK_CONF;
+    struct sys_info *sys = per_cpu_get(system);
+    rt_scheduler *s;
+
+    if (cpu<0 || cpu>=sys->num_cpus) {
+	return -1;
+    }
+
+    s = sys->cpus[cpu]->sched_state;
+    LOCAL_LOCK(s);
+    stats->pending = s->pending.size;
+    stats->runnable = s->runnable.size;
+    stats->aperiodic = s->aperiodic.size;
+    stats->num_thefts = s->num_thefts;
+    stats->reinject_count = s->reinject_count;
+    stats->interrupt_count = sys->cpus[cpu]->interrupt_count;
+    stats->current_tid = s->current->thread->tid;
+    LOCAL_UNLOCK(s);
+
+    return 0;
+}
+
+int nk_sched_get_thread_stats(struct nk_thread *t, struct nk_sched_thread_stats *stats)
+{
+    rt_thread *r = t->sched_state;
+
+    stats->type = r->constraints.type;
+    stats->run_time = r->run_time;
+    stats->arrival_count = r->arrival_count;
+    stats->resched_count = r->resched_count;
+    stats->resched_long_count = r->resched_long_count;
+    stats->switch_in_count = r->switch_in_count;
+    stats->miss_count = r->miss_count;
+    stats->miss_time_sum = r->miss_time_sum;
+
+    return 0;
+}
+
 static inline uint64_t get_avg_per(rt_priority_queue *runnable, rt_priority_queue *pending, rt_thread *new_thread)
 {
     uint64_t sum_period = 0;
@@ -1,7 +1,18 @@
 #include <nautilus/cpu.h>
+#include <nautilus/mb_utils.h>
+// after mb_utils.h, for struct list_head
+#include <nautilus/cmdline.h>
 #include <nautilus/nautilus.h>
+#include <nautilus/numa.h>
 #include <nautilus/shell.h>
 #include <nautilus/spinlock.h>
+#include <nautilus/timer.h>
+#ifdef NAUT_CONFIG_PROVENANCE
+#include <nautilus/provenance.h>
+#endif
+#ifdef NAUT_CONFIG_HPET
+#include <dev/hpet.h>
+#endif
 
 // Rust function we will call from C
 extern int example_shell_entry(char *, void *);
@@ -16,14 +27,295 @@ static struct shell_cmd_impl rust_example_impl = {
 // be called using the Rust FFI
 nk_register_shell_cmd(rust_example_impl);
 
-// parport
-
 // direct wrappers around inline functions
 uint8_t spin_lock_irq(spinlock_t *lock) { return spin_lock_irq_save(lock); }
+int spin_try_lock_irq(spinlock_t *lock, uint8_t *flags) {
+  return spin_try_lock_irq_save(lock, flags);
+}
 void spin_unlock_irq(spinlock_t *lock, uint8_t flags) {
   spin_unlock_irq_restore(lock, flags);
 }
 
+// logging
+
+// log prefixes need the same CPU/thread info as the C *_PRINT macros,
+// which is only reachable through inline functions and macros
+void *nk_rust_cpu_state_get_cpu(void) { return __cpu_state_get_cpu(); }
+int nk_rust_my_cpu_id(void) { return my_cpu_id(); }
+int nk_rust_in_interrupt_context(void) { return in_interrupt_context(); }
+int nk_rust_preempt_is_disabled(void) { return preempt_is_disabled(); }
+void nk_rust_preempt_disable(void) { preempt_disable(); }
+void nk_rust_preempt_enable(void) { preempt_enable(); }
+struct nk_thread *nk_rust_get_cur_thread(void) { return get_cur_thread(); }
+// field offsets in `struct nk_thread` depend on Kconfig options that
+// bindgen does not see, so read them on the C side
+unsigned long nk_rust_thread_tid(struct nk_thread *t) { return t->tid; }
+int nk_rust_thread_is_idle(struct nk_thread *t) { return t->is_idle; }
+const char *nk_rust_thread_name(struct nk_thread *t) { return t->name; }
+
+extern int rust_log_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_log_impl = {
+    .cmd = "rust_log",
+    .help_str = "rust_log [<module>|all <level> on|off | color on|off|auto | time off|boot|wall | reset | sink ...]",
+    .handler = rust_log_shell_entry,
+};
+nk_register_shell_cmd(rust_log_impl);
+
+extern int rust_dmesg_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_dmesg_impl = {
+    .cmd = "rust_dmesg",
+    .help_str = "rust_dmesg [-c | clear]",
+    .handler = rust_dmesg_shell_entry,
+};
+nk_register_shell_cmd(rust_dmesg_impl);
+
+// metrics
+
+extern int rust_stats_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_stats_impl = {
+    .cmd = "rust_stats",
+    .help_str = "rust_stats [<module> | reset]",
+    .handler = rust_stats_shell_entry,
+};
+nk_register_shell_cmd(rust_stats_impl);
+
+extern int rust_profile_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_profile_impl = {
+    .cmd = "rust_profile",
+    .help_str = "rust_profile [reset]",
+    .handler = rust_profile_shell_entry,
+};
+nk_register_shell_cmd(rust_profile_impl);
+
+extern int rust_bench_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_bench_impl = {
+    .cmd = "rust_bench",
+    .help_str = "rust_bench [<name> [iterations]]",
+    .handler = rust_bench_shell_entry,
+};
+nk_register_shell_cmd(rust_bench_impl);
+
+// tests
+
+extern int rust_test_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_test_impl = {
+    .cmd = "rust_test",
+    .help_str = "rust_test [<filter>]",
+    .handler = rust_test_shell_entry,
+};
+nk_register_shell_cmd(rust_test_impl);
+
+// boot parameters
+
+// NULL until multiboot's information has been parsed
+const char *nk_rust_boot_cmdline(void) {
+  struct multiboot_info *mb = nk_get_nautilus_info()->sys.mb_info;
+  return mb ? mb->boot_cmd_line : NULL;
+}
+
+// nk_cmdline parses -rust's arguments itself, long before flags are
+// dispatched; this only keeps the dispatcher from rejecting the flag
+static int rust_cmdline_handler(char *args) { return 0; }
+static struct nk_cmdline_impl rust_cmdline_impl = {
+    .name = "rust",
+    .handler = rust_cmdline_handler,
+};
+nk_register_cmdline_flag(rust_cmdline_impl);
+
+// init calls
+
+extern int rust_init_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_init_impl = {
+    .cmd = "rust_init",
+    .help_str = "rust_init",
+    .handler = rust_init_shell_entry,
+};
+nk_register_shell_cmd(rust_init_impl);
+
+// power
+
+extern int rust_reboot_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_reboot_impl = {
+    .cmd = "reboot",
+    .help_str = "reboot",
+    .handler = rust_reboot_shell_entry,
+};
+nk_register_shell_cmd(rust_reboot_impl);
+
+extern int rust_poweroff_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_poweroff_impl = {
+    .cmd = "poweroff",
+    .help_str = "poweroff",
+    .handler = rust_poweroff_shell_entry,
+};
+nk_register_shell_cmd(rust_poweroff_impl);
+
+// backtraces
+
+// like __do_backtrace, only follow frame pointers into physical memory
+uint64_t nk_rust_phys_mem_avail(void) {
+  return nk_get_nautilus_info()->sys.mem.phys_mem_avail;
+}
+
+// the name points into the symbol table, so it outlives the info struct
+const char *nk_rust_symbol_name(uint64_t addr) {
+#ifdef NAUT_CONFIG_PROVENANCE
+  provenance_info *info = nk_prov_get_info(addr);
+  const char *name = NULL;
+  if (info) {
+    name = (const char *)info->symbol;
+    free(info);
+  }
+  return name;
+#else
+  return NULL;
+#endif
+}
+
+// heap
+
+// per-CPU caches are only touched with interrupts off
+uint8_t nk_rust_irq_save(void) { return irq_disable_save(); }
+void nk_rust_irq_restore(uint8_t flags) { irq_enable_restore(flags); }
+
+// `struct cpu` is full of Kconfig-dependent fields
+int nk_rust_cpu_numa_node(int cpu) {
+  if (cpu < 0 || cpu >= nk_get_num_cpus()) {
+    return -1;
+  }
+  return nk_get_nautilus_info()->sys.cpus[cpu]->domain->id;
+}
+
+extern int rust_mem_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_mem_impl = {
+    .cmd = "rust_mem",
+    .help_str = "rust_mem [reset | oom [panic | reclaim]]",
+    .handler = rust_mem_shell_entry,
+};
+nk_register_shell_cmd(rust_mem_impl);
+
+extern int rust_memtest_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_memtest_impl = {
+    .cmd = "rust_memtest",
+    .help_str = "rust_memtest [iterations [threads]]",
+    .handler = rust_memtest_shell_entry,
+};
+nk_register_shell_cmd(rust_memtest_impl);
+
+#ifdef NAUT_CONFIG_RUST_LEAK_TRACKING
+extern int rust_leaks_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_leaks_impl = {
+    .cmd = "rust_leaks",
+    .help_str = "rust_leaks [mark | all]",
+    .handler = rust_leaks_shell_entry,
+};
+nk_register_shell_cmd(rust_leaks_impl);
+#endif
+
+// fault injection
+
+#ifdef NAUT_CONFIG_RUST_FAULT_INJECTION
+extern int rust_fault_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_fault_impl = {
+    .cmd = "rust_fault",
+    .help_str = "rust_fault [reset | <point> off | <point> nth <n> | <point> percent <p>]",
+    .handler = rust_fault_shell_entry,
+};
+nk_register_shell_cmd(rust_fault_impl);
+#endif
+
+// scheduler
+
+// like the thread accessors under logging, which this shares
+int nk_rust_thread_cpu(struct nk_thread *t) { return t->current_cpu; }
+
+// what nk_join does once the thread has exited, for a parent that never
+// waits for it; thread_detach itself is static
+void nk_rust_thread_detach(struct nk_thread *t) {
+  preempt_disable();
+  list_del(&t->child_node);
+  --t->refcount;
+  preempt_enable();
+}
+
+extern int rust_sched_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_sched_impl = {
+    .cmd = "rust_sched",
+    .help_str = "rust_sched [cpu]",
+    .handler = rust_sched_shell_entry,
+};
+nk_register_shell_cmd(rust_sched_impl);
+
+// cross-CPU calls
+
+// smp_xcall complains on the console each time it finds the target's
+// queue busy, so callers that retry look first
+int nk_rust_xcall_pending(int cpu) {
+  nk_queue_t *q = nk_get_nautilus_info()->sys.cpus[cpu]->xcall_q;
+  return q && !nk_queue_empty_atomic(q);
+}
+
+// time
+
+extern int rust_timers_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_timers_impl = {
+    .cmd = "rust_timers",
+    .help_str = "rust_timers",
+    .handler = rust_timers_shell_entry,
+};
+nk_register_shell_cmd(rust_timers_impl);
+
+// nk_timer_cancel marks a timer that already expired inactive, so that
+// waiting on it fails; this leaves expired timers signalled
+int nk_rust_timer_cancel(nk_timer_t *t) {
+  if (t->state != NK_TIMER_ACTIVE) {
+    return -1;
+  }
+  if (nk_timer_cancel(t)) {
+    // it expired after the check above
+    __sync_bool_compare_and_swap(&t->state, NK_TIMER_INACTIVE,
+                                 NK_TIMER_SIGNALLED);
+    return -1;
+  }
+  return 0;
+}
+
+// `struct cpu` is full of Kconfig-dependent fields
+unsigned long nk_rust_cpu_khz(void) {
+  return nk_get_nautilus_info()->sys.cpus[my_cpu_id()]->cpu_khz;
+}
+
+// the HPET's counter frequency in Hz, 0 if there is no HPET
+uint64_t nk_rust_hpet_freq(void) {
+#ifdef NAUT_CONFIG_HPET
+  if (nk_get_nautilus_info()->sys.hpet) {
+    return nk_hpet_get_freq();
+  }
+#endif
+  return 0;
+}
+
+// only valid if nk_rust_hpet_freq() is not 0
+uint64_t nk_rust_hpet_counter(void) {
+#ifdef NAUT_CONFIG_HPET
+  return nk_hpet_get_cntr();
+#else
+  return 0;
+#endif
+}
+
+// graphics
+
+extern int rust_gpu_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_gpu_impl = {
+    .cmd = "rust_gpu",
+    .help_str = "rust_gpu <gpu device> [seconds]",
+    .handler = rust_gpu_shell_entry,
+};
+nk_register_shell_cmd(rust_gpu_impl);
+
+// parport
+
 extern int parport_shell_entry(char *, void *);
 static struct shell_cmd_impl rust_parport_impl = {
     .cmd = "parport",
@@ -31,3 +323,33 @@ static struct shell_cmd_impl rust_parport_impl = {
     .handler = parport_shell_entry,
 };
 nk_register_shell_cmd(rust_parport_impl);
+
+// virtio input
+
+extern int rust_vinput_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_vinput_impl = {
+    .cmd = "rust_vinput",
+    .help_str = "rust_vinput",
+    .handler = rust_vinput_shell_entry,
+};
+nk_register_shell_cmd(rust_vinput_impl);
+
+// framebuffer console
+
+extern int rust_fbcon_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_fbcon_impl = {
+    .cmd = "rust_fbcon",
+    .help_str = "rust_fbcon <gpu device> | off | scroll <rows>",
+    .handler = rust_fbcon_shell_entry,
+};
+nk_register_shell_cmd(rust_fbcon_impl);
+
+// snake
+
+extern int rust_snake_shell_entry(char *, void *);
+static struct shell_cmd_impl rust_snake_impl = {
+    .cmd = "rust_snake",
+    .help_str = "rust_snake <gpu device> [tick ms]",
+    .handler = rust_snake_shell_entry,
+};
+nk_register_shell_cmd(rust_snake_impl);
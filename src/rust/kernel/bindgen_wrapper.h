@@ -111,3 +111,6 @@
 #include "nautilus/vmm.h"
 #include "nautilus/waitqueue.h"
 #include "nautilus/watchdog.h"
+// after nautilus/, as virtio_pci.h defines `u8` and `le32` as macros
+#include "dev/pci.h"
+#include "dev/virtio_pci.h"
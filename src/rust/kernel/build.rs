@@ -0,0 +1,291 @@
+extern crate bindgen;
+
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Kconfig's output for the kernel being built
+const DOT_CONFIG: &str = "../../../.config";
+
+// turns each NAUT_CONFIG_* line of .config into a const in `nk_config`,
+// and lists the options that are on, for `nk_config_enabled!`, and as
+// `nk_config = "..."` cfgs and, for `config_module!`, `NAUT_CONFIG_*`
+// ones. crates built on this one get the same cfgs from their build
+// scripts, from the lists passed on here (see ../build.rs)
+fn gen_config(out_path: &Path) {
+    println!("cargo:rerun-if-changed={}", DOT_CONFIG);
+    let text = fs::read_to_string(DOT_CONFIG).unwrap_or_else(|_| {
+        println!(
+            "cargo:warning={} not found, every Kconfig option is off",
+            DOT_CONFIG
+        );
+        String::new()
+    });
+
+    let mut consts = String::new();
+    let mut enabled = Vec::new();
+    let mut known = Vec::new();
+    for line in text.lines() {
+        let (name, value) = if let Some(name) = line
+            .strip_prefix("# NAUT_CONFIG_")
+            .and_then(|l| l.strip_suffix(" is not set"))
+        {
+            (name, "n")
+        } else if let Some((name, value)) = line
+            .strip_prefix("NAUT_CONFIG_")
+            .and_then(|l| l.split_once('='))
+        {
+            (name, value)
+        } else {
+            continue;
+        };
+        known.push(name);
+        let (ty, value) = match value {
+            "y" | "m" => {
+                enabled.push(name);
+                ("bool", "true")
+            }
+            "n" => ("bool", "false"),
+            // Kconfig escapes strings the same way Rust does
+            v if v.starts_with('"') => ("&str", v),
+            v if v.starts_with('-') => ("i64", v),
+            // decimal, or hex with a 0x prefix
+            v => ("u64", v),
+        };
+        writeln!(consts, "pub const {}: {} = {};", name, ty, value).unwrap();
+    }
+
+    println!("cargo:rustc-check-cfg=cfg(nk_config, values(any()))");
+    // a `NAUT_CONFIG_*` cfg that isn't in .config is likely misspelled
+    for name in &known {
+        println!("cargo:rustc-check-cfg=cfg(NAUT_CONFIG_{})", name);
+    }
+    println!("cargo:known={}", known.join(","));
+    println!("cargo:enabled={}", enabled.join(","));
+    writeln!(consts, "#[doc(hidden)]").unwrap();
+    writeln!(consts, "pub const ENABLED_OPTIONS: &[&str] = &[").unwrap();
+    for name in &enabled {
+        println!("cargo:rustc-cfg=nk_config=\"{}\"", name);
+        println!("cargo:rustc-cfg=NAUT_CONFIG_{}", name);
+        writeln!(consts, "    \"{}\",", name).unwrap();
+    }
+    writeln!(consts, "];").unwrap();
+
+    fs::write(out_path.join("config.rs"), consts).expect("Couldn't write config!");
+}
+
+// the C items bindings are generated for; whatever they depend on
+// comes along. to use a new one from Rust, add it here and re-export
+// it from `nk_raw`.
+const ALLOWED_FUNCTIONS: &[&str] = &[
+    "acpi_shutdown",
+    "apic_do_eoi",
+    "idt_find_and_reserve_range",
+    "kmem_find_block",
+    "kmem_free",
+    "kmem_malloc",
+    "kmem_malloc_specific",
+    "kmem_mallocz",
+    "nk_block_dev_register",
+    "nk_block_dev_unregister",
+    "nk_char_dev_register",
+    "nk_char_dev_unregister",
+    "nk_char_dev_write",
+    "nk_dev_find",
+    "nk_dev_signal",
+    "nk_get_num_cpus",
+    "nk_get_num_domains",
+    "nk_gpu_dev_bitmap_create",
+    "nk_gpu_dev_bitmap_destroy",
+    "nk_gpu_dev_find",
+    "nk_gpu_dev_flush",
+    "nk_gpu_dev_get_available_modes",
+    "nk_gpu_dev_get_mode",
+    "nk_gpu_dev_graphics_copy_box",
+    "nk_gpu_dev_graphics_draw_line",
+    "nk_gpu_dev_graphics_draw_pixel",
+    "nk_gpu_dev_graphics_draw_poly",
+    "nk_gpu_dev_graphics_fill_box_with_pixel",
+    "nk_gpu_dev_graphics_set_clipping_box",
+    "nk_gpu_dev_graphics_set_cursor",
+    "nk_gpu_dev_graphics_set_cursor_bitmap",
+    "nk_gpu_dev_set_mode",
+    "nk_irq_is_assigned",
+    "nk_join",
+    "nk_map_page",
+    "nk_mask_irq",
+    "nk_my_numa_node",
+    "nk_net_dev_register",
+    "nk_net_dev_unregister",
+    "nk_sched_get_cpu_stats",
+    "nk_sched_get_realtime",
+    "nk_sched_get_thread_stats",
+    "nk_sched_map_threads",
+    "nk_sched_thread_change_constraints",
+    "nk_sleep",
+    "nk_thread_exit",
+    "nk_thread_name",
+    "nk_thread_start",
+    "nk_timer_cancel",
+    "nk_timer_create",
+    "nk_timer_destroy",
+    "nk_timer_dump_timers",
+    "nk_timer_set",
+    "nk_timer_start",
+    "nk_timer_wait",
+    "nk_unmask_irq",
+    "nk_vc_get_keycode",
+    "nk_vc_handle_keyboard",
+    "nk_vc_handle_mouse",
+    "nk_vc_log",
+    "nk_vc_print",
+    "nk_vc_start_chardev_console",
+    "nk_vc_stop_chardev_console",
+    "nk_yield",
+    "panic",
+    "pci_dev_cfg_readw",
+    "pci_dev_cfg_writew",
+    "pci_dev_mask_msi_x_all",
+    "pci_dev_set_msi_x_entry",
+    "pci_dev_unmask_msi_x_all",
+    "pci_dev_unmask_msi_x_entry",
+    "printk",
+    "qemu_shutdown_with_code",
+    "register_int_handler",
+    "register_irq_handler",
+    "smp_xcall",
+    "virtio_pci_ack_device",
+    "virtio_pci_desc_chain_alloc",
+    "virtio_pci_desc_chain_free",
+    "virtio_pci_read_features",
+    "virtio_pci_read_regb",
+    "virtio_pci_read_regl",
+    "virtio_pci_reset_device",
+    "virtio_pci_start_device",
+    "virtio_pci_virtqueue_deinit",
+    "virtio_pci_virtqueue_init",
+    "virtio_pci_virtqueue_notify",
+    "virtio_pci_write_features",
+];
+
+const ALLOWED_TYPES: &[&str] = &[
+    "excp_entry_t",
+    "excp_vec_t",
+    "nk_block_dev",
+    "nk_block_dev_characteristics",
+    "nk_block_dev_int",
+    "nk_block_dev_status_t",
+    "nk_char_dev",
+    "nk_char_dev_characteristics",
+    "nk_char_dev_int",
+    "nk_dev",
+    "nk_dev_int",
+    "nk_dev_request_type_t",
+    "nk_gpu_dev_bit_blit_op_t",
+    "nk_gpu_dev_bitmap_t",
+    "nk_gpu_dev_box_t",
+    "nk_gpu_dev_coordinate_t",
+    "nk_gpu_dev_pixel_t",
+    "nk_gpu_dev_t",
+    "nk_gpu_dev_video_mode_t",
+    "nk_keycode_t",
+    "nk_mouse_event_t",
+    "nk_net_dev",
+    "nk_net_dev_characteristics",
+    "nk_net_dev_int",
+    "nk_net_dev_status_t",
+    "nk_scancode_t",
+    "nk_sched_constraint_type_t",
+    "nk_sched_constraints",
+    "nk_sched_cpu_stats",
+    "nk_sched_thread_stats",
+    "nk_thread",
+    "nk_thread_id_t",
+    "nk_timer_t",
+    "page_size_t",
+    "pci_dev",
+    "shell_cmd_impl",
+    "spinlock_t",
+    "virtio_pci_common_cfg",
+    "virtio_pci_dev",
+    "virtio_pci_virtq",
+    "virtq",
+    "virtq_avail",
+    "virtq_desc",
+    "virtq_used",
+];
+
+const ALLOWED_VARS: &[&str] = &[
+    "DEVICE_REGS_START_LEGACY",
+    "DEVICE_REGS_START_MSI_X",
+    "EBUSY",
+    "EEXIST",
+    "EINVAL",
+    "EIO",
+    "ENODEV",
+    "ENOENT",
+    "ENOMEM",
+    "ENOSPC",
+    "ISR_STATUS",
+    "MAX_THREAD_NAME",
+    "NK_CHARDEV_ERROR",
+    "NK_CHARDEV_READABLE",
+    "NK_CHARDEV_WRITEABLE",
+    "NK_GPU_DEV_HAS_CLIPPING",
+    "NK_GPU_DEV_HAS_MOUSE_CURSOR",
+    "NK_TIMER_CALLBACK",
+    "NK_TIMER_CALLBACK_LOCAL_SYNC",
+    "NK_TIMER_WAIT_ALL",
+    "VIRTQ_DESC_F_NEXT",
+    "VIRTQ_DESC_F_WRITE",
+];
+
+fn main() {
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+    gen_config(&out_path);
+
+    // Tell cargo to invalidate the built crate whenever the wrapper changes
+    println!("cargo:rerun-if-changed=bindgen_wrapper.h");
+
+    // The bindgen::Builder is the main entry point
+    // to bindgen, and lets you build up options for
+    // the resulting bindings.
+    let mut builder = bindgen::Builder::default();
+    for f in ALLOWED_FUNCTIONS {
+        builder = builder.allowlist_function(f);
+    }
+    for t in ALLOWED_TYPES {
+        builder = builder.allowlist_type(t);
+    }
+    for v in ALLOWED_VARS {
+        builder = builder.allowlist_var(v);
+    }
+
+    let bindings = builder
+        // The input header we would like to generate bindings for.
+        .header("bindgen_wrapper.h")
+        // set the root directory for nested `#include`s
+        .clang_arg("-F../../../include/")
+        // prefix types with `core::ffi` for a `no_std` environment
+        .ctypes_prefix("core::ffi")
+        // `core` instead of `libstd`
+        .use_core()
+        .rust_target(bindgen::RustTarget::Nightly)
+        // use with caution - NK's C code is built with GCC
+        // whereas bindgen (and rustc) use clang.
+        //.emit_builtins()
+        //
+        // Tell cargo to invalidate the built crate whenever any of the
+        // included header files changed.
+        .parse_callbacks(Box::new(bindgen::CargoCallbacks))
+        // Finish the builder and generate the bindings.
+        .generate()
+        // Unwrap the Result and panic on failure.
+        .expect("Unable to generate bindings");
+
+    // Write the bindings to the $OUT_DIR/bindings.rs file.
+    bindings
+        .write_to_file(out_path.join("bindings.rs"))
+        .expect("Couldn't write bindings!");
+}
@@ -0,0 +1,61 @@
+// unstable feature core::ffi
+#![feature(panic_info_message)]
+#![feature(alloc_error_handler)]
+#![feature(alloc_c_string)]
+#![feature(core_ffi_c)]
+#![feature(core_c_str)]
+#![feature(c_size_t)]
+#![feature(lang_items)]
+// no stdlib
+#![no_std]
+#![no_builtins]
+// use saner, more strict interpretation of `unsafe fn`
+// (ie. ONLY an obligation to the caller, not a carte-
+// blanche to discharge unsafe obligations inside)
+// See this RFC for explanation and details:
+// https://rust-lang.github.io/rfcs/2585-unsafe-block-in-unsafe-fn.html
+#![deny(unsafe_op_in_unsafe_fn)]
+
+extern crate alloc;
+// macros must be defined before the modules that use them
+#[macro_use]
+pub mod nk_config;
+#[macro_use]
+pub mod nk_log;
+#[macro_use]
+pub mod nk_assert;
+#[macro_use]
+pub mod nk_metrics;
+#[macro_use]
+pub mod nk_test;
+#[macro_use]
+pub mod nk_fault;
+#[macro_use]
+pub mod nk_init;
+
+pub mod nk_alloc;
+pub mod nk_aspace;
+pub mod nk_backtrace;
+mod nk_bindings;
+pub mod nk_blkdev;
+pub mod nk_chardev;
+pub mod nk_cmdline;
+pub mod nk_crash;
+pub mod nk_error;
+pub mod nk_gdb;
+pub mod nk_gpudev;
+pub mod nk_lock;
+pub mod nk_netdev;
+pub mod nk_panic;
+pub mod nk_power;
+pub mod nk_rand;
+pub mod nk_raw;
+pub mod nk_sched;
+pub mod nk_smp;
+pub mod nk_time;
+// virtio_pci is only built with NAUT_CONFIG_VIRTIO_PCI
+config_module!(NAUT_CONFIG_VIRTIO_PCI, pub mod nk_virtio);
+//pub mod nk_shell_cmd;
+// the supported API, for drivers and subsystems; see there
+pub mod prelude;
+pub mod utils;
@@ -0,0 +1,249 @@
+// optional heap for Rust, carved out of kmem once at boot
+// (NAUT_CONFIG_RUST_HEAP_ARENA) and managed here, so that Rust heap
+// corruption stays out of the C heap and can be instrumented
+// without touching kmem.
+//
+// a first-fit free list, sorted by address so neighbors coalesce on
+// free. every block is a multiple of `MIN_BLOCK` bytes and aligned to
+// it, which leaves room for the free list node in any free block.
+
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use super::poison;
+use crate::nk_lock::IRQLock;
+
+const MIN_BLOCK: usize = 16;
+
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+struct Arena {
+    // lowest free block
+    free: *mut FreeBlock,
+    free_bytes: usize,
+}
+
+// the free list is only touched with the lock held
+unsafe impl Send for Arena {}
+
+static ARENA: IRQLock<Arena> = IRQLock::new(Arena {
+    free: null_mut(),
+    free_bytes: 0,
+});
+
+// bounds of the arena, both 0 until `init`. kept outside the lock,
+// since every free checks them.
+static START: AtomicUsize = AtomicUsize::new(0);
+static END: AtomicUsize = AtomicUsize::new(0);
+
+fn round_up(x: usize, align: usize) -> usize {
+    (x + align - 1) & !(align - 1)
+}
+
+// size and alignment of the block used for `layout`
+fn block_layout(layout: &Layout) -> (usize, usize) {
+    let align = layout.align().max(MIN_BLOCK);
+    let size = round_up(layout.size().max(MIN_BLOCK), MIN_BLOCK);
+    (size, align)
+}
+
+impl Arena {
+    unsafe fn alloc(&mut self, layout: &Layout) -> *mut u8 {
+        let (size, align) = block_layout(layout);
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut cur = self.free;
+        while !cur.is_null() {
+            let block = cur as usize;
+            let block_size = unsafe { (*cur).size };
+            let next = unsafe { (*cur).next };
+
+            // padding in front of the allocation must be big enough
+            // to stay on the free list
+            let mut start = round_up(block, align);
+            if start != block && start - block < MIN_BLOCK {
+                start = round_up(block + MIN_BLOCK, align);
+            }
+            let end = start + size;
+            let block_end = block + block_size;
+            // same for whatever is left at the end
+            if end <= block_end && (end == block_end || block_end - end >= MIN_BLOCK) {
+                // the tail, then the front, replace `cur` in the list
+                let mut rest = next;
+                if end != block_end {
+                    let tail = end as *mut FreeBlock;
+                    unsafe {
+                        tail.write(FreeBlock {
+                            size: block_end - end,
+                            next: rest,
+                        });
+                    }
+                    rest = tail;
+                }
+                if start != block {
+                    unsafe {
+                        (*cur).size = start - block;
+                        (*cur).next = rest;
+                    }
+                    rest = cur;
+                }
+                if prev.is_null() {
+                    self.free = rest;
+                } else {
+                    unsafe { (*prev).next = rest };
+                }
+                self.free_bytes -= size;
+                return start as *mut u8;
+            }
+
+            prev = cur;
+            cur = next;
+        }
+        null_mut()
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: &Layout) {
+        let (size, _) = block_layout(layout);
+        let addr = ptr as usize;
+        // always in debug builds, to make use-after-free show up as
+        // obviously bad data
+        if cfg!(debug_assertions) || poison::enabled() {
+            unsafe { poison::fill(ptr, size) };
+        }
+
+        // find the free blocks on either side
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut next = self.free;
+        while !next.is_null() && (next as usize) < addr {
+            prev = next;
+            next = unsafe { (*next).next };
+        }
+
+        let block = ptr as *mut FreeBlock;
+        unsafe { block.write(FreeBlock { size, next }) };
+        if !next.is_null() && addr + size == next as usize {
+            unsafe {
+                (*block).size += (*next).size;
+                (*block).next = (*next).next;
+            }
+        }
+        if prev.is_null() {
+            self.free = block;
+        } else if prev as usize + unsafe { (*prev).size } == addr {
+            unsafe {
+                (*prev).size += (*block).size;
+                (*prev).next = (*block).next;
+            }
+        } else {
+            unsafe { (*prev).next = block };
+        }
+        self.free_bytes += size;
+    }
+}
+
+/// Hands `[start, start + size)` to the arena. Called once, at boot.
+///
+/// # Safety
+///
+/// The memory must be unused, and stay reserved for the arena forever.
+pub unsafe fn init(start: *mut u8, size: usize) {
+    let mut arena = ARENA.lock();
+    let first = round_up(start as usize, MIN_BLOCK);
+    let end = (start as usize + size) & !(MIN_BLOCK - 1);
+    if end <= first {
+        return;
+    }
+    let block = first as *mut FreeBlock;
+    unsafe {
+        block.write(FreeBlock {
+            size: end - first,
+            next: null_mut(),
+        });
+    }
+    *arena = Arena {
+        free: block,
+        free_bytes: end - first,
+    };
+    START.store(first, Ordering::Relaxed);
+    END.store(end, Ordering::Release);
+}
+
+/// Whether the arena was set up, i.e. whether `alloc` is worth trying.
+pub fn active() -> bool {
+    END.load(Ordering::Acquire) != 0
+}
+
+/// Whether `ptr` came from the arena (rather than from kmem).
+pub fn contains(ptr: *mut u8) -> bool {
+    let end = END.load(Ordering::Acquire);
+    (START.load(Ordering::Relaxed)..end).contains(&(ptr as usize))
+}
+
+// for crash reports
+pub(crate) fn locked() -> bool {
+    ARENA.is_locked()
+}
+
+pub fn alloc(layout: &Layout) -> *mut u8 {
+    // the free list is consistent whenever the lock is free
+    unsafe { ARENA.lock().alloc(layout) }
+}
+
+/// # Safety
+///
+/// `ptr` must have come from `alloc` with the same `layout`.
+pub unsafe fn dealloc(ptr: *mut u8, layout: &Layout) {
+    unsafe { ARENA.lock().dealloc(ptr, layout) }
+}
+
+pub struct Usage {
+    pub size: usize,
+    pub free_bytes: usize,
+    pub largest_free: usize,
+    pub free_blocks: usize,
+}
+
+impl Usage {
+    /// How much of the free memory is outside the largest free block,
+    /// in percent; high values mean big allocations may fail even with
+    /// plenty free.
+    pub fn fragmentation_percent(&self) -> usize {
+        (self.free_bytes - self.largest_free)
+            .saturating_mul(100)
+            .checked_div(self.free_bytes)
+            .unwrap_or(0)
+    }
+}
+
+pub fn usage() -> Usage {
+    usage_of(&ARENA.lock())
+}
+
+/// Like `usage`, but `None` rather than waiting if the arena is locked.
+pub fn try_usage() -> Option<Usage> {
+    ARENA.try_lock().map(|arena| usage_of(&arena))
+}
+
+fn usage_of(arena: &Arena) -> Usage {
+    let mut u = Usage {
+        size: END.load(Ordering::Acquire) - START.load(Ordering::Relaxed),
+        free_bytes: arena.free_bytes,
+        largest_free: 0,
+        free_blocks: 0,
+    };
+    let mut cur = arena.free;
+    while !cur.is_null() {
+        let size = unsafe { (*cur).size };
+        u.largest_free = u.largest_free.max(size);
+        u.free_blocks += 1;
+        cur = unsafe { (*cur).next };
+    }
+    u
+}
+
+// every free block must hold a list node
+const _: () = assert!(MIN_BLOCK >= size_of::<FreeBlock>());
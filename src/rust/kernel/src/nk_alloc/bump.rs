@@ -0,0 +1,133 @@
+// bump allocation for short-lived data tied to one operation (a GPU
+// transaction, a packet, ...): allocations just advance an offset into
+// one buffer, and are all freed together when the arena is reset.
+//
+// allocated values are never dropped, so they should not own anything
+// that needs dropping (heap memory, locks, ...).
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use core::cell::Cell;
+use core::ptr::NonNull;
+use core::slice;
+
+use crate::nk_error::{KError, Result};
+
+// alignment of the buffer, and so the largest alignment that never
+// needs padding at the start
+const BUF_ALIGN: usize = 16;
+
+pub struct BumpArena {
+    buf: NonNull<u8>,
+    capacity: usize,
+    // offset of the first free byte
+    next: Cell<usize>,
+    // largest `next` since the arena was created, for sizing it
+    peak: Cell<usize>,
+}
+
+// the buffer is owned; `Cell` keeps the arena to one thread at a time
+unsafe impl Send for BumpArena {}
+
+impl BumpArena {
+    /// An arena of `capacity` bytes, allocated from the heap now.
+    pub fn new(capacity: usize) -> Result<Self> {
+        if capacity == 0 {
+            return Err(KError::INVALID_ARG);
+        }
+        let layout =
+            Layout::from_size_align(capacity, BUF_ALIGN).map_err(|_| KError::INVALID_ARG)?;
+        let buf = NonNull::new(unsafe { alloc(layout) }).ok_or(KError::NO_MEM)?;
+        Ok(BumpArena {
+            buf,
+            capacity,
+            next: Cell::new(0),
+            peak: Cell::new(0),
+        })
+    }
+
+    fn alloc_layout(&self, layout: Layout) -> Result<NonNull<u8>> {
+        let base = self.buf.as_ptr() as usize;
+        let start = (base + self.next.get() + layout.align() - 1) & !(layout.align() - 1);
+        let end = start.checked_add(layout.size()).ok_or(KError::NO_MEM)?;
+        if end > base + self.capacity {
+            return Err(KError::NO_MEM);
+        }
+        self.next.set(end - base);
+        self.peak.set(self.peak.get().max(end - base));
+        // within the buffer, which outlives every borrow of `self`
+        Ok(unsafe { NonNull::new_unchecked(start as *mut u8) })
+    }
+
+    /// Moves `value` into the arena.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc<T>(&self, value: T) -> Result<&mut T> {
+        let p = self.alloc_layout(Layout::new::<T>())?.cast::<T>();
+        // fresh, aligned, and handed out only once
+        unsafe {
+            p.as_ptr().write(value);
+            Ok(&mut *p.as_ptr())
+        }
+    }
+
+    /// `len` `T`s, `f(i)` at index `i`.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_with<T>(
+        &self,
+        len: usize,
+        mut f: impl FnMut(usize) -> T,
+    ) -> Result<&mut [T]> {
+        let layout = Layout::array::<T>(len).map_err(|_| KError::NO_MEM)?;
+        let p = self.alloc_layout(layout)?.cast::<T>();
+        for i in 0..len {
+            unsafe { p.as_ptr().add(i).write(f(i)) };
+        }
+        Ok(unsafe { slice::from_raw_parts_mut(p.as_ptr(), len) })
+    }
+
+    /// A copy of `src` in the arena.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_slice_copy<T: Copy>(&self, src: &[T]) -> Result<&mut [T]> {
+        self.alloc_slice_with(src.len(), |i| src[i])
+    }
+
+    pub fn alloc_str(&self, s: &str) -> Result<&str> {
+        let bytes = self.alloc_slice_copy(s.as_bytes())?;
+        // copied from a `str`
+        Ok(unsafe { core::str::from_utf8_unchecked(bytes) })
+    }
+
+    /// Frees everything allocated so far. Borrowing `self` mutably
+    /// ensures nothing allocated is still in use.
+    pub fn reset(&mut self) {
+        self.next.set(0);
+    }
+
+    /// Runs `f` with the arena, then frees whatever it allocated.
+    pub fn scope<R>(&mut self, f: impl FnOnce(&BumpArena) -> R) -> R {
+        let r = f(self);
+        self.reset();
+        r
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Bytes in use, including alignment padding.
+    pub fn used(&self) -> usize {
+        self.next.get()
+    }
+
+    /// The most bytes ever in use at once.
+    pub fn peak(&self) -> usize {
+        self.peak.get()
+    }
+}
+
+impl Drop for BumpArena {
+    fn drop(&mut self) {
+        // the layout `new` allocated with
+        let layout = unsafe { Layout::from_size_align_unchecked(self.capacity, BUF_ALIGN) };
+        unsafe { dealloc(self.buf.as_ptr(), layout) };
+    }
+}
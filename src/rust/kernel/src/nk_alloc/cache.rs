@@ -0,0 +1,187 @@
+// per-CPU caches ("magazines") of freed small blocks, so that frequent
+// small allocations (task boxes, wakers, ...) rarely reach kmem and
+// its locks.
+//
+// each CPU only touches its own cache, with interrupts off, so the
+// caches need no locks. a block freed on another CPU than it was
+// allocated on simply joins that CPU's cache.
+
+use core::alloc::Layout;
+use core::ffi::{c_int, c_void};
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use super::poison;
+use crate::nk_raw;
+
+extern "C" {
+    // glue.c
+    fn nk_rust_irq_save() -> u8;
+    fn nk_rust_irq_restore(flags: u8);
+    fn nk_rust_cpu_state_get_cpu() -> *mut c_void;
+    fn nk_rust_my_cpu_id() -> c_int;
+}
+
+// NK's upper limit on NAUT_CONFIG_MAX_CPUS
+const MAX_CPUS: usize = 256;
+// blocks of 16, 32, ..., 256 bytes
+pub const CLASSES: usize = 5;
+const MIN_CLASS_SIZE: usize = 16;
+// blocks kept per class per CPU; more are returned to kmem
+const MAGAZINE_LEN: usize = 32;
+
+counter!(HITS, "cache_hits");
+counter!(MISSES, "cache_misses");
+
+struct Magazine {
+    count: usize,
+    blocks: [*mut u8; MAGAZINE_LEN],
+}
+
+struct CpuCache {
+    magazines: [Magazine; CLASSES],
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_CACHE: AtomicPtr<CpuCache> = AtomicPtr::new(null_mut());
+// allocated on first use, and never freed
+static CACHES: [AtomicPtr<CpuCache>; MAX_CPUS] = [NO_CACHE; MAX_CPUS];
+
+pub fn class_size(class: usize) -> usize {
+    MIN_CLASS_SIZE << class
+}
+
+// kmem blocks are aligned to their size, so any block of the class
+// is aligned well enough as long as the alignment is at most the size
+fn class_of(layout: &Layout) -> Option<usize> {
+    let size = layout.pad_to_align().size();
+    (0..CLASSES).find(|&c| size <= class_size(c) && layout.align() <= class_size(c))
+}
+
+/// Whether blocks for `layout` go through the caches.
+pub fn cacheable(layout: &Layout) -> bool {
+    class_of(layout).is_some()
+}
+
+/// Whether a block for `old` may be freed as one for `new`: both are
+/// in the same class, or neither is cached.
+pub fn same_class(old: &Layout, new: &Layout) -> bool {
+    class_of(old) == class_of(new)
+}
+
+// the current CPU's cache, created if need be. interrupts must be off.
+fn my_cache() -> Option<&'static mut CpuCache> {
+    let cpu = unsafe {
+        // no per-CPU state yet early in boot
+        if nk_rust_cpu_state_get_cpu().is_null() {
+            return None;
+        }
+        nk_rust_my_cpu_id() as usize
+    };
+    let slot = CACHES.get(cpu)?;
+    let mut cache = slot.load(Ordering::Relaxed);
+    if cache.is_null() {
+        // only this CPU sets its slot, and it cannot be preempted here
+        cache = unsafe {
+            nk_raw::kmem_mallocz(core::mem::size_of::<CpuCache>() as u64) as *mut CpuCache
+        };
+        if cache.is_null() {
+            return None;
+        }
+        slot.store(cache, Ordering::Relaxed);
+    }
+    // zeroed memory is a valid `CpuCache` (empty magazines), and with
+    // interrupts off nothing else on this CPU can be using it
+    Some(unsafe { &mut *cache })
+}
+
+fn with_my_cache<R>(f: impl FnOnce(&mut CpuCache) -> Option<R>) -> Option<R> {
+    let flags = unsafe { nk_rust_irq_save() };
+    let r = my_cache().and_then(f);
+    unsafe { nk_rust_irq_restore(flags) };
+    r
+}
+
+/// A cached block for `layout`, if it is small and this CPU has one.
+pub fn alloc(layout: &Layout) -> Option<*mut u8> {
+    let class = class_of(layout)?;
+    let block = with_my_cache(|cache| {
+        let mag = &mut cache.magazines[class];
+        if mag.count == 0 {
+            return None;
+        }
+        mag.count -= 1;
+        Some(mag.blocks[mag.count])
+    });
+    match block {
+        Some(b) => {
+            HITS.inc();
+            if poison::checking() {
+                // poisoned whole by `free`, and untouched since
+                unsafe { poison::check(b, class_size(class)) };
+            }
+        }
+        None => MISSES.inc(),
+    }
+    block
+}
+
+/// Keeps `ptr`, a kmem block for `layout`, in this CPU's cache if it
+/// is small and there is room. Returns whether it was kept.
+///
+/// # Safety
+///
+/// `ptr` must be a kmem block allocated for `layout`, no longer in use.
+pub unsafe fn free(ptr: *mut u8, layout: &Layout) -> bool {
+    let class = match class_of(layout) {
+        Some(c) => c,
+        None => return false,
+    };
+    with_my_cache(|cache| {
+        let mag = &mut cache.magazines[class];
+        if mag.count == MAGAZINE_LEN {
+            return None;
+        }
+        if poison::enabled() {
+            // the whole kmem block, whatever part of it was used
+            unsafe { poison::fill(ptr, class_size(class)) };
+        }
+        mag.blocks[mag.count] = ptr;
+        mag.count += 1;
+        Some(())
+    })
+    .is_some()
+}
+
+/// Returns this CPU's cached blocks to kmem, and how many bytes that
+/// was. Other CPUs' caches are theirs to touch.
+pub fn drain() -> usize {
+    with_my_cache(|cache| {
+        let mut freed = 0;
+        for (class, mag) in cache.magazines.iter_mut().enumerate() {
+            for &block in &mag.blocks[..mag.count] {
+                unsafe { nk_raw::kmem_free(block as *mut c_void) };
+            }
+            freed += mag.count * class_size(class);
+            mag.count = 0;
+        }
+        Some(freed)
+    })
+    .unwrap_or(0)
+}
+
+/// Number of blocks cached in each class, over all CPUs.
+pub fn cached() -> [usize; CLASSES] {
+    let mut counts = [0; CLASSES];
+    for slot in &CACHES {
+        let cache = slot.load(Ordering::Relaxed);
+        if cache.is_null() {
+            continue;
+        }
+        for (count, mag) in counts.iter_mut().zip(unsafe { &(*cache).magazines }) {
+            // racy, but only used for reporting
+            *count += mag.count;
+        }
+    }
+    counts
+}
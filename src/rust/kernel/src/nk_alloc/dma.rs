@@ -0,0 +1,159 @@
+// memory shared with devices: physically contiguous, never moved, and
+// with a known physical address to hand to the device.
+//
+// kmem blocks are physically contiguous and identity mapped, so a
+// block's physical address is its address. they are also aligned to
+// their (power-of-two) size, which covers the alignment of any `T`
+// that fits in them.
+
+use core::ffi::c_void;
+use core::mem::{align_of, size_of};
+use core::ops::{Deref, DerefMut};
+use core::ptr::{drop_in_place, slice_from_raw_parts_mut, NonNull};
+use core::slice;
+
+use super::{leaks, stats};
+use crate::nk_error::{KError, Result};
+use crate::{nk_backtrace, nk_raw};
+
+fn dma_alloc(size: usize, align: usize) -> Result<NonNull<u8>> {
+    if size == 0 {
+        return Err(KError::INVALID_ARG);
+    }
+    let p = unsafe { nk_raw::kmem_malloc(size as u64) } as *mut u8;
+    let p = NonNull::new(p).ok_or_else(|| {
+        stats::on_failure();
+        KError::NO_MEM
+    })?;
+    kassert!(
+        p.as_ptr() as usize % align == 0,
+        "{:p} for align {}",
+        p,
+        align
+    );
+    stats::on_alloc(size as u64);
+    leaks::on_alloc(p.as_ptr(), size as u64, nk_backtrace::frame_pointer());
+    Ok(p)
+}
+
+fn dma_free(p: NonNull<u8>, size: usize) {
+    stats::on_free(size as u64);
+    leaks::on_free(p.as_ptr());
+    unsafe { nk_raw::kmem_free(p.as_ptr() as *mut c_void) };
+}
+
+/// A `T` in DMA-able memory, e.g. a device's descriptor ring.
+pub struct DmaBox<T> {
+    ptr: NonNull<T>,
+}
+
+unsafe impl<T: Send> Send for DmaBox<T> {}
+unsafe impl<T: Sync> Sync for DmaBox<T> {}
+
+impl<T> DmaBox<T> {
+    pub fn new(value: T) -> Result<Self> {
+        let ptr = dma_alloc(size_of::<T>(), align_of::<T>())?.cast::<T>();
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(DmaBox { ptr })
+    }
+
+    /// The physical address of the `T`, for the device.
+    pub fn phys_addr(&self) -> u64 {
+        self.ptr.as_ptr() as u64
+    }
+
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<T> Deref for DmaBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // written in `new`, owned by us
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> DerefMut for DmaBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T> Drop for DmaBox<T> {
+    fn drop(&mut self) {
+        unsafe { drop_in_place(self.ptr.as_ptr()) };
+        dma_free(self.ptr.cast(), size_of::<T>());
+    }
+}
+
+/// `len` `T`s in DMA-able memory, e.g. a device's buffers.
+pub struct DmaSlice<T> {
+    ptr: NonNull<T>,
+    len: usize,
+}
+
+unsafe impl<T: Send> Send for DmaSlice<T> {}
+unsafe impl<T: Sync> Sync for DmaSlice<T> {}
+
+impl<T> DmaSlice<T> {
+    /// `len` `T`s, `f(i)` at index `i`.
+    ///
+    /// If `f` panics, the elements written so far and the memory are
+    /// leaked.
+    pub fn from_fn(len: usize, mut f: impl FnMut(usize) -> T) -> Result<Self> {
+        let size = size_of::<T>().checked_mul(len).ok_or(KError::INVALID_ARG)?;
+        let ptr = dma_alloc(size, align_of::<T>())?.cast::<T>();
+        for i in 0..len {
+            unsafe { ptr.as_ptr().add(i).write(f(i)) };
+        }
+        Ok(DmaSlice { ptr, len })
+    }
+
+    pub fn filled(len: usize, value: T) -> Result<Self>
+    where
+        T: Clone,
+    {
+        Self::from_fn(len, |_| value.clone())
+    }
+
+    /// The physical address of the first element.
+    pub fn phys_addr(&self) -> u64 {
+        self.ptr.as_ptr() as u64
+    }
+
+    /// The physical address of element `i`.
+    pub fn phys_addr_of(&self, i: usize) -> u64 {
+        kassert!(i < self.len, "index {} of {}", i, self.len);
+        self.phys_addr() + (i * size_of::<T>()) as u64
+    }
+
+    /// Size in bytes.
+    pub fn size(&self) -> usize {
+        self.len * size_of::<T>()
+    }
+}
+
+impl<T> Deref for DmaSlice<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // all `len` elements were written in `from_fn`
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> DerefMut for DmaSlice<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T> Drop for DmaSlice<T> {
+    fn drop(&mut self) {
+        unsafe { drop_in_place(slice_from_raw_parts_mut(self.ptr.as_ptr(), self.len)) };
+        dma_free(self.ptr.cast(), self.size());
+    }
+}
@@ -0,0 +1,268 @@
+// leak tracking (NAUT_CONFIG_RUST_LEAK_TRACKING): every live Rust heap
+// block is recorded in a side table, with its size, the return
+// addresses it was allocated from, and the snapshot it was allocated
+// in. `rust_leaks mark` starts a new snapshot, and `rust_leaks` lists
+// what was allocated since then and is still live.
+//
+// the table is a fixed-size open-addressing hash table allocated from
+// kmem when tracking is turned on, so recording never allocates. blocks
+// allocated before that, or while the table is full, go untracked.
+
+use core::ffi::c_int;
+use core::fmt::Write;
+use core::mem::size_of;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::nk_backtrace;
+use crate::nk_lock::IRQLock;
+use crate::nk_raw;
+use crate::utils::VcWriter;
+
+// a power of two
+const ENTRIES: usize = 8192;
+// return addresses kept per block
+pub const ORIGIN_FRAMES: usize = 4;
+// blocks listed by `rust_leaks`, grouped by origin
+const MAX_GROUPS: usize = 32;
+
+// `ptr` values of unused entries
+const EMPTY: usize = 0;
+const DELETED: usize = 1;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    ptr: usize,
+    size: u64,
+    snapshot: u32,
+    origin: [u64; ORIGIN_FRAMES],
+}
+
+struct Table {
+    entries: *mut Entry,
+    live: usize,
+    // allocations not recorded because the table was full
+    untracked: u64,
+    snapshot: u32,
+}
+
+// the entries are only touched with the lock held
+unsafe impl Send for Table {}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TABLE: IRQLock<Table> = IRQLock::new(Table {
+    entries: null_mut(),
+    live: 0,
+    untracked: 0,
+    snapshot: 0,
+});
+
+fn hash(ptr: usize) -> usize {
+    // blocks are at least 16 byte aligned
+    ((ptr >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15)) >> (usize::BITS - ENTRIES.trailing_zeros())
+}
+
+impl Table {
+    fn slots(&mut self) -> &mut [Entry] {
+        // `ENTRIES` entries, allocated by `nk_rust_leaks_init`
+        unsafe { core::slice::from_raw_parts_mut(self.entries, ENTRIES) }
+    }
+
+    fn find(&mut self, ptr: usize) -> Option<&mut Entry> {
+        let start = hash(ptr);
+        let slots = self.slots();
+        let i = (0..ENTRIES)
+            .map(|i| (start + i) & (ENTRIES - 1))
+            .take_while(|&i| slots[i].ptr != EMPTY)
+            .find(|&i| slots[i].ptr == ptr)?;
+        Some(&mut slots[i])
+    }
+
+    fn insert(&mut self, entry: Entry) {
+        let start = hash(entry.ptr);
+        let slots = self.slots();
+        for i in 0..ENTRIES {
+            let e = &mut slots[(start + i) & (ENTRIES - 1)];
+            if e.ptr == EMPTY || e.ptr == DELETED {
+                *e = entry;
+                self.live += 1;
+                return;
+            }
+        }
+        self.untracked += 1;
+    }
+}
+
+/// Turns tracking on. Called at boot, once kmem is up, when
+/// NAUT_CONFIG_RUST_LEAK_TRACKING is set.
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn nk_rust_leaks_init() -> c_int {
+    let mut table = TABLE.lock();
+    if !table.entries.is_null() {
+        return 0;
+    }
+    // zeroed entries are all `EMPTY`
+    let entries = unsafe { nk_raw::kmem_mallocz((ENTRIES * size_of::<Entry>()) as u64) };
+    if entries.is_null() {
+        return -1;
+    }
+    table.entries = entries as *mut Entry;
+    ENABLED.store(true, Ordering::Release);
+    0
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Acquire)
+}
+
+// for crash reports
+pub(crate) fn locked() -> bool {
+    TABLE.is_locked()
+}
+
+/// Records `ptr`, a new block of `size` bytes, allocated by the
+/// function whose frame `entry` (see `nk_backtrace::frame_pointer`)
+/// points to. Its origin starts at that function's caller, so it is
+/// where the block was asked for rather than the allocator's own
+/// frames.
+pub(super) fn on_alloc(ptr: *mut u8, size: u64, entry: u64) {
+    if !enabled() || ptr.is_null() {
+        return;
+    }
+    let mut origin = [0; ORIGIN_FRAMES];
+    nk_backtrace::walk_from(entry, |depth, rip, _| {
+        if let Some(o) = origin.get_mut(depth) {
+            *o = rip;
+        }
+    });
+    let mut table = TABLE.lock();
+    let snapshot = table.snapshot;
+    table.insert(Entry {
+        ptr: ptr as usize,
+        size,
+        snapshot,
+        origin,
+    });
+}
+
+/// Forgets `ptr`, which is being freed.
+pub(super) fn on_free(ptr: *mut u8) {
+    if !enabled() {
+        return;
+    }
+    let mut table = TABLE.lock();
+    if let Some(e) = table.find(ptr as usize) {
+        e.ptr = DELETED;
+        table.live -= 1;
+    }
+}
+
+/// Notes that `ptr` was resized in place.
+pub(super) fn on_resize(ptr: *mut u8, size: u64) {
+    if !enabled() {
+        return;
+    }
+    if let Some(e) = TABLE.lock().find(ptr as usize) {
+        e.size = size;
+    }
+}
+
+// where `ptr` was recorded as allocated from
+fn origin(ptr: *mut u8) -> Option<[u64; ORIGIN_FRAMES]> {
+    TABLE.lock().find(ptr as usize).map(|e| e.origin)
+}
+
+/// Starts a new snapshot; later listings only show blocks allocated
+/// from here on.
+pub fn mark() {
+    TABLE.lock().snapshot += 1;
+}
+
+/// The current snapshot.
+pub fn snapshot() -> u32 {
+    TABLE.lock().snapshot
+}
+
+struct Group {
+    origin: [u64; ORIGIN_FRAMES],
+    blocks: usize,
+    bytes: u64,
+}
+
+/// Lists live blocks allocated in snapshot `since` or later, grouped
+/// by where they were allocated.
+pub fn print_leaks(since: u32) {
+    const NO_GROUP: Option<Group> = None;
+    let mut groups = [NO_GROUP; MAX_GROUPS];
+    let (mut blocks, mut bytes, mut ungrouped) = (0, 0, 0);
+    let (live, untracked, snapshot) = {
+        let mut table = TABLE.lock();
+        for e in table.slots().iter() {
+            if e.ptr == EMPTY || e.ptr == DELETED || e.snapshot < since {
+                continue;
+            }
+            blocks += 1;
+            bytes += e.size;
+            let slot = groups.iter_mut().find(|g| match g {
+                Some(g) => g.origin == e.origin,
+                None => true,
+            });
+            match slot {
+                Some(Some(g)) => {
+                    g.blocks += 1;
+                    g.bytes += e.size;
+                }
+                Some(empty) => {
+                    *empty = Some(Group {
+                        origin: e.origin,
+                        blocks: 1,
+                        bytes: e.size,
+                    })
+                }
+                None => ungrouped += 1,
+            }
+        }
+        (table.live, table.untracked, table.snapshot)
+    };
+
+    // symbol lookups allocate on the C side, so only print once the
+    // table is unlocked
+    let mut w = VcWriter::new();
+    let _ = writeln!(
+        w,
+        "{} blocks ({} bytes) live since snapshot {}; {} tracked in all (snapshot {}), {} untracked",
+        blocks, bytes, since, live, snapshot, untracked
+    );
+    for g in groups.iter().flatten() {
+        let _ = writeln!(w, "{} blocks, {} bytes, from:", g.blocks, g.bytes);
+        for &rip in g.origin.iter().take_while(|&&rip| rip != 0) {
+            let _ = writeln!(w, "    {:#018x} {}", rip, nk_backtrace::symbol_name(rip));
+        }
+    }
+    if ungrouped != 0 {
+        let _ = writeln!(w, "{} more blocks from other places", ungrouped);
+    }
+}
+
+kernel_test!(
+    fn leak_origins_are_the_allocating_sites() {
+        if !enabled() {
+            return;
+        }
+        #[inline(never)]
+        fn here() -> alloc::boxed::Box<u64> {
+            alloc::boxed::Box::new(1)
+        }
+        #[inline(never)]
+        fn there() -> alloc::boxed::Box<u64> {
+            alloc::boxed::Box::new(2)
+        }
+        let (x, y) = (here(), there());
+        // both came through `Box::new`, so only their origins' first
+        // frames tell them apart
+        let a = origin(&*x as *const u64 as *mut u8).unwrap();
+        let b = origin(&*y as *const u64 as *mut u8).unwrap();
+        kassert!(a[0] != b[0]);
+    }
+);
@@ -0,0 +1,281 @@
+// allocator self tests, run by `rust_memtest`: random allocation,
+// reallocation and free patterns checked for alignment and for blocks
+// overwriting each other, the same from several threads at once, and
+// a quick pass over the other allocation facilities.
+
+use alloc::alloc::{alloc, dealloc, realloc, Layout};
+use core::arch::x86_64::_rdtsc;
+use core::ffi::{c_int, c_void};
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use super::bump::BumpArena;
+use super::dma::DmaSlice;
+use super::pages::{Pages, PAGE_SIZE};
+use super::slab::SlabCache;
+use super::{numa, uninit};
+use crate::nk_error::{KError, Result};
+use crate::nk_raw;
+
+// blocks live at once in each random test
+const SLOTS: usize = 64;
+// up to 16KB
+const MAX_SIZE_SHIFT: u32 = 14;
+// up to a page
+const MAX_ALIGN_SHIFT: u32 = 12;
+const STRESS_STACK_SIZE: u64 = 64 * 1024;
+/// Most threads `stress_test` runs.
+pub const MAX_THREADS: usize = 64;
+
+// xorshift64, good enough to vary sizes and orders
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Rng(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+struct Block {
+    ptr: *mut u8,
+    layout: Layout,
+    pattern: u8,
+}
+
+fn fill(ptr: *mut u8, len: usize, pattern: u8) {
+    for i in 0..len {
+        unsafe { ptr.add(i).write(pattern.wrapping_add(i as u8)) };
+    }
+}
+
+// index of the first byte of `[ptr, ptr + len)` not matching `fill`
+fn damaged(ptr: *mut u8, len: usize, pattern: u8) -> Option<usize> {
+    (0..len).find(|&i| unsafe { ptr.add(i).read() } != pattern.wrapping_add(i as u8))
+}
+
+fn random_layout(rng: &mut Rng) -> Layout {
+    // small sizes as often as large ones
+    let max_size = 1 << rng.below(MAX_SIZE_SHIFT as u64 + 1);
+    let size = 1 + rng.below(max_size) as usize;
+    let align = 1 << rng.below(MAX_ALIGN_SHIFT as u64 + 1);
+    // sizes and alignments are in range
+    Layout::from_size_align(size, align).unwrap()
+}
+
+/// Allocates, reallocates and frees blocks of random sizes and
+/// alignments, `iterations` times, and returns how many problems it
+/// saw (misaligned or overwritten blocks, failed allocations).
+pub fn random_test(seed: u64, iterations: usize) -> u64 {
+    const EMPTY: Option<Block> = None;
+    let mut rng = Rng::new(seed);
+    let mut slots = [EMPTY; SLOTS];
+    let mut errors = 0;
+
+    for _ in 0..iterations {
+        let slot = &mut slots[rng.below(SLOTS as u64) as usize];
+        match slot.take() {
+            None => {
+                let layout = random_layout(&mut rng);
+                let ptr = unsafe { alloc(layout) };
+                if ptr.is_null() {
+                    warn_print!("allocation of {:?} failed", layout);
+                    errors += 1;
+                    continue;
+                }
+                if ptr as usize % layout.align() != 0 {
+                    error_print!("{:p} is misaligned for {:?}", ptr, layout);
+                    errors += 1;
+                }
+                let pattern = rng.next() as u8;
+                fill(ptr, layout.size(), pattern);
+                *slot = Some(Block {
+                    ptr,
+                    layout,
+                    pattern,
+                });
+            }
+            Some(b) => {
+                if let Some(i) = damaged(b.ptr, b.layout.size(), b.pattern) {
+                    error_print!(
+                        "{:?} block at {:p} damaged at offset {}",
+                        b.layout,
+                        b.ptr,
+                        i
+                    );
+                    errors += 1;
+                }
+                if rng.below(4) != 0 {
+                    unsafe { dealloc(b.ptr, b.layout) };
+                    continue;
+                }
+                // keep it, at a new size
+                let new_size = random_layout(&mut rng).size();
+                let ptr = unsafe { realloc(b.ptr, b.layout, new_size) };
+                if ptr.is_null() {
+                    warn_print!("reallocation to {} bytes failed", new_size);
+                    errors += 1;
+                    unsafe { dealloc(b.ptr, b.layout) };
+                    continue;
+                }
+                let kept = b.layout.size().min(new_size);
+                if let Some(i) = damaged(ptr, kept, b.pattern) {
+                    error_print!("reallocation to {:p} lost data at offset {}", ptr, i);
+                    errors += 1;
+                }
+                // same alignment as before
+                let layout = Layout::from_size_align(new_size, b.layout.align()).unwrap();
+                fill(ptr, new_size, b.pattern);
+                *slot = Some(Block {
+                    ptr,
+                    layout,
+                    pattern: b.pattern,
+                });
+            }
+        }
+    }
+
+    for b in slots.iter_mut().filter_map(|s| s.take()) {
+        if damaged(b.ptr, b.layout.size(), b.pattern).is_some() {
+            errors += 1;
+        }
+        unsafe { dealloc(b.ptr, b.layout) };
+    }
+    errors
+}
+
+static STRESS_ITERATIONS: AtomicUsize = AtomicUsize::new(0);
+static STRESS_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+unsafe extern "C" fn stress_thread(input: *mut c_void, _output: *mut *mut c_void) {
+    // `input` is the thread's index, used to vary the seed
+    let seed = unsafe { _rdtsc() } ^ ((input as u64) << 32);
+    let errors = random_test(seed, STRESS_ITERATIONS.load(Ordering::Relaxed));
+    STRESS_ERRORS.fetch_add(errors, Ordering::Relaxed);
+}
+
+/// Runs `random_test` on `threads` threads at once, spread over the
+/// CPUs so the per-CPU caches see blocks freed on other CPUs, and
+/// returns the total number of problems.
+pub fn stress_test(threads: usize, iterations: usize) -> Result<u64> {
+    STRESS_ITERATIONS.store(iterations, Ordering::Relaxed);
+    STRESS_ERRORS.store(0, Ordering::Relaxed);
+    let num_cpus = unsafe { nk_raw::nk_get_num_cpus() }.max(1) as usize;
+
+    let mut started = 0;
+    let mut tids = [null_mut(); MAX_THREADS];
+    let mut result = Ok(());
+    for (i, tid) in tids.iter_mut().take(threads).enumerate() {
+        let r = unsafe {
+            nk_raw::nk_thread_start(
+                Some(stress_thread),
+                i as *mut c_void,
+                null_mut(),
+                0,
+                STRESS_STACK_SIZE,
+                tid,
+                (i % num_cpus) as c_int,
+            )
+        };
+        if let Err(e) = KError::from_ret(r) {
+            result = Err(e);
+            break;
+        }
+        started += 1;
+    }
+    for &tid in &tids[..started] {
+        unsafe { nk_raw::nk_join(tid, null_mut()) };
+    }
+    result.map(|_| STRESS_ERRORS.load(Ordering::Relaxed))
+}
+
+static SLAB_TEST: SlabCache<[u64; 4]> = SlabCache::new("memtest", || [7; 4], Some(|o| *o = [7; 4]));
+
+// one check of `facilities_test`
+fn check(what: &str, ok: Result<bool>, errors: &mut u64) {
+    match ok {
+        Ok(true) => {}
+        Ok(false) => {
+            error_print!("{}: wrong result", what);
+            *errors += 1;
+        }
+        Err(e) => {
+            error_print!("{}: {}", what, e);
+            *errors += 1;
+        }
+    }
+}
+
+/// Exercises each allocation facility once, and returns how many of
+/// them misbehaved.
+pub fn facilities_test() -> u64 {
+    let mut errors = 0;
+
+    check(
+        "pages",
+        Pages::zeroed(3).map(|p| {
+            p.phys_addr() % PAGE_SIZE as u64 == 0
+                && p.len() == 3 * PAGE_SIZE
+                && p.as_slice().iter().all(|&b| b == 0)
+        }),
+        &mut errors,
+    );
+    check(
+        "dma",
+        DmaSlice::filled(100, 0xa5u32).map(|d| {
+            d.phys_addr() % 4 == 0
+                && d.phys_addr_of(10) == d.phys_addr() + 40
+                && d.iter().all(|&x| x == 0xa5)
+        }),
+        &mut errors,
+    );
+    check(
+        "slab",
+        SLAB_TEST.alloc().map(|mut a| {
+            let fresh = *a == [7; 4];
+            a[0] = 1;
+            drop(a);
+            // the reset hook ran on the way back
+            fresh && matches!(SLAB_TEST.alloc(), Ok(b) if *b == [7; 4])
+        }),
+        &mut errors,
+    );
+    check(
+        "bump",
+        BumpArena::new(4096).and_then(|mut arena| {
+            let ok = arena.scope(|a| -> Result<bool> {
+                let x = a.alloc(42u64)?;
+                let s = a.alloc_str("nautilus")?;
+                Ok(*x == 42 && s == "nautilus" && (x as *mut u64 as usize) % 8 == 0)
+            })?;
+            Ok(ok && arena.used() == 0)
+        }),
+        &mut errors,
+    );
+    check(
+        "uninit",
+        uninit::try_new_slice_with(1000, |i| i as u32)
+            .map(|b| b.iter().enumerate().all(|(i, &x)| x == i as u32)),
+        &mut errors,
+    );
+    check(
+        "numa",
+        numa::alloc_local(Layout::from_size_align(256, 64).unwrap()).map(|p| {
+            let ok = p.as_ptr() as usize % 64 == 0;
+            unsafe { numa::dealloc(p, Layout::from_size_align(256, 64).unwrap()) };
+            ok
+        }),
+        &mut errors,
+    );
+    errors
+}
@@ -0,0 +1,222 @@
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    cmp::min,
+    ffi::{c_int, c_void},
+    ptr::{copy_nonoverlapping, null_mut, write_bytes},
+};
+
+use crate::{nk_backtrace, nk_raw};
+
+pub mod arena;
+pub mod bump;
+pub mod cache;
+pub mod dma;
+pub mod leaks;
+pub mod memtest;
+mod nk_shell_cmd;
+pub mod numa;
+pub mod oom;
+pub mod pages;
+pub mod poison;
+pub mod slab;
+pub mod stats;
+pub mod uninit;
+
+counter!(REALLOCS_IN_PLACE, "reallocs_in_place");
+histogram!(ALLOC_BYTES, "alloc_bytes");
+// an injected failure of an infallible allocation panics, as a real
+// one would
+fault_point!(ALLOC_FAULT, "alloc");
+
+pub struct NkAllocator;
+
+impl NkAllocator {
+    // `alloc`, on behalf of the allocator entry point whose frame
+    // `entry` points to, for leak tracking
+    unsafe fn alloc_from(&self, layout: Layout, entry: u64) -> *mut u8 {
+        let malloc_size = layout.pad_to_align().size() as u64;
+        ALLOC_BYTES.record(malloc_size);
+        if ALLOC_FAULT.should_fail() {
+            track_alloc(null_mut(), malloc_size, entry);
+            return null_mut();
+        }
+        let allocated = oom::retry(|| {
+            if arena::active() {
+                arena::alloc(&layout)
+            } else if let Some(cached) = cache::alloc(&layout) {
+                cached
+            } else {
+                // TODO: is kmem_malloc thread-safe?? `NkAllocator` does NOT lock
+                unsafe { nk_raw::kmem_malloc(malloc_size) as *mut u8 }
+            }
+        });
+        track_alloc(allocated, malloc_size, entry);
+        if allocated as usize % layout.align() != 0 {
+            // the current allocator is a buddy allocator,
+            // which guarantees this shouldn't happen.
+            panic!("kmem_malloc returned unaligned pointer");
+        }
+        allocated
+    }
+}
+
+unsafe impl GlobalAlloc for NkAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.alloc_from(layout, nk_backtrace::frame_pointer()) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        stats::on_free(layout.pad_to_align().size() as u64);
+        leaks::on_free(ptr);
+        // anything allocated before the arena was set up came from kmem
+        if arena::contains(ptr) {
+            unsafe { arena::dealloc(ptr, &layout) };
+        } else if !unsafe { cache::free(ptr, &layout) } {
+            if poison::enabled() {
+                unsafe { poison::fill(ptr, layout.size()) };
+            }
+            unsafe { nk_raw::kmem_free(ptr as *mut c_void) };
+        }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let entry = nk_backtrace::frame_pointer();
+        // small blocks may come from the cache, which does not zero them
+        if arena::active() || cache::cacheable(&layout) {
+            let allocated = unsafe { self.alloc_from(layout, entry) };
+            if !allocated.is_null() {
+                unsafe { write_bytes(allocated, 0, layout.size()) };
+            }
+            return allocated;
+        }
+
+        let malloc_size = layout.pad_to_align().size() as u64;
+        ALLOC_BYTES.record(malloc_size);
+        if ALLOC_FAULT.should_fail() {
+            track_alloc(null_mut(), malloc_size, entry);
+            return null_mut();
+        }
+        // kmem zeroes the block itself, sparing us a second pass
+        let allocated = oom::retry(|| unsafe { nk_raw::kmem_mallocz(malloc_size) } as *mut u8);
+        track_alloc(allocated, malloc_size, entry);
+        if allocated as usize % layout.align() != 0 {
+            panic!("kmem_mallocz returned unaligned pointer");
+        }
+        allocated
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        // kmem hands out power-of-two blocks, so there is often
+        // room to grow without moving. the block is later freed under
+        // `new_layout`, so it must not change cache class. (the arena
+        // is itself a kmem block, so arena pointers must not be looked
+        // up.)
+        if !arena::contains(ptr)
+            && cache::same_class(&layout, &new_layout)
+            && block_size(ptr) >= new_size as u64
+        {
+            REALLOCS_IN_PLACE.inc();
+            stats::on_free(layout.pad_to_align().size() as u64);
+            stats::on_alloc(new_layout.pad_to_align().size() as u64);
+            leaks::on_resize(ptr, new_layout.pad_to_align().size() as u64);
+            return ptr;
+        }
+
+        // not `kmem_realloc`, which copies the whole old block and
+        // panics on failure instead of returning null
+        let new_ptr = unsafe { self.alloc_from(new_layout, nk_backtrace::frame_pointer()) };
+        if !new_ptr.is_null() {
+            unsafe {
+                // caller guarantees `ptr` holds `layout.size()` bytes;
+                // the new block holds at least `new_size`
+                copy_nonoverlapping(ptr, new_ptr, min(layout.size(), new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
+
+fn track_alloc(allocated: *mut u8, size: u64, entry: u64) {
+    if allocated.is_null() {
+        stats::on_failure();
+    } else {
+        stats::on_alloc(size);
+        leaks::on_alloc(allocated, size, entry);
+    }
+}
+
+// size of the kmem block starting at `ptr`, 0 if there is none
+fn block_size(ptr: *mut u8) -> u64 {
+    let mut block = null_mut();
+    let mut size = 0;
+    let mut flags = 0;
+    let r =
+        unsafe { nk_raw::kmem_find_block(ptr as *mut c_void, &mut block, &mut size, &mut flags) };
+    if r == 0 && block == ptr as *mut c_void {
+        size
+    } else {
+        0
+    }
+}
+
+kernel_test!(
+    fn reallocs_stay_in_their_cache_class() {
+        if arena::active() {
+            return;
+        }
+        let large = Layout::from_size_align(200, 8).unwrap();
+        let ptr = unsafe { ALLOCATOR.alloc(large) };
+        kassert!(!ptr.is_null());
+        unsafe { ptr.write_bytes(7, 200) };
+
+        // both in the 256-byte class
+        let same = unsafe { ALLOCATOR.realloc(ptr, large, 130) };
+        kassert_eq!(same, ptr);
+
+        // into the 16-byte class, where the block would be cached once
+        // freed
+        let shrunk = Layout::from_size_align(130, 8).unwrap();
+        let moved = unsafe { ALLOCATOR.realloc(same, shrunk, 10) };
+        kassert!(!moved.is_null() && moved != same);
+        kassert_eq!(unsafe { *moved.add(9) }, 7);
+        unsafe { ALLOCATOR.dealloc(moved, Layout::from_size_align(10, 8).unwrap()) };
+    }
+);
+
+/// Sets up the Rust heap arena with `size` bytes from kmem. Called at
+/// boot, right after kmem is up, when NAUT_CONFIG_RUST_HEAP_ARENA is set.
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn nk_rust_heap_init(size: u64) -> c_int {
+    let mem = unsafe { nk_raw::kmem_malloc(size) } as *mut u8;
+    if mem.is_null() {
+        // Rust keeps allocating from kmem
+        return -1;
+    }
+    unsafe {
+        // the block is ours, and never freed
+        arena::init(mem, size as usize);
+    }
+    0
+}
+
+#[global_allocator]
+static ALLOCATOR: NkAllocator = NkAllocator {};
+
+#[cfg(not(test))]
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    // the panic message is formatted, so NK's panic cannot show it;
+    // log the details first
+    let s = stats::stats();
+    error_print!(
+        "out of memory allocating {} bytes (align {}), {} bytes live, {} failed allocations",
+        layout.size(),
+        layout.align(),
+        s.live_bytes,
+        s.failed
+    );
+    panic!("allocation error: {:?}", layout)
+}
@@ -0,0 +1,189 @@
+use core::ffi::{c_char, c_int, c_void, CStr};
+use core::fmt::Write;
+
+use super::oom::{self, OomPolicy};
+use super::stats::{self, CLASSES};
+use super::{arena, cache, leaks, memtest};
+use crate::utils::{print_to_vc, VcWriter};
+
+fn print_stats() {
+    let s = stats::stats();
+    let mut w = VcWriter::new();
+    let _ = writeln!(
+        w,
+        "live {} bytes, peak {} bytes, {} allocs, {} frees, {} failed",
+        s.live_bytes,
+        s.peak_bytes,
+        s.allocs(),
+        s.frees(),
+        s.failed
+    );
+    let _ = writeln!(
+        w,
+        "{} bytes of blocks, {}% lost to rounding",
+        s.block_bytes,
+        s.waste_percent()
+    );
+    let _ = writeln!(
+        w,
+        "{:>10} {:>10} {:>10} {:>12} {:>12} {:>6}",
+        "size", "allocs", "frees", "live bytes", "peak bytes", "waste"
+    );
+    for class in 0..CLASSES {
+        let c = &s.by_class[class];
+        if c.allocs == 0 {
+            continue;
+        }
+        let _ = match stats::class_limit(class) {
+            Some(limit) => write!(w, "{:>10}", limit),
+            None => write!(w, "{:>10}", "larger"),
+        };
+        let _ = writeln!(
+            w,
+            " {:>10} {:>10} {:>12} {:>12} {:>5}%",
+            c.allocs,
+            c.frees,
+            c.live_bytes,
+            c.peak_bytes,
+            c.waste_percent()
+        );
+    }
+    for (class, count) in cache::cached().iter().enumerate() {
+        if *count != 0 {
+            let _ = writeln!(
+                w,
+                "cached {}-byte blocks: {}",
+                cache::class_size(class),
+                count
+            );
+        }
+    }
+    print_arena(&mut w);
+}
+
+fn print_arena(w: &mut VcWriter) {
+    if arena::active() {
+        let u = arena::usage();
+        let _ = writeln!(
+            w,
+            "arena: {} of {} bytes free in {} blocks, largest {} ({}% fragmented)",
+            u.free_bytes,
+            u.size,
+            u.free_blocks,
+            u.largest_free,
+            u.fragmentation_percent()
+        );
+    }
+}
+
+/// Prints a summary of Rust heap usage, as part of `meminfo`.
+#[no_mangle]
+pub extern "C" fn nk_rust_meminfo() {
+    let s = stats::stats();
+    let mut w = VcWriter::new();
+    let _ = writeln!(
+        w,
+        "rust heap: {} bytes live, {} bytes peak, {}% lost to rounding, {} failed",
+        s.live_bytes,
+        s.peak_bytes,
+        s.waste_percent(),
+        s.failed
+    );
+    print_arena(&mut w);
+}
+
+// `rust_mem` prints Rust heap usage, `rust_mem reset` restarts peak
+// tracking, and `rust_mem oom` shows or sets the out-of-memory policy
+#[no_mangle]
+pub unsafe extern "C" fn rust_mem_shell_entry(buf: *const c_char, _priv_: *const c_void) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let mut args = line.split_whitespace().skip(1);
+
+    match args.next() {
+        None => print_stats(),
+        Some("reset") => stats::reset_peak(),
+        Some("oom") => match args.next() {
+            None => {
+                let _ = writeln!(VcWriter::new(), "oom policy: {:?}", oom::policy());
+            }
+            Some("panic") => oom::set_policy(OomPolicy::Panic),
+            Some("reclaim") => oom::set_policy(OomPolicy::Reclaim),
+            _ => print_to_vc("rust_mem oom [panic | reclaim]\n"),
+        },
+        _ => print_to_vc("rust_mem [reset | oom [panic | reclaim]]\n"),
+    }
+
+    0
+}
+
+// `rust_leaks` lists blocks allocated since the last `rust_leaks mark`
+// that are still live, `rust_leaks all` those since tracking started
+#[no_mangle]
+pub unsafe extern "C" fn rust_leaks_shell_entry(
+    buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let mut args = line.split_whitespace().skip(1);
+
+    if !leaks::enabled() {
+        print_to_vc("leak tracking is off (NAUT_CONFIG_RUST_LEAK_TRACKING)\n");
+        return 0;
+    }
+    match args.next() {
+        None => leaks::print_leaks(leaks::snapshot()),
+        Some("mark") => leaks::mark(),
+        Some("all") => leaks::print_leaks(0),
+        _ => print_to_vc("rust_leaks [mark | all]\n"),
+    }
+
+    0
+}
+
+// `rust_memtest [iterations [threads]]` runs the allocator self tests
+#[no_mangle]
+pub unsafe extern "C" fn rust_memtest_shell_entry(
+    buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let mut args = line.split_whitespace().skip(1);
+
+    let iterations = args.next().map_or(Ok(10000), str::parse::<usize>);
+    let threads = args.next().map_or(Ok(4), str::parse::<usize>);
+    let (iterations, threads) = match (iterations, threads) {
+        (Ok(i), Ok(t)) if t <= memtest::MAX_THREADS => (i, t),
+        _ => {
+            print_to_vc("rust_memtest [iterations [threads]]\n");
+            return 0;
+        }
+    };
+
+    let mut w = VcWriter::new();
+    let seed = unsafe { core::arch::x86_64::_rdtsc() };
+    let _ = writeln!(
+        w,
+        "random: {} problems in {} iterations",
+        memtest::random_test(seed, iterations),
+        iterations
+    );
+    w.flush();
+    match memtest::stress_test(threads, iterations) {
+        Ok(errors) => {
+            let _ = writeln!(
+                w,
+                "stress: {} problems in {} iterations on {} threads",
+                errors, iterations, threads
+            );
+        }
+        Err(e) => {
+            let _ = writeln!(w, "stress: cannot start threads: {}", e);
+        }
+    }
+    let _ = writeln!(w, "facilities: {} problems", memtest::facilities_test());
+
+    0
+}
@@ -0,0 +1,72 @@
+// allocations placed on a particular NUMA node, e.g. for a driver's
+// descriptor rings, so they are local to the CPU servicing its
+// interrupts.
+//
+// kmem searches memory in distance order from a given CPU, so to
+// allocate on a node we allocate on behalf of a CPU in that node.
+
+use core::alloc::Layout;
+use core::ffi::c_int;
+use core::ptr::NonNull;
+
+use super::{leaks, stats};
+use crate::nk_error::{KError, Result};
+use crate::{nk_backtrace, nk_raw};
+
+extern "C" {
+    // glue.c; -1 if `cpu` does not exist
+    fn nk_rust_cpu_numa_node(cpu: c_int) -> c_int;
+}
+
+/// The node of the CPU we are running on.
+pub fn current_node() -> u32 {
+    unsafe { nk_raw::nk_my_numa_node() }
+}
+
+pub fn num_nodes() -> u32 {
+    unsafe { nk_raw::nk_get_num_domains() }
+}
+
+pub fn cpu_node(cpu: u32) -> Option<u32> {
+    let node = unsafe { nk_rust_cpu_numa_node(cpu as c_int) };
+    (node >= 0).then(|| node as u32)
+}
+
+pub(super) fn first_cpu_on(node: u32) -> Option<u32> {
+    let num_cpus = unsafe { nk_raw::nk_get_num_cpus() };
+    (0..num_cpus).find(|&cpu| cpu_node(cpu) == Some(node))
+}
+
+/// Allocates memory for `layout`, preferring `node`. If the node's
+/// memory is exhausted, kmem falls back to the nearest other node.
+///
+/// The memory may be freed with `dealloc`, or owned by a `Box` or other
+/// container using the global allocator.
+pub fn alloc_on_node(layout: Layout, node: u32) -> Result<NonNull<u8>> {
+    let cpu = first_cpu_on(node).ok_or(KError::INVALID_ARG)?;
+    let size = layout.pad_to_align().size() as u64;
+    let p = unsafe { nk_raw::kmem_malloc_specific(size, cpu as c_int, 0) } as *mut u8;
+    let p = NonNull::new(p).ok_or_else(|| {
+        stats::on_failure();
+        KError::NO_MEM
+    })?;
+    // kmem blocks are aligned to their (power-of-two) size
+    kassert!(p.as_ptr() as usize % layout.align() == 0, "{:?}", layout);
+    stats::on_alloc(size);
+    leaks::on_alloc(p.as_ptr(), size, nk_backtrace::frame_pointer());
+    Ok(p)
+}
+
+/// Allocates on the node of the CPU we are running on.
+pub fn alloc_local(layout: Layout) -> Result<NonNull<u8>> {
+    alloc_on_node(layout, current_node())
+}
+
+/// # Safety
+///
+/// `ptr` must have come from `alloc_on_node` or `alloc_local` with
+/// the same `layout`, and must not be used afterwards.
+pub unsafe fn dealloc(ptr: NonNull<u8>, layout: Layout) {
+    // the global allocator frees anything that did not come from the arena
+    unsafe { alloc::alloc::dealloc(ptr.as_ptr(), layout) }
+}
@@ -0,0 +1,93 @@
+// what to do when the Rust heap runs out: by default, failed
+// allocations are retried once after asking subsystems to give back
+// whatever memory they can spare (cached blocks, buffers, ...). only
+// if that does not help does the allocation fail, and Rust code that
+// cannot handle failure then panics through `alloc_error_handler`.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use super::cache;
+use crate::nk_error::{KError, Result};
+use crate::nk_lock::IRQLock;
+
+const MAX_RECLAIMERS: usize = 8;
+
+/// Frees memory the caller can do without, and returns roughly how
+/// many bytes it freed.
+///
+/// Reclaimers run in whatever context the allocation failed in,
+/// possibly with interrupts off, so they must not block. They may free
+/// memory, but allocations they make are not retried.
+pub type Reclaimer = fn() -> usize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OomPolicy {
+    /// Fail the allocation right away.
+    Panic = 0,
+    /// Run the reclaimers, then retry the allocation once.
+    Reclaim = 1,
+}
+
+static POLICY: AtomicU8 = AtomicU8::new(OomPolicy::Reclaim as u8);
+static RECLAIMERS: IRQLock<[Option<(&'static str, Reclaimer)>; MAX_RECLAIMERS]> =
+    IRQLock::new([None; MAX_RECLAIMERS]);
+// one reclamation at a time; a failure while reclaiming (or on
+// another CPU meanwhile) just fails
+static RECLAIMING: AtomicBool = AtomicBool::new(false);
+
+pub fn policy() -> OomPolicy {
+    match POLICY.load(Ordering::Relaxed) {
+        0 => OomPolicy::Panic,
+        _ => OomPolicy::Reclaim,
+    }
+}
+
+pub fn set_policy(policy: OomPolicy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Registers `reclaimer`, under `name`, to run when the heap is out of
+/// memory. Reclaimers run in the order they were registered and cannot
+/// be removed; `BUSY` once `MAX_RECLAIMERS` are.
+pub fn register_reclaimer(name: &'static str, reclaimer: Reclaimer) -> Result<()> {
+    let mut reclaimers = RECLAIMERS.lock();
+    let slot = reclaimers
+        .iter_mut()
+        .find(|r| r.is_none())
+        .ok_or(KError::BUSY)?;
+    *slot = Some((name, reclaimer));
+    Ok(())
+}
+
+// for crash reports
+pub(crate) fn locked() -> bool {
+    RECLAIMERS.is_locked()
+}
+
+/// Runs the reclaimers, and returns whether any memory was freed.
+pub fn reclaim() -> bool {
+    if RECLAIMING.swap(true, Ordering::Acquire) {
+        return false;
+    }
+    // this CPU's cached blocks first, since they are always there
+    let mut freed = cache::drain();
+    // a copy, so reclaimers can free (and allocate) without the lock
+    let reclaimers = *RECLAIMERS.lock();
+    for (name, reclaimer) in reclaimers.iter().flatten() {
+        let n = reclaimer();
+        debug_print!("reclaimer {} freed {} bytes", name, n);
+        freed += n;
+    }
+    RECLAIMING.store(false, Ordering::Release);
+    freed != 0
+}
+
+/// Calls `alloc`, and if it fails and the policy allows, reclaims
+/// memory and calls it again.
+pub fn retry(mut alloc: impl FnMut() -> *mut u8) -> *mut u8 {
+    let p = alloc();
+    if p.is_null() && policy() == OomPolicy::Reclaim && reclaim() {
+        return alloc();
+    }
+    p
+}
@@ -0,0 +1,115 @@
+// whole, contiguous pages, for things that are page-sized by nature
+// (framebuffers, DMA rings, ...) and should not go through the
+// global allocator.
+//
+// these come straight from kmem, whose power-of-two blocks are
+// aligned to their size, so any block of a page or more is page
+// aligned. kernel memory is identity mapped, so the physical address
+// of the pages is their virtual address.
+
+use core::ffi::{c_int, c_void};
+use core::ptr::NonNull;
+use core::slice;
+
+use super::{leaks, numa, stats};
+use crate::nk_error::{KError, Result};
+use crate::nk_raw;
+
+// base pages, whatever page size the kernel maps with
+pub const PAGE_SIZE: usize = 4096;
+
+/// `count` contiguous pages, freed on drop.
+pub struct Pages {
+    ptr: NonNull<u8>,
+    count: usize,
+}
+
+// the pages belong to whoever holds the `Pages`
+unsafe impl Send for Pages {}
+unsafe impl Sync for Pages {}
+
+impl Pages {
+    /// Allocates `count` pages, contents undefined.
+    pub fn new(count: usize) -> Result<Self> {
+        Self::alloc(count, |size| unsafe { nk_raw::kmem_malloc(size) })
+    }
+
+    /// Allocates `count` zeroed pages.
+    pub fn zeroed(count: usize) -> Result<Self> {
+        Self::alloc(count, |size| unsafe { nk_raw::kmem_mallocz(size) })
+    }
+
+    /// Allocates `count` zeroed pages, preferring memory on `node`
+    /// (see `numa::alloc_on_node`).
+    pub fn zeroed_on_node(count: usize, node: u32) -> Result<Self> {
+        let cpu = numa::first_cpu_on(node).ok_or(KError::INVALID_ARG)?;
+        Self::alloc(count, |size| unsafe {
+            nk_raw::kmem_malloc_specific(size, cpu as c_int, 1)
+        })
+    }
+
+    fn alloc(count: usize, malloc: impl FnOnce(u64) -> *mut c_void) -> Result<Self> {
+        if count == 0 {
+            return Err(KError::INVALID_ARG);
+        }
+        let size = count.checked_mul(PAGE_SIZE).ok_or(KError::INVALID_ARG)?;
+        let ptr = NonNull::new(malloc(size as u64) as *mut u8).ok_or_else(|| {
+            stats::on_failure();
+            KError::NO_MEM
+        })?;
+        kassert!(ptr.as_ptr() as usize % PAGE_SIZE == 0, "{:p}", ptr);
+        stats::on_alloc(size as u64);
+        leaks::on_alloc(ptr.as_ptr(), size as u64);
+        Ok(Pages { ptr, count })
+    }
+
+    /// Number of pages.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Size in bytes.
+    pub fn len(&self) -> usize {
+        self.count * PAGE_SIZE
+    }
+
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// The physical address of the first page, e.g. for a device.
+    pub fn phys_addr(&self) -> u64 {
+        self.ptr.as_ptr() as u64
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len()) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len()) }
+    }
+
+    /// Views the pages as `T`s, as many as fit.
+    ///
+    /// # Safety
+    ///
+    /// Any bit pattern in the pages must be a valid `T`, and `T` must be
+    /// aligned to at most `PAGE_SIZE`.
+    pub unsafe fn as_mut_slice_of<T>(&mut self) -> &mut [T] {
+        let n = self.len() / core::mem::size_of::<T>().max(1);
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr() as *mut T, n) }
+    }
+}
+
+impl Drop for Pages {
+    fn drop(&mut self) {
+        stats::on_free(self.len() as u64);
+        leaks::on_free(self.ptr.as_ptr());
+        unsafe { nk_raw::kmem_free(self.ptr.as_ptr() as *mut c_void) };
+    }
+}
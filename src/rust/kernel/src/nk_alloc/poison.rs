@@ -0,0 +1,73 @@
+// heap poisoning (NAUT_CONFIG_RUST_HEAP_POISON): freed Rust heap
+// blocks are filled with `POISON`, so use after free shows up as
+// obviously bad data instead of plausible stale values.
+//
+// with NAUT_CONFIG_RUST_HEAP_POISON_CHECK, blocks are also checked
+// when they are handed out again, and a block written to after it was
+// freed panics. only blocks reused from the per-CPU caches can be
+// checked; kmem and the arena keep their own bookkeeping in free
+// memory, so their blocks are poisoned but not checked.
+
+use core::ffi::c_int;
+use core::ptr::write_bytes;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+pub const POISON: u8 = 0x6b;
+
+const OFF: u8 = 0;
+const FILL: u8 = 1;
+const CHECK: u8 = 2;
+
+static MODE: AtomicU8 = AtomicU8::new(OFF);
+
+/// Turns poisoning (and checking, if configured) on. Called at boot,
+/// right after kmem is up and before the per-CPU caches are, so every
+/// cached block has been poisoned.
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn nk_rust_heap_poison_init() -> c_int {
+    let mode = if nk_config_enabled!(RUST_HEAP_POISON_CHECK) {
+        CHECK
+    } else {
+        FILL
+    };
+    MODE.store(mode, Ordering::Relaxed);
+    0
+}
+
+pub fn enabled() -> bool {
+    MODE.load(Ordering::Relaxed) != OFF
+}
+
+pub fn checking() -> bool {
+    MODE.load(Ordering::Relaxed) == CHECK
+}
+
+/// Poisons `len` bytes at `ptr`, a block being freed.
+///
+/// # Safety
+///
+/// `ptr` must be valid for `len` bytes of writes.
+pub(super) unsafe fn fill(ptr: *mut u8, len: usize) {
+    unsafe { write_bytes(ptr, POISON, len) };
+}
+
+/// Panics if any of the `len` bytes at `ptr`, poisoned when the block
+/// was freed, has changed since.
+///
+/// # Safety
+///
+/// `ptr` must be valid for `len` bytes of reads.
+pub(super) unsafe fn check(ptr: *mut u8, len: usize) {
+    let block = unsafe { core::slice::from_raw_parts(ptr, len) };
+    if let Some(offset) = block.iter().position(|&b| b != POISON) {
+        error_print!(
+            "freed {}-byte block at {:p} was written at offset {} (now {:#04x})",
+            len,
+            ptr,
+            offset,
+            block[offset]
+        );
+        panic!("Rust heap use after free");
+    }
+}
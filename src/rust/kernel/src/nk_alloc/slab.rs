@@ -0,0 +1,162 @@
+// pools of constructed objects of one type, for hot paths that would
+// otherwise allocate and free the same kind of object over and over
+// (request headers, tasks, ...).
+//
+// objects are constructed once, when the slab holding them is
+// allocated, and reset when they are returned, so a free object is
+// always ready to be handed out again. slabs are never freed.
+
+use alloc::alloc::{alloc, Layout};
+use core::mem::{size_of, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::ptr::{null_mut, NonNull};
+
+use crate::nk_error::{KError, Result};
+use crate::nk_lock::IRQLock;
+
+// slabs are about this big, but hold at least one object
+const SLAB_BYTES: usize = 4096;
+
+struct Slot<T> {
+    // next free slot, while this one is free
+    next: *mut Slot<T>,
+    obj: MaybeUninit<T>,
+}
+
+struct Inner<T> {
+    free: *mut Slot<T>,
+    total: usize,
+    in_use: usize,
+}
+
+// the slots are only reached through the lock, or through the
+// `SlabBox` that owns one
+unsafe impl<T: Send> Send for Inner<T> {}
+
+/// A pool of `T`s, usually a `static`:
+///
+/// ```ignore
+/// static HEADERS: SlabCache<ReqHeader> = SlabCache::new("req_header", ReqHeader::new, None);
+/// let h = HEADERS.alloc()?;
+/// ```
+pub struct SlabCache<T> {
+    name: &'static str,
+    ctor: fn() -> T,
+    reset: Option<fn(&mut T)>,
+    inner: IRQLock<Inner<T>>,
+}
+
+impl<T: Send> SlabCache<T> {
+    /// `ctor` builds each object; `reset`, if any, returns a used
+    /// object to the state `ctor` left it in.
+    pub const fn new(name: &'static str, ctor: fn() -> T, reset: Option<fn(&mut T)>) -> Self {
+        SlabCache {
+            name,
+            ctor,
+            reset,
+            inner: IRQLock::new(Inner {
+                free: null_mut(),
+                total: 0,
+                in_use: 0,
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Objects in the pool, and how many of them are handed out.
+    pub fn stats(&self) -> (usize, usize) {
+        let inner = self.inner.lock();
+        (inner.total, inner.in_use)
+    }
+
+    /// Takes an object from the pool, growing it if it is empty.
+    pub fn alloc(&self) -> Result<SlabBox<'_, T>> {
+        let mut inner = self.inner.lock();
+        if inner.free.is_null() {
+            // constructors may allocate, so do not hold the lock
+            drop(inner);
+            self.grow()?;
+            inner = self.inner.lock();
+        }
+        // `grow` added at least one free slot, but others may have taken
+        // it in the meantime
+        let slot = NonNull::new(inner.free).ok_or(KError::NO_MEM)?;
+        inner.free = unsafe { slot.as_ref().next };
+        inner.in_use += 1;
+        Ok(SlabBox { cache: self, slot })
+    }
+
+    fn grow(&self) -> Result<()> {
+        let count = (SLAB_BYTES / size_of::<Slot<T>>()).max(1);
+        let layout = Layout::array::<Slot<T>>(count).map_err(|_| KError::INVALID_ARG)?;
+        let slab = unsafe { alloc(layout) } as *mut Slot<T>;
+        if slab.is_null() {
+            return Err(KError::NO_MEM);
+        }
+
+        // chain the new slots together before taking the lock
+        for i in 0..count {
+            let next = if i + 1 < count {
+                unsafe { slab.add(i + 1) }
+            } else {
+                null_mut()
+            };
+            unsafe {
+                slab.add(i).write(Slot {
+                    next,
+                    obj: MaybeUninit::new((self.ctor)()),
+                });
+            }
+        }
+
+        let mut inner = self.inner.lock();
+        unsafe { (*slab.add(count - 1)).next = inner.free };
+        inner.free = slab;
+        inner.total += count;
+        Ok(())
+    }
+
+    fn release(&self, mut slot: NonNull<Slot<T>>) {
+        if let Some(reset) = self.reset {
+            // the object was constructed when its slab was
+            reset(unsafe { slot.as_mut().obj.assume_init_mut() });
+        }
+        let mut inner = self.inner.lock();
+        unsafe { slot.as_mut().next = inner.free };
+        inner.free = slot.as_ptr();
+        inner.in_use -= 1;
+    }
+}
+
+/// An object on loan from a `SlabCache`, returned to it on drop.
+pub struct SlabBox<'a, T: Send> {
+    cache: &'a SlabCache<T>,
+    slot: NonNull<Slot<T>>,
+}
+
+unsafe impl<T: Send> Send for SlabBox<'_, T> {}
+unsafe impl<T: Send + Sync> Sync for SlabBox<'_, T> {}
+
+impl<T: Send> Deref for SlabBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // we own the slot, whose object was constructed by `grow`
+        unsafe { self.slot.as_ref().obj.assume_init_ref() }
+    }
+}
+
+impl<T: Send> DerefMut for SlabBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.slot.as_mut().obj.assume_init_mut() }
+    }
+}
+
+impl<T: Send> Drop for SlabBox<'_, T> {
+    fn drop(&mut self) {
+        self.cache.release(self.slot);
+    }
+}
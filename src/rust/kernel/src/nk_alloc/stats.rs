@@ -0,0 +1,161 @@
+// Rust heap usage, bucketed by size class since `GlobalAlloc` is not
+// told who is allocating. everything is a relaxed atomic, so this is
+// cheap enough to leave on and safe to update from any context.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+// class `i` holds allocations of up to `MIN_CLASS_SIZE << i` bytes,
+// the last one everything bigger
+pub const CLASSES: usize = 16;
+pub const MIN_CLASS_SIZE: u64 = 16;
+
+struct ClassCounters {
+    allocs: AtomicU64,
+    frees: AtomicU64,
+    live_bytes: AtomicU64,
+    peak_bytes: AtomicU64,
+    block_bytes: AtomicU64,
+}
+
+impl ClassCounters {
+    const fn new() -> Self {
+        ClassCounters {
+            allocs: AtomicU64::new(0),
+            frees: AtomicU64::new(0),
+            live_bytes: AtomicU64::new(0),
+            peak_bytes: AtomicU64::new(0),
+            block_bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const CLASS_INIT: ClassCounters = ClassCounters::new();
+static BY_CLASS: [ClassCounters; CLASSES] = [CLASS_INIT; CLASSES];
+
+static LIVE_BYTES: AtomicU64 = AtomicU64::new(0);
+static PEAK_BYTES: AtomicU64 = AtomicU64::new(0);
+static BLOCK_BYTES: AtomicU64 = AtomicU64::new(0);
+static FAILED: AtomicU64 = AtomicU64::new(0);
+
+pub fn class_of(size: u64) -> usize {
+    let mut class = 0;
+    while class < CLASSES - 1 && size > MIN_CLASS_SIZE << class {
+        class += 1;
+    }
+    class
+}
+
+// size of the kmem block holding `size` bytes: buddy blocks are powers
+// of two. (arena blocks are smaller, so this overstates their waste.)
+fn block_size(size: u64) -> u64 {
+    size.next_power_of_two().max(MIN_CLASS_SIZE)
+}
+
+/// Largest size in `class`, `None` for the last (unbounded) class.
+pub fn class_limit(class: usize) -> Option<u64> {
+    (class < CLASSES - 1).then(|| MIN_CLASS_SIZE << class)
+}
+
+pub(super) fn on_alloc(size: u64) {
+    let c = &BY_CLASS[class_of(size)];
+    c.allocs.fetch_add(1, Ordering::Relaxed);
+    let class_live = c.live_bytes.fetch_add(size, Ordering::Relaxed) + size;
+    c.peak_bytes.fetch_max(class_live, Ordering::Relaxed);
+    c.block_bytes.fetch_add(block_size(size), Ordering::Relaxed);
+    BLOCK_BYTES.fetch_add(block_size(size), Ordering::Relaxed);
+    let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+}
+
+pub(super) fn on_free(size: u64) {
+    let c = &BY_CLASS[class_of(size)];
+    c.frees.fetch_add(1, Ordering::Relaxed);
+    c.live_bytes.fetch_sub(size, Ordering::Relaxed);
+    c.block_bytes.fetch_sub(block_size(size), Ordering::Relaxed);
+    BLOCK_BYTES.fetch_sub(block_size(size), Ordering::Relaxed);
+    LIVE_BYTES.fetch_sub(size, Ordering::Relaxed);
+}
+
+pub(super) fn on_failure() {
+    FAILED.fetch_add(1, Ordering::Relaxed);
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ClassStats {
+    pub allocs: u64,
+    pub frees: u64,
+    pub live_bytes: u64,
+    /// High watermark of `live_bytes`.
+    pub peak_bytes: u64,
+    /// Bytes of the kmem blocks holding the live allocations.
+    pub block_bytes: u64,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Stats {
+    pub live_bytes: u64,
+    pub peak_bytes: u64,
+    pub block_bytes: u64,
+    pub failed: u64,
+    pub by_class: [ClassStats; CLASSES],
+}
+
+impl Stats {
+    pub fn allocs(&self) -> u64 {
+        self.by_class.iter().map(|c| c.allocs).sum()
+    }
+
+    pub fn frees(&self) -> u64 {
+        self.by_class.iter().map(|c| c.frees).sum()
+    }
+
+    /// Bytes lost to rounding allocations up to block sizes, in
+    /// percent of the blocks' bytes.
+    pub fn waste_percent(&self) -> u64 {
+        waste_percent(self.live_bytes, self.block_bytes)
+    }
+}
+
+impl ClassStats {
+    pub fn waste_percent(&self) -> u64 {
+        waste_percent(self.live_bytes, self.block_bytes)
+    }
+}
+
+fn waste_percent(live: u64, blocks: u64) -> u64 {
+    (blocks.saturating_sub(live) * 100)
+        .checked_div(blocks)
+        .unwrap_or(0)
+}
+
+/// The current counters. Not an atomic snapshot: allocations made
+/// while this runs may be partially reflected.
+pub fn stats() -> Stats {
+    let mut s = Stats {
+        live_bytes: LIVE_BYTES.load(Ordering::Relaxed),
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+        block_bytes: BLOCK_BYTES.load(Ordering::Relaxed),
+        failed: FAILED.load(Ordering::Relaxed),
+        ..Stats::default()
+    };
+    for (dst, src) in s.by_class.iter_mut().zip(BY_CLASS.iter()) {
+        *dst = ClassStats {
+            allocs: src.allocs.load(Ordering::Relaxed),
+            frees: src.frees.load(Ordering::Relaxed),
+            live_bytes: src.live_bytes.load(Ordering::Relaxed),
+            peak_bytes: src.peak_bytes.load(Ordering::Relaxed),
+            block_bytes: src.block_bytes.load(Ordering::Relaxed),
+        };
+    }
+    s
+}
+
+/// Restarts peak tracking from the current usage.
+pub fn reset_peak() {
+    PEAK_BYTES.store(LIVE_BYTES.load(Ordering::Relaxed), Ordering::Relaxed);
+    for c in BY_CLASS.iter() {
+        c.peak_bytes
+            .store(c.live_bytes.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
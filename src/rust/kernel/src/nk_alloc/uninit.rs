@@ -0,0 +1,70 @@
+// large buffers (framebuffers, packet rings, ...) allocated without
+// being initialized first, and then filled exactly once.
+//
+// unlike `vec![x; n]` or `Box::new([x; n])`, allocation failure is
+// returned instead of going to `alloc_error_handler`, since a buffer
+// that big failing to fit is not a reason to take the kernel down.
+
+use alloc::alloc::{alloc, Layout};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+use core::ptr::{slice_from_raw_parts_mut, NonNull};
+
+use crate::nk_error::{KError, Result};
+
+/// `len` uninitialized `T`s on the heap.
+pub fn try_new_uninit_slice<T>(len: usize) -> Result<Box<[MaybeUninit<T>]>> {
+    let layout = Layout::array::<T>(len).map_err(|_| KError::INVALID_ARG)?;
+    let ptr = if layout.size() == 0 {
+        NonNull::<MaybeUninit<T>>::dangling().as_ptr()
+    } else {
+        let p = unsafe { alloc(layout) } as *mut MaybeUninit<T>;
+        if p.is_null() {
+            return Err(KError::NO_MEM);
+        }
+        p
+    };
+    // allocated by the global allocator with `Box`'s layout for the slice
+    Ok(unsafe { Box::from_raw(slice_from_raw_parts_mut(ptr, len)) })
+}
+
+/// Treats every element of `buf` as initialized.
+///
+/// # Safety
+///
+/// Every element must have been written.
+pub unsafe fn assume_init<T>(buf: Box<[MaybeUninit<T>]>) -> Box<[T]> {
+    // `MaybeUninit<T>` has the layout of `T`
+    unsafe { Box::from_raw(Box::into_raw(buf) as *mut [T]) }
+}
+
+/// Initializes element `i` of `buf` to `f(i)`, in order.
+///
+/// If `f` panics, the elements written so far are leaked.
+pub fn init_with<T>(mut buf: Box<[MaybeUninit<T>]>, mut f: impl FnMut(usize) -> T) -> Box<[T]> {
+    for (i, elem) in buf.iter_mut().enumerate() {
+        elem.write(f(i));
+    }
+    // every element was written above
+    unsafe { assume_init(buf) }
+}
+
+/// Initializes every element of `buf` to `value`.
+pub fn fill<T: Clone>(buf: Box<[MaybeUninit<T>]>, value: T) -> Box<[T]> {
+    init_with(buf, |_| value.clone())
+}
+
+/// `len` `T`s, `f(i)` at index `i`, written once each.
+pub fn try_new_slice_with<T>(len: usize, f: impl FnMut(usize) -> T) -> Result<Box<[T]>> {
+    Ok(init_with(try_new_uninit_slice(len)?, f))
+}
+
+/// An empty `Vec` with room for exactly `capacity` elements, whose
+/// spare capacity can be filled through `Vec::spare_capacity_mut`
+/// before `Vec::set_len`.
+pub fn try_vec_with_capacity<T>(capacity: usize) -> Result<Vec<T>> {
+    let mut v = Vec::new();
+    v.try_reserve_exact(capacity).map_err(|_| KError::NO_MEM)?;
+    Ok(v)
+}
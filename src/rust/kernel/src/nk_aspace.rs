@@ -0,0 +1,343 @@
+// mapping physical ranges (device BARs, framebuffers, ...) into the
+// kernel address space, volatile access to the result, and checked
+// access to physical memory for tools that inspect it.
+//
+// the kernel address space is the boot identity map, so a mapping's
+// virtual address is its physical address. the map is built from 1GB
+// pages where the CPU has them, and from 2MB pages otherwise, but
+// `nk_map_page` only knows how to write 2MB ones: a 1GB page a mapping
+// falls in is first split into 2MB pages with its attributes, and
+// mappings are made (and their caching chosen) 2MB at a time. NK has
+// no way to remove such a mapping, so mappings are never undone; a
+// range mapped twice keeps the caching of the second mapping.
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::ptr::{read_volatile, write_volatile};
+
+use x86_64::instructions::tlb;
+use x86_64::registers::control::Cr3;
+use x86_64::VirtAddr;
+
+use crate::nk_error::{KError, Result};
+use crate::nk_lock::IRQLock;
+use crate::{nk_raw, nk_smp};
+
+extern "C" {
+    // glue.c; end of physical memory
+    fn nk_rust_phys_mem_avail() -> u64;
+}
+
+// granularity of our mappings, and of a split 1GB page
+const MAP_SIZE: u64 = 2 * 1024 * 1024;
+const GIG_SIZE: u64 = 1024 * 1024 * 1024;
+const TABLE_ENTRIES: usize = 512;
+const TABLE_SIZE: u64 = 4096;
+
+// paging.h
+const PTE_PRESENT: u64 = 1 << 0;
+const PTE_WRITABLE: u64 = 1 << 1;
+const PTE_WRITE_THROUGH: u64 = 1 << 3;
+const PTE_CACHE_DISABLE: u64 = 1 << 4;
+const PTE_USER: u64 = 1 << 2;
+const PTE_PAGE_SIZE: u64 = 1 << 7;
+const PTE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+// what a 1GB or 2MB entry holds besides its address: the low flags,
+// PAT included, and no-execute
+const PTE_LARGE_FLAGS: u64 = 0x1fff | 1 << 63;
+
+// two mappings in the same 1GB page must not both split it
+static TABLES: IRQLock<()> = IRQLock::new(());
+
+/// A physical address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PhysAddr(pub u64);
+
+impl PhysAddr {
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub fn checked_add(self, offset: u64) -> Option<PhysAddr> {
+        self.0.checked_add(offset).map(PhysAddr)
+    }
+
+    pub fn is_aligned(self, align: u64) -> bool {
+        self.0 & (align - 1) == 0
+    }
+
+    /// Whether `[self, self + len)` is RAM, and so identity mapped.
+    pub fn is_ram(self, len: u64) -> bool {
+        let end = unsafe { nk_rust_phys_mem_avail() };
+        matches!(self.checked_add(len), Some(e) if e.0 <= end)
+    }
+
+    /// The kernel's virtual address for it, which under the identity
+    /// map is the same number.
+    pub fn as_ptr<T>(self) -> *mut T {
+        self.0 as *mut T
+    }
+}
+
+impl fmt::LowerHex for PhysAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+/// How the CPU may cache a mapping.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Caching {
+    /// Normal memory.
+    WriteBack,
+    /// Writes go straight to memory, e.g. for framebuffers.
+    WriteThrough,
+    /// Device registers.
+    Uncached,
+}
+
+impl Caching {
+    fn pte_bits(self) -> u64 {
+        match self {
+            Caching::WriteBack => 0,
+            Caching::WriteThrough => PTE_WRITE_THROUGH,
+            Caching::Uncached => PTE_CACHE_DISABLE | PTE_WRITE_THROUGH,
+        }
+    }
+}
+
+/// Maps `[paddr, paddr + len)` into the kernel address space, and
+/// returns the virtual address of `paddr`.
+///
+/// The mapping covers whole 2MB pages, so memory around the range
+/// gets the same caching. Addresses past the boot map's PML4 entries
+/// are `INVALID_ARG`.
+///
+/// Every CPU's stale translations of those pages are flushed with an
+/// xcall, so this must be called with interrupts on, and not before
+/// the other CPUs are up.
+pub fn map_phys(paddr: PhysAddr, len: u64, caching: Caching) -> Result<*mut u8> {
+    if len == 0 {
+        return Err(KError::INVALID_ARG);
+    }
+    let end = paddr.checked_add(len).ok_or(KError::INVALID_ARG)?.0;
+    let flags = PTE_PRESENT | PTE_WRITABLE | caching.pte_bits();
+
+    let first = paddr.0 & !(MAP_SIZE - 1);
+    {
+        let _tables = TABLES.lock();
+        let mut page = first;
+        while page < end {
+            unsafe { make_page_directory(page)? };
+            KError::from_ret(unsafe {
+                nk_raw::nk_map_page(page, page, flags, nk_raw::page_size_t_PS_2M)
+            })?;
+            page += MAP_SIZE;
+        }
+    }
+    // `nk_map_page` only flushes this CPU's TLB, and the others may
+    // still hold the old caching for these pages
+    for cpu in 0..nk_smp::num_cpus() {
+        nk_smp::call_on(cpu, || flush_pages(first, end))?;
+    }
+    Ok(paddr.as_ptr())
+}
+
+// a zeroed table for the identity map, never freed
+fn new_table() -> Result<*mut u64> {
+    let t = unsafe { nk_raw::kmem_mallocz(TABLE_SIZE) } as *mut u64;
+    if t.is_null() {
+        return Err(KError::NO_MEM);
+    }
+    kassert!(t as u64 % TABLE_SIZE == 0, "{:p}", t);
+    Ok(t)
+}
+
+// makes sure `addr` is under a page directory that `nk_map_page` can
+// write a 2MB entry into. a 1GB page there is split into 2MB pages
+// with the same attributes, and a missing one gets an empty directory,
+// since `nk_map_page` would take it from the boot allocator.
+//
+// # Safety
+//
+// the caller holds `TABLES`.
+unsafe fn make_page_directory(addr: u64) -> Result<()> {
+    // the tables are in identity-mapped memory
+    let pml4 = Cr3::read().0.start_address().as_u64() as *mut u64;
+    let pml4e = unsafe { read_volatile(pml4.add((addr >> 39) as usize & 511)) };
+    if pml4e & PTE_PRESENT == 0 {
+        return Err(KError::INVALID_ARG);
+    }
+    let pdpt = (pml4e & PTE_ADDR_MASK) as *mut u64;
+    let pdpte_ptr = unsafe { pdpt.add((addr >> 30) as usize & 511) };
+    let pdpte = unsafe { read_volatile(pdpte_ptr) };
+    if pdpte & PTE_PRESENT != 0 && pdpte & PTE_PAGE_SIZE == 0 {
+        return Ok(());
+    }
+
+    let pd = new_table()?;
+    if pdpte & PTE_PRESENT != 0 {
+        let base = pdpte & PTE_ADDR_MASK & !(GIG_SIZE - 1);
+        let attrs = pdpte & PTE_LARGE_FLAGS;
+        for i in 0..TABLE_ENTRIES {
+            let entry = (base + i as u64 * MAP_SIZE) | attrs;
+            unsafe { write_volatile(pd.add(i), entry) };
+        }
+    }
+    let dir = pd as u64 | PTE_PRESENT | PTE_WRITABLE | (pdpte & PTE_USER);
+    // the old 1GB translation stays in TLBs until `map_phys` flushes
+    // its range, but it says the same as the new entries
+    unsafe { write_volatile(pdpte_ptr, dir) };
+    Ok(())
+}
+
+// drops this CPU's translations of the 2MB pages from `first` to `end`
+fn flush_pages(first: u64, end: u64) {
+    let mut page = first;
+    while page < end {
+        tlb::flush(VirtAddr::new(page));
+        page += MAP_SIZE;
+    }
+}
+
+/// Values that can be read and written as single MMIO accesses.
+pub trait MmioValue: Copy + private::Sealed {}
+
+impl MmioValue for u8 {}
+impl MmioValue for u16 {}
+impl MmioValue for u32 {}
+impl MmioValue for u64 {}
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for u8 {}
+    impl Sealed for u16 {}
+    impl Sealed for u32 {}
+    impl Sealed for u64 {}
+}
+
+/// A mapped, uncached range of device registers.
+///
+/// Accesses are volatile and bounds checked; `offset`s are in bytes
+/// from the start of the range.
+pub struct Mmio {
+    base: *mut u8,
+    len: usize,
+    // registers are shared with the device, not owned
+    _regs: PhantomData<*mut u8>,
+}
+
+// register access has no Rust-visible state; ordering between
+// CPUs is up to the driver, as for any device
+unsafe impl Send for Mmio {}
+unsafe impl Sync for Mmio {}
+
+impl Mmio {
+    /// Maps `len` bytes of registers at `paddr`.
+    ///
+    /// # Safety
+    ///
+    /// `[paddr, paddr + len)` must be device memory (not RAM in use),
+    /// e.g. a BAR of a device owned by the caller.
+    pub unsafe fn map(paddr: PhysAddr, len: usize) -> Result<Self> {
+        let base = map_phys(paddr, len as u64, Caching::Uncached)?;
+        Ok(Mmio {
+            base,
+            len,
+            _regs: PhantomData,
+        })
+    }
+
+    pub fn phys_addr(&self) -> PhysAddr {
+        PhysAddr(self.base as u64)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn reg<T: MmioValue>(&self, offset: usize) -> *mut T {
+        let size = core::mem::size_of::<T>();
+        kassert!(
+            offset % size == 0 && size <= self.len && offset <= self.len - size,
+            "MMIO access of {} bytes at {:#x} out of {:#x}",
+            size,
+            offset,
+            self.len
+        );
+        unsafe { self.base.add(offset) as *mut T }
+    }
+
+    pub fn read<T: MmioValue>(&self, offset: usize) -> T {
+        // in bounds and aligned, and mapped by `map`
+        unsafe { read_volatile(self.reg(offset)) }
+    }
+
+    pub fn write<T: MmioValue>(&self, offset: usize, val: T) {
+        unsafe { write_volatile(self.reg(offset), val) }
+    }
+
+    pub fn read32(&self, offset: usize) -> u32 {
+        self.read(offset)
+    }
+
+    pub fn write32(&self, offset: usize, val: u32) {
+        self.write(offset, val)
+    }
+}
+
+fn check_ram<T>(paddr: PhysAddr, len: u64) -> Result<()> {
+    if !paddr.is_aligned(core::mem::align_of::<T>() as u64) || !paddr.is_ram(len) {
+        return Err(KError::INVALID_ARG);
+    }
+    Ok(())
+}
+
+/// Reads the value at `paddr`, which must be aligned RAM. Device
+/// memory, where reads can have side effects, is read through `Mmio`.
+pub fn phys_read<T: MmioValue>(paddr: PhysAddr) -> Result<T> {
+    check_ram::<T>(paddr, core::mem::size_of::<T>() as u64)?;
+    // identity mapped RAM; whatever is there is a valid integer
+    Ok(unsafe { read_volatile(paddr.as_ptr::<T>()) })
+}
+
+/// Copies the RAM at `paddr` into `buf`, e.g. to dump it.
+pub fn phys_read_bytes(paddr: PhysAddr, buf: &mut [u8]) -> Result<()> {
+    check_ram::<u8>(paddr, buf.len() as u64)?;
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = unsafe { read_volatile(paddr.as_ptr::<u8>().add(i)) };
+    }
+    Ok(())
+}
+
+/// Writes `val` to `paddr`, which must be aligned RAM.
+///
+/// # Safety
+///
+/// Nothing else may be relying on the memory at `paddr`; it may belong
+/// to anything in the kernel.
+pub unsafe fn phys_write<T: MmioValue>(paddr: PhysAddr, val: T) -> Result<()> {
+    check_ram::<T>(paddr, core::mem::size_of::<T>() as u64)?;
+    unsafe { write_volatile(paddr.as_ptr::<T>(), val) };
+    Ok(())
+}
+
+kernel_test!(
+    fn remapping_ram_keeps_its_contents() {
+        // write-back is how RAM is already mapped, so this changes
+        // nothing but the page tables, splitting a 1GB page if need be
+        let mut word = alloc::boxed::Box::new(0x5a5a_u64);
+        let paddr = PhysAddr(&mut *word as *mut u64 as u64);
+        let p = map_phys(paddr, 8, Caching::WriteBack).unwrap();
+        kassert_eq!(p as u64, paddr.as_u64());
+        kassert_eq!(unsafe { read_volatile(p as *const u64) }, 0x5a5a);
+        kassert_eq!(
+            map_phys(paddr, 0, Caching::WriteBack),
+            Err(KError::INVALID_ARG)
+        );
+    }
+);
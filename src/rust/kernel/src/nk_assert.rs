@@ -0,0 +1,125 @@
+use core::fmt;
+
+use crate::nk_log::{self, Level};
+
+// logged at error level, which no filter can turn off, so the CPU,
+// thread, and location make it to the log even if the panic message
+// is lost (e.g. on a graphical console)
+#[doc(hidden)]
+#[cold]
+pub fn _kassert_failed(
+    module_path: &str,
+    file: &str,
+    line: u32,
+    what: fmt::Arguments,
+    state: Option<fmt::Arguments>,
+) -> ! {
+    nk_log::_log(Level::Error, module_path, file, line, what);
+    if let Some(state) = state {
+        nk_log::_log(
+            Level::Error,
+            module_path,
+            file,
+            line,
+            format_args!("state: {}", state),
+        );
+    }
+    panic!("{} at {}:{}", what, file, line);
+}
+
+/// Like `assert!`, but logs the failed expression and the current CPU
+/// and thread before panicking. Extra arguments are a format string
+/// describing the relevant state, which is only formatted on failure:
+/// `kassert!(head < len, "head {} len {}", head, len)`.
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr $(,)?) => {
+        if !$cond {
+            $crate::nk_assert::_kassert_failed(
+                module_path!(),
+                file!(),
+                line!(),
+                format_args!("assertion failed: {}", stringify!($cond)),
+                None,
+            )
+        }
+    };
+    ($cond:expr, $($state:tt)+) => {
+        if !$cond {
+            $crate::nk_assert::_kassert_failed(
+                module_path!(),
+                file!(),
+                line!(),
+                format_args!("assertion failed: {}", stringify!($cond)),
+                Some(format_args!($($state)+)),
+            )
+        }
+    };
+}
+
+/// Like `assert_eq!`, with the logging of `kassert!`.
+#[macro_export]
+macro_rules! kassert_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        match (&$left, &$right) {
+            (l, r) => {
+                if !(*l == *r) {
+                    $crate::nk_assert::_kassert_failed(
+                        module_path!(),
+                        file!(),
+                        line!(),
+                        format_args!(
+                            "assertion failed: {} == {} (left: {:?}, right: {:?})",
+                            stringify!($left),
+                            stringify!($right),
+                            l,
+                            r
+                        ),
+                        None,
+                    )
+                }
+            }
+        }
+    };
+    ($left:expr, $right:expr, $($state:tt)+) => {
+        match (&$left, &$right) {
+            (l, r) => {
+                if !(*l == *r) {
+                    $crate::nk_assert::_kassert_failed(
+                        module_path!(),
+                        file!(),
+                        line!(),
+                        format_args!(
+                            "assertion failed: {} == {} (left: {:?}, right: {:?})",
+                            stringify!($left),
+                            stringify!($right),
+                            l,
+                            r
+                        ),
+                        Some(format_args!($($state)+)),
+                    )
+                }
+            }
+        }
+    };
+}
+
+/// `kassert!` in debug builds only, like `debug_assert!`.
+#[macro_export]
+macro_rules! debug_kassert {
+    ($($arg:tt)+) => {
+        if cfg!(debug_assertions) {
+            $crate::kassert!($($arg)+)
+        }
+    };
+}
+
+/// `kassert_eq!` in debug builds only, like `debug_assert_eq!`.
+#[macro_export]
+macro_rules! debug_kassert_eq {
+    ($($arg:tt)+) => {
+        if cfg!(debug_assertions) {
+            $crate::kassert_eq!($($arg)+)
+        }
+    };
+}
@@ -0,0 +1,100 @@
+use core::arch::asm;
+use core::ffi::{c_char, c_uint, CStr};
+
+use crate::nk_raw;
+
+extern "C" {
+    // glue.c
+    fn nk_rust_phys_mem_avail() -> u64;
+    // name of the symbol containing `addr`, or null if there is no
+    // symbol table (NAUT_CONFIG_PROVENANCE is off, or it was not loaded)
+    fn nk_rust_symbol_name(addr: u64) -> *const c_char;
+}
+
+// stop here, in case the chain of frame pointers is corrupt
+const MAX_FRAMES: usize = 32;
+
+fn is_canonical(addr: u64) -> bool {
+    !(0x0000_8000_0000_0000..0xffff_8000_0000_0000).contains(&addr)
+}
+
+/// The frame pointer of the function this is called from.
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let fp: u64;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) fp);
+    }
+    fp
+}
+
+/// Calls `f` with the depth, return address, and frame pointer of each
+/// frame on the current stack, innermost first.
+///
+/// This follows the chain of saved frame pointers, so frames from code
+/// built without them (`"frame-pointer": "always"` in the target spec,
+/// `-fno-omit-frame-pointer` for C) are skipped or end the walk.
+pub fn walk(f: impl FnMut(usize, u64, u64)) {
+    walk_from(frame_pointer(), f)
+}
+
+/// Like `walk`, starting at the frame `fp` points to, so the first
+/// return address is into the caller of the function it belongs to.
+pub fn walk_from(mut fp: u64, mut f: impl FnMut(usize, u64, u64)) {
+    // NK identity-maps physical memory, so like the C backtrace we
+    // only follow frame pointers that land inside it
+    let mem_end = unsafe { nk_rust_phys_mem_avail() };
+    for depth in 0..MAX_FRAMES {
+        if fp == 0 || fp & 7 != 0 || !is_canonical(fp) || fp + 16 > mem_end {
+            break;
+        }
+        let frame = fp as *const u64;
+        // checked above that both words of the frame record are mapped
+        let (next_fp, rip) = unsafe { (*frame, *frame.add(1)) };
+        if rip == 0 {
+            break;
+        }
+        f(depth, rip, fp);
+        // stacks grow down, so callers' frames are at higher addresses
+        if next_fp <= fp {
+            break;
+        }
+        fp = next_fp;
+    }
+}
+
+/// Name of the symbol containing `addr`, or "???".
+pub fn symbol_name(addr: u64) -> &'static str {
+    let sym = unsafe { nk_rust_symbol_name(addr) };
+    if sym.is_null() {
+        return "???";
+    }
+    // names live in the kernel's symbol table, which is never freed
+    unsafe { CStr::from_ptr(sym) }.to_str().unwrap_or("???")
+}
+
+/// Prints the current call stack with printk, symbolized if possible.
+/// Nothing is allocated on the Rust side, so this is safe to call
+/// while panicking.
+pub fn print_backtrace() {
+    unsafe {
+        nk_raw::printk("[------------- Rust Call Trace -------------]\n\0".as_ptr() as *const i8);
+    }
+    walk(|depth, rip, fp| {
+        let sym = unsafe { nk_rust_symbol_name(rip) };
+        let sym = if sym.is_null() {
+            "???\0".as_ptr() as *const i8
+        } else {
+            sym
+        };
+        unsafe {
+            nk_raw::printk(
+                "[%2u] RIP: %p RBP: %p %s\n\0".as_ptr() as *const i8,
+                depth as c_uint,
+                rip,
+                fp,
+                sym,
+            );
+        }
+    });
+}
@@ -4,6 +4,9 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 #![allow(unsafe_op_in_unsafe_fn)]
+// only what `nk_raw` re-exports is used; the rest was pulled in
+// because those items depend on it
+#![allow(dead_code)]
 
 // include generated bindings in this file
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
@@ -0,0 +1,401 @@
+// block device drivers' side of NK's block device interface
+// (nautilus/blkdev.h): a driver implements `BlkDev`, and `Registration`
+// registers it, and hands NK's calls on to it with the device locked.
+//
+// requests are asynchronous: a driver queues each, and completes it
+// once the device is done, usually from its interrupt handler. a caller
+// that wants to wait goes through nk_block_dev_read/write, which do the
+// waiting.
+
+use alloc::boxed::Box;
+use alloc::ffi::CString;
+use alloc::sync::Arc;
+use core::ffi::{c_char, c_int, c_void};
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+use crate::nk_crash;
+use crate::nk_error::{KError, Result};
+use crate::nk_lock::IRQLock;
+use crate::nk_raw;
+
+counter!(REQUESTS, "requests");
+counter!(FAILED, "failed");
+fault_point!(REGISTER_FAULT, "blkdev_register");
+
+type RawCallback = unsafe extern "C" fn(nk_raw::nk_block_dev_status_t, *mut c_void);
+
+/// A device's geometry, in blocks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Characteristics {
+    pub block_size: u64,
+    pub num_blocks: u64,
+}
+
+/// Where to report a request's result, once the device is done with
+/// it. A request the driver refuses is not completed; one it queues
+/// must be, or whoever made it waits for good.
+#[must_use]
+pub struct Completion {
+    callback: Option<RawCallback>,
+    context: *mut c_void,
+}
+
+// the callback is NK's or the caller's, and may be called from any
+// context, interrupts included
+unsafe impl Send for Completion {}
+
+impl Completion {
+    pub fn complete(self, result: Result<()>) {
+        let status = match result {
+            Ok(()) => nk_raw::nk_block_dev_status_t_NK_BLOCK_DEV_STATUS_SUCCESS,
+            Err(_) => {
+                FAILED.inc();
+                nk_raw::nk_block_dev_status_t_NK_BLOCK_DEV_STATUS_ERROR
+            }
+        };
+        if let Some(callback) = self.callback {
+            // the caller passed them together with the request
+            unsafe { callback(status, self.context) };
+        }
+    }
+}
+
+/// A block device driver.
+pub trait BlkDev: Send {
+    fn get_characteristics(&self) -> Characteristics;
+
+    /// Queues a read of `count` blocks from `blocknum` on into `dest`,
+    /// and completes `done` once the device has. If it cannot be
+    /// queued, `done` is dropped instead. The blocks are at least one,
+    /// and all within the device, as `get_characteristics` has it.
+    ///
+    /// # Safety
+    ///
+    /// `dest` must be valid for writes of `count` blocks, and stay so,
+    /// and not be otherwise used, until the request completes.
+    unsafe fn read_blocks(
+        &mut self,
+        blocknum: u64,
+        count: u64,
+        dest: *mut u8,
+        done: Completion,
+    ) -> Result<()>;
+
+    /// Queues a write of `count` blocks from `src` to `blocknum` on.
+    /// See `read_blocks`.
+    ///
+    /// # Safety
+    ///
+    /// `src` must be valid for reads of `count` blocks, and stay so
+    /// until the request completes.
+    unsafe fn write_blocks(
+        &mut self,
+        blocknum: u64,
+        count: u64,
+        src: *const u8,
+        done: Completion,
+    ) -> Result<()>;
+}
+
+/// `T` registered as a block device, unregistered when dropped.
+pub struct Registration<T: BlkDev> {
+    dev: NonNull<nk_raw::nk_block_dev>,
+    // NK keeps a pointer to it until the device is unregistered
+    _interface: Box<nk_raw::nk_block_dev_int>,
+    _driver: PhantomData<Arc<IRQLock<T>>>,
+}
+
+// only handed back to nk_block_dev_unregister
+unsafe impl<T: BlkDev> Send for Registration<T> {}
+
+impl<T: BlkDev> Registration<T> {
+    /// Fails with `INVALID_ARG` if the device's blocks have no size.
+    pub fn register(name: &str, driver: Arc<IRQLock<T>>) -> Result<Self> {
+        REGISTER_FAULT.check(KError::FAILED)?;
+        if driver.lock().get_characteristics().block_size == 0 {
+            return Err(KError::INVALID_ARG);
+        }
+        let interface = Box::new(nk_raw::nk_block_dev_int {
+            get_characteristics: Some(get_characteristics::<T>),
+            read_blocks: Some(read_blocks::<T>),
+            write_blocks: Some(write_blocks::<T>),
+            dev_int: nk_raw::nk_dev_int {
+                open: None,
+                close: None,
+            },
+        });
+        // NK copies the name, so it is ours again once registered
+        let name_bytes = CString::new(name).map_err(|_| KError::INVALID_ARG)?;
+        let driver_ptr = Arc::into_raw(driver);
+        // not actually mutable, but C code had no `const` qualifier
+        let r = unsafe {
+            nk_raw::nk_block_dev_register(
+                name_bytes.as_ptr() as *mut c_char,
+                0,
+                &*interface as *const _ as *mut nk_raw::nk_block_dev_int,
+                driver_ptr as *mut c_void,
+            )
+        };
+        let dev = match NonNull::new(r) {
+            Some(d) => d,
+            None => {
+                // not registered, so the reference is still ours
+                drop(unsafe { Arc::from_raw(driver_ptr) });
+                return Err(KError::FAILED);
+            }
+        };
+        nk_crash::devices::register(r as *const u8, "blkdev", name);
+        Ok(Registration {
+            dev,
+            _interface: interface,
+            _driver: PhantomData,
+        })
+    }
+}
+
+impl<T: BlkDev> Drop for Registration<T> {
+    fn drop(&mut self) {
+        let ptr = self.dev.as_ptr();
+        nk_crash::devices::unregister(ptr as *const u8);
+        unsafe {
+            let state = (*ptr).dev.state as *const IRQLock<T>;
+            // NK calls into `state` until it is unregistered
+            nk_raw::nk_block_dev_unregister(ptr);
+            // taking back `Arc` is safe from any `blkdev` we registered
+            drop(Arc::from_raw(state));
+        }
+    }
+}
+
+unsafe fn deref_locked_state<'a, T>(state: *mut c_void) -> &'a IRQLock<T> {
+    // caller must guarantee `state` is what `register` passed, and that
+    // the device is still registered
+    let l = state as *const IRQLock<T>;
+    unsafe { l.as_ref() }.unwrap()
+}
+
+unsafe extern "C" fn get_characteristics<T: BlkDev>(
+    state: *mut c_void,
+    c: *mut nk_raw::nk_block_dev_characteristics,
+) -> c_int {
+    let d = unsafe { deref_locked_state::<T>(state) };
+    let chars = d.lock().get_characteristics();
+    unsafe {
+        (*c).block_size = chars.block_size;
+        (*c).num_blocks = chars.num_blocks;
+    }
+    0
+}
+
+// a request's blocks must all be the device's, so that drivers need not
+// check
+fn check_range(c: Characteristics, blocknum: u64, count: u64) -> Result<()> {
+    match blocknum.checked_add(count) {
+        Some(end) if count > 0 && end <= c.num_blocks => Ok(()),
+        _ => Err(KError::INVALID_ARG),
+    }
+}
+
+fn request_ret(r: Result<()>) -> c_int {
+    REQUESTS.inc();
+    match r {
+        Ok(()) => 0,
+        Err(e) => {
+            FAILED.inc();
+            debug_print!("blkdev request failed: {}", e.name());
+            -1
+        }
+    }
+}
+
+unsafe extern "C" fn read_blocks<T: BlkDev>(
+    state: *mut c_void,
+    blocknum: u64,
+    count: u64,
+    dest: *mut u8,
+    callback: Option<RawCallback>,
+    context: *mut c_void,
+) -> c_int {
+    let mut d = unsafe { deref_locked_state::<T>(state) }.lock();
+    let done = Completion { callback, context };
+    let r = check_range(d.get_characteristics(), blocknum, count)
+        .and_then(|_| unsafe { d.read_blocks(blocknum, count, dest, done) });
+    request_ret(r)
+}
+
+unsafe extern "C" fn write_blocks<T: BlkDev>(
+    state: *mut c_void,
+    blocknum: u64,
+    count: u64,
+    src: *mut u8,
+    callback: Option<RawCallback>,
+    context: *mut c_void,
+) -> c_int {
+    let mut d = unsafe { deref_locked_state::<T>(state) }.lock();
+    let done = Completion { callback, context };
+    let r = check_range(d.get_characteristics(), blocknum, count)
+        .and_then(|_| unsafe { d.write_blocks(blocknum, count, src, done) });
+    request_ret(r)
+}
+
+// two blocks of four bytes, done as soon as asked. it takes the wrapper
+// at its word that requests are in range, so indexes out of bounds if
+// one is not
+#[derive(Default)]
+struct TestRam {
+    bytes: [u8; 8],
+    block_size: u64,
+    // refuses writes
+    read_only: bool,
+    // completes requests with an error
+    failing: bool,
+}
+
+impl TestRam {
+    fn result(&self) -> Result<()> {
+        if self.failing {
+            Err(KError::IO)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl BlkDev for TestRam {
+    fn get_characteristics(&self) -> Characteristics {
+        Characteristics {
+            block_size: self.block_size,
+            num_blocks: 2,
+        }
+    }
+
+    unsafe fn read_blocks(
+        &mut self,
+        blocknum: u64,
+        count: u64,
+        dest: *mut u8,
+        done: Completion,
+    ) -> Result<()> {
+        let from = &self.bytes[(blocknum * 4) as usize..((blocknum + count) * 4) as usize];
+        unsafe { core::ptr::copy_nonoverlapping(from.as_ptr(), dest, from.len()) };
+        done.complete(self.result());
+        Ok(())
+    }
+
+    unsafe fn write_blocks(
+        &mut self,
+        blocknum: u64,
+        count: u64,
+        src: *const u8,
+        done: Completion,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(KError::INVALID_ARG);
+        }
+        let to = &mut self.bytes[(blocknum * 4) as usize..((blocknum + count) * 4) as usize];
+        unsafe { core::ptr::copy_nonoverlapping(src, to.as_mut_ptr(), to.len()) };
+        done.complete(self.result());
+        Ok(())
+    }
+}
+
+kernel_test!(
+    fn blkdev_registration_lasts_until_dropped() {
+        use crate::nk_test::fixture::{registered, Driver};
+
+        let ram = Driver::new(TestRam {
+            block_size: 4,
+            ..Default::default()
+        });
+        let reg = Registration::register("rust-test-blk", ram.driver.clone()).unwrap();
+        kassert!(registered("rust-test-blk"));
+        kassert_eq!(ram.shared(), 1);
+        drop(reg);
+        kassert!(!registered("rust-test-blk"));
+        kassert_eq!(ram.shared(), 0);
+
+        // with no block size, not at all
+        ram.driver.lock().block_size = 0;
+        kassert!(Registration::register("rust-test-blk", ram.driver.clone()).is_err());
+        kassert!(!registered("rust-test-blk"));
+        kassert_eq!(ram.shared(), 0);
+    }
+);
+
+kernel_test!(
+    fn blkdev_requests_stay_on_the_device() {
+        use crate::nk_test::fixture::{Done, Driver};
+
+        let ram = Driver::new(TestRam {
+            block_size: 4,
+            ..Default::default()
+        });
+        let state = ram.state();
+        let mut chars = nk_raw::nk_block_dev_characteristics {
+            block_size: 0,
+            num_blocks: 0,
+        };
+        kassert_eq!(
+            unsafe { get_characteristics::<TestRam>(state, &mut chars) },
+            0
+        );
+        kassert_eq!((chars.block_size, chars.num_blocks), (4, 2));
+
+        let done = Done::default();
+        let (callback, context) = done.callback();
+        let mut buf = *b"abcdefgh";
+        let write = |blocknum, count, buf: &mut [u8; 8]| unsafe {
+            write_blocks::<TestRam>(state, blocknum, count, buf.as_mut_ptr(), callback, context)
+        };
+        kassert_eq!(write(1, 1, &mut buf), 0);
+        kassert_eq!(
+            done.take(),
+            Some(nk_raw::nk_block_dev_status_t_NK_BLOCK_DEV_STATUS_SUCCESS)
+        );
+        kassert_eq!(&ram.driver.lock().bytes, b"\0\0\0\0abcd");
+
+        // past the end, none at all, or wrapping around; refused before
+        // the driver sees them, so not completed
+        for (blocknum, count) in [(1, 2), (2, 1), (0, 0), (u64::MAX, 2)] {
+            kassert_eq!(write(blocknum, count, &mut buf), -1);
+            let r = unsafe {
+                read_blocks::<TestRam>(state, blocknum, count, buf.as_mut_ptr(), callback, context)
+            };
+            kassert_eq!(r, -1);
+            kassert_eq!(done.take(), None);
+        }
+        kassert_eq!(&ram.driver.lock().bytes, b"\0\0\0\0abcd");
+    }
+);
+
+kernel_test!(
+    fn blkdev_errors_reach_the_caller() {
+        use crate::nk_test::fixture::{Done, Driver};
+
+        let ram = Driver::new(TestRam {
+            block_size: 4,
+            read_only: true,
+            ..Default::default()
+        });
+        let state = ram.state();
+        let done = Done::default();
+        let (callback, context) = done.callback();
+        let mut buf = [0u8; 8];
+
+        // refused, so not completed
+        let r =
+            unsafe { write_blocks::<TestRam>(state, 0, 1, buf.as_mut_ptr(), callback, context) };
+        kassert_eq!(r, -1);
+        kassert_eq!(done.take(), None);
+
+        // queued, then failed
+        ram.driver.lock().failing = true;
+        let r = unsafe { read_blocks::<TestRam>(state, 0, 2, buf.as_mut_ptr(), callback, context) };
+        kassert_eq!(r, 0);
+        kassert_eq!(
+            done.take(),
+            Some(nk_raw::nk_block_dev_status_t_NK_BLOCK_DEV_STATUS_ERROR)
+        );
+    }
+);
@@ -0,0 +1,316 @@
+// character device drivers' side of NK's chardev interface
+// (nautilus/chardev.h): a driver implements `CharDev`, and `Registration`
+// registers it, and hands NK's calls on to it with the device locked.
+//
+// reads and writes are a byte at a time, and never block: a driver says
+// when it would, and a caller that wants to wait goes through
+// nk_char_dev_read/write, which wait on the device until the driver
+// signals it, e.g. from its interrupt handler once there is input.
+
+use alloc::boxed::Box;
+use alloc::ffi::CString;
+use alloc::sync::Arc;
+use core::ffi::{c_char, c_int, c_void};
+use core::marker::PhantomData;
+use core::ptr::{write_bytes, NonNull};
+
+use crate::nk_crash;
+use crate::nk_error::{KError, Result};
+use crate::nk_lock::IRQLock;
+use crate::nk_raw;
+
+counter!(READS, "reads");
+counter!(WRITES, "writes");
+counter!(FAILED, "failed");
+fault_point!(REGISTER_FAULT, "chardev_register");
+
+/// Whether a device can be read or written without blocking, or has
+/// failed.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub readable: bool,
+    pub writeable: bool,
+    pub error: bool,
+}
+
+impl Status {
+    fn bits(self) -> c_int {
+        let mut bits = 0;
+        if self.readable {
+            bits |= nk_raw::NK_CHARDEV_READABLE;
+        }
+        if self.writeable {
+            bits |= nk_raw::NK_CHARDEV_WRITEABLE;
+        }
+        if self.error {
+            bits |= nk_raw::NK_CHARDEV_ERROR;
+        }
+        bits as c_int
+    }
+}
+
+/// A character device driver.
+pub trait CharDev: Send {
+    /// The next byte, or `None` if there is none yet.
+    fn read(&mut self) -> Result<Option<u8>>;
+
+    /// Writes `byte`, or returns `false` if the device cannot take it
+    /// yet.
+    fn write(&mut self, byte: u8) -> Result<bool>;
+
+    fn status(&self) -> Status;
+}
+
+/// Wakes whoever waits on a registered device, once it may be readable
+/// or writeable again.
+#[derive(Copy, Clone)]
+pub struct Signal {
+    dev: NonNull<nk_raw::nk_dev>,
+}
+
+// nk_dev_signal may be called from any context, interrupts included
+unsafe impl Send for Signal {}
+
+impl Signal {
+    /// Whoever is woken checks the device's status, so if it is called
+    /// with the device locked, they wait for the lock.
+    ///
+    /// # Safety
+    ///
+    /// The `Registration` it came from must not have been dropped.
+    pub unsafe fn signal(&self) {
+        unsafe { nk_raw::nk_dev_signal(self.dev.as_ptr()) };
+    }
+}
+
+/// `T` registered as a chardev, unregistered when dropped.
+pub struct Registration<T: CharDev> {
+    dev: NonNull<nk_raw::nk_char_dev>,
+    // NK keeps a pointer to it until the device is unregistered
+    _interface: Box<nk_raw::nk_char_dev_int>,
+    _driver: PhantomData<Arc<IRQLock<T>>>,
+}
+
+// only handed back to nk_char_dev_unregister
+unsafe impl<T: CharDev> Send for Registration<T> {}
+
+impl<T: CharDev> Registration<T> {
+    pub fn register(name: &str, driver: Arc<IRQLock<T>>) -> Result<Self> {
+        REGISTER_FAULT.check(KError::FAILED)?;
+        let interface = Box::new(nk_raw::nk_char_dev_int {
+            get_characteristics: Some(get_characteristics),
+            read: Some(read::<T>),
+            write: Some(write::<T>),
+            status: Some(status::<T>),
+            dev_int: nk_raw::nk_dev_int {
+                open: None,
+                close: None,
+            },
+        });
+        // NK copies the name, so it is ours again once registered
+        let name_bytes = CString::new(name).map_err(|_| KError::INVALID_ARG)?;
+        let driver_ptr = Arc::into_raw(driver);
+        // not actually mutable, but C code had no `const` qualifier
+        let r = unsafe {
+            nk_raw::nk_char_dev_register(
+                name_bytes.as_ptr() as *mut c_char,
+                0,
+                &*interface as *const _ as *mut nk_raw::nk_char_dev_int,
+                driver_ptr as *mut c_void,
+            )
+        };
+        let dev = match NonNull::new(r) {
+            Some(d) => d,
+            None => {
+                // not registered, so the reference is still ours
+                drop(unsafe { Arc::from_raw(driver_ptr) });
+                return Err(KError::FAILED);
+            }
+        };
+        nk_crash::devices::register(r as *const u8, "chardev", name);
+        Ok(Registration {
+            dev,
+            _interface: interface,
+            _driver: PhantomData,
+        })
+    }
+
+    pub fn signal(&self) -> Signal {
+        Signal {
+            // a chardev is an `nk_dev` first
+            dev: self.dev.cast(),
+        }
+    }
+}
+
+impl<T: CharDev> Drop for Registration<T> {
+    fn drop(&mut self) {
+        let ptr = self.dev.as_ptr();
+        nk_crash::devices::unregister(ptr as *const u8);
+        unsafe {
+            let state = (*ptr).dev.state as *const IRQLock<T>;
+            // NK calls into `state` until it is unregistered
+            nk_raw::nk_char_dev_unregister(ptr);
+            // taking back `Arc` is safe from any `chardev` we registered
+            drop(Arc::from_raw(state));
+        }
+    }
+}
+
+unsafe fn deref_locked_state<'a, T>(state: *mut c_void) -> &'a IRQLock<T> {
+    // caller must guarantee `state` is what `register` passed, and that
+    // the device is still registered
+    let l = state as *const IRQLock<T>;
+    unsafe { l.as_ref() }.unwrap()
+}
+
+unsafe extern "C" fn get_characteristics(
+    _state: *mut c_void,
+    c: *mut nk_raw::nk_char_dev_characteristics,
+) -> c_int {
+    unsafe {
+        // memset the (single) struct to bytes of 0
+        write_bytes(c, 0, 1);
+    }
+    0
+}
+
+// 1 for a byte, 0 for none yet, -1 for an error
+fn byte_ret(r: Result<bool>) -> c_int {
+    match r {
+        Ok(true) => 1,
+        Ok(false) => 0,
+        Err(e) => {
+            FAILED.inc();
+            debug_print!("chardev request failed: {}", e.name());
+            -1
+        }
+    }
+}
+
+unsafe extern "C" fn read<T: CharDev>(state: *mut c_void, dest: *mut u8) -> c_int {
+    let d = unsafe { deref_locked_state::<T>(state) };
+    let r = d.lock().read().map(|b| match b {
+        Some(b) => {
+            READS.inc();
+            // caller guarantees `dest` points to the byte to read into
+            unsafe { *dest = b };
+            true
+        }
+        None => false,
+    });
+    byte_ret(r)
+}
+
+unsafe extern "C" fn write<T: CharDev>(state: *mut c_void, src: *mut u8) -> c_int {
+    let d = unsafe { deref_locked_state::<T>(state) };
+    // caller guarantees `src` points to the byte to write
+    let byte = unsafe { *src };
+    let r = d.lock().write(byte).inspect(|&written| {
+        if written {
+            WRITES.inc();
+        }
+    });
+    byte_ret(r)
+}
+
+unsafe extern "C" fn status<T: CharDev>(state: *mut c_void) -> c_int {
+    let d = unsafe { deref_locked_state::<T>(state) };
+    let s = d.lock().status();
+    s.bits()
+}
+
+// holds one byte, which reads take back
+#[derive(Default)]
+struct TestLatch {
+    byte: Option<u8>,
+    // fails reads and writes, and says so
+    failing: bool,
+}
+
+impl CharDev for TestLatch {
+    fn read(&mut self) -> Result<Option<u8>> {
+        if self.failing {
+            return Err(KError::IO);
+        }
+        Ok(self.byte.take())
+    }
+
+    fn write(&mut self, byte: u8) -> Result<bool> {
+        if self.failing {
+            return Err(KError::IO);
+        }
+        if self.byte.is_some() {
+            return Ok(false);
+        }
+        self.byte = Some(byte);
+        Ok(true)
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            readable: self.byte.is_some(),
+            writeable: self.byte.is_none(),
+            error: self.failing,
+        }
+    }
+}
+
+kernel_test!(
+    fn chardev_registration_lasts_until_dropped() {
+        use crate::nk_test::fixture::{registered, Driver};
+
+        let latch = Driver::new(TestLatch::default());
+        let reg = Registration::register("rust-test-char", latch.driver.clone()).unwrap();
+        kassert!(registered("rust-test-char"));
+        kassert_eq!(latch.shared(), 1);
+        // waking no one, as no one waits
+        unsafe { reg.signal().signal() };
+        drop(reg);
+        kassert!(!registered("rust-test-char"));
+        kassert_eq!(latch.shared(), 0);
+    }
+);
+
+kernel_test!(
+    fn chardev_status_tracks_the_driver() {
+        use crate::nk_test::fixture::Driver;
+
+        let latch = Driver::new(TestLatch::default());
+        let state = latch.state();
+        let (r, w) = (
+            nk_raw::NK_CHARDEV_READABLE as c_int,
+            nk_raw::NK_CHARDEV_WRITEABLE as c_int,
+        );
+        let mut byte = b'x';
+        kassert_eq!(unsafe { status::<TestLatch>(state) }, w);
+        kassert_eq!(unsafe { read::<TestLatch>(state, &mut byte) }, 0);
+        kassert_eq!(unsafe { write::<TestLatch>(state, &mut byte) }, 1);
+        kassert_eq!(unsafe { status::<TestLatch>(state) }, r);
+        // full, so not yet
+        kassert_eq!(unsafe { write::<TestLatch>(state, &mut byte) }, 0);
+
+        byte = 0;
+        kassert_eq!(unsafe { read::<TestLatch>(state, &mut byte) }, 1);
+        kassert_eq!(byte, b'x');
+    }
+);
+
+kernel_test!(
+    fn chardev_errors_reach_the_caller() {
+        use crate::nk_test::fixture::Driver;
+
+        let latch = Driver::new(TestLatch {
+            byte: Some(b'x'),
+            failing: true,
+        });
+        let state = latch.state();
+        // and the byte is left alone
+        let mut byte = 0;
+        kassert_eq!(unsafe { read::<TestLatch>(state, &mut byte) }, -1);
+        kassert_eq!(byte, 0);
+        kassert_eq!(unsafe { write::<TestLatch>(state, &mut byte) }, -1);
+        let error = nk_raw::NK_CHARDEV_ERROR as c_int;
+        kassert_eq!(unsafe { status::<TestLatch>(state) } & error, error);
+    }
+);
@@ -0,0 +1,139 @@
+// boot parameters for Rust code, from the kernel command line:
+//
+//   multiboot2 /nautilus.bin -rust rust.loglevel=debug foo.verbose
+//
+// everything after `-rust`, up to the next flag, is a list of
+// `module.param=value` words; a value with spaces can be quoted
+// (`foo.name="a b"`), and a word without a value is a flag that is
+// on. `-rust` can be given more than once, and a later value wins.
+//
+// NK's own flag dispatcher runs late in boot, so this reads the
+// command line multiboot passed in directly, and can be used from
+// early boot on. glue.c registers `-rust` with the dispatcher only so
+// that it doesn't reject it.
+
+use core::ffi::{c_char, CStr};
+use core::str::FromStr;
+
+use crate::nk_error::{KError, Result};
+
+extern "C" {
+    // glue.c
+    fn nk_rust_boot_cmdline() -> *const c_char;
+}
+
+const FLAG: &str = "-rust";
+
+/// The whole command line, or "" before multiboot's information has
+/// been parsed.
+pub fn raw() -> &'static str {
+    let line = unsafe { nk_rust_boot_cmdline() };
+    if line.is_null() {
+        return "";
+    }
+    // the command line is copied into boot memory, which is never freed
+    unsafe { CStr::from_ptr(line) }.to_str().unwrap_or("")
+}
+
+// whitespace-separated words, where whitespace between quotes does
+// not separate
+struct Words<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let s = self.rest.trim_start_matches([' ', '\t']);
+        if s.is_empty() {
+            return None;
+        }
+        let mut quoted = false;
+        let end = s
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                !quoted && (c == ' ' || c == '\t')
+            })
+            .map_or(s.len(), |(i, _)| i);
+        self.rest = &s[end..];
+        Some(&s[..end])
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+// the parameters given to `-rust` in `line`, in order
+fn params_in(line: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    let mut ours = false;
+    Words { rest: line }.filter_map(move |word| {
+        if word.starts_with('-') {
+            ours = word == FLAG;
+            return None;
+        }
+        if !ours {
+            return None;
+        }
+        Some(match word.split_once('=') {
+            Some((key, value)) => (key, Some(unquote(value))),
+            None => (word, None),
+        })
+    })
+}
+
+/// Every parameter, in the order given, with its value if it has one.
+pub fn params() -> impl Iterator<Item = (&'static str, Option<&'static str>)> {
+    params_in(raw())
+}
+
+/// The value of `key` (`module.param`), "" if it was given without one,
+/// or `None` if it wasn't given.
+pub fn get(key: &str) -> Option<&'static str> {
+    params()
+        .filter(|&(k, _)| k == key)
+        .last()
+        .map(|(_, v)| v.unwrap_or(""))
+}
+
+/// Whether `key` is on: given without a value, or as one of `1`, `y`,
+/// `yes`, `on`, or `true`.
+pub fn enabled(key: &str) -> bool {
+    matches!(get(key), Some("" | "1" | "y" | "yes" | "on" | "true"))
+}
+
+/// The value of `key`, parsed as a `T`; `None` if it wasn't given. A
+/// value that doesn't parse is logged and is `INVALID_ARG`, so that
+/// the caller can fall back to its default.
+pub fn parse<T: FromStr>(key: &str) -> Result<Option<T>> {
+    let Some(value) = get(key) else {
+        return Ok(None);
+    };
+    match value.parse() {
+        Ok(v) => Ok(Some(v)),
+        Err(_) => {
+            warn_print!("ignoring bad boot parameter {}={}", key, value);
+            Err(KError::INVALID_ARG)
+        }
+    }
+}
+
+kernel_test!(
+    fn params_are_split_like_nk_flags() {
+        let line = "nautilus.bin -test t1 \"a b\" -rust rust.loglevel=debug \
+                    foo.name=\"a b\" foo.on  -other x.y=1 -rust foo.n=2";
+        let mut p = params_in(line);
+        kassert_eq!(p.next(), Some(("rust.loglevel", Some("debug"))));
+        kassert_eq!(p.next(), Some(("foo.name", Some("a b"))));
+        kassert_eq!(p.next(), Some(("foo.on", None)));
+        kassert_eq!(p.next(), Some(("foo.n", Some("2"))));
+        kassert_eq!(p.next(), None);
+    }
+);
@@ -0,0 +1,119 @@
+// the kernel's Kconfig options, generated by build.rs from the .config
+// the kernel is built with. rustc never sees the C preprocessor's
+// NAUT_CONFIG_* defines, so this is how Rust code finds out what is
+// configured, rather than asking glue.c or being told at init.
+//
+// every option listed in .config is a const here, without the
+// NAUT_CONFIG_ prefix: bools as `bool`, ints and hex as `u64` (`i64` if
+// negative), and strings as `&str`. options whose dependencies are not
+// met are left out of .config, and so out of this module, so code that
+// only asks whether an option is on should use `nk_config_enabled!`:
+//
+// ```ignore
+// if nk_config_enabled!(DEBUG_PRINTS) {
+//     dump_state();
+// }
+// ```
+//
+// which is `true` if NAUT_CONFIG_DEBUG_PRINTS is on, like `#ifdef` in
+// C, and `false` otherwise, including for options that do not exist.
+// the code it guards is still type-checked when it is off.
+//
+// options that are on are also set as cfgs, to leave out whole items:
+//
+// ```ignore
+// #[cfg(nk_config = "RUST_LEAK_TRACKING")]
+// mod leaks;
+// ```
+//
+// and as `NAUT_CONFIG_*` ones, which is what `config_module!` uses to
+// leave out a whole subsystem.
+
+include!(concat!(env!("OUT_DIR"), "/config.rs"));
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+#[doc(hidden)]
+pub const fn enabled(option: &str) -> bool {
+    let mut i = 0;
+    while i < ENABLED_OPTIONS.len() {
+        if str_eq(ENABLED_OPTIONS[i], option) {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Whether NAUT_CONFIG_`option` is on; see the top of this file.
+#[macro_export]
+macro_rules! nk_config_enabled {
+    ($option:ident) => {{
+        // evaluated at compile time, so the code it guards is dropped
+        const ENABLED: bool = $crate::nk_config::enabled(stringify!($option));
+        ENABLED
+    }};
+}
+
+/// Declares a module that is only built if a Kconfig option is on:
+///
+/// ```ignore
+/// config_module!(NAUT_CONFIG_RUST_FOO, mod foo, stubs: [rust_foo_shell_entry]);
+/// ```
+///
+/// is `mod foo;` if NAUT_CONFIG_RUST_FOO is on. If it is off, `foo` is
+/// empty but for the listed shell entry points, which glue.c can then
+/// register either way: they log that the module is disabled by
+/// Kconfig, and fail. Rust code that uses `foo` has to be gated on the
+/// option too, and the crate needs the cfgs src/rust/build.rs sets up.
+#[macro_export]
+macro_rules! config_module {
+    ($option:ident, $vis:vis mod $name:ident $(, stubs: [$($stub:ident),* $(,)?])?) => {
+        #[cfg($option)]
+        $vis mod $name;
+
+        #[cfg(not($option))]
+        $vis mod $name {
+            $($(
+                #[doc(hidden)]
+                #[no_mangle]
+                pub extern "C" fn $stub(
+                    _buf: *const core::ffi::c_char,
+                    _priv_: *const core::ffi::c_void,
+                ) -> core::ffi::c_int {
+                    $crate::warn_print!(concat!(
+                        stringify!($name),
+                        " is disabled by Kconfig (",
+                        stringify!($option),
+                        ")"
+                    ));
+                    -1
+                }
+            )*)?
+        }
+    };
+}
+
+// the test macros are defined after this module, so are used by path
+crate::kernel_test!(
+    fn enabled_matches_generated_list() {
+        crate::kassert!(!nk_config_enabled!(NO_SUCH_OPTION));
+        for option in ENABLED_OPTIONS {
+            crate::kassert!(enabled(option), "{}", option);
+            crate::kassert!(!enabled(&option[1..]), "{}", option);
+        }
+    }
+);
@@ -0,0 +1,78 @@
+// devices Rust drivers currently have registered with NK, so a crash
+// report can say which drivers were up. a fixed table, since it is
+// read while panicking.
+
+use crate::nk_lock::IRQLock;
+
+const MAX_DEVICES: usize = 32;
+const NAME_LEN: usize = 32;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    // whatever NK handed back for the registration, which identifies it
+    dev: usize,
+    kind: &'static str,
+    name: [u8; NAME_LEN],
+}
+
+impl Entry {
+    fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+        // copied from a `str`, cut at a char boundary
+        unsafe { core::str::from_utf8_unchecked(&self.name[..len]) }
+    }
+}
+
+static DEVICES: IRQLock<[Option<Entry>; MAX_DEVICES]> = IRQLock::new([None; MAX_DEVICES]);
+
+fn truncated(name: &str) -> [u8; NAME_LEN] {
+    let mut len = name.len().min(NAME_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut buf = [0; NAME_LEN];
+    buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+    buf
+}
+
+/// Records that `dev` was registered with NK's `kind` subsystem
+/// ("chardev", "irq", ...). Past `MAX_DEVICES`, devices still work
+/// but are left out of crash reports.
+pub fn register(dev: *const u8, kind: &'static str, name: &str) {
+    if let Some(slot) = DEVICES.lock().iter_mut().find(|e| e.is_none()) {
+        *slot = Some(Entry {
+            dev: dev as usize,
+            kind,
+            name: truncated(name),
+        });
+    }
+}
+
+pub fn unregister(dev: *const u8) {
+    let mut devices = DEVICES.lock();
+    if let Some(slot) = devices
+        .iter_mut()
+        .find(|e| matches!(e, Some(e) if e.dev == dev as usize))
+    {
+        *slot = None;
+    }
+}
+
+pub(super) fn locked() -> bool {
+    DEVICES.is_locked()
+}
+
+/// Calls `f` with the kind and name of every registered device, unless
+/// the table is locked, e.g. by the code that panicked. Returns whether
+/// it was.
+pub(super) fn try_for_each(mut f: impl FnMut(&str, &str)) -> bool {
+    match DEVICES.try_lock() {
+        Some(devices) => {
+            for e in devices.iter().flatten() {
+                f(e.kind, e.name());
+            }
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,180 @@
+// a crash report, printed when Rust code panics, ahead of the backtrace:
+// where it panicked and on which CPU and thread, which of the shared
+// Rust locks were held, the devices Rust drivers had registered, heap
+// usage, and the last lines of the log ring. it is meant to be read
+// from QEMU's serial log after the fact, so it goes straight to printk
+// and allocates nothing.
+
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::nk_alloc::{arena, leaks, oom, stats};
+use crate::nk_log::{context, ring, sink};
+use crate::nk_raw;
+use crate::nk_time::registry;
+
+pub mod devices;
+
+// how much of the log ring to include
+const LOG_LINES: usize = 32;
+const LINE_LEN: usize = 256;
+
+// set by the first report, so a panic while reporting does not recurse
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+// a name, and whether that lock is held
+type LockHint = (&'static str, fn() -> bool);
+
+// a lock held here is only a hint: it may belong to another CPU that
+// is about to release it, or be why the panicking code got stuck
+const LOCKS: &[LockHint] = &[
+    ("log ring", ring::locked),
+    ("log sinks", sink::locked),
+    ("heap arena", arena::locked),
+    ("leak table", leaks::locked),
+    ("oom reclaimers", oom::locked),
+    ("timer registry", registry::locked),
+    ("device table", devices::locked),
+];
+
+// formats into a line buffer and hands whole lines to printk, which
+// needs neither the heap nor the log's locks
+struct PrintkWriter {
+    // room for the nul
+    buf: [u8; LINE_LEN + 1],
+    len: usize,
+}
+
+impl PrintkWriter {
+    fn new() -> Self {
+        PrintkWriter {
+            buf: [0; LINE_LEN + 1],
+            len: 0,
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+        self.buf[self.len] = 0;
+        unsafe {
+            nk_raw::printk("%s\0".as_ptr() as *const i8, self.buf.as_ptr());
+        }
+        self.len = 0;
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            // printk would stop at a nul
+            self.buf[self.len] = if b == 0 { b'?' } else { b };
+            self.len += 1;
+            if b == b'\n' || self.len == LINE_LEN {
+                self.flush();
+            }
+        }
+    }
+}
+
+impl Write for PrintkWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+impl Drop for PrintkWriter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn interrupts_enabled() -> bool {
+    let rflags: u64;
+    unsafe {
+        asm!("pushfq", "pop {}", out(reg) rflags, options(nomem, preserves_flags));
+    }
+    rflags & (1 << 9) != 0
+}
+
+fn print_locks(w: &mut PrintkWriter) {
+    let _ = write!(w, "locks held:");
+    let mut sep = " ";
+    for (name, locked) in LOCKS {
+        if locked() {
+            let _ = write!(w, "{}{}", sep, name);
+            sep = ", ";
+        }
+    }
+    let _ = writeln!(w, "{}", if sep == " " { " none" } else { "" });
+}
+
+fn print_devices(w: &mut PrintkWriter) {
+    let _ = writeln!(w, "devices:");
+    let mut count = 0;
+    let listed = devices::try_for_each(|kind, name| {
+        let _ = writeln!(w, "  {} {}", kind, name);
+        count += 1;
+    });
+    if !listed {
+        let _ = writeln!(w, "  (device table is locked)");
+    } else if count == 0 {
+        let _ = writeln!(w, "  none");
+    }
+}
+
+fn print_heap(w: &mut PrintkWriter) {
+    let s = stats::stats();
+    let _ = writeln!(
+        w,
+        "heap: live {} bytes, peak {} bytes, {} allocs, {} frees, {} failed",
+        s.live_bytes,
+        s.peak_bytes,
+        s.allocs(),
+        s.frees(),
+        s.failed
+    );
+    if arena::active() {
+        let _ = match arena::try_usage() {
+            Some(u) => writeln!(
+                w,
+                "arena: {} of {} bytes free, largest free block {} bytes",
+                u.free_bytes, u.size, u.largest_free
+            ),
+            None => writeln!(w, "arena: (locked)"),
+        };
+    }
+}
+
+fn print_log(w: &mut PrintkWriter) {
+    let _ = writeln!(w, "last {} log lines:", LOG_LINES);
+    if !ring::try_tail(LOG_LINES, |piece| w.write_bytes(piece)) {
+        let _ = writeln!(w, "  (log ring is locked)");
+    }
+    // the ring may not end in a newline
+    w.flush();
+}
+
+/// Prints the crash report for `info`. Only the first panic gets one.
+pub(crate) fn report(info: &PanicInfo) {
+    if REPORTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let mut w = PrintkWriter::new();
+    let _ = writeln!(w, "[------------- Rust Crash Report -------------]");
+    let _ = writeln!(w, "{}", info);
+    context::with_current(|ctx| {
+        let _ = writeln!(w, "context: {}", ctx);
+    });
+    let _ = writeln!(
+        w,
+        "interrupts: {}",
+        if interrupts_enabled() { "on" } else { "off" }
+    );
+    print_locks(&mut w);
+    print_devices(&mut w);
+    print_heap(&mut w);
+    print_log(&mut w);
+}
@@ -0,0 +1,104 @@
+use core::ffi::c_int;
+use core::fmt;
+use core::num::NonZeroI32;
+
+use crate::nk_raw;
+
+/// Errors returned by the kernel wrappers: the negative error code
+/// NK's C interfaces would return.
+///
+/// The code is never zero, so `Result<(), KError>` is the size of an
+/// `i32`. Converting to and from C return values is explicit, through
+/// `code`, `from_code`, and `from_ret`.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct KError(NonZeroI32);
+
+pub type Result<T> = core::result::Result<T, KError>;
+
+// NK's errno.h stops at ERANGE; this is Linux's value
+const ETIMEDOUT: u32 = 110;
+
+const fn errno(e: u32) -> KError {
+    // errno values are positive and small, so the negation is nonzero
+    KError(unsafe { NonZeroI32::new_unchecked(-(e as i32)) })
+}
+
+impl KError {
+    /// The catch-all `-1` most of NK returns.
+    pub const FAILED: KError = KError(unsafe { NonZeroI32::new_unchecked(-1) });
+    pub const NO_MEM: KError = errno(nk_raw::ENOMEM);
+    pub const INVALID_ARG: KError = errno(nk_raw::EINVAL);
+    pub const BUSY: KError = errno(nk_raw::EBUSY);
+    pub const TIMEOUT: KError = errno(ETIMEDOUT);
+    pub const NOT_FOUND: KError = errno(nk_raw::ENOENT);
+    pub const EXISTS: KError = errno(nk_raw::EEXIST);
+    pub const NO_DEVICE: KError = errno(nk_raw::ENODEV);
+    pub const IO: KError = errno(nk_raw::EIO);
+    /// The scheduler has no room for a real-time thread's constraints.
+    pub const NOT_ADMITTED: KError = errno(nk_raw::ENOSPC);
+
+    /// The error for a C return value, or a device-specific status
+    /// with no errno equivalent. A code of zero is not an error and
+    /// becomes `FAILED`.
+    pub fn from_code(code: c_int) -> KError {
+        NonZeroI32::new(code).map_or(KError::FAILED, KError)
+    }
+
+    /// Maps a C return value to a result: zero and positive values
+    /// are success, negative ones are errors.
+    pub fn from_ret(ret: c_int) -> Result<c_int> {
+        if ret >= 0 {
+            Ok(ret)
+        } else {
+            Err(KError::from_code(ret))
+        }
+    }
+
+    /// The value to return to C.
+    pub fn code(self) -> c_int {
+        self.0.get()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KError::FAILED => "failed",
+            KError::NO_MEM => "out of memory",
+            KError::INVALID_ARG => "invalid argument",
+            KError::BUSY => "busy",
+            KError::TIMEOUT => "timed out",
+            KError::NOT_FOUND => "not found",
+            KError::EXISTS => "already exists",
+            KError::NO_DEVICE => "no such device",
+            KError::IO => "I/O error",
+            KError::NOT_ADMITTED => "not admitted",
+            _ => "device error",
+        }
+    }
+}
+
+// lets wrappers use `?` on `write!` and friends
+impl From<fmt::Error> for KError {
+    fn from(_: fmt::Error) -> KError {
+        KError::FAILED
+    }
+}
+
+impl fmt::Debug for KError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "KError({}, {})", self.name(), self.code())
+    }
+}
+
+impl fmt::Display for KError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.name(), self.code())
+    }
+}
+
+/// Converts the result of a `#[no_mangle]` entry point to what C expects.
+pub fn to_c_ret(r: Result<()>) -> c_int {
+    match r {
+        Ok(()) => 0,
+        Err(e) => e.code(),
+    }
+}
@@ -0,0 +1,216 @@
+// fault injection (NAUT_CONFIG_RUST_FAULT_INJECTION): code that can
+// fail declares a `fault_point!`, and asks it whether to fail before
+// doing the real work. from the `rust_fault` shell command, each point
+// can be made to fail on its Nth call or on some percentage of calls,
+// so the error paths behind it actually run.
+//
+// points are collected in the `.rust_fault_points` link section (see
+// link/nautilus.ld), so every point can be listed and armed before it
+// is first reached. without the Kconfig option, asking a point costs
+// one relaxed load.
+
+use core::ffi::c_int;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+use crate::nk_error::{KError, Result};
+use crate::nk_log::module_name;
+use crate::nk_time::cycles;
+
+mod nk_shell_cmd;
+
+extern "C" {
+    // link/nautilus.ld
+    static __start_rust_fault_points: [u8; 0];
+    static __stop_rust_fault_points: [u8; 0];
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// xorshift64 state for `Mode::Percent`; never 0
+static RNG: AtomicU64 = AtomicU64::new(0x9e37_79b9_7f4a_7c15);
+
+/// Allows faults to be injected. Called at boot when the Kconfig option
+/// is on; until then every point is off, whatever it was set to.
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn nk_rust_fault_init() -> c_int {
+    RNG.store(cycles() | 1, Ordering::Relaxed);
+    ENABLED.store(true, Ordering::Relaxed);
+    0
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// races between CPUs only make it less random
+fn random() -> u64 {
+    let mut x = RNG.load(Ordering::Relaxed);
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    RNG.store(x, Ordering::Relaxed);
+    x
+}
+
+/// When a point fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Off,
+    /// The Nth call from when the mode was set fails, once; calls are
+    /// counted from 1.
+    Nth(u64),
+    /// Each call fails with this probability, in percent.
+    Percent(u8),
+}
+
+const OFF: u8 = 0;
+const NTH: u8 = 1;
+const PERCENT: u8 = 2;
+
+/// A place where a fault can be injected. Declared by `fault_point!`.
+pub struct FaultPoint {
+    module_path: &'static str,
+    name: &'static str,
+    mode: AtomicU8,
+    arg: AtomicU64,
+    // since the mode was last set
+    calls: AtomicU64,
+    injected: AtomicU64,
+}
+
+impl FaultPoint {
+    pub const fn new(module_path: &'static str, name: &'static str) -> Self {
+        FaultPoint {
+            module_path,
+            name,
+            mode: AtomicU8::new(OFF),
+            arg: AtomicU64::new(0),
+            calls: AtomicU64::new(0),
+            injected: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the caller should fail this time.
+    pub fn should_fail(&self) -> bool {
+        if !enabled() {
+            return false;
+        }
+        let fail = match self.mode.load(Ordering::Relaxed) {
+            OFF => return false,
+            NTH => {
+                let call = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+                call == self.arg.load(Ordering::Relaxed)
+            }
+            _ => {
+                self.calls.fetch_add(1, Ordering::Relaxed);
+                random() % 100 < self.arg.load(Ordering::Relaxed)
+            }
+        };
+        if fail {
+            self.injected.fetch_add(1, Ordering::Relaxed);
+            warn_print!("injecting fault at {}.{}", self.module(), self.name);
+        }
+        fail
+    }
+
+    /// `Err(err)` if the caller should fail this time, for `?`.
+    pub fn check(&self, err: KError) -> Result<()> {
+        if self.should_fail() {
+            Err(err)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn set(&self, mode: Mode) {
+        let (m, arg) = match mode {
+            Mode::Off => (OFF, 0),
+            Mode::Nth(n) => (NTH, n),
+            Mode::Percent(p) => (PERCENT, p.min(100) as u64),
+        };
+        // off while the count restarts, so no call sees a half-set mode
+        self.mode.store(OFF, Ordering::SeqCst);
+        self.calls.store(0, Ordering::SeqCst);
+        self.arg.store(arg, Ordering::SeqCst);
+        self.mode.store(m, Ordering::SeqCst);
+    }
+
+    pub fn mode(&self) -> Mode {
+        let arg = self.arg.load(Ordering::Relaxed);
+        match self.mode.load(Ordering::Relaxed) {
+            OFF => Mode::Off,
+            NTH => Mode::Nth(arg),
+            _ => Mode::Percent(arg as u8),
+        }
+    }
+
+    /// Calls since the mode was last set, while it isn't `Off`.
+    pub fn calls(&self) -> u64 {
+        self.calls.load(Ordering::Relaxed)
+    }
+
+    /// Faults injected here since boot.
+    pub fn injected(&self) -> u64 {
+        self.injected.load(Ordering::Relaxed)
+    }
+
+    /// The module that declared the point, as used by `rust_log`.
+    pub fn module(&self) -> &'static str {
+        module_name(self.module_path)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// Every fault point linked into the kernel.
+pub fn points() -> &'static [&'static FaultPoint] {
+    // the linker puts nothing but `fault_point!`'s pointers between the
+    // two symbols
+    unsafe {
+        let start = __start_rust_fault_points.as_ptr() as *const &'static FaultPoint;
+        let stop = __stop_rust_fault_points.as_ptr() as *const &'static FaultPoint;
+        core::slice::from_raw_parts(start, stop.offset_from(start) as usize)
+    }
+}
+
+/// The point named `module.name`, or just `name` if that is unique.
+pub fn find(name: &str) -> Option<&'static FaultPoint> {
+    let mut found = points().iter().filter(|p| match name.split_once('.') {
+        Some((m, n)) => p.module() == m && p.name == n,
+        None => p.name == name,
+    });
+    match (found.next(), found.next()) {
+        (Some(p), None) => Some(p),
+        _ => None,
+    }
+}
+
+/// Turns every point off.
+pub fn reset_all() {
+    for p in points() {
+        p.set(Mode::Off);
+    }
+}
+
+/// Declares a `FaultPoint` static, like `counter!`:
+///
+/// ```ignore
+/// fault_point!(REGISTER_FAULT, "register");
+///
+/// REGISTER_FAULT.check(KError::FAILED)?;
+/// ```
+#[macro_export]
+macro_rules! fault_point {
+    ($vis:vis $ident:ident, $name:expr) => {
+        $vis static $ident: $crate::nk_fault::FaultPoint =
+            $crate::nk_fault::FaultPoint::new(module_path!(), $name);
+
+        const _: () = {
+            #[used]
+            #[link_section = ".rust_fault_points"]
+            static POINT: &$crate::nk_fault::FaultPoint = &$ident;
+        };
+    };
+}
@@ -0,0 +1,59 @@
+use alloc::format;
+use core::ffi::{c_char, c_int, c_void, CStr};
+use core::fmt::Write;
+
+use super::{enabled, find, points, reset_all, Mode};
+use crate::utils::VcWriter;
+
+const USAGE: &str =
+    "usage: rust_fault [reset | <point> off | <point> nth <n> | <point> percent <p>]";
+
+fn parse_mode<'a>(mut args: impl Iterator<Item = &'a str>) -> Option<Mode> {
+    let mode = match (args.next()?, args.next()) {
+        ("off", None) => Mode::Off,
+        ("nth", Some(n)) => Mode::Nth(n.parse().ok().filter(|&n| n > 0)?),
+        ("percent", Some(p)) => Mode::Percent(p.parse().ok().filter(|&p| p <= 100)?),
+        _ => return None,
+    };
+    args.next().is_none().then_some(mode)
+}
+
+// `rust_fault` lists every fault point, `rust_fault <point> <mode>`
+// arms or disarms one, `rust_fault reset` disarms them all
+#[no_mangle]
+pub unsafe extern "C" fn rust_fault_shell_entry(
+    buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let mut args = line.split_whitespace().skip(1);
+
+    if !enabled() {
+        warn_print!("fault injection is disabled by Kconfig");
+    }
+
+    match args.next() {
+        None => {
+            let mut w = VcWriter::new();
+            for p in points() {
+                let _ = writeln!(
+                    w,
+                    "{:<32} {:<14} calls {:>8} injected {:>8}",
+                    format!("{}.{}", p.module(), p.name()),
+                    // `{:?}` ignores width
+                    format!("{:?}", p.mode()),
+                    p.calls(),
+                    p.injected()
+                );
+            }
+        }
+        Some("reset") => reset_all(),
+        Some(name) => match (find(name), parse_mode(args)) {
+            (Some(p), Some(mode)) => p.set(mode),
+            (None, _) => error_print!("no single fault point {}", name),
+            (_, None) => error_print!("{}", USAGE),
+        },
+    }
+    0
+}
@@ -0,0 +1,59 @@
+// source-level debugging of Rust code in QEMU, through NK's serial GDB
+// stub (src/nautilus/gdb-stub.c, NAUT_CONFIG_ENABLE_REMOTE_DEBUGGING).
+// the stub already handles breakpoints, register and memory access,
+// and single stepping; this only lets Rust code stop in it.
+//
+// point gdb at the kernel ELF, which carries the Rust staticlib's
+// DWARF, and `target remote` the stub's serial port.
+
+use core::arch::asm;
+use core::ffi::c_int;
+use core::panic::{Location, PanicInfo};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::nk_panic;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Marks the stub as present. Called at boot, right after
+/// `nk_gdb_init()` has hooked the debug exceptions.
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn nk_rust_gdb_init() -> c_int {
+    ENABLED.store(true, Ordering::Relaxed);
+    if nk_config_enabled!(RUST_BREAK_ON_PANIC) {
+        if let Err(e) = nk_panic::set_hook(break_on_panic_hook) {
+            error_print!("cannot break on panic: {}", e);
+            return -1;
+        }
+    }
+    0
+}
+
+/// Whether a debugger can be attached, i.e. `breakpoint()` stops.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Stops in the debugger, like `nk_gdb_breakpoint_here()` in C, and
+/// waits for it to attach if it hasn't yet. Without the stub, `int3`
+/// would end in NK's default exception handler, so this only logs.
+#[track_caller]
+pub fn breakpoint() {
+    let loc = Location::caller();
+    if !enabled() {
+        warn_print!("ignoring breakpoint at {}: remote debugging is off", loc);
+        return;
+    }
+    info_print!("breakpoint at {}", loc);
+    // the stub reads and may rewrite registers, memory and the stack
+    unsafe { asm!("int3") };
+}
+
+fn break_on_panic_hook(info: &PanicInfo) {
+    match info.location() {
+        Some(loc) => error_print!("panic at {}, stopping in debugger", loc),
+        None => error_print!("panic, stopping in debugger"),
+    }
+    breakpoint();
+}
@@ -0,0 +1,281 @@
+// a mouse cursor in any graphics mode: the device's own if the mode has
+// one, or else drawn in software. the software cursor is xored onto the
+// screen, so xoring it again where it was puts back what was under it;
+// gpudev has no way to read the screen to save it first.
+
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+
+use super::{BlitOp, GpuDev, Pixel, Point, Rect, VideoMode};
+use crate::nk_error::{KError, Result};
+use crate::nk_lock::IRQLock;
+use crate::nk_raw;
+
+// blank bitmaps handed to drivers in place of a dropped cursor's, by
+// size; a driver may use one for as long as it likes, so they are
+// never destroyed
+static BLANKS: IRQLock<Vec<((u32, u32), usize)>> = IRQLock::new(Vec::new());
+
+/// A cursor's shape as a mask of up to 16 pixels a row, the leftmost
+/// in the high bit. The hot spot is the top left.
+pub struct Shape {
+    pub width: u32,
+    pub rows: &'static [u16],
+}
+
+/// An arrow pointing up and to the left.
+pub const ARROW: Shape = Shape {
+    width: 10,
+    rows: &[
+        0x8000, 0xc000, 0xe000, 0xf000, 0xf800, 0xfc00, 0xfe00, 0xff00, 0xff80, 0xffc0, 0xfc00,
+        0xee00, 0xce00, 0x8700, 0x0700, 0x0300,
+    ],
+};
+
+impl Shape {
+    pub fn height(&self) -> u32 {
+        self.rows.len() as u32
+    }
+
+    pub fn is_set(&self, x: u32, y: u32) -> bool {
+        x < self.width.min(16)
+            && self
+                .rows
+                .get(y as usize)
+                .is_some_and(|r| r & (0x8000 >> x) != 0)
+    }
+
+    // the runs of set pixels in each row, relative to the hot spot
+    fn spans(&self) -> Vec<Rect> {
+        let mut spans = Vec::new();
+        for y in 0..self.height() {
+            let mut x = 0;
+            while x < self.width {
+                let start = x;
+                while self.is_set(x, y) {
+                    x += 1;
+                }
+                if x > start {
+                    spans.push(Rect::new(start, y, x - start, 1));
+                } else {
+                    x += 1;
+                }
+            }
+        }
+        spans
+    }
+}
+
+enum Kind {
+    // the bitmap given to the driver, which may use it until it is
+    // given another, and its size
+    Hardware(NonNull<nk_raw::nk_gpu_dev_bitmap_t>, (u32, u32)),
+    Software {
+        spans: Vec<Rect>,
+        // where the cursor is on screen, if it is
+        drawn: Option<Point>,
+    },
+}
+
+/// A mouse cursor on a device in a graphics mode. `move_to` moves it;
+/// a software cursor only gets there on `flush`.
+///
+/// A software cursor is part of what is on screen, so it should be
+/// hidden while drawing over it. It is hidden when dropped.
+pub struct Cursor {
+    dev: GpuDev,
+    kind: Kind,
+    pixel: Pixel,
+    at: Point,
+}
+
+// the bitmap is only handed to the driver, which does its own locking
+unsafe impl Send for Cursor {}
+
+impl Cursor {
+    /// A cursor of `shape` in white, for `dev` in `mode`. It is only
+    /// drawn in software if the mode has no cursor of its own big
+    /// enough. Around the shape, the mode's cursor is transparent, if
+    /// the mode has an alpha channel.
+    pub fn new(dev: &GpuDev, mode: &VideoMode, shape: &Shape) -> Result<Self> {
+        if !mode.is_graphics() {
+            return Err(KError::INVALID_ARG);
+        }
+        let pixel = mode.rgb(0xff, 0xff, 0xff);
+        let kind = match mode.mouse_cursor_size() {
+            Some((w, h)) if w >= shape.width && h >= shape.height() => {
+                let bitmap = dev.set_cursor_bitmap(shape, (w, h), pixel, mode.clear())?;
+                Kind::Hardware(bitmap, (w, h))
+            }
+            _ => Kind::Software {
+                spans: shape.spans(),
+                drawn: None,
+            },
+        };
+        Ok(Cursor {
+            dev: dev.clone(),
+            kind,
+            pixel,
+            at: Point::new(0, 0),
+        })
+    }
+
+    pub fn is_hardware(&self) -> bool {
+        matches!(self.kind, Kind::Hardware(..))
+    }
+
+    pub fn position(&self) -> Point {
+        self.at
+    }
+
+    pub fn move_to(&mut self, at: Point) -> Result<()> {
+        self.at = at;
+        match self.kind {
+            Kind::Hardware(..) => self.dev.set_cursor(at),
+            Kind::Software { .. } => Ok(()),
+        }
+    }
+
+    // xors the software cursor's shape at `at`
+    fn xor(dev: &GpuDev, spans: &[Rect], at: Point, pixel: Pixel) -> Result<()> {
+        for s in spans {
+            let r = Rect::new(at.x + s.x, at.y + s.y, s.width, s.height);
+            dev.fill_box(r, pixel, BlitOp::Xor)?;
+        }
+        Ok(())
+    }
+
+    /// Takes a software cursor off the screen until the next `flush`.
+    pub fn hide(&mut self) -> Result<()> {
+        if let Kind::Software { spans, drawn } = &mut self.kind {
+            if let Some(old) = *drawn {
+                Self::xor(&self.dev, spans, old, self.pixel)?;
+                *drawn = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws a software cursor where it was moved to, and flushes the
+    /// device.
+    pub fn flush(&mut self) -> Result<()> {
+        if let Kind::Software { spans, drawn } = &mut self.kind {
+            if *drawn != Some(self.at) {
+                if let Some(old) = *drawn {
+                    Self::xor(&self.dev, spans, old, self.pixel)?;
+                    *drawn = None;
+                }
+                Self::xor(&self.dev, spans, self.at, self.pixel)?;
+                *drawn = Some(self.at);
+            }
+        }
+        self.dev.flush()
+    }
+}
+
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        match self.kind {
+            Kind::Hardware(bitmap, size) => match self.dev.blank_cursor(size) {
+                // ours since `set_cursor_bitmap`, and the driver has
+                // let go of it
+                Ok(()) => unsafe { nk_raw::nk_gpu_dev_bitmap_destroy(bitmap.as_ptr()) },
+                // the driver may still draw it, so it is leaked
+                Err(_) => warn_print!("could not hide the cursor on {}", self.dev.name()),
+            },
+            Kind::Software { .. } => {
+                if self.hide().and_then(|_| self.dev.flush()).is_err() {
+                    warn_print!("could not hide the cursor on {}", self.dev.name());
+                }
+            }
+        }
+    }
+}
+
+impl GpuDev {
+    /// Moves the mode's own cursor, if it has one. See `Cursor` for a
+    /// cursor in any mode.
+    pub fn set_cursor(&self, at: Point) -> Result<()> {
+        let mut c = at.raw();
+        Self::check(unsafe { nk_raw::nk_gpu_dev_graphics_set_cursor(self.ptr(), &mut c) })
+    }
+
+    // gives the driver a `size` bitmap of `shape`, `fg` on `bg`
+    fn set_cursor_bitmap(
+        &self,
+        shape: &Shape,
+        (w, h): (u32, u32),
+        fg: Pixel,
+        bg: Pixel,
+    ) -> Result<NonNull<nk_raw::nk_gpu_dev_bitmap_t>> {
+        let bitmap = Self::cursor_bitmap(shape, (w, h), fg, bg)?;
+        let r =
+            unsafe { nk_raw::nk_gpu_dev_graphics_set_cursor_bitmap(self.ptr(), bitmap.as_ptr()) };
+        if let Err(e) = Self::check(r) {
+            unsafe { nk_raw::nk_gpu_dev_bitmap_destroy(bitmap.as_ptr()) };
+            return Err(e);
+        }
+        Ok(bitmap)
+    }
+
+    // gives the driver a blank `size` bitmap, so it lets go of the one
+    // it had
+    fn blank_cursor(&self, size: (u32, u32)) -> Result<()> {
+        let mut blanks = BLANKS.lock();
+        let blank = match blanks.iter().find(|(s, _)| *s == size) {
+            Some(&(_, b)) => b as *mut nk_raw::nk_gpu_dev_bitmap_t,
+            None => {
+                let empty = Shape {
+                    width: 0,
+                    rows: &[],
+                };
+                // as `VideoMode::clear` in any mode
+                let clear = Pixel(0);
+                let b = Self::cursor_bitmap(&empty, size, clear, clear)?;
+                blanks.push((size, b.as_ptr() as usize));
+                b.as_ptr()
+            }
+        };
+        Self::check(unsafe { nk_raw::nk_gpu_dev_graphics_set_cursor_bitmap(self.ptr(), blank) })
+    }
+
+    // a `size` bitmap of `shape`, `fg` on `bg`
+    fn cursor_bitmap(
+        shape: &Shape,
+        (w, h): (u32, u32),
+        fg: Pixel,
+        bg: Pixel,
+    ) -> Result<NonNull<nk_raw::nk_gpu_dev_bitmap_t>> {
+        let bitmap = unsafe { nk_raw::nk_gpu_dev_bitmap_create(w, h) };
+        let bitmap = NonNull::new(bitmap).ok_or(KError::NO_MEM)?;
+        // nk_gpu_dev_bitmap_create allocated `w * h` pixels after the
+        // header, and nothing else has the bitmap yet
+        let pixels = unsafe { (*bitmap.as_ptr()).pixels.as_mut_slice((w * h) as usize) };
+        for y in 0..h {
+            for x in 0..w {
+                let p = if shape.is_set(x, y) { fg } else { bg };
+                pixels[(y * w + x) as usize] = p.raw();
+            }
+        }
+        Ok(bitmap)
+    }
+}
+
+kernel_test!(
+    fn cursor_shape_spans() {
+        let shape = Shape {
+            width: 4,
+            rows: &[0x9000, 0xf000, 0x0000],
+        };
+        kassert!(shape.is_set(3, 0) && !shape.is_set(1, 0) && !shape.is_set(4, 1));
+        kassert_eq!(
+            shape.spans(),
+            alloc::vec![
+                Rect::new(0, 0, 1, 1),
+                Rect::new(3, 0, 1, 1),
+                Rect::new(0, 1, 4, 1)
+            ]
+        );
+        kassert_eq!(ARROW.spans().first(), Some(&Rect::new(0, 0, 1, 1)));
+    }
+);
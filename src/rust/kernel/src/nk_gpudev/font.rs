@@ -0,0 +1,389 @@
+// bitmap fonts for drawing text on a gpudev device. a glyph is
+// `height` rows of `(width + 7) / 8` bytes each, with the leftmost
+// pixel in the high bit of a row's first byte, as in PSF fonts.
+//
+// fonts are built in, or loaded from PSF (version 1 or 2) files, such
+// as the Linux console's. which one text is drawn in can be changed at
+// runtime with `select`.
+
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+use super::{GpuDev, Pixel, Point, Rect};
+use crate::nk_error::{KError, Result};
+use crate::nk_lock::IRQLock;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_HEADER_LEN: usize = 4;
+// in the mode byte: the font has 512 glyphs rather than 256
+const PSF1_MODE_512: u8 = 0x01;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_LEN: usize = 32;
+
+/// A bitmap font of `glyph_count` glyphs, indexed by character code.
+pub struct Font {
+    width: u32,
+    height: u32,
+    count: usize,
+    data: Cow<'static, [u8]>,
+}
+
+impl Font {
+    /// A font from glyph data laid out as described at the top of this
+    /// file, or `None` if it has no glyphs, or `data` is too short for
+    /// `count` of them.
+    pub fn new(width: u32, height: u32, count: usize, data: Cow<'static, [u8]>) -> Option<Self> {
+        let font = Font {
+            width,
+            height,
+            count,
+            data,
+        };
+        let len = count.checked_mul(font.glyph_len())?;
+        (width > 0 && height > 0 && count > 0 && font.data.len() >= len).then_some(font)
+    }
+
+    /// Parses a PSF1 or PSF2 font. Glyphs are indexed by their position
+    /// in the file; the unicode table some fonts have is ignored.
+    pub fn from_psf(data: Cow<'static, [u8]>) -> Result<Self> {
+        let (width, height, count, start) = psf_header(&data).ok_or(KError::INVALID_ARG)?;
+        let end = count
+            .checked_mul(height as usize * row_len(width))
+            .and_then(|len| len.checked_add(start))
+            .ok_or(KError::INVALID_ARG)?;
+        if data.len() < end {
+            return Err(KError::INVALID_ARG);
+        }
+        let glyphs = match data {
+            Cow::Borrowed(d) => Cow::Borrowed(&d[start..end]),
+            Cow::Owned(mut d) => {
+                d.truncate(end);
+                d.drain(..start);
+                Cow::Owned(d)
+            }
+        };
+        Font::new(width, height, count, glyphs).ok_or(KError::INVALID_ARG)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.count
+    }
+
+    fn row_len(&self) -> usize {
+        row_len(self.width)
+    }
+
+    fn glyph_len(&self) -> usize {
+        self.row_len() * self.height as usize
+    }
+
+    /// The glyph for `c`, or for '?' if the font has none.
+    pub fn glyph(&self, c: u8) -> &[u8] {
+        let i = if (c as usize) < self.count {
+            c as usize
+        } else {
+            b'?' as usize % self.count
+        };
+        let len = self.glyph_len();
+        &self.data[i * len..(i + 1) * len]
+    }
+
+    /// Whether pixel (`x`, `y`) of `c`'s glyph is set.
+    pub fn is_set(&self, c: u8, x: u32, y: u32) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+        let byte = self.glyph(c)[y as usize * self.row_len() + x as usize / 8];
+        byte & (0x80 >> (x % 8)) != 0
+    }
+
+    /// `c`'s glyph as the runs of set pixels in each of its rows,
+    /// relative to its top left, so it takes a few fills to draw.
+    pub fn spans(&self, c: u8) -> Vec<Rect> {
+        let mut spans = Vec::new();
+        for y in 0..self.height {
+            let mut x = 0;
+            while x < self.width {
+                if !self.is_set(c, x, y) {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < self.width && self.is_set(c, x, y) {
+                    x += 1;
+                }
+                spans.push(Rect::new(start, y, x - start, 1));
+            }
+        }
+        spans
+    }
+}
+
+fn row_len(width: u32) -> usize {
+    (width as usize).div_ceil(8)
+}
+
+fn le32(data: &[u8], at: usize) -> Option<u32> {
+    let b = data.get(at..at + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// the width, height, and number of glyphs of a PSF font, and where its
+// glyphs start
+fn psf_header(data: &[u8]) -> Option<(u32, u32, usize, usize)> {
+    if data.starts_with(&PSF1_MAGIC) && data.len() >= PSF1_HEADER_LEN {
+        let count = if data[2] & PSF1_MODE_512 != 0 {
+            512
+        } else {
+            256
+        };
+        return Some((8, data[3] as u32, count, PSF1_HEADER_LEN));
+    }
+    if !data.starts_with(&PSF2_MAGIC) {
+        return None;
+    }
+    let header_len = le32(data, 8)? as usize;
+    let count = le32(data, 16)? as usize;
+    let glyph_len = le32(data, 20)? as usize;
+    let (height, width) = (le32(data, 24)?, le32(data, 28)?);
+    // glyphs may not be padded beyond their rows
+    if header_len < PSF2_HEADER_LEN || glyph_len != height as usize * row_len(width) {
+        return None;
+    }
+    Some((width, height, count, header_len))
+}
+
+// the printable ASCII characters of the public domain font8x8 (Daniel
+// Hepper's, after the IBM PC BIOS font), with the leftmost pixel in
+// the low bit
+#[rustfmt::skip]
+const BASIC_8X8: [[u8; 8]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // space
+    [0x18, 0x3c, 0x3c, 0x18, 0x18, 0x00, 0x18, 0x00], // !
+    [0x36, 0x36, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // "
+    [0x36, 0x36, 0x7f, 0x36, 0x7f, 0x36, 0x36, 0x00], // #
+    [0x0c, 0x3e, 0x03, 0x1e, 0x30, 0x1f, 0x0c, 0x00], // $
+    [0x00, 0x63, 0x33, 0x18, 0x0c, 0x66, 0x63, 0x00], // %
+    [0x1c, 0x36, 0x1c, 0x6e, 0x3b, 0x33, 0x6e, 0x00], // &
+    [0x06, 0x06, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00], // '
+    [0x18, 0x0c, 0x06, 0x06, 0x06, 0x0c, 0x18, 0x00], // (
+    [0x06, 0x0c, 0x18, 0x18, 0x18, 0x0c, 0x06, 0x00], // )
+    [0x00, 0x66, 0x3c, 0xff, 0x3c, 0x66, 0x00, 0x00], // *
+    [0x00, 0x0c, 0x0c, 0x3f, 0x0c, 0x0c, 0x00, 0x00], // +
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x06], // ,
+    [0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x00, 0x00], // -
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c, 0x00], // .
+    [0x60, 0x30, 0x18, 0x0c, 0x06, 0x03, 0x01, 0x00], // /
+    [0x3e, 0x63, 0x73, 0x7b, 0x6f, 0x67, 0x3e, 0x00], // 0
+    [0x0c, 0x0e, 0x0c, 0x0c, 0x0c, 0x0c, 0x3f, 0x00], // 1
+    [0x1e, 0x33, 0x30, 0x1c, 0x06, 0x33, 0x3f, 0x00], // 2
+    [0x1e, 0x33, 0x30, 0x1c, 0x30, 0x33, 0x1e, 0x00], // 3
+    [0x38, 0x3c, 0x36, 0x33, 0x7f, 0x30, 0x78, 0x00], // 4
+    [0x3f, 0x03, 0x1f, 0x30, 0x30, 0x33, 0x1e, 0x00], // 5
+    [0x1c, 0x06, 0x03, 0x1f, 0x33, 0x33, 0x1e, 0x00], // 6
+    [0x3f, 0x33, 0x30, 0x18, 0x0c, 0x0c, 0x0c, 0x00], // 7
+    [0x1e, 0x33, 0x33, 0x1e, 0x33, 0x33, 0x1e, 0x00], // 8
+    [0x1e, 0x33, 0x33, 0x3e, 0x30, 0x18, 0x0e, 0x00], // 9
+    [0x00, 0x0c, 0x0c, 0x00, 0x00, 0x0c, 0x0c, 0x00], // :
+    [0x00, 0x0c, 0x0c, 0x00, 0x00, 0x0c, 0x0c, 0x06], // ;
+    [0x18, 0x0c, 0x06, 0x03, 0x06, 0x0c, 0x18, 0x00], // <
+    [0x00, 0x00, 0x3f, 0x00, 0x00, 0x3f, 0x00, 0x00], // =
+    [0x06, 0x0c, 0x18, 0x30, 0x18, 0x0c, 0x06, 0x00], // >
+    [0x1e, 0x33, 0x30, 0x18, 0x0c, 0x00, 0x0c, 0x00], // ?
+    [0x3e, 0x63, 0x7b, 0x7b, 0x7b, 0x03, 0x1e, 0x00], // @
+    [0x0c, 0x1e, 0x33, 0x33, 0x3f, 0x33, 0x33, 0x00], // A
+    [0x3f, 0x66, 0x66, 0x3e, 0x66, 0x66, 0x3f, 0x00], // B
+    [0x3c, 0x66, 0x03, 0x03, 0x03, 0x66, 0x3c, 0x00], // C
+    [0x1f, 0x36, 0x66, 0x66, 0x66, 0x36, 0x1f, 0x00], // D
+    [0x7f, 0x46, 0x16, 0x1e, 0x16, 0x46, 0x7f, 0x00], // E
+    [0x7f, 0x46, 0x16, 0x1e, 0x16, 0x06, 0x0f, 0x00], // F
+    [0x3c, 0x66, 0x03, 0x03, 0x73, 0x66, 0x7c, 0x00], // G
+    [0x33, 0x33, 0x33, 0x3f, 0x33, 0x33, 0x33, 0x00], // H
+    [0x1e, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // I
+    [0x78, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1e, 0x00], // J
+    [0x67, 0x66, 0x36, 0x1e, 0x36, 0x66, 0x67, 0x00], // K
+    [0x0f, 0x06, 0x06, 0x06, 0x46, 0x66, 0x7f, 0x00], // L
+    [0x63, 0x77, 0x7f, 0x7f, 0x6b, 0x63, 0x63, 0x00], // M
+    [0x63, 0x67, 0x6f, 0x7b, 0x73, 0x63, 0x63, 0x00], // N
+    [0x1c, 0x36, 0x63, 0x63, 0x63, 0x36, 0x1c, 0x00], // O
+    [0x3f, 0x66, 0x66, 0x3e, 0x06, 0x06, 0x0f, 0x00], // P
+    [0x1e, 0x33, 0x33, 0x33, 0x3b, 0x1e, 0x38, 0x00], // Q
+    [0x3f, 0x66, 0x66, 0x3e, 0x36, 0x66, 0x67, 0x00], // R
+    [0x1e, 0x33, 0x07, 0x0e, 0x38, 0x33, 0x1e, 0x00], // S
+    [0x3f, 0x2d, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // T
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x3f, 0x00], // U
+    [0x33, 0x33, 0x33, 0x33, 0x33, 0x1e, 0x0c, 0x00], // V
+    [0x63, 0x63, 0x63, 0x6b, 0x7f, 0x77, 0x63, 0x00], // W
+    [0x63, 0x63, 0x36, 0x1c, 0x1c, 0x36, 0x63, 0x00], // X
+    [0x33, 0x33, 0x33, 0x1e, 0x0c, 0x0c, 0x1e, 0x00], // Y
+    [0x7f, 0x63, 0x31, 0x18, 0x4c, 0x66, 0x7f, 0x00], // Z
+    [0x1e, 0x06, 0x06, 0x06, 0x06, 0x06, 0x1e, 0x00], // [
+    [0x03, 0x06, 0x0c, 0x18, 0x30, 0x60, 0x40, 0x00], // \
+    [0x1e, 0x18, 0x18, 0x18, 0x18, 0x18, 0x1e, 0x00], // ]
+    [0x08, 0x1c, 0x36, 0x63, 0x00, 0x00, 0x00, 0x00], // ^
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff], // _
+    [0x0c, 0x0c, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00], // `
+    [0x00, 0x00, 0x1e, 0x30, 0x3e, 0x33, 0x6e, 0x00], // a
+    [0x07, 0x06, 0x06, 0x3e, 0x66, 0x66, 0x3b, 0x00], // b
+    [0x00, 0x00, 0x1e, 0x33, 0x03, 0x33, 0x1e, 0x00], // c
+    [0x38, 0x30, 0x30, 0x3e, 0x33, 0x33, 0x6e, 0x00], // d
+    [0x00, 0x00, 0x1e, 0x33, 0x3f, 0x03, 0x1e, 0x00], // e
+    [0x1c, 0x36, 0x06, 0x0f, 0x06, 0x06, 0x0f, 0x00], // f
+    [0x00, 0x00, 0x6e, 0x33, 0x33, 0x3e, 0x30, 0x1f], // g
+    [0x07, 0x06, 0x36, 0x6e, 0x66, 0x66, 0x67, 0x00], // h
+    [0x0c, 0x00, 0x0e, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // i
+    [0x30, 0x00, 0x30, 0x30, 0x30, 0x33, 0x33, 0x1e], // j
+    [0x07, 0x06, 0x66, 0x36, 0x1e, 0x36, 0x67, 0x00], // k
+    [0x0e, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x1e, 0x00], // l
+    [0x00, 0x00, 0x33, 0x7f, 0x7f, 0x6b, 0x63, 0x00], // m
+    [0x00, 0x00, 0x1f, 0x33, 0x33, 0x33, 0x33, 0x00], // n
+    [0x00, 0x00, 0x1e, 0x33, 0x33, 0x33, 0x1e, 0x00], // o
+    [0x00, 0x00, 0x3b, 0x66, 0x66, 0x3e, 0x06, 0x0f], // p
+    [0x00, 0x00, 0x6e, 0x33, 0x33, 0x3e, 0x30, 0x78], // q
+    [0x00, 0x00, 0x3b, 0x6e, 0x66, 0x06, 0x0f, 0x00], // r
+    [0x00, 0x00, 0x3e, 0x03, 0x1e, 0x30, 0x1f, 0x00], // s
+    [0x08, 0x0c, 0x3e, 0x0c, 0x0c, 0x2c, 0x18, 0x00], // t
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x33, 0x6e, 0x00], // u
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x1e, 0x0c, 0x00], // v
+    [0x00, 0x00, 0x63, 0x6b, 0x7f, 0x7f, 0x36, 0x00], // w
+    [0x00, 0x00, 0x63, 0x36, 0x1c, 0x36, 0x63, 0x00], // x
+    [0x00, 0x00, 0x33, 0x33, 0x33, 0x3e, 0x30, 0x1f], // y
+    [0x00, 0x00, 0x3f, 0x19, 0x0c, 0x26, 0x3f, 0x00], // z
+    [0x38, 0x0c, 0x0c, 0x07, 0x0c, 0x0c, 0x38, 0x00], // {
+    [0x18, 0x18, 0x18, 0x00, 0x18, 0x18, 0x18, 0x00], // |
+    [0x07, 0x0c, 0x0c, 0x38, 0x0c, 0x0c, 0x07, 0x00], // }
+    [0x6e, 0x3b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], // ~
+];
+
+const DEFAULT_COUNT: usize = 128;
+
+// BASIC_8X8 as an 8x16 font of all of ASCII: rows doubled, bits
+// reversed, and the control characters blank
+const fn default_glyphs() -> [u8; DEFAULT_COUNT * 16] {
+    let mut data = [0; DEFAULT_COUNT * 16];
+    let mut c = 0;
+    while c < BASIC_8X8.len() {
+        let mut row = 0;
+        while row < 8 {
+            let bits = BASIC_8X8[c][row].reverse_bits();
+            let at = (c + 0x20) * 16 + row * 2;
+            data[at] = bits;
+            data[at + 1] = bits;
+            row += 1;
+        }
+        c += 1;
+    }
+    data
+}
+
+static DEFAULT_GLYPHS: [u8; DEFAULT_COUNT * 16] = default_glyphs();
+
+/// The built-in 8x16 ASCII font.
+pub static DEFAULT: Font = Font {
+    width: 8,
+    height: 16,
+    count: DEFAULT_COUNT,
+    data: Cow::Borrowed(&DEFAULT_GLYPHS),
+};
+
+static SELECTED: IRQLock<&'static Font> = IRQLock::new(&DEFAULT);
+
+/// Makes `font` the one text is drawn in from now on. A font loaded at
+/// runtime can be leaked to get a `'static` one.
+pub fn select(font: &'static Font) {
+    *SELECTED.lock() = font;
+}
+
+/// The font text is drawn in, `DEFAULT` unless another was selected.
+pub fn selected() -> &'static Font {
+    *SELECTED.lock()
+}
+
+impl GpuDev {
+    /// Draws `text` in `font` on one line, from `at` at its top left.
+    /// Only the glyphs' set pixels are drawn; the rest are left as they
+    /// were. Characters beyond ASCII are drawn as '?'.
+    pub fn draw_text(&self, at: Point, text: &str, font: &Font, pixel: Pixel) -> Result<()> {
+        for (i, c) in text.chars().enumerate() {
+            let c = if c.is_ascii() { c as u8 } else { b'?' };
+            let x = at.x + i as u32 * font.width();
+            for span in font.spans(c) {
+                let r = Rect::new(x + span.x, at.y + span.y, span.width, span.height);
+                self.fill_box(r, pixel, super::BlitOp::Copy)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+kernel_test!(
+    fn default_font_glyphs() {
+        kassert_eq!(DEFAULT.glyph(b'A').len(), 16);
+        // the top of the A's apex, and the gap under its crossbar
+        kassert!(DEFAULT.is_set(b'A', 2, 0) && DEFAULT.is_set(b'A', 3, 0));
+        kassert!(!DEFAULT.is_set(b'A', 0, 0));
+        kassert!(DEFAULT.is_set(b'A', 0, 9) && !DEFAULT.is_set(b'A', 2, 11));
+        kassert!((0..8).all(|x| (0..16).all(|y| !DEFAULT.is_set(b' ', x, y))));
+        // out of range characters are drawn as '?'
+        kassert_eq!(DEFAULT.glyph(200), DEFAULT.glyph(b'?'));
+    }
+);
+
+kernel_test!(
+    fn glyph_spans() {
+        kassert_eq!(
+            DEFAULT.spans(b'-'),
+            alloc::vec![Rect::new(0, 6, 6, 1), Rect::new(0, 7, 6, 1)]
+        );
+        kassert!(DEFAULT.spans(b' ').is_empty());
+    }
+);
+
+kernel_test!(
+    fn psf_fonts() {
+        // two 8x2 glyphs for a PSF1 font of 256, padded out with blanks
+        let mut psf1 = alloc::vec![0x36, 0x04, 0x00, 2, 0x80, 0x01, 0xff, 0x00];
+        psf1.resize(PSF1_HEADER_LEN + 256 * 2, 0);
+        let font = Font::from_psf(Cow::Owned(psf1)).unwrap();
+        kassert_eq!(
+            (font.width(), font.height(), font.glyph_count()),
+            (8, 2, 256)
+        );
+        kassert!(font.is_set(0, 0, 0) && font.is_set(0, 7, 1) && !font.is_set(0, 1, 0));
+        kassert_eq!(font.spans(1), alloc::vec![Rect::new(0, 0, 8, 1)]);
+
+        // a 10x1 PSF2 font of one glyph, with a longer header than usual
+        let mut psf2 = Vec::from(PSF2_MAGIC);
+        for field in [0u32, 36, 0, 1, 2, 1, 10] {
+            psf2.extend_from_slice(&field.to_le_bytes());
+        }
+        psf2.extend_from_slice(&[0xff; 4]);
+        psf2.extend_from_slice(&[0x80, 0x40]);
+        let font = Font::from_psf(Cow::Owned(psf2.clone())).unwrap();
+        kassert_eq!(
+            (font.width(), font.height(), font.glyph_count()),
+            (10, 1, 1)
+        );
+        kassert!(font.is_set(0, 0, 0) && font.is_set(0, 9, 0) && !font.is_set(0, 8, 0));
+
+        // no glyphs
+        let mut empty = psf2.clone();
+        empty[16..20].copy_from_slice(&0u32.to_le_bytes());
+        kassert!(Font::from_psf(Cow::Owned(empty)).is_err());
+        kassert!(Font::new(8, 16, 0, Cow::Borrowed(&[])).is_none());
+        kassert!(Font::new(8, 16, 2, Cow::Borrowed(&DEFAULT_GLYPHS[..31])).is_none());
+
+        // cut short, or not a font
+        psf2.pop();
+        kassert!(Font::from_psf(Cow::Owned(psf2)).is_err());
+        kassert!(Font::from_psf(Cow::Borrowed(&[0u8; 64])).is_err());
+    }
+);
@@ -0,0 +1,369 @@
+// drawing on a gpudev device through NK's consumer interface
+// (nautilus/gpudev.h): finding one, switching its video mode, and the
+// drawing operations its driver implements. drivers register with
+// gpudev from C; this is the side that uses them.
+//
+// drawing is asynchronous: nothing has to be on the screen until
+// `flush` returns. an operation the driver does not implement fails.
+//
+// every call into the driver is counted for `rust_stats`, and flushes
+// are timed for `rust_profile`.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ffi::c_int;
+use core::mem::MaybeUninit;
+use core::ptr::NonNull;
+
+use crate::nk_error::{KError, Result};
+use crate::nk_raw;
+
+pub use cursor::{Cursor, Shape, ARROW};
+
+mod cursor;
+pub mod font;
+mod nk_shell_cmd;
+mod shapes;
+
+counter!(OPS, "ops");
+counter!(ERRORS, "errors");
+// the area of boxes filled and copied
+counter!(BOX_PIXELS, "box_pixels");
+
+// how many modes we ask a device for
+const MAX_MODES: usize = 64;
+// a channel offset of -1 means the mode has no such channel
+const NO_CHANNEL: u8 = 0xff;
+
+/// A point on the screen; (0, 0) is the top left.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Point {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl Point {
+    pub const fn new(x: u32, y: u32) -> Self {
+        Point { x, y }
+    }
+
+    fn raw(self) -> nk_raw::nk_gpu_dev_coordinate_t {
+        nk_raw::nk_gpu_dev_coordinate_t {
+            x: self.x,
+            y: self.y,
+        }
+    }
+}
+
+/// A box on the screen, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn area(self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    fn raw(self) -> nk_raw::nk_gpu_dev_box_t {
+        nk_raw::nk_gpu_dev_box_t {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// A pixel, with its channels laid out as the video mode it was made
+/// for says; see `VideoMode::rgb`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Pixel(pub u32);
+
+impl Pixel {
+    fn raw(self) -> nk_raw::nk_gpu_dev_pixel_t {
+        nk_raw::nk_gpu_dev_pixel_t { raw: self.0 }
+    }
+}
+
+/// How a drawing operation combines its pixels with those on screen.
+/// The arithmetic ones saturate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlitOp {
+    Copy,
+    Not,
+    And,
+    Or,
+    Nand,
+    Nor,
+    Xor,
+    Xnor,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+}
+
+impl BlitOp {
+    fn raw(self) -> nk_raw::nk_gpu_dev_bit_blit_op_t {
+        match self {
+            BlitOp::Copy => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_COPY,
+            BlitOp::Not => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_NOT,
+            BlitOp::And => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_AND,
+            BlitOp::Or => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_OR,
+            BlitOp::Nand => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_NAND,
+            BlitOp::Nor => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_NOR,
+            BlitOp::Xor => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_XOR,
+            BlitOp::Xnor => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_XNOR,
+            BlitOp::Plus => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_PLUS,
+            BlitOp::Minus => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_MINUS,
+            BlitOp::Multiply => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_MULTIPLY,
+            BlitOp::Divide => nk_raw::nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_DIVIDE,
+        }
+    }
+}
+
+/// A video mode a device supports, as its driver describes it.
+#[derive(Copy, Clone)]
+pub struct VideoMode(nk_raw::nk_gpu_dev_video_mode_t);
+
+impl VideoMode {
+    pub fn is_graphics(&self) -> bool {
+        self.0.type_ == nk_raw::nk_gpu_dev_video_mode_NK_GPU_DEV_MODE_TYPE_GRAPHICS_2D
+    }
+
+    /// In pixels for a graphics mode, characters for a text mode.
+    pub fn width(&self) -> u32 {
+        self.0.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.0.height
+    }
+
+    pub fn has_clipping(&self) -> bool {
+        self.0.flags & nk_raw::NK_GPU_DEV_HAS_CLIPPING as u64 != 0
+    }
+
+    pub fn has_mouse_cursor(&self) -> bool {
+        self.0.flags & nk_raw::NK_GPU_DEV_HAS_MOUSE_CURSOR as u64 != 0
+    }
+
+    /// The size of the mouse cursor bitmap, if the mode has one.
+    pub fn mouse_cursor_size(&self) -> Option<(u32, u32)> {
+        self.has_mouse_cursor()
+            .then_some((self.0.mouse_cursor_width, self.0.mouse_cursor_height))
+    }
+
+    /// The pixel for a color in this mode's channel layout, opaque if
+    /// the mode has an alpha channel.
+    pub fn rgb(&self, red: u8, green: u8, blue: u8) -> Pixel {
+        let mut channel = [0u8; 4];
+        for (i, v) in [red, green, blue, 0xff].into_iter().enumerate() {
+            let offset = self.0.channel_offset[i];
+            if offset != NO_CHANNEL {
+                channel[offset as usize] = v;
+            }
+        }
+        Pixel(u32::from_ne_bytes(channel))
+    }
+
+    /// Black, and fully transparent if the mode has an alpha channel.
+    pub fn clear(&self) -> Pixel {
+        Pixel(0)
+    }
+}
+
+impl core::fmt::Display for VideoMode {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let kind = if self.is_graphics() {
+            "graphics"
+        } else {
+            "text"
+        };
+        write!(f, "{} {}x{}", kind, self.width(), self.height())?;
+        if let Some((w, h)) = self.mouse_cursor_size() {
+            write!(f, ", {}x{} cursor", w, h)?;
+        }
+        Ok(())
+    }
+}
+
+/// A registered gpudev device.
+#[derive(Clone)]
+pub struct GpuDev {
+    dev: NonNull<nk_raw::nk_gpu_dev_t>,
+    name: String,
+}
+
+// gpudev drivers do their own locking
+unsafe impl Send for GpuDev {}
+unsafe impl Sync for GpuDev {}
+
+impl GpuDev {
+    pub fn find(name: &str) -> Result<Self> {
+        let name_c = format!("{}\0", name);
+        // nk_gpu_dev_find only reads the name, and does not keep it
+        let dev = unsafe { nk_raw::nk_gpu_dev_find(name_c.as_ptr() as *mut i8) };
+        let dev = NonNull::new(dev).ok_or(KError::NO_DEVICE)?;
+        Ok(GpuDev {
+            dev,
+            name: name.into(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn ptr(&self) -> *mut nk_raw::nk_gpu_dev_t {
+        self.dev.as_ptr()
+    }
+
+    // what a driver call returned, counted
+    fn check(ret: c_int) -> Result<()> {
+        OPS.inc();
+        KError::from_ret(ret).inspect_err(|_| ERRORS.inc())?;
+        Ok(())
+    }
+
+    pub fn available_modes(&self) -> Result<Vec<VideoMode>> {
+        let mut modes: [MaybeUninit<nk_raw::nk_gpu_dev_video_mode_t>; MAX_MODES] =
+            [MaybeUninit::uninit(); MAX_MODES];
+        let mut num = MAX_MODES as u32;
+        Self::check(unsafe {
+            nk_raw::nk_gpu_dev_get_available_modes(self.ptr(), modes.as_mut_ptr().cast(), &mut num)
+        })?;
+        Ok(modes[..(num as usize).min(MAX_MODES)]
+            .iter()
+            // the driver filled in the first `num`
+            .map(|m| VideoMode(unsafe { m.assume_init() }))
+            .collect())
+    }
+
+    pub fn mode(&self) -> Result<VideoMode> {
+        let mut mode = MaybeUninit::uninit();
+        Self::check(unsafe { nk_raw::nk_gpu_dev_get_mode(self.ptr(), mode.as_mut_ptr()) })?;
+        // filled in by a successful get_mode
+        Ok(VideoMode(unsafe { mode.assume_init() }))
+    }
+
+    /// Switches to `mode`, which should be one of `available_modes`,
+    /// or what `mode` returned earlier.
+    pub fn set_mode(&self, mode: &VideoMode) -> Result<()> {
+        let mut raw = mode.0;
+        Self::check(unsafe { nk_raw::nk_gpu_dev_set_mode(self.ptr(), &mut raw) })
+    }
+
+    /// Remembers the mode the device is in, to put it back after
+    /// taking over the display for a while.
+    pub fn save_mode(&self) -> Result<SavedMode> {
+        Ok(SavedMode {
+            dev: self.clone(),
+            mode: self.mode()?,
+            restored: false,
+        })
+    }
+
+    /// Waits until everything drawn so far is on the screen.
+    pub fn flush(&self) -> Result<()> {
+        profile_scope!("flush");
+        Self::check(unsafe { nk_raw::nk_gpu_dev_flush(self.ptr()) })
+    }
+
+    /// Confines drawing to `rect`, if the mode has clipping.
+    pub fn set_clipping_box(&self, rect: Rect) -> Result<()> {
+        let mut b = rect.raw();
+        Self::check(unsafe { nk_raw::nk_gpu_dev_graphics_set_clipping_box(self.ptr(), &mut b) })
+    }
+
+    pub fn draw_pixel(&self, at: Point, pixel: Pixel) -> Result<()> {
+        let (mut c, mut p) = (at.raw(), pixel.raw());
+        Self::check(unsafe { nk_raw::nk_gpu_dev_graphics_draw_pixel(self.ptr(), &mut c, &mut p) })
+    }
+
+    pub fn draw_line(&self, from: Point, to: Point, pixel: Pixel) -> Result<()> {
+        let (mut s, mut e, mut p) = (from.raw(), to.raw(), pixel.raw());
+        Self::check(unsafe {
+            nk_raw::nk_gpu_dev_graphics_draw_line(self.ptr(), &mut s, &mut e, &mut p)
+        })
+    }
+
+    /// The outline of the polygon with corners `points`, closed back
+    /// to the first.
+    pub fn draw_poly(&self, points: &[Point], pixel: Pixel) -> Result<()> {
+        let mut coords: Vec<_> = points.iter().map(|p| p.raw()).collect();
+        let mut p = pixel.raw();
+        Self::check(unsafe {
+            nk_raw::nk_gpu_dev_graphics_draw_poly(
+                self.ptr(),
+                coords.as_mut_ptr(),
+                coords.len() as u32,
+                &mut p,
+            )
+        })
+    }
+
+    pub fn fill_box(&self, rect: Rect, pixel: Pixel, op: BlitOp) -> Result<()> {
+        BOX_PIXELS.add(rect.area());
+        let (mut b, mut p) = (rect.raw(), pixel.raw());
+        Self::check(unsafe {
+            nk_raw::nk_gpu_dev_graphics_fill_box_with_pixel(self.ptr(), &mut b, &mut p, op.raw())
+        })
+    }
+
+    /// Combines what is in `from` into `to`, which must be the same
+    /// size.
+    pub fn copy_box(&self, from: Rect, to: Rect, op: BlitOp) -> Result<()> {
+        BOX_PIXELS.add(to.area());
+        let (mut s, mut d) = (from.raw(), to.raw());
+        Self::check(unsafe {
+            nk_raw::nk_gpu_dev_graphics_copy_box(self.ptr(), &mut s, &mut d, op.raw())
+        })
+    }
+}
+
+/// A device's mode, as `GpuDev::save_mode` found it. It is put back
+/// by `restore`, or when this is dropped. What was on the screen is
+/// not: gpudev has no way to read it, so whoever drew it has to again.
+pub struct SavedMode {
+    dev: GpuDev,
+    mode: VideoMode,
+    restored: bool,
+}
+
+impl SavedMode {
+    pub fn mode(&self) -> &VideoMode {
+        &self.mode
+    }
+
+    pub fn restore(mut self) -> Result<()> {
+        self.restored = true;
+        self.dev.set_mode(&self.mode)
+    }
+}
+
+impl Drop for SavedMode {
+    fn drop(&mut self) {
+        if !self.restored && self.dev.set_mode(&self.mode).is_err() {
+            warn_print!(
+                "could not restore {}'s previous video mode",
+                self.dev.name()
+            );
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use alloc::format;
+use core::ffi::{c_char, c_int, c_void, CStr};
+use core::fmt::Write;
+
+use super::font;
+use super::{BlitOp, GpuDev, Point, Rect, VideoMode};
+use crate::nk_error::Result;
+use crate::nk_time::{sleep, Duration};
+use crate::utils::VcWriter;
+
+const DEFAULT_SECS: u64 = 3;
+
+// the test patterns, each run whether or not the others worked, as a
+// driver need not implement every operation
+const PATTERNS: [(&str, fn(&GpuDev, &VideoMode) -> Result<()>); 6] = [
+    ("gradients", gradients),
+    ("lines", lines),
+    ("boxes", boxes),
+    ("circles", circles),
+    ("polygons", polygons),
+    ("text", text),
+];
+
+// red, green, blue, and grey bands across the top quarter of the screen
+fn gradients(dev: &GpuDev, mode: &VideoMode) -> Result<()> {
+    let (w, band) = (mode.width(), mode.height() / 16);
+    for x in 0..w {
+        let v = (x * 255 / w) as u8;
+        for (i, (r, g, b)) in [(v, 0, 0), (0, v, 0), (0, 0, v), (v, v, v)]
+            .into_iter()
+            .enumerate()
+        {
+            let at = Rect::new(x, i as u32 * band, 1, band);
+            dev.fill_box(at, mode.rgb(r, g, b), BlitOp::Copy)?;
+        }
+    }
+    Ok(())
+}
+
+// a fan of lines from the top left of the left half of the middle
+fn lines(dev: &GpuDev, mode: &VideoMode) -> Result<()> {
+    let (top, w, h) = (mode.height() / 4, mode.width() / 2, mode.height() / 2);
+    let from = Point::new(0, top);
+    for i in 0..=16 {
+        let to = if i <= 8 {
+            Point::new(w - 1, top + (h - 1) * i / 8)
+        } else {
+            Point::new((w - 1) * (16 - i) / 8, top + h - 1)
+        };
+        dev.draw_line(from, to, mode.rgb(0xff, 0xff, 0))?;
+    }
+    let outline = [
+        Point::new(0, top),
+        Point::new(w - 1, top),
+        Point::new(w - 1, top + h - 1),
+        Point::new(0, top + h - 1),
+    ];
+    dev.draw_poly(&outline, mode.rgb(0xff, 0xff, 0xff))
+}
+
+// nested boxes in the right half of the middle, the inner ones xored
+// onto the outer, a copy of the whole, and an outline around both
+fn boxes(dev: &GpuDev, mode: &VideoMode) -> Result<()> {
+    let (left, top) = (mode.width() / 2, mode.height() / 4);
+    let (w, h) = (mode.width() / 4, mode.height() / 2);
+    dev.fill_box(
+        Rect::new(left, top, w, h),
+        mode.rgb(0, 0x80, 0xff),
+        BlitOp::Copy,
+    )?;
+    for i in 1..4 {
+        let (dx, dy) = (w * i / 8, h * i / 8);
+        let inner = Rect::new(left + dx, top + dy, w - 2 * dx, h - 2 * dy);
+        dev.fill_box(inner, mode.rgb(0xff, 0xff, 0xff), BlitOp::Xor)?;
+    }
+    let from = Rect::new(left, top, w, h);
+    dev.copy_box(from, Rect::new(left + w, top, w, h), BlitOp::Copy)?;
+    dev.draw_rect(Rect::new(left, top, 2 * w, h), mode.rgb(0xff, 0xff, 0xff))
+}
+
+// circles in the bottom quarter
+fn circles(dev: &GpuDev, mode: &VideoMode) -> Result<()> {
+    let r = mode.height() / 8;
+    let center = Point::new(mode.width() - r - 1, mode.height() - r - 1);
+    for i in 1..=4 {
+        dev.draw_circle(center, r * i / 4, mode.rgb(0xff, 0, 0xff))?;
+    }
+    dev.draw_ellipse(center, r, r / 2, mode.rgb(0, 0xff, 0xff))
+}
+
+// a filled triangle in the middle of the bottom quarter
+fn polygons(dev: &GpuDev, mode: &VideoMode) -> Result<()> {
+    let (mid, r) = (mode.width() / 2, mode.height() / 8);
+    let bottom = mode.height() - 1;
+    let corners = [
+        Point::new(mid - r, bottom),
+        Point::new(mid, bottom - 2 * r),
+        Point::new(mid + r, bottom),
+    ];
+    dev.fill_poly(&corners, mode.rgb(0xff, 0x80, 0))
+}
+
+// the mode, written at the bottom left
+fn text(dev: &GpuDev, mode: &VideoMode) -> Result<()> {
+    let f = font::selected();
+    let at = Point::new(f.width(), mode.height() * 3 / 4 + f.height());
+    let s = format!("{}: {}", dev.name(), mode);
+    dev.draw_text(at, &s, f, mode.rgb(0xff, 0xff, 0xff))
+}
+
+// runs every pattern in `mode`, reporting how each went
+fn self_test(dev: &GpuDev, mode: &VideoMode, show: Duration, w: &mut VcWriter) -> Result<()> {
+    dev.set_mode(mode)?;
+    let whole = Rect::new(0, 0, mode.width(), mode.height());
+    if let Err(e) = dev.fill_box(whole, mode.rgb(0, 0, 0), BlitOp::Copy) {
+        let _ = writeln!(w, "clear: {}", e.name());
+    }
+    for (name, draw) in PATTERNS {
+        match draw(dev, mode) {
+            Ok(()) => {
+                let _ = writeln!(w, "{}: ok", name);
+            }
+            Err(e) => {
+                let _ = writeln!(w, "{}: {}", name, e.name());
+            }
+        }
+    }
+    dev.flush()?;
+    sleep(show);
+    Ok(())
+}
+
+// `rust_gpu <device> [seconds]` lists a gpudev device's modes, then draws
+// test patterns in its first graphics mode, shows them for a few seconds,
+// and puts the device back in the mode it was in
+#[no_mangle]
+pub unsafe extern "C" fn rust_gpu_shell_entry(buf: *const c_char, _priv_: *const c_void) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let mut args = line.split_whitespace().skip(1);
+    let usage = || {
+        error_print!("usage: rust_gpu <device> [seconds]");
+        -1
+    };
+    let name = match args.next() {
+        Some(n) => n,
+        None => return usage(),
+    };
+    let show = match args.next().map(str::parse::<u64>) {
+        None => Duration::from_secs(DEFAULT_SECS),
+        Some(Ok(s)) => Duration::from_secs(s),
+        Some(Err(_)) => return usage(),
+    };
+
+    let dev = match GpuDev::find(name) {
+        Ok(d) => d,
+        Err(e) => {
+            error_print!("rust_gpu: no gpudev device {}", name);
+            return e.code();
+        }
+    };
+    let (saved, modes) = match dev
+        .save_mode()
+        .and_then(|s| dev.available_modes().map(|modes| (s, modes)))
+    {
+        Ok(r) => r,
+        Err(e) => {
+            error_print!("rust_gpu: cannot get {}'s modes: {}", name, e.name());
+            return e.code();
+        }
+    };
+
+    let mut w = VcWriter::new();
+    let _ = writeln!(w, "{}: now {}", name, saved.mode());
+    for (i, m) in modes.iter().enumerate() {
+        let _ = writeln!(w, "{:>3} {}", i, m);
+    }
+    let mode = match modes.iter().find(|m| m.is_graphics()) {
+        Some(m) => m,
+        None => {
+            let _ = writeln!(w, "no graphics mode to test");
+            return 0;
+        }
+    };
+
+    let result = self_test(&dev, mode, show, &mut w);
+    if let Err(e) = saved.restore() {
+        error_print!(
+            "rust_gpu: could not restore the previous mode: {}",
+            e.name()
+        );
+        return e.code();
+    }
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            error_print!("rust_gpu: cannot test {} in {}: {}", name, mode, e.name());
+            e.code()
+        }
+    }
+}
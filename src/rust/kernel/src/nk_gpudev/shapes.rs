@@ -0,0 +1,459 @@
+// circles, ellipses, and arcs, drawn a pixel at a time with the
+// midpoint algorithms, and anti-aliased lines, with Wu's, so they work
+// with any driver that can draw a pixel; and filled polygons, a row at
+// a time. integer only: the core kernel is built without floating
+// point.
+//
+// points left or above the screen are skipped; those right or below it
+// are left to the driver to clip.
+
+use alloc::vec::Vec;
+
+use super::{BlitOp, GpuDev, Pixel, Point, Rect, VideoMode};
+use crate::nk_error::Result;
+
+// sin of 0..=90 degrees, times 1024
+#[rustfmt::skip]
+const SIN: [i64; 91] = [
+    0, 18, 36, 54, 71, 89, 107, 125, 143, 160, 178, 195, 213, 230, 248, 265,
+    282, 299, 316, 333, 350, 367, 384, 400, 416, 433, 449, 465, 481, 496, 512,
+    527, 543, 558, 573, 587, 602, 616, 630, 644, 658, 672, 685, 698, 711, 724,
+    737, 749, 761, 773, 784, 796, 807, 818, 828, 839, 849, 859, 868, 878, 887,
+    896, 904, 912, 920, 928, 935, 943, 949, 956, 962, 968, 974, 979, 984, 989,
+    994, 998, 1002, 1005, 1008, 1011, 1014, 1016, 1018, 1020, 1022, 1023, 1023,
+    1024, 1024,
+];
+
+// (cos, sin) of `deg`, times 1024
+fn direction(deg: u32) -> (i64, i64) {
+    let d = (deg % 360) as usize;
+    match d {
+        0..=90 => (SIN[90 - d], SIN[d]),
+        91..=180 => (-SIN[d - 90], SIN[180 - d]),
+        181..=270 => (-SIN[270 - d], -SIN[d - 180]),
+        _ => (SIN[d - 270], -SIN[360 - d]),
+    }
+}
+
+fn cross(a: (i64, i64), b: (i64, i64)) -> i64 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+/// Which offsets from the center an arc covers. Angles are in degrees,
+/// counterclockwise from the right, as on a clock face turned the
+/// other way: 90 is straight up.
+struct Arc {
+    start: (i64, i64),
+    end: (i64, i64),
+    sweep: u32,
+}
+
+impl Arc {
+    fn new(start_deg: u32, end_deg: u32) -> Self {
+        let sweep = (end_deg % 360 + 360 - start_deg % 360) % 360;
+        // an arc all the way round is a circle
+        let sweep = if sweep == 0 && end_deg != start_deg {
+            360
+        } else {
+            sweep
+        };
+        Arc {
+            start: direction(start_deg),
+            end: direction(end_deg),
+            sweep,
+        }
+    }
+
+    // `(dx, dy)` is an offset on screen, where y grows downwards
+    fn contains(&self, dx: i64, dy: i64) -> bool {
+        let p = (dx, -dy);
+        match self.sweep {
+            360 => true,
+            0..=180 => cross(self.start, p) >= 0 && cross(p, self.end) >= 0,
+            // the complement of the arc from end to start
+            _ => !(cross(self.end, p) > 0 && cross(p, self.start) > 0),
+        }
+    }
+}
+
+// the offsets from the center of a circle of radius `r`, one octant at
+// a time (points on the octants' edges come up more than once)
+fn circle_points(r: u32, mut plot: impl FnMut(i64, i64)) {
+    let (mut x, mut y) = (r as i64, 0i64);
+    let mut err = 1 - x;
+    while x >= y {
+        for (dx, dy) in [(x, y), (y, x), (-y, x), (-x, y)] {
+            plot(dx, dy);
+            plot(dx, -dy);
+        }
+        y += 1;
+        if err < 0 {
+            err += 2 * y + 1;
+        } else {
+            x -= 1;
+            err += 2 * (y - x) + 1;
+        }
+    }
+}
+
+// the offsets from the center of an ellipse with radii `rx` and `ry`.
+// the decision variables are scaled by 4 to keep them integers.
+fn ellipse_points(rx: u32, ry: u32, mut plot: impl FnMut(i64, i64)) {
+    let (a2, b2) = ((rx as i64).pow(2), (ry as i64).pow(2));
+    let mut plot4 = |x: i64, y: i64| {
+        plot(x, y);
+        plot(-x, y);
+        plot(x, -y);
+        plot(-x, -y);
+    };
+    let (mut x, mut y) = (0i64, ry as i64);
+    let (mut dx, mut dy) = (0, 2 * a2 * y);
+
+    // where the slope is under 1, step x
+    let mut d = 4 * b2 - 4 * a2 * y + a2;
+    while dx < dy {
+        plot4(x, y);
+        x += 1;
+        dx += 2 * b2;
+        if d < 0 {
+            d += 4 * (dx + b2);
+        } else {
+            y -= 1;
+            dy -= 2 * a2;
+            d += 4 * (dx - dy + b2);
+        }
+    }
+
+    // then step y
+    let mut d = b2 * (2 * x + 1).pow(2) + 4 * a2 * (y - 1).pow(2) - 4 * a2 * b2;
+    while y >= 0 {
+        plot4(x, y);
+        y -= 1;
+        dy -= 2 * a2;
+        if d > 0 {
+            d += 4 * (a2 - dy);
+        } else {
+            x += 1;
+            dx += 2 * b2;
+            d += 4 * (dx - dy + a2);
+        }
+    }
+}
+
+// the pixels of a line from `from` to `to`, with how much of each the
+// line covers, out of 255. each step along the line's long axis covers
+// two pixels across it, in proportion to how close the line passes.
+// the position across is in 16.16 fixed point.
+fn wu_points(from: Point, to: Point, mut plot: impl FnMut(i64, i64, u8)) {
+    let (mut x0, mut y0, mut x1, mut y1) = (from.x as i64, from.y as i64, to.x as i64, to.y as i64);
+    let steep = (y1 - y0).abs() > (x1 - x0).abs();
+    if steep {
+        core::mem::swap(&mut x0, &mut y0);
+        core::mem::swap(&mut x1, &mut y1);
+    }
+    if x0 > x1 {
+        core::mem::swap(&mut x0, &mut x1);
+        core::mem::swap(&mut y0, &mut y1);
+    }
+    let (dx, dy) = (x1 - x0, y1 - y0);
+    let gradient = if dx == 0 { 0 } else { (dy << 16) / dx };
+    let mut across = y0 << 16;
+    for along in x0..=x1 {
+        let (y, frac) = (across >> 16, ((across & 0xffff) >> 8) as u8);
+        let mut put = |x: i64, y: i64, coverage: u8| {
+            if steep {
+                plot(y, x, coverage)
+            } else {
+                plot(x, y, coverage)
+            }
+        };
+        put(along, y, 255 - frac);
+        if frac != 0 {
+            put(along, y + 1, frac);
+        }
+        across += gradient;
+    }
+}
+
+// the corners of `rect`'s outline, clockwise from the top left, or
+// none if it is empty
+fn rect_corners(rect: Rect) -> Option<[Point; 4]> {
+    if rect.width == 0 || rect.height == 0 {
+        return None;
+    }
+    let (right, bottom) = (rect.x + rect.width - 1, rect.y + rect.height - 1);
+    Some([
+        Point::new(rect.x, rect.y),
+        Point::new(right, rect.y),
+        Point::new(right, bottom),
+        Point::new(rect.x, bottom),
+    ])
+}
+
+// the pixel at or right of x (in 16.16 fixed point) whose center is
+// not left of it
+fn first_pixel_from(x: i64) -> i64 {
+    (x - 0x8000 + 0xffff) >> 16
+}
+
+// the rows of pixels inside the polygon with corners `points`, as
+// (y, first x, width). a pixel is inside if its center is, by the
+// even-odd rule; one whose center is exactly on a left or top edge
+// is, on a right or bottom edge it is not, so polygons sharing an
+// edge do not overlap.
+fn poly_spans(points: &[Point], mut span: impl FnMut(u32, u32, u32)) {
+    let (top, bottom) = match (
+        points.iter().map(|p| p.y).min(),
+        points.iter().map(|p| p.y).max(),
+    ) {
+        (Some(t), Some(b)) => (t, b),
+        _ => return,
+    };
+    let mut xs = Vec::new();
+    for y in top..bottom {
+        // the center of the row, doubled to keep it an integer
+        let center = 2 * y as i64 + 1;
+        xs.clear();
+        for (i, a) in points.iter().enumerate() {
+            let b = points[(i + 1) % points.len()];
+            let (ay, by) = (2 * a.y as i64, 2 * b.y as i64);
+            if (ay <= center) == (by <= center) {
+                continue;
+            }
+            let (ax, bx) = (a.x as i64, b.x as i64);
+            xs.push((ax << 16) + (((center - ay) * (bx - ax)) << 16) / (by - ay));
+        }
+        xs.sort_unstable();
+        for pair in xs.chunks_exact(2) {
+            let (from, to) = (first_pixel_from(pair[0]), first_pixel_from(pair[1]));
+            if to > from {
+                span(y, from as u32, (to - from) as u32);
+            }
+        }
+    }
+}
+
+// `fg` over `bg` by `coverage` out of 255
+fn blend(fg: (u8, u8, u8), bg: (u8, u8, u8), coverage: u8) -> (u8, u8, u8) {
+    let c = coverage as u32;
+    let mix = |f: u8, b: u8| ((f as u32 * c + b as u32 * (255 - c)) / 255) as u8;
+    (mix(fg.0, bg.0), mix(fg.1, bg.1), mix(fg.2, bg.2))
+}
+
+impl GpuDev {
+    // draws each offset from `center`, stopping at the first error
+    fn draw_offsets(
+        &self,
+        center: Point,
+        pixel: Pixel,
+        points: impl FnOnce(&mut dyn FnMut(i64, i64)),
+    ) -> Result<()> {
+        let mut result = Ok(());
+        points(&mut |dx, dy| {
+            let (x, y) = (center.x as i64 + dx, center.y as i64 + dy);
+            if result.is_err() || x < 0 || y < 0 || x > u32::MAX as i64 || y > u32::MAX as i64 {
+                return;
+            }
+            result = self.draw_pixel(Point::new(x as u32, y as u32), pixel);
+        });
+        result
+    }
+
+    pub fn draw_circle(&self, center: Point, radius: u32, pixel: Pixel) -> Result<()> {
+        self.draw_offsets(center, pixel, |plot| circle_points(radius, plot))
+    }
+
+    pub fn draw_ellipse(&self, center: Point, rx: u32, ry: u32, pixel: Pixel) -> Result<()> {
+        self.draw_offsets(center, pixel, |plot| ellipse_points(rx, ry, plot))
+    }
+
+    /// The part of a circle from `start_deg` counterclockwise to
+    /// `end_deg`; 0 degrees is to the right of the center, 90 above it.
+    pub fn draw_arc(
+        &self,
+        center: Point,
+        radius: u32,
+        start_deg: u32,
+        end_deg: u32,
+        pixel: Pixel,
+    ) -> Result<()> {
+        let arc = Arc::new(start_deg, end_deg);
+        self.draw_offsets(center, pixel, |plot| {
+            circle_points(radius, |dx, dy| {
+                if arc.contains(dx, dy) {
+                    plot(dx, dy)
+                }
+            })
+        })
+    }
+
+    /// The outline of `rect`, just inside it.
+    pub fn draw_rect(&self, rect: Rect, pixel: Pixel) -> Result<()> {
+        if let Some(corners) = rect_corners(rect) {
+            for i in 0..4 {
+                self.draw_line(corners[i], corners[(i + 1) % 4], pixel)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fills the polygon with corners `points`, closed back to the
+    /// first. Where it crosses itself, the parts inside an even number
+    /// of times are left unfilled.
+    pub fn fill_poly(&self, points: &[Point], pixel: Pixel) -> Result<()> {
+        let mut result = Ok(());
+        poly_spans(points, |y, x, width| {
+            if result.is_ok() {
+                result = self.fill_box(Rect::new(x, y, width, 1), pixel, BlitOp::Copy);
+            }
+        });
+        result
+    }
+
+    /// A line in `color` with its edges smoothed into `background`,
+    /// which should be the color under the line, as a device's pixels
+    /// cannot be read back to blend with. Use `draw_line` over
+    /// anything else.
+    pub fn draw_line_smooth(
+        &self,
+        mode: &VideoMode,
+        from: Point,
+        to: Point,
+        color: (u8, u8, u8),
+        background: (u8, u8, u8),
+    ) -> Result<()> {
+        let mut result = Ok(());
+        wu_points(from, to, |x, y, coverage| {
+            if result.is_err() || x < 0 || y < 0 || x > u32::MAX as i64 || y > u32::MAX as i64 {
+                return;
+            }
+            let (r, g, b) = blend(color, background, coverage);
+            result = self.draw_pixel(Point::new(x as u32, y as u32), mode.rgb(r, g, b));
+        });
+        result
+    }
+}
+
+kernel_test!(
+    fn wu_lines_cover_one_pixel_per_step() {
+        let mut points = alloc::vec::Vec::new();
+        wu_points(Point::new(4, 2), Point::new(0, 0), |x, y, c| {
+            points.push((x, y, c))
+        });
+        // the ends are on the line, and each column adds up to a pixel
+        kassert_eq!(points.first(), Some(&(0, 0, 255)));
+        kassert_eq!(points.last(), Some(&(4, 2, 255)));
+        for x in 0..=4 {
+            let total: u32 = points.iter().filter(|p| p.0 == x).map(|p| p.2 as u32).sum();
+            kassert_eq!(total, 255, "column {}", x);
+        }
+        kassert!(points.contains(&(1, 0, 127)) && points.contains(&(1, 1, 128)));
+
+        // straight lines are solid, and steep ones step along y
+        let mut n = 0;
+        wu_points(Point::new(3, 0), Point::new(3, 5), |x, _, c| {
+            n += 1;
+            kassert!(x == 3 && c == 255);
+        });
+        kassert_eq!(n, 6);
+
+        kassert_eq!(blend((255, 0, 100), (0, 255, 100), 255), (255, 0, 100));
+        kassert_eq!(blend((255, 0, 100), (0, 255, 100), 0), (0, 255, 100));
+        kassert_eq!(blend((255, 0, 100), (0, 255, 100), 51), (51, 204, 100));
+    }
+);
+
+kernel_test!(
+    fn rect_outlines_are_inside() {
+        kassert_eq!(
+            rect_corners(Rect::new(1, 2, 3, 4)),
+            Some([
+                Point::new(1, 2),
+                Point::new(3, 2),
+                Point::new(3, 5),
+                Point::new(1, 5)
+            ])
+        );
+        kassert_eq!(rect_corners(Rect::new(1, 2, 0, 4)), None);
+    }
+);
+
+kernel_test!(
+    fn polygons_fill_their_area() {
+        let area = |points: &[Point]| {
+            let mut n = 0;
+            poly_spans(points, |_, _, w| n += w);
+            n
+        };
+        let square = [
+            Point::new(2, 2),
+            Point::new(6, 2),
+            Point::new(6, 6),
+            Point::new(2, 6),
+        ];
+        let mut rows = Vec::new();
+        poly_spans(&square, |y, x, w| rows.push((y, x, w)));
+        kassert_eq!(
+            rows,
+            alloc::vec![(2, 2, 4), (3, 2, 4), (4, 2, 4), (5, 2, 4)]
+        );
+
+        // half of an 8x8 square, give or take the diagonal
+        let triangle = [Point::new(0, 0), Point::new(8, 8), Point::new(0, 8)];
+        kassert_eq!(area(&triangle), 28);
+
+        // a bow tie: two triangles meeting at (4, 4)
+        let bow_tie = [
+            Point::new(0, 0),
+            Point::new(8, 8),
+            Point::new(8, 0),
+            Point::new(0, 8),
+        ];
+        kassert_eq!(area(&bow_tie), 2 * 16);
+        kassert_eq!(area(&[Point::new(1, 1), Point::new(5, 1)]), 0);
+    }
+);
+
+kernel_test!(
+    fn circle_points_are_on_the_circle() {
+        let r = 20i64;
+        let mut n = 0;
+        circle_points(r as u32, |x, y| {
+            n += 1;
+            kassert!((x * x + y * y - r * r).abs() <= r, "({}, {})", x, y);
+        });
+        kassert!(n >= 8 * r as usize / 2);
+    }
+);
+
+kernel_test!(
+    fn ellipse_points_are_on_the_ellipse() {
+        let (a, b) = (30i64, 10i64);
+        let mut extremes = 0;
+        ellipse_points(a as u32, b as u32, |x, y| {
+            // within a pixel of x²/a² + y²/b² = 1
+            let v = x * x * b * b + y * y * a * a;
+            kassert!((v - a * a * b * b).abs() <= 2 * a * a * b, "({}, {})", x, y);
+            if (x.abs(), y.abs()) == (a, 0) || (x.abs(), y.abs()) == (0, b) {
+                extremes += 1;
+            }
+        });
+        kassert!(extremes >= 4);
+    }
+);
+
+kernel_test!(
+    fn arcs_cover_their_angles() {
+        let quarter = Arc::new(0, 90);
+        // up and to the right on screen
+        kassert!(quarter.contains(5, -5));
+        kassert!(quarter.contains(10, 0) && quarter.contains(0, -10));
+        kassert!(!quarter.contains(-5, -5) && !quarter.contains(5, 5));
+
+        let most = Arc::new(90, 0);
+        kassert!(most.contains(-5, 5) && most.contains(5, 5));
+        kassert!(!most.contains(5, -5));
+
+        kassert!(Arc::new(30, 390).contains(1, 1));
+    }
+);
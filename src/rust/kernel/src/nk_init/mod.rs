@@ -0,0 +1,209 @@
+// boot-time init calls: `nk_init!` wraps a function and puts a pointer
+// to it in the `.rust_init` link section (see link/nautilus.ld), like
+// `kernel_test!`, so a driver starts itself at boot instead of waiting
+// for a shell command or a call added to init.c.
+//
+// each call has a level, and init.c runs the levels at fixed points of
+// boot, in order, through `nk_rust_init_level`. every call of a level
+// has run, or failed, before any call of the next one starts, so code
+// that needs another module's init only has to use a later level.
+// within a level, calls run in link order, which is unspecified.
+
+use core::ffi::c_int;
+use core::sync::atomic::{AtomicI32, AtomicU8, Ordering};
+
+use crate::nk_error::{self, KError, Result};
+use crate::nk_log::module_name;
+
+mod nk_shell_cmd;
+
+extern "C" {
+    // link/nautilus.ld
+    static __start_rust_init: [u8; 0];
+    static __stop_rust_init: [u8; 0];
+}
+
+/// When, in boot, an init call runs. Levels run in this order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    /// Logging, timekeeping, the scheduler and interrupts are up.
+    Core = 0,
+    /// Rust subsystems other code builds on; runs right after `Core`.
+    Subsys = 1,
+    /// Device drivers, after the C ones, before filesystems are
+    /// mounted.
+    Device = 2,
+    /// After the kernel command line has been handled, before the
+    /// boot tests and the shell.
+    Late = 3,
+}
+
+impl Level {
+    /// Every level, in the order they run.
+    pub const ALL: [Level; 4] = [Level::Core, Level::Subsys, Level::Device, Level::Late];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Core => "core",
+            Level::Subsys => "subsys",
+            Level::Device => "device",
+            Level::Late => "late",
+        }
+    }
+}
+
+// `InitCall::ret` before the call has run; C return values that are
+// errors are negative
+const NOT_RUN: i32 = 1;
+
+/// An init call, as collected by `nk_init!`.
+pub struct InitCall {
+    pub module_path: &'static str,
+    pub name: &'static str,
+    pub level: Level,
+    pub run: fn() -> Result<()>,
+    ret: AtomicI32,
+}
+
+impl InitCall {
+    #[doc(hidden)]
+    pub const fn new(
+        module_path: &'static str,
+        name: &'static str,
+        level: Level,
+        run: fn() -> Result<()>,
+    ) -> Self {
+        InitCall {
+            module_path,
+            name,
+            level,
+            run,
+            ret: AtomicI32::new(NOT_RUN),
+        }
+    }
+
+    /// The module that declared the call, as used by `rust_log`.
+    pub fn module(&self) -> &'static str {
+        module_name(self.module_path)
+    }
+
+    /// How the call went, or `None` if its level hasn't run yet.
+    pub fn status(&self) -> Option<Result<()>> {
+        match self.ret.load(Ordering::Relaxed) {
+            NOT_RUN => None,
+            0 => Some(Ok(())),
+            ret => Some(Err(KError::from_code(ret))),
+        }
+    }
+}
+
+// the first level that hasn't run
+static NEXT_LEVEL: AtomicU8 = AtomicU8::new(Level::Core as u8);
+
+/// Every init call linked into the kernel.
+pub fn calls() -> &'static [&'static InitCall] {
+    // the linker puts nothing but `nk_init!`'s pointers between the two
+    // symbols
+    unsafe {
+        let start = __start_rust_init.as_ptr() as *const &'static InitCall;
+        let stop = __stop_rust_init.as_ptr() as *const &'static InitCall;
+        core::slice::from_raw_parts(start, stop.offset_from(start) as usize)
+    }
+}
+
+/// Whether every call of `level` has run.
+pub fn done(level: Level) -> bool {
+    (level as u8) < NEXT_LEVEL.load(Ordering::SeqCst)
+}
+
+// a failed call is logged and does not stop the others, so one broken
+// driver doesn't keep the kernel from booting
+fn run_level(level: Level) -> Result<()> {
+    let mut result = Ok(());
+    for &call in calls().iter().filter(|c| c.level == level) {
+        debug_print!("{} init: {}::{}", level.name(), call.module(), call.name);
+        let r = (call.run)();
+        call.ret.store(nk_error::to_c_ret(r), Ordering::Relaxed);
+        if let Err(e) = r {
+            error_print!("{}::{} failed: {}", call.module(), call.name, e);
+            result = Err(e);
+        }
+    }
+    result
+}
+
+/// Runs the init calls of `level`, and first those of every earlier
+/// level that hasn't run. Called by init.c, on the boot CPU; levels
+/// that have already run are not run again. Returns the error of the
+/// last call that failed, if any did.
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn nk_rust_init_level(level: c_int) -> c_int {
+    let Some(&level) = usize::try_from(level).ok().and_then(|l| Level::ALL.get(l)) else {
+        error_print!("no init level {}", level);
+        return KError::INVALID_ARG.code();
+    };
+    let mut result = Ok(());
+    for l in Level::ALL {
+        if l > level {
+            break;
+        }
+        if done(l) {
+            continue;
+        }
+        if let Err(e) = run_level(l) {
+            result = Err(e);
+        }
+        NEXT_LEVEL.store(l as u8 + 1, Ordering::SeqCst);
+    }
+    nk_error::to_c_ret(result)
+}
+
+/// Declares a function to call at boot, at a `Level`:
+///
+/// ```ignore
+/// nk_init!(Device, fn parport_init() -> Result<()> {
+///     bringup_device("parport0", PARPORT0_BASE, PARPORT0_IRQ)
+/// });
+/// ```
+///
+/// A function that cannot fail can leave out the return type. An error
+/// is logged, and shown by `rust_init`.
+#[macro_export]
+macro_rules! nk_init {
+    ($level:ident, fn $name:ident() $body:block) => {
+        $crate::nk_init!($level, fn $name() -> $crate::nk_error::Result<()> {
+            $body;
+            Ok(())
+        });
+    };
+    ($level:ident, fn $name:ident() -> $ret:ty $body:block) => {
+        fn $name() -> $ret $body
+
+        const _: () = {
+            static CALL: $crate::nk_init::InitCall = $crate::nk_init::InitCall::new(
+                module_path!(),
+                stringify!($name),
+                $crate::nk_init::Level::$level,
+                $name,
+            );
+
+            #[used]
+            #[link_section = ".rust_init"]
+            static POINTER: &$crate::nk_init::InitCall = &CALL;
+        };
+    };
+}
+
+kernel_test!(
+    fn earlier_levels_ran() {
+        for call in calls() {
+            if done(call.level) {
+                kassert!(call.status().is_some(), "{}", call.name);
+            } else {
+                kassert!(call.status().is_none(), "{}", call.name);
+            }
+        }
+    }
+);
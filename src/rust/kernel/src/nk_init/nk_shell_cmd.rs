@@ -0,0 +1,31 @@
+use alloc::format;
+use core::ffi::{c_char, c_int, c_void};
+use core::fmt::Write;
+
+use super::{calls, Level};
+use crate::utils::VcWriter;
+
+// `rust_init` lists every init call, by level, and how it went
+#[no_mangle]
+pub unsafe extern "C" fn rust_init_shell_entry(
+    _buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    let mut w = VcWriter::new();
+    for level in Level::ALL {
+        for c in calls().iter().filter(|c| c.level == level) {
+            let _ = write!(
+                w,
+                "{:<8} {:<40} ",
+                level.name(),
+                format!("{}::{}", c.module(), c.name)
+            );
+            let _ = match c.status() {
+                None => writeln!(w, "not run"),
+                Some(Ok(())) => writeln!(w, "ok"),
+                Some(Err(e)) => writeln!(w, "FAILED: {}", e),
+            };
+        }
+    }
+    0
+}
@@ -1,17 +1,18 @@
-use crate::nk_bindings;
+use crate::nk_raw;
 use core::cell::UnsafeCell;
 use lock_api::{GuardSend, RawMutex};
 
 extern "C" {
-    fn spin_lock_irq(lock: *mut nk_bindings::spinlock_t) -> u8;
-    fn spin_unlock_irq(lock: *mut nk_bindings::spinlock_t, flags: u8);
+    fn spin_lock_irq(lock: *mut nk_raw::spinlock_t) -> u8;
+    fn spin_try_lock_irq(lock: *mut nk_raw::spinlock_t, flags: *mut u8) -> i32;
+    fn spin_unlock_irq(lock: *mut nk_raw::spinlock_t, flags: u8);
 }
 
 pub type IRQLock<T> = lock_api::Mutex<NkIrqLock, T>;
 //pub type IRQLockGuard<'a, T> = lock_api::MutexGuard<'a, NkIrqLock, T>;
 
 pub struct NkIrqLock {
-    spinlock: UnsafeCell<nk_bindings::spinlock_t>,
+    spinlock: UnsafeCell<nk_raw::spinlock_t>,
     state_flags: UnsafeCell<u8>,
 }
 
@@ -26,6 +27,11 @@ impl NkIrqLock {
     }
 }
 
+// the spinlock serializes all access to `state_flags`,
+// so the lock can be shared between threads and CPUs
+unsafe impl Send for NkIrqLock {}
+unsafe impl Sync for NkIrqLock {}
+
 unsafe impl RawMutex for NkIrqLock {
     #[allow(clippy::declare_interior_mutable_const)]
     const INIT: NkIrqLock = NkIrqLock::new();
@@ -41,7 +47,17 @@ unsafe impl RawMutex for NkIrqLock {
     }
 
     fn try_lock(&self) -> bool {
-        unimplemented!()
+        let lock_ptr = self.spinlock.get();
+        let mut flags = 0;
+        unsafe {
+            // `state_flags` belongs to the current holder until we
+            // actually get the lock
+            if spin_try_lock_irq(lock_ptr, &mut flags) != 0 {
+                return false;
+            }
+            *self.state_flags.get() = flags;
+        }
+        true
     }
 
     unsafe fn unlock(&self) {
@@ -0,0 +1,65 @@
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use super::Level;
+
+// escape sequences are nul-terminated so they can be passed straight to C
+pub const RESET: &str = "\x1b[0m\0";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorMode {
+    Off,
+    On,
+    // color only when log output reaches a terminal that understands
+    // ANSI escapes. the VGA-backed VC shows them as garbage, so this
+    // means the serial mirror.
+    Auto,
+}
+
+impl ColorMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorMode::Off => "off",
+            ColorMode::On => "on",
+            ColorMode::Auto => "auto",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ColorMode> {
+        [ColorMode::Off, ColorMode::On, ColorMode::Auto]
+            .into_iter()
+            .find(|m| m.name() == name)
+    }
+}
+
+static MODE: AtomicU8 = AtomicU8::new(ColorMode::Off as u8);
+
+pub fn mode() -> ColorMode {
+    match MODE.load(Ordering::Relaxed) {
+        x if x == ColorMode::On as u8 => ColorMode::On,
+        x if x == ColorMode::Auto as u8 => ColorMode::Auto,
+        _ => ColorMode::Off,
+    }
+}
+
+pub fn set_mode(mode: ColorMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Whether log lines should currently carry ANSI color codes.
+pub fn enabled() -> bool {
+    match mode() {
+        ColorMode::Off => false,
+        ColorMode::On => true,
+        ColorMode::Auto => nk_config_enabled!(VIRTUAL_CONSOLE_SERIAL_MIRROR),
+    }
+}
+
+/// The escape sequence that starts a line at `level`, if it is colored.
+pub fn code(level: Level) -> Option<&'static str> {
+    match level {
+        Level::Error => Some("\x1b[1;31m\0"), // bold red
+        Level::Warn => Some("\x1b[33m\0"),    // yellow
+        Level::Info => None,
+        Level::Debug => Some("\x1b[90m\0"), // grey
+    }
+}
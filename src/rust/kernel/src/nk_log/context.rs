@@ -0,0 +1,114 @@
+use core::ffi::{c_char, c_int, c_ulong, c_void, CStr};
+use core::fmt;
+
+use crate::nk_raw;
+
+// wrappers around inline functions and macros in glue.c
+extern "C" {
+    fn nk_rust_cpu_state_get_cpu() -> *mut c_void;
+    fn nk_rust_my_cpu_id() -> c_int;
+    fn nk_rust_in_interrupt_context() -> c_int;
+    fn nk_rust_preempt_is_disabled() -> c_int;
+    fn nk_rust_preempt_disable();
+    fn nk_rust_preempt_enable();
+    fn nk_rust_get_cur_thread() -> *mut nk_raw::nk_thread;
+    fn nk_rust_thread_tid(t: *mut nk_raw::nk_thread) -> c_ulong;
+    fn nk_rust_thread_is_idle(t: *mut nk_raw::nk_thread) -> c_int;
+    fn nk_rust_thread_name(t: *mut nk_raw::nk_thread) -> *const c_char;
+}
+
+/// The CPU and thread a log message was emitted from, formatted
+/// the same way as the prefix of NK's C `DEBUG_PRINT` and friends.
+pub struct Context<'a> {
+    // `None` before per-CPU state is set up during boot
+    cpu: Option<i32>,
+    interrupt: bool,
+    preemptible: bool,
+    tid: u64,
+    thread_name: &'a str,
+}
+
+impl Context<'_> {
+    pub fn cpu(&self) -> Option<i32> {
+        self.cpu
+    }
+
+    pub fn tid(&self) -> u64 {
+        self.tid
+    }
+
+    pub fn thread_name(&self) -> &str {
+        self.thread_name
+    }
+
+    pub fn in_interrupt(&self) -> bool {
+        self.interrupt
+    }
+
+    pub fn preemptible(&self) -> bool {
+        self.preemptible
+    }
+}
+
+impl fmt::Display for Context<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let i = if self.interrupt { "I" } else { "" };
+        let p = if self.preemptible { "P" } else { "" };
+        match self.cpu {
+            Some(cpu) => write!(
+                f,
+                "CPU {} ({}{} {} \"{}\")",
+                cpu, i, p, self.tid, self.thread_name
+            ),
+            None => write!(f, "CPU ? ({}{})", i, p),
+        }
+    }
+}
+
+/// Runs `f` with the context of the caller. Preemption is disabled
+/// while `f` runs so the CPU and thread cannot change underneath it.
+pub fn with_current<R>(f: impl FnOnce(&Context) -> R) -> R {
+    unsafe {
+        let preemptible = nk_rust_preempt_is_disabled() == 0;
+        nk_rust_preempt_disable();
+
+        let interrupt = nk_rust_in_interrupt_context() != 0;
+        let ctx = if nk_rust_cpu_state_get_cpu().is_null() {
+            Context {
+                cpu: None,
+                interrupt,
+                preemptible,
+                tid: 0,
+                thread_name: "",
+            }
+        } else {
+            let t = nk_rust_get_cur_thread();
+            let (tid, thread_name) = if t.is_null() {
+                (0, "*none*")
+            } else if nk_rust_thread_is_idle(t) != 0 {
+                (nk_rust_thread_tid(t), "*idle*")
+            } else {
+                // the current thread cannot exit while it is running this
+                // code, so its name outlives `f`
+                let name = CStr::from_ptr(nk_rust_thread_name(t));
+                let name = match name.to_str() {
+                    Ok("") => "*unnamed*",
+                    Ok(s) => s,
+                    Err(_) => "*invalid*",
+                };
+                (nk_rust_thread_tid(t), name)
+            };
+            Context {
+                cpu: Some(nk_rust_my_cpu_id()),
+                interrupt,
+                preemptible,
+                tid,
+                thread_name,
+            }
+        };
+
+        let r = f(&ctx);
+        nk_rust_preempt_enable();
+        r
+    }
+}
@@ -0,0 +1,188 @@
+// messages logged from interrupt context are queued here and handed to
+// the sinks by a thread, since the sinks call into C code (the VC,
+// chardevs) that takes locks the interrupted code may already hold.
+//
+// the queue is a fixed set of slots, each claimed with a compare-and-
+// swap on its state, so handlers never spin or take a lock to log.
+
+use core::cell::UnsafeCell;
+use core::cmp::min;
+use core::ffi::{c_int, c_void, CStr};
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+
+use super::{sink, Level};
+use crate::nk_raw;
+use crate::nk_time::{self, Duration};
+
+// at most this many messages wait for the drain thread; more are dropped
+const SLOTS: usize = 64;
+// longer lines are truncated
+const SLOT_LINE_LEN: usize = 256;
+const SLOT_MODULE_LEN: usize = 32;
+
+const DRAIN_PERIOD: Duration = Duration::from_millis(10);
+// room for the log buffer of the dropped-messages warning
+const DRAIN_STACK_SIZE: u64 = 64 * 1024;
+
+// slot states
+const EMPTY: u8 = 0;
+const WRITING: u8 = 1;
+const READY: u8 = 2;
+
+struct Message {
+    // position in the order messages were logged
+    seq: usize,
+    level: Level,
+    timestamp_ns: u64,
+    module: [u8; SLOT_MODULE_LEN],
+    module_len: usize,
+    // nul-terminated
+    line: [u8; SLOT_LINE_LEN],
+}
+
+struct Slot {
+    state: AtomicU8,
+    msg: UnsafeCell<Message>,
+}
+
+// `msg` is only written by whoever moved `state` from EMPTY to
+// WRITING, and only read by the drain thread once it is READY
+unsafe impl Sync for Slot {}
+
+impl Slot {
+    const fn new() -> Self {
+        Slot {
+            state: AtomicU8::new(EMPTY),
+            msg: UnsafeCell::new(Message {
+                seq: 0,
+                level: Level::Error,
+                timestamp_ns: 0,
+                module: [0; SLOT_MODULE_LEN],
+                module_len: 0,
+                line: [0; SLOT_LINE_LEN],
+            }),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const SLOT_INIT: Slot = Slot::new();
+static QUEUE: [Slot; SLOTS] = [SLOT_INIT; SLOTS];
+
+static NEXT_SEQ: AtomicUsize = AtomicUsize::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Whether the drain thread is up. Until it is, interrupt context
+/// logs straight to the sinks, like everything else.
+pub fn running() -> bool {
+    RUNNING.load(Ordering::Acquire)
+}
+
+/// Queues `record` for the drain thread, without blocking.
+/// If the queue is full, the message is dropped and counted.
+pub fn push(record: &sink::Record) {
+    let seq = NEXT_SEQ.fetch_add(1, Ordering::Relaxed);
+    for i in 0..SLOTS {
+        let slot = &QUEUE[(seq + i) % SLOTS];
+        if slot
+            .state
+            .compare_exchange(EMPTY, WRITING, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            continue;
+        }
+
+        // we own the slot until we mark it READY
+        let msg = unsafe { &mut *slot.msg.get() };
+        msg.seq = seq;
+        msg.level = record.level;
+        msg.timestamp_ns = record.timestamp_ns;
+        msg.module_len = min(record.module.len(), SLOT_MODULE_LEN);
+        msg.module[..msg.module_len].copy_from_slice(&record.module.as_bytes()[..msg.module_len]);
+        let line = record.line.to_bytes();
+        if line.len() < SLOT_LINE_LEN {
+            msg.line[..line.len()].copy_from_slice(line);
+            msg.line[line.len()] = 0;
+        } else {
+            // keep the trailing newline
+            let n = SLOT_LINE_LEN - 2;
+            msg.line[..n].copy_from_slice(&line[..n]);
+            msg.line[n] = b'\n';
+            msg.line[n + 1] = 0;
+        }
+
+        slot.state.store(READY, Ordering::Release);
+        return;
+    }
+    DROPPED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Hands everything queued so far to the sinks, oldest first.
+pub fn drain() {
+    let mut ready = [0usize; SLOTS];
+    let mut n = 0;
+    for (i, slot) in QUEUE.iter().enumerate() {
+        if slot.state.load(Ordering::Acquire) == READY {
+            ready[n] = i;
+            n += 1;
+        }
+    }
+    // READY slots are only changed by us
+    ready[..n].sort_unstable_by_key(|&i| unsafe { (*QUEUE[i].msg.get()).seq });
+
+    for &i in &ready[..n] {
+        let slot = &QUEUE[i];
+        let msg = unsafe { &*slot.msg.get() };
+        let line_len = msg.line.iter().position(|&b| b == 0).unwrap_or(0);
+        sink::dispatch(&sink::Record {
+            level: msg.level,
+            module: core::str::from_utf8(&msg.module[..msg.module_len]).unwrap_or("?"),
+            timestamp_ns: msg.timestamp_ns,
+            // `push` nul-terminated the line
+            line: CStr::from_bytes_with_nul(&msg.line[..=line_len]).unwrap(),
+        });
+        slot.state.store(EMPTY, Ordering::Release);
+    }
+
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped != 0 {
+        crate::warn_print!("dropped {} messages logged from interrupt context", dropped);
+    }
+}
+
+unsafe extern "C" fn drain_thread(_input: *mut c_void, _output: *mut *mut c_void) {
+    RUNNING.store(true, Ordering::Release);
+    loop {
+        drain();
+        nk_time::sleep(DRAIN_PERIOD);
+    }
+}
+
+/// Starts the thread that drains messages logged from interrupt
+/// context. Called once the scheduler is up.
+#[no_mangle]
+pub extern "C" fn nk_rust_log_init() -> c_int {
+    let mut tid: nk_raw::nk_thread_id_t = null_mut();
+    let r = unsafe {
+        nk_raw::nk_thread_start(
+            Some(drain_thread),
+            null_mut(),
+            null_mut(),
+            1,
+            DRAIN_STACK_SIZE,
+            &mut tid,
+            -1,
+        )
+    };
+    if r != 0 {
+        crate::error_print!("cannot start log drain thread");
+        return r;
+    }
+    unsafe {
+        // the name is copied
+        nk_raw::nk_thread_name(tid, "rust-log\0".as_ptr() as *mut i8);
+    }
+    0
+}
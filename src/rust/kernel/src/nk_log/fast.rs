@@ -0,0 +1,280 @@
+// formatting for hot paths (per-interrupt, per-pixel, ...), where
+// going through `core::fmt` and a 1KB buffer costs too much. pieces
+// are written straight into a small buffer by simple itoa/hex helpers.
+
+use core::cmp::min;
+use core::ffi::CStr;
+
+use super::{context::Context, filter, module_name, sink, timestamp, Level};
+
+// short lines only; longer ones are truncated
+const FAST_BUF_LEN: usize = 192;
+
+pub struct FastBuf {
+    buf: [u8; FAST_BUF_LEN],
+    len: usize,
+}
+
+impl FastBuf {
+    pub const fn new() -> Self {
+        FastBuf {
+            buf: [0; FAST_BUF_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn bytes(&mut self, b: &[u8]) -> &mut Self {
+        // always leave room for the newline and nul terminator
+        let space = FAST_BUF_LEN - 2 - self.len;
+        let n = min(space, b.len());
+        for (dst, &src) in self.buf[self.len..self.len + n].iter_mut().zip(b) {
+            *dst = if src == 0 { b'?' } else { src };
+        }
+        self.len += n;
+        self
+    }
+
+    pub fn str(&mut self, s: &str) -> &mut Self {
+        self.bytes(s.as_bytes())
+    }
+
+    pub fn dec(&mut self, mut v: u64) -> &mut Self {
+        let mut digits = [0u8; 20];
+        let mut i = digits.len();
+        loop {
+            i -= 1;
+            digits[i] = b'0' + (v % 10) as u8;
+            v /= 10;
+            if v == 0 {
+                break;
+            }
+        }
+        self.bytes(&digits[i..])
+    }
+
+    // right-aligned in `width` columns, padded with `fill`
+    pub fn dec_padded(&mut self, v: u64, width: usize, fill: u8) -> &mut Self {
+        let mut digits = 1;
+        let mut rest = v / 10;
+        while rest != 0 {
+            digits += 1;
+            rest /= 10;
+        }
+        for _ in digits..width {
+            self.bytes(&[fill]);
+        }
+        self.dec(v)
+    }
+
+    pub fn dec_signed(&mut self, v: i64) -> &mut Self {
+        if v < 0 {
+            self.bytes(b"-");
+        }
+        self.dec(v.unsigned_abs())
+    }
+
+    // lowercase, with a 0x prefix and no leading zeros
+    pub fn hex(&mut self, v: u64) -> &mut Self {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        let mut digits = [0u8; 16];
+        let mut i = digits.len();
+        let mut v = v;
+        loop {
+            i -= 1;
+            digits[i] = DIGITS[(v & 0xf) as usize];
+            v >>= 4;
+            if v == 0 {
+                break;
+            }
+        }
+        self.bytes(b"0x").bytes(&digits[i..])
+    }
+
+    // terminates the line and returns it
+    fn finish(&mut self) -> &CStr {
+        self.buf[self.len] = b'\n';
+        self.buf[self.len + 1] = 0;
+        CStr::from_bytes_with_nul(&self.buf[..self.len + 2]).unwrap()
+    }
+}
+
+impl Default for FastBuf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Something that can be written into a `FastBuf` without `core::fmt`.
+pub trait FastFmt {
+    fn fast_fmt(&self, buf: &mut FastBuf);
+}
+
+impl FastFmt for &str {
+    fn fast_fmt(&self, buf: &mut FastBuf) {
+        buf.str(self);
+    }
+}
+
+macro_rules! impl_fast_fmt_unsigned {
+    ($($t:ty),*) => {$(
+        impl FastFmt for $t {
+            fn fast_fmt(&self, buf: &mut FastBuf) {
+                buf.dec(*self as u64);
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_fast_fmt_signed {
+    ($($t:ty),*) => {$(
+        impl FastFmt for $t {
+            fn fast_fmt(&self, buf: &mut FastBuf) {
+                buf.dec_signed(*self as i64);
+            }
+        }
+    )*};
+}
+
+impl_fast_fmt_unsigned!(u8, u16, u32, u64, usize);
+impl_fast_fmt_signed!(i8, i16, i32, i64, isize);
+
+/// Formats the wrapped value in hex in the fast logging macros.
+pub struct Hex<T>(pub T);
+
+macro_rules! impl_fast_fmt_hex {
+    ($($t:ty),*) => {$(
+        impl FastFmt for Hex<$t> {
+            fn fast_fmt(&self, buf: &mut FastBuf) {
+                buf.hex(self.0 as u64);
+            }
+        }
+    )*};
+}
+
+impl_fast_fmt_hex!(u8, u16, u32, u64, usize);
+
+impl FastFmt for timestamp::Stamp {
+    fn fast_fmt(&self, buf: &mut FastBuf) {
+        buf.str("[")
+            .dec_padded(self.secs(), 5, b' ')
+            .str(".")
+            .dec_padded(self.subsec_nanos(), 9, b'0')
+            .str("] ");
+    }
+}
+
+impl FastFmt for Context<'_> {
+    fn fast_fmt(&self, buf: &mut FastBuf) {
+        let i = if self.in_interrupt() { "I" } else { "" };
+        let p = if self.preemptible() { "P" } else { "" };
+        match self.cpu() {
+            Some(cpu) => {
+                buf.str("CPU ")
+                    .dec_signed(cpu.into())
+                    .str(" (")
+                    .str(i)
+                    .str(p);
+                buf.str(" ").dec(self.tid()).str(" \"");
+                buf.str(self.thread_name()).str("\")");
+            }
+            None => {
+                buf.str("CPU ? (").str(i).str(p).str(")");
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn _enabled(level: Level, module_path: &str) -> bool {
+    filter::enabled(module_name(module_path), level)
+}
+
+#[doc(hidden)]
+pub fn _log_fast(level: Level, module_path: &str, file: &str, line: u32, pieces: &[&dyn FastFmt]) {
+    let module = module_name(module_path);
+    let timestamp_ns = timestamp::now();
+    super::context::with_current(|ctx| {
+        let mut buf = FastBuf::new();
+        if let Some(stamp) = timestamp::stamp(timestamp_ns) {
+            stamp.fast_fmt(&mut buf);
+        }
+        ctx.fast_fmt(&mut buf);
+        match level {
+            Level::Error => buf
+                .str(": ERROR at ")
+                .str(file)
+                .str("(")
+                .dec(line.into())
+                .str("): "),
+            Level::Warn => buf.str(": WARNING : "),
+            Level::Info => buf.str(": "),
+            Level::Debug => buf.str(": DEBUG: "),
+        };
+        buf.str(module).str(": ");
+        for p in pieces {
+            p.fast_fmt(&mut buf);
+        }
+
+        let record = sink::Record {
+            level,
+            module,
+            timestamp_ns,
+            line: buf.finish(),
+        };
+        super::emit(&record, ctx);
+    })
+}
+
+#[macro_export]
+#[doc(hidden)]
+macro_rules! _log_fast {
+    ($level:expr, $($piece:expr),+ $(,)?) => {
+        if $crate::nk_log::fast::_enabled($level, module_path!()) {
+            $crate::nk_log::fast::_log_fast(
+                $level,
+                module_path!(),
+                file!(),
+                line!(),
+                &[$(&$piece as &dyn $crate::nk_log::fast::FastFmt),+],
+            )
+        }
+    };
+}
+
+/// Like `error_print!`, but takes a list of string literals, integers,
+/// and `Hex(..)` values instead of a format string:
+/// `error_fast!("irq ", irq, " status ", Hex(status))`.
+#[macro_export]
+macro_rules! error_fast {
+    ($($piece:expr),+ $(,)?) => {
+        $crate::_log_fast!($crate::nk_log::Level::Error, $($piece),+)
+    };
+}
+
+#[macro_export]
+macro_rules! warn_fast {
+    ($($piece:expr),+ $(,)?) => {
+        $crate::_log_fast!($crate::nk_log::Level::Warn, $($piece),+)
+    };
+}
+
+#[macro_export]
+macro_rules! info_fast {
+    ($($piece:expr),+ $(,)?) => {
+        $crate::_log_fast!($crate::nk_log::Level::Info, $($piece),+)
+    };
+}
+
+/// Like `debug_print!`, `config = DEBUG_FOO` first compiles it out
+/// unless NAUT_CONFIG_DEBUG_FOO is on.
+#[macro_export]
+macro_rules! debug_fast {
+    (config = $option:ident, $($piece:expr),+ $(,)?) => {
+        if $crate::nk_config_enabled!($option) {
+            $crate::_log_fast!($crate::nk_log::Level::Debug, $($piece),+)
+        }
+    };
+    ($($piece:expr),+ $(,)?) => {
+        $crate::_log_fast!($crate::nk_log::Level::Debug, $($piece),+)
+    };
+}
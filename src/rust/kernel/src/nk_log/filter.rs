@@ -0,0 +1,79 @@
+use alloc::{borrow::ToOwned, string::String, vec::Vec};
+
+use crate::nk_cmdline;
+use crate::nk_error::Result;
+use crate::nk_lock::IRQLock;
+
+use super::Level;
+
+// the most verbose level printed for modules without an override
+const DEFAULT_LEVEL: Level = Level::Info;
+
+struct Filter {
+    default: Level,
+    // per-module overrides, keyed by top-level module name
+    modules: Vec<(String, Level)>,
+}
+
+// held with interrupts off, since logging is allowed from interrupt handlers
+static FILTER: IRQLock<Filter> = IRQLock::new(Filter {
+    default: DEFAULT_LEVEL,
+    modules: Vec::new(),
+});
+
+// `-rust rust.loglevel=<level>` on the kernel command line sets the
+// default level
+crate::nk_init!(
+    Core,
+    fn level_from_cmdline() -> Result<()> {
+        if let Some(level) = nk_cmdline::parse("rust.loglevel")? {
+            set_default_level(level);
+        }
+        Ok(())
+    }
+);
+
+/// Whether a message at `level` from `module` should be printed.
+pub fn enabled(module: &str, level: Level) -> bool {
+    level <= max_level(module)
+}
+
+/// The most verbose level currently printed for `module`.
+pub fn max_level(module: &str) -> Level {
+    let f = FILTER.lock();
+    f.modules
+        .iter()
+        .find(|(m, _)| m == module)
+        .map(|(_, l)| *l)
+        .unwrap_or(f.default)
+}
+
+/// Print messages from `module` up to and including `level`.
+pub fn set_max_level(module: &str, level: Level) {
+    let mut f = FILTER.lock();
+    match f.modules.iter_mut().find(|(m, _)| m == module) {
+        Some((_, l)) => *l = level,
+        None => f.modules.push((module.to_owned(), level)),
+    }
+}
+
+/// Set the level for modules without an override.
+pub fn set_default_level(level: Level) {
+    FILTER.lock().default = level;
+}
+
+pub fn default_level() -> Level {
+    FILTER.lock().default
+}
+
+/// Drop all per-module overrides and restore the default level.
+pub fn reset() {
+    let mut f = FILTER.lock();
+    f.default = DEFAULT_LEVEL;
+    f.modules.clear();
+}
+
+/// Snapshot of the per-module overrides.
+pub fn overrides() -> Vec<(String, Level)> {
+    FILTER.lock().modules.clone()
+}
@@ -0,0 +1,232 @@
+use core::cmp::min;
+use core::ffi::CStr;
+use core::fmt::{self, Write};
+use core::str::FromStr;
+
+pub mod color;
+pub(crate) mod context;
+mod deferred;
+#[macro_use]
+pub mod fast;
+pub mod filter;
+mod nk_shell_cmd;
+pub mod ring;
+pub mod sink;
+pub mod timestamp;
+pub mod writer;
+
+// messages longer than this are truncated
+const LOG_BUF_LEN: usize = 1024;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub const ALL: [Level; 4] = [Level::Error, Level::Warn, Level::Info, Level::Debug];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        Level::ALL.into_iter().find(|l| l.name() == name)
+    }
+
+    /// The next less verbose level, or `None` for `Error`.
+    pub fn quieter(&self) -> Option<Level> {
+        match self {
+            Level::Error => None,
+            Level::Warn => Some(Level::Error),
+            Level::Info => Some(Level::Warn),
+            Level::Debug => Some(Level::Info),
+        }
+    }
+}
+
+/// Returns the name of the top-level module that a `module_path!()`
+/// belongs to (e.g. "parport" for "nk_rust::parport::chardev").
+/// This is the name used by the runtime filter and the `rust_log` command.
+pub fn module_name(module_path: &str) -> &str {
+    let mut parts = module_path.split("::");
+    let krate = parts.next().unwrap_or(module_path);
+    parts.next().unwrap_or(krate)
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Level, ()> {
+        Level::from_name(s).ok_or(())
+    }
+}
+
+// fixed-size, nul-terminated buffer to format log messages into.
+// we do not allocate here, so that logging works in the allocation
+// failure and panic paths.
+struct LogBuffer {
+    buf: [u8; LOG_BUF_LEN],
+    len: usize,
+    truncated: bool,
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        LogBuffer {
+            buf: [0; LOG_BUF_LEN],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    // marks a truncated message and nul-terminates the buffer.
+    // call once all output has been written.
+    fn finish(&mut self) {
+        if self.truncated {
+            let trunc_msg = "...(trunc)\n".as_bytes();
+            let start = LOG_BUF_LEN - 1 - trunc_msg.len();
+            self.buf[start..LOG_BUF_LEN - 1].copy_from_slice(trunc_msg);
+            self.len = LOG_BUF_LEN - 1;
+        }
+        self.buf[self.len] = 0;
+    }
+
+    // contents of a finished buffer
+    fn as_c_str(&self) -> &CStr {
+        // `write_str` replaces interior nul bytes, and `finish` terminates
+        CStr::from_bytes_with_nul(&self.buf[..=self.len]).unwrap()
+    }
+}
+
+impl Write for LogBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // always leave room for the nul terminator. interior nul
+        // bytes would cut the message short, so replace them.
+        let space = LOG_BUF_LEN - 1 - self.len;
+        let copy_len = min(space, s.len());
+        for (dst, src) in self.buf[self.len..self.len + copy_len]
+            .iter_mut()
+            .zip(s.bytes())
+        {
+            *dst = if src == 0 { b'?' } else { src };
+        }
+        self.len += copy_len;
+        if copy_len < s.len() {
+            self.truncated = true;
+        }
+        Ok(())
+    }
+}
+
+// interrupt handlers can run while the code they interrupted holds
+// locks the sinks need, so from interrupt context, defer to a thread
+fn emit(record: &sink::Record, ctx: &context::Context) {
+    if ctx.in_interrupt() && deferred::running() {
+        deferred::push(record);
+    } else {
+        sink::dispatch(record);
+    }
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, module_path: &str, file: &str, line: u32, args: fmt::Arguments) {
+    let module = module_name(module_path);
+    if !filter::enabled(module, level) {
+        return;
+    }
+
+    let timestamp_ns = timestamp::now();
+    context::with_current(|ctx| {
+        let mut buf = LogBuffer::new();
+        // writing to a `LogBuffer` never fails; overlong output is truncated
+        if let Some(stamp) = timestamp::stamp(timestamp_ns) {
+            let _ = write!(buf, "{}", stamp);
+        }
+        let _ = match level {
+            Level::Error => write!(buf, "{}: ERROR at {}({}): {}: ", ctx, file, line, module),
+            Level::Warn => write!(buf, "{}: WARNING : {}: ", ctx, module),
+            Level::Info => write!(buf, "{}: {}: ", ctx, module),
+            Level::Debug => write!(buf, "{}: DEBUG: {}: ", ctx, module),
+        };
+        let _ = buf.write_fmt(args);
+        let _ = buf.write_char('\n');
+        buf.finish();
+
+        let record = sink::Record {
+            level,
+            module,
+            timestamp_ns,
+            line: buf.as_c_str(),
+        };
+        emit(&record, ctx);
+    })
+}
+
+#[macro_export]
+macro_rules! error_print {
+    ($($arg:tt)*) => {
+        $crate::nk_log::_log(
+            $crate::nk_log::Level::Error,
+            module_path!(),
+            file!(),
+            line!(),
+            format_args!($($arg)*),
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! warn_print {
+    ($($arg:tt)*) => {
+        $crate::nk_log::_log(
+            $crate::nk_log::Level::Warn,
+            module_path!(),
+            file!(),
+            line!(),
+            format_args!($($arg)*),
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! info_print {
+    ($($arg:tt)*) => {
+        $crate::nk_log::_log(
+            $crate::nk_log::Level::Info,
+            module_path!(),
+            file!(),
+            line!(),
+            format_args!($($arg)*),
+        )
+    };
+}
+
+/// Off by default, and turned on per module at runtime (`rust_log`).
+/// `debug_print!(config = DEBUG_FOO, ...)` is also compiled out unless
+/// NAUT_CONFIG_DEBUG_FOO is on, like a C module's own `DEBUG`.
+#[macro_export]
+macro_rules! debug_print {
+    (config = $option:ident, $($arg:tt)*) => {
+        if $crate::nk_config_enabled!($option) {
+            $crate::debug_print!($($arg)*)
+        }
+    };
+    ($($arg:tt)*) => {
+        $crate::nk_log::_log(
+            $crate::nk_log::Level::Debug,
+            module_path!(),
+            file!(),
+            line!(),
+            format_args!($($arg)*),
+        )
+    };
+}
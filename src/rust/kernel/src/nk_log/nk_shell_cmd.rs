@@ -0,0 +1,125 @@
+use alloc::{boxed::Box, format, string::String};
+use core::ffi::{c_char, c_int, c_void, CStr};
+
+use super::{color, filter, ring, sink, timestamp, Level};
+use crate::utils::print_to_vc;
+
+const USAGE: &str =
+    "rust_log [<module>|all <level> on|off | color on|off|auto | time off|boot|wall |\n          \
+                     reset | sink <sink> <level>|off | sink add <chardev>]\n";
+
+fn print_filter() {
+    print_to_vc(&format!("color: {}\n", color::mode().name()));
+    print_to_vc(&format!("time: {}\n", timestamp::mode().name()));
+    print_to_vc(&format!("default: {}\n", filter::default_level().name()));
+    for (module, level) in filter::overrides() {
+        print_to_vc(&format!("{}: {}\n", module, level.name()));
+    }
+    for (name, level) in sink::sinks() {
+        let level = level.map_or("off", |l| l.name());
+        print_to_vc(&format!("sink {}: {}\n", name, level));
+    }
+}
+
+fn add_chardev_sink(dev: &str) {
+    let added =
+        sink::ChardevSink::new(dev).and_then(|s| sink::register(Box::new(s), Some(Level::Debug)));
+    if let Err(e) = added {
+        print_to_vc(&format!("cannot log to chardev {}: {}\n", dev, e));
+    }
+}
+
+fn set_sink_level(name: &str, level: &str) {
+    let level = match level {
+        "off" => None,
+        l => match Level::from_name(l) {
+            Some(l) => Some(l),
+            None => {
+                print_to_vc("unknown level (error, warn, info, debug)\n");
+                return;
+            }
+        },
+    };
+    if sink::set_max_level(name, level).is_err() {
+        print_to_vc(&format!("no sink named {}\n", name));
+    }
+}
+
+// `on` prints messages up to and including `level`,
+// `off` prints only messages less verbose than `level`
+fn set_level(module: &str, level: Level, on: bool) {
+    let new_level = if on {
+        level
+    } else {
+        // turning off errors leaves nothing less verbose to print,
+        // so errors are the floor
+        level.quieter().unwrap_or(Level::Error)
+    };
+
+    if module == "all" {
+        filter::set_default_level(new_level);
+    } else {
+        filter::set_max_level(module, new_level);
+    }
+}
+
+// `rust_log` lists the current filter,
+// `rust_log virtio_gpu debug on` enables debug output for one module,
+// `rust_log all debug off` changes the default for all other modules,
+// `rust_log sink vc warn` limits what one sink receives
+#[no_mangle]
+pub unsafe extern "C" fn rust_log_shell_entry(buf: *const c_char, _priv_: *const c_void) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let mut args = line.split_whitespace().skip(1);
+
+    match (args.next(), args.next(), args.next()) {
+        (None, _, _) => print_filter(),
+        (Some("reset"), None, _) => filter::reset(),
+        (Some("color"), Some(mode), None) => match color::ColorMode::from_name(mode) {
+            Some(m) => color::set_mode(m),
+            None => print_to_vc(USAGE),
+        },
+        (Some("time"), Some(mode), None) => match timestamp::TimestampMode::from_name(mode) {
+            Some(m) => timestamp::set_mode(m),
+            None => print_to_vc(USAGE),
+        },
+        (Some("sink"), Some("add"), Some(dev)) => add_chardev_sink(dev),
+        (Some("sink"), Some(name), Some(level)) => set_sink_level(name, level),
+        (Some(module), Some(level), Some(state @ ("on" | "off"))) => {
+            match Level::from_name(level) {
+                Some(l) => set_level(module, l, state == "on"),
+                None => {
+                    print_to_vc("unknown level (error, warn, info, debug)\n");
+                }
+            }
+        }
+        _ => print_to_vc(USAGE),
+    }
+
+    0
+}
+
+// `rust_dmesg` prints the log ring, `rust_dmesg -c` also clears it,
+// `rust_dmesg clear` clears it without printing
+#[no_mangle]
+pub unsafe extern "C" fn rust_dmesg_shell_entry(
+    buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let mut args = line.split_whitespace().skip(1);
+
+    match args.next() {
+        None => print_to_vc(&String::from_utf8_lossy(&ring::contents())),
+        Some("-c") => {
+            print_to_vc(&String::from_utf8_lossy(&ring::contents()));
+            ring::clear();
+        }
+        Some("clear") => ring::clear(),
+        _ => print_to_vc("rust_dmesg [-c | clear]\n"),
+    }
+
+    0
+}
@@ -0,0 +1,168 @@
+use alloc::vec::Vec;
+
+use crate::nk_lock::IRQLock;
+
+// oldest messages are overwritten once this fills up
+const RING_LEN: usize = 16 * 1024;
+
+struct Ring {
+    buf: [u8; RING_LEN],
+    // next byte to write
+    head: usize,
+    // whether `head` has gone around at least once
+    wrapped: bool,
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Ring {
+            buf: [0; RING_LEN],
+            head: 0,
+            wrapped: false,
+        }
+    }
+
+    fn push(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            let n = core::cmp::min(bytes.len(), RING_LEN - self.head);
+            self.buf[self.head..self.head + n].copy_from_slice(&bytes[..n]);
+            self.head += n;
+            if self.head == RING_LEN {
+                self.head = 0;
+                self.wrapped = true;
+            }
+            bytes = &bytes[n..];
+        }
+    }
+
+    fn contents(&self) -> Vec<u8> {
+        if !self.wrapped {
+            return self.buf[..self.head].to_vec();
+        }
+
+        let mut v = Vec::with_capacity(RING_LEN);
+        v.extend_from_slice(&self.buf[self.head..]);
+        v.extend_from_slice(&self.buf[..self.head]);
+        // the oldest line was partially overwritten, drop what is left of it
+        match v.iter().position(|&b| b == b'\n') {
+            Some(i) => v.split_off(i + 1),
+            None => v,
+        }
+    }
+
+    // the last `lines` lines, in the two pieces of `buf` they may span
+    fn tail(&self, lines: usize) -> (&[u8], &[u8]) {
+        let (old, new) = if self.wrapped {
+            (&self.buf[self.head..], &self.buf[..self.head])
+        } else {
+            (&self.buf[..0], &self.buf[..self.head])
+        };
+        let len = old.len() + new.len();
+        let byte = |i: usize| {
+            if i < old.len() {
+                old[i]
+            } else {
+                new[i - old.len()]
+            }
+        };
+
+        // scan back for the newline before the oldest line wanted,
+        // skipping the one that ends the newest line
+        let mut start = len;
+        let mut found = 0;
+        for i in (0..len.saturating_sub(1)).rev() {
+            if found == lines {
+                break;
+            }
+            if byte(i) == b'\n' {
+                start = i + 1;
+                found += 1;
+            }
+        }
+        // short of lines, everything is wanted, except what is left of
+        // a partially overwritten oldest line
+        if found < lines && !self.wrapped {
+            start = 0;
+        }
+
+        if start < old.len() {
+            (&old[start..], new)
+        } else {
+            (&new[..0], &new[start - old.len()..])
+        }
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.wrapped = false;
+    }
+}
+
+// held with interrupts off, since logging is allowed from interrupt handlers
+static RING: IRQLock<Ring> = IRQLock::new(Ring::new());
+
+/// Append a log line, which carries its own timestamp (if enabled), to the ring.
+pub fn record(line: &[u8]) {
+    RING.lock().push(line);
+}
+
+/// Copy of everything currently in the ring, oldest line first.
+pub fn contents() -> Vec<u8> {
+    RING.lock().contents()
+}
+
+/// Calls `f` with the last `lines` lines in the ring, in up to two
+/// pieces, without allocating. Returns false if the ring was locked,
+/// e.g. by a panic while logging, in which case `f` is not called.
+pub fn try_tail(lines: usize, mut f: impl FnMut(&[u8])) -> bool {
+    match RING.try_lock() {
+        Some(ring) => {
+            let (a, b) = ring.tail(lines);
+            f(a);
+            f(b);
+            true
+        }
+        None => false,
+    }
+}
+
+// for crash reports
+pub(crate) fn locked() -> bool {
+    RING.is_locked()
+}
+
+pub fn clear() {
+    RING.lock().clear();
+}
+
+fn tail_of(ring: &Ring, lines: usize) -> Vec<u8> {
+    let (a, b) = ring.tail(lines);
+    [a, b].concat()
+}
+
+// the test macros are defined after this module, so are used by path
+crate::kernel_test!(
+    fn tail_takes_the_newest_lines() {
+        let mut ring = Ring::new();
+        crate::kassert_eq!(tail_of(&ring, 4), b"");
+        ring.push(b"one\ntwo\nthree\n");
+        crate::kassert_eq!(tail_of(&ring, 2), b"two\nthree\n");
+        crate::kassert_eq!(tail_of(&ring, 4), b"one\ntwo\nthree\n");
+        crate::kassert_eq!(tail_of(&ring, 0), b"");
+    }
+);
+
+crate::kernel_test!(
+    fn tail_skips_overwritten_line() {
+        let mut ring = Ring::new();
+        // 10 bytes a line, so the oldest line left is partial
+        for _ in 0..RING_LEN / 10 + 1 {
+            ring.push(b"012345678\n");
+        }
+        ring.push(b"last\n");
+        crate::kassert_eq!(tail_of(&ring, 2), b"012345678\nlast\n");
+        let all = tail_of(&ring, RING_LEN);
+        crate::kassert!(all.starts_with(b"012345678\n"));
+        crate::kassert_eq!(all.len() % 10, 5);
+    }
+);
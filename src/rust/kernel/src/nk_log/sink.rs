@@ -0,0 +1,239 @@
+use alloc::{boxed::Box, ffi::CString, string::String, vec::Vec};
+use core::ffi::{c_int, CStr};
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use x86_64::instructions::interrupts;
+
+use crate::{
+    nk_error::{KError, Result},
+    nk_lock::IRQLock,
+    nk_raw, nk_smp,
+};
+
+use super::{color, ring, Level};
+
+// built-in sinks plus room for a few registered at runtime
+const MAX_SINKS: usize = 8;
+// `ChardevSink::writer` when no CPU is writing
+const NO_CPU: u32 = u32::MAX;
+
+/// A formatted log message, as handed to each sink.
+pub struct Record<'a> {
+    pub level: Level,
+    pub module: &'a str,
+    pub timestamp_ns: u64,
+    /// The complete message, prefix and trailing newline included.
+    pub line: &'a CStr,
+}
+
+/// A destination for log output.
+///
+/// `write` is called with interrupts off, possibly from an interrupt
+/// handler, and must neither block nor log.
+pub trait LogSink: Sync {
+    fn name(&self) -> &str;
+    fn write(&self, record: &Record);
+}
+
+struct Entry {
+    sink: &'static dyn LogSink,
+    // `None` disables the sink
+    max_level: Option<Level>,
+}
+
+/// The virtual console (and whatever it mirrors to), via `nk_vc_log`.
+pub struct VcSink;
+
+impl LogSink for VcSink {
+    fn name(&self) -> &str {
+        "vc"
+    }
+
+    fn write(&self, record: &Record) {
+        let line = record.line.as_ptr() as *mut i8;
+        match color::code(record.level).filter(|_| color::enabled()) {
+            Some(code) => {
+                // reset before the trailing newline, so the color
+                // does not bleed into whatever is printed next
+                let fmt = "%s%.*s%s\n\0".as_ptr() as *mut i8;
+                let line_len = (record.line.to_bytes().len() - 1) as c_int;
+                unsafe {
+                    // all strings are nul-terminated and outlive the call
+                    nk_raw::nk_vc_log(fmt, code.as_ptr(), line_len, line, color::RESET.as_ptr());
+                }
+            }
+            None => {
+                let fmt = "%s\0".as_ptr() as *mut i8;
+                unsafe {
+                    // both strings are nul-terminated and outlive the call
+                    nk_raw::nk_vc_log(fmt, line);
+                }
+            }
+        }
+    }
+}
+
+/// The in-memory ring read by `rust_dmesg`.
+pub struct RingSink;
+
+impl LogSink for RingSink {
+    fn name(&self) -> &str {
+        "ring"
+    }
+
+    fn write(&self, record: &Record) {
+        ring::record(record.line.to_bytes());
+    }
+}
+
+/// A character device, such as a serial port. Bytes the device is
+/// not ready to take are dropped rather than waited for, as are
+/// messages while the device is unregistered.
+pub struct ChardevSink {
+    name: CString,
+    // the CPU writing a message, which others wait for. a message the
+    // device's driver logs while writing one is dropped, rather than
+    // written into the middle of it
+    writer: AtomicU32,
+}
+
+// the device is looked up by name for each message, since it may have
+// been unregistered (and freed) since the last one. not
+// nk_char_dev_find, which dereferences a failed lookup.
+fn find_chardev(name: &CStr) -> Option<*mut nk_raw::nk_char_dev> {
+    // only reads the name
+    let dev = unsafe { nk_raw::nk_dev_find(name.as_ptr() as *mut i8) };
+    if dev.is_null() || unsafe { (*dev).type_ } != nk_raw::nk_dev_type_t_NK_DEV_CHAR {
+        return None;
+    }
+    // a chardev is an `nk_dev` first
+    Some(dev as *mut nk_raw::nk_char_dev)
+}
+
+impl ChardevSink {
+    /// A sink for the chardev called `name`, which must exist for now.
+    pub fn new(name: &str) -> Result<Self> {
+        let name = CString::new(name).map_err(|_| KError::INVALID_ARG)?;
+        find_chardev(&name).ok_or(KError::NOT_FOUND)?;
+        Ok(ChardevSink {
+            name,
+            writer: AtomicU32::new(NO_CPU),
+        })
+    }
+}
+
+impl LogSink for ChardevSink {
+    fn name(&self) -> &str {
+        // made from a `&str`
+        self.name.to_str().unwrap()
+    }
+
+    fn write(&self, record: &Record) {
+        // interrupts are off, so finding ourselves writing means the
+        // driver logged
+        let cpu = nk_smp::current_cpu();
+        loop {
+            match self
+                .writer
+                .compare_exchange(NO_CPU, cpu, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(w) if w == cpu => return,
+                Err(_) => spin_loop(),
+            }
+        }
+        if let Some(dev) = find_chardev(&self.name) {
+            let bytes = record.line.to_bytes();
+            unsafe {
+                // `dev` was registered when looked up, just now
+                nk_raw::nk_char_dev_write(
+                    dev,
+                    bytes.len() as u64,
+                    bytes.as_ptr() as *mut u8,
+                    nk_raw::nk_dev_request_type_t_NK_DEV_REQ_NONBLOCKING,
+                );
+            }
+        }
+        self.writer.store(NO_CPU, Ordering::Release);
+    }
+}
+
+// held with interrupts off, since logging is allowed from interrupt handlers
+static SINKS: IRQLock<[Option<Entry>; MAX_SINKS]> = IRQLock::new([
+    Some(Entry {
+        sink: &VcSink,
+        max_level: Some(Level::Debug),
+    }),
+    Some(Entry {
+        sink: &RingSink,
+        max_level: Some(Level::Debug),
+    }),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+]);
+
+// for crash reports
+pub(crate) fn locked() -> bool {
+    SINKS.is_locked()
+}
+
+/// Hands `record` to every sink that accepts its level.
+pub fn dispatch(record: &Record) {
+    // sinks are never removed, so they can be called after the lock is
+    // dropped, which keeps one that calls into a driver from holding it
+    let mut targets: [Option<&'static dyn LogSink>; MAX_SINKS] = [None; MAX_SINKS];
+    for (target, entry) in targets.iter_mut().zip(SINKS.lock().iter()) {
+        *target = entry
+            .as_ref()
+            .filter(|e| matches!(e.max_level, Some(l) if record.level <= l))
+            .map(|e| e.sink);
+    }
+    interrupts::without_interrupts(|| {
+        for sink in targets.iter().flatten() {
+            sink.write(record);
+        }
+    });
+}
+
+/// Adds a sink that receives messages up to and including `max_level`.
+/// Sinks stay registered for the lifetime of the kernel, so this is
+/// `BUSY` once `MAX_SINKS` are.
+pub fn register(sink: Box<dyn LogSink>, max_level: Option<Level>) -> Result<()> {
+    let mut sinks = SINKS.lock();
+    if sinks.iter().flatten().any(|e| e.sink.name() == sink.name()) {
+        return Err(KError::EXISTS);
+    }
+    let slot = sinks.iter_mut().find(|e| e.is_none()).ok_or(KError::BUSY)?;
+    *slot = Some(Entry {
+        sink: Box::leak(sink),
+        max_level,
+    });
+    Ok(())
+}
+
+/// Changes the most verbose level `name` receives, or disables it.
+pub fn set_max_level(name: &str, max_level: Option<Level>) -> Result<()> {
+    let mut sinks = SINKS.lock();
+    let entry = sinks
+        .iter_mut()
+        .flatten()
+        .find(|e| e.sink.name() == name)
+        .ok_or(KError::NOT_FOUND)?;
+    entry.max_level = max_level;
+    Ok(())
+}
+
+/// Names and levels of all registered sinks.
+pub fn sinks() -> Vec<(String, Option<Level>)> {
+    SINKS
+        .lock()
+        .iter()
+        .flatten()
+        .map(|e| (String::from(e.sink.name()), e.max_level))
+        .collect()
+}
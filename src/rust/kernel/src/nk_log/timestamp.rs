@@ -0,0 +1,88 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::nk_time::Instant;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimestampMode {
+    Off,
+    // time since boot, from the scheduler's clock
+    Boot,
+    // boot time plus the offset given to `set_wall_clock`. until
+    // something (e.g. an RTC driver) calls it, same as `Boot`.
+    Wall,
+}
+
+impl TimestampMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimestampMode::Off => "off",
+            TimestampMode::Boot => "boot",
+            TimestampMode::Wall => "wall",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TimestampMode> {
+        [TimestampMode::Off, TimestampMode::Boot, TimestampMode::Wall]
+            .into_iter()
+            .find(|m| m.name() == name)
+    }
+}
+
+static MODE: AtomicU8 = AtomicU8::new(TimestampMode::Boot as u8);
+
+// nanoseconds between the epoch and boot, 0 if unknown
+static WALL_OFFSET_NS: AtomicU64 = AtomicU64::new(0);
+
+pub fn mode() -> TimestampMode {
+    match MODE.load(Ordering::Relaxed) {
+        x if x == TimestampMode::Off as u8 => TimestampMode::Off,
+        x if x == TimestampMode::Wall as u8 => TimestampMode::Wall,
+        _ => TimestampMode::Boot,
+    }
+}
+
+pub fn set_mode(mode: TimestampMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Tells the log what time it is, in nanoseconds since the epoch.
+pub fn set_wall_clock(now_ns: u64) {
+    let offset = now_ns.saturating_sub(now());
+    WALL_OFFSET_NS.store(offset, Ordering::Relaxed);
+}
+
+/// Nanoseconds since boot.
+pub fn now() -> u64 {
+    Instant::now().as_nanos()
+}
+
+/// How the line for a message logged at `boot_ns` should be stamped,
+/// if at all.
+pub fn stamp(boot_ns: u64) -> Option<Stamp> {
+    match mode() {
+        TimestampMode::Off => None,
+        TimestampMode::Boot => Some(Stamp(boot_ns)),
+        TimestampMode::Wall => Some(Stamp(boot_ns + WALL_OFFSET_NS.load(Ordering::Relaxed))),
+    }
+}
+
+/// Formats as the `[seconds.nanoseconds] ` prefix of a log line.
+#[derive(Copy, Clone)]
+pub struct Stamp(pub u64);
+
+impl Stamp {
+    pub fn secs(&self) -> u64 {
+        self.0 / 1_000_000_000
+    }
+
+    pub fn subsec_nanos(&self) -> u64 {
+        self.0 % 1_000_000_000
+    }
+}
+
+impl fmt::Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{:5}.{:09}] ", self.secs(), self.subsec_nanos())
+    }
+}
@@ -0,0 +1,97 @@
+use core::fmt::{self, Write};
+use core::panic::Location;
+
+use super::{_log, Level};
+
+// leaves room for the prefix in the log buffer
+const LINE_LEN: usize = 512;
+
+/// Streams formatted output into the log at a fixed level, one log
+/// message per line. Lines longer than the buffer are split. Anything
+/// after the last newline is logged when the writer is dropped.
+///
+/// ```ignore
+/// let mut w = LogWriter::new(Level::Info, module_path!());
+/// for (name, count) in counters {
+///     writeln!(w, "{:>16} {}", name, count)?;
+/// }
+/// ```
+pub struct LogWriter {
+    level: Level,
+    module_path: &'static str,
+    file: &'static str,
+    line: u32,
+    buf: [u8; LINE_LEN],
+    len: usize,
+}
+
+impl LogWriter {
+    /// Messages are attributed to the caller's location, and filtered
+    /// like those from `module_path`.
+    #[track_caller]
+    pub fn new(level: Level, module_path: &'static str) -> Self {
+        let caller = Location::caller();
+        LogWriter {
+            level,
+            module_path,
+            file: caller.file(),
+            line: caller.line(),
+            buf: [0; LINE_LEN],
+            len: 0,
+        }
+    }
+
+    /// Logs the current partial line, if any.
+    pub fn flush(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+        // only whole `str`s are copied in, so this never fails
+        let text = core::str::from_utf8(&self.buf[..self.len]).unwrap_or("");
+        _log(
+            self.level,
+            self.module_path,
+            self.file,
+            self.line,
+            format_args!("{}", text),
+        );
+        self.len = 0;
+    }
+
+    fn push(&mut self, s: &str) {
+        self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+    }
+}
+
+impl Write for LogWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for piece in s.split_inclusive('\n') {
+            let (mut text, newline) = match piece.strip_suffix('\n') {
+                Some(t) => (t, true),
+                None => (piece, false),
+            };
+            while self.len + text.len() > LINE_LEN {
+                // split at a char boundary so every message is valid UTF-8
+                let mut n = LINE_LEN - self.len;
+                while !text.is_char_boundary(n) {
+                    n -= 1;
+                }
+                self.push(&text[..n]);
+                self.flush();
+                text = &text[n..];
+            }
+            self.push(text);
+            if newline {
+                self.flush();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LogWriter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
@@ -0,0 +1,170 @@
+// microbenchmarks: `Bench::new("name").run(|| ...)` warms the closure
+// up, times each call with serialized TSC reads, subtracts what the
+// reads themselves cost, and drops samples slowed by interrupts, so
+// numbers from different modules can be compared. `rust_bench` runs
+// the built-in ones.
+
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::arch::x86_64::{__rdtscp, _rdtsc};
+use core::fmt;
+use core::hint::black_box;
+
+use crate::nk_error::{KError, Result};
+use crate::nk_time::tsc;
+
+// timed calls to an empty closure, to measure the reads
+const OVERHEAD_SAMPLES: u32 = 1000;
+// how many interquartile ranges past the third quartile a sample may be
+// before it is dropped
+const FENCE_IQRS: u64 = 3;
+
+#[inline(always)]
+fn lfence() {
+    // the kernel is built without SSE, so no `_mm_lfence`
+    unsafe { asm!("lfence", options(nostack, preserves_flags)) };
+}
+
+// `lfence` keeps earlier instructions from running into the timed
+// region, and `rdtscp` waits for the timed ones to finish
+#[inline(always)]
+fn start() -> u64 {
+    lfence();
+    let t = unsafe { _rdtsc() };
+    lfence();
+    t
+}
+
+#[inline(always)]
+fn stop() -> u64 {
+    let mut aux = 0;
+    let t = unsafe { __rdtscp(&mut aux) };
+    lfence();
+    t
+}
+
+#[inline(always)]
+fn time<R>(f: &mut impl FnMut() -> R) -> u64 {
+    let t0 = start();
+    black_box(f());
+    stop().wrapping_sub(t0)
+}
+
+// the fewest cycles a timed empty closure took
+fn overhead() -> u64 {
+    let mut f = || ();
+    (0..OVERHEAD_SAMPLES)
+        .map(|_| time(&mut f))
+        .min()
+        .unwrap_or(0)
+}
+
+/// A microbenchmark: how many times to run the closure untimed first,
+/// and how many times to time it.
+#[derive(Clone, Copy, Debug)]
+pub struct Bench {
+    name: &'static str,
+    warmup: u32,
+    iterations: u32,
+}
+
+impl Bench {
+    pub const fn new(name: &'static str) -> Self {
+        Bench {
+            name,
+            warmup: 100,
+            iterations: 10_000,
+        }
+    }
+
+    pub const fn warmup(mut self, n: u32) -> Self {
+        self.warmup = n;
+        self
+    }
+
+    pub const fn iterations(mut self, n: u32) -> Self {
+        self.iterations = n;
+        self
+    }
+
+    /// Runs `f` `warmup` times, then times it `iterations` times.
+    /// `NO_MEM` if the samples don't fit in memory, `INVALID_ARG` for no
+    /// iterations.
+    pub fn run<R>(&self, mut f: impl FnMut() -> R) -> Result<Report> {
+        if self.iterations == 0 {
+            return Err(KError::INVALID_ARG);
+        }
+        let mut samples: Vec<u64> = Vec::new();
+        samples
+            .try_reserve_exact(self.iterations as usize)
+            .map_err(|_| KError::NO_MEM)?;
+
+        for _ in 0..self.warmup {
+            black_box(f());
+        }
+        let overhead = overhead();
+        for _ in 0..self.iterations {
+            samples.push(time(&mut f).saturating_sub(overhead));
+        }
+
+        // interrupts and SMIs only ever make a sample slower, so only
+        // the slow end is fenced off
+        samples.sort_unstable();
+        let n = samples.len();
+        let (q1, q3) = (samples[n / 4], samples[3 * n / 4]);
+        let fence = q3.saturating_add((q3 - q1).saturating_mul(FENCE_IQRS));
+        let kept = samples.partition_point(|&s| s <= fence);
+        let samples = &samples[..kept];
+
+        let sum: u128 = samples.iter().map(|&s| s as u128).sum();
+        Ok(Report {
+            name: self.name,
+            iterations: self.iterations,
+            rejected: (n - kept) as u32,
+            overhead,
+            min: samples[0],
+            median: samples[kept / 2],
+            mean: (sum / kept as u128) as u64,
+            p99: samples[kept * 99 / 100],
+            max: samples[kept - 1],
+        })
+    }
+}
+
+/// What a `Bench` measured, in cycles per call, over the samples left
+/// after outliers were dropped.
+#[derive(Clone, Copy, Debug)]
+pub struct Report {
+    pub name: &'static str,
+    pub iterations: u32,
+    /// Samples dropped as outliers.
+    pub rejected: u32,
+    /// Cycles the TSC reads around each call took, already subtracted.
+    pub overhead: u64,
+    pub min: u64,
+    pub median: u64,
+    pub mean: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+impl Report {
+    /// The median in ns, once the TSC is calibrated.
+    pub fn median_ns(&self) -> Option<u64> {
+        tsc::cycles_to_ns(self.median)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<20} median {:>8} mean {:>8} min {:>8} p99 {:>8} max {:>8} cycles",
+            self.name, self.median, self.mean, self.min, self.p99, self.max
+        )?;
+        if let Some(ns) = self.median_ns() {
+            write!(f, " ({} ns)", ns)?;
+        }
+        write!(f, ", {} of {} dropped", self.rejected, self.iterations)
+    }
+}
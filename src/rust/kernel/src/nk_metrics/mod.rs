@@ -0,0 +1,181 @@
+// counters and histograms that modules declare as statics and update
+// from anywhere, including interrupt handlers. updates are single
+// relaxed atomic adds; `rust_stats` prints everything.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::nk_lock::IRQLock;
+use crate::nk_log::module_name;
+
+pub mod bench;
+mod nk_shell_cmd;
+pub mod profile;
+
+const MAX_METRICS: usize = 128;
+// bucket `i` counts values below 2^i (and at least 2^(i-1)),
+// the last one everything bigger
+pub const HISTOGRAM_BUCKETS: usize = 32;
+
+/// A count of events, e.g. interrupts taken.
+pub struct Counter {
+    module_path: &'static str,
+    name: &'static str,
+    value: AtomicU64,
+    registered: AtomicBool,
+}
+
+impl Counter {
+    pub const fn new(module_path: &'static str, name: &'static str) -> Self {
+        Counter {
+            module_path,
+            name,
+            value: AtomicU64::new(0),
+            registered: AtomicBool::new(false),
+        }
+    }
+
+    pub fn add(&'static self, n: u64) {
+        register_once(&self.registered, Metric::Counter(self));
+        self.value.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn inc(&'static self) {
+        self.add(1);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.value.store(0, Ordering::Relaxed);
+    }
+}
+
+/// A distribution of values, e.g. latencies in ns, in power-of-two buckets.
+pub struct Histogram {
+    module_path: &'static str,
+    name: &'static str,
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS],
+    count: AtomicU64,
+    sum: AtomicU64,
+    registered: AtomicBool,
+}
+
+impl Histogram {
+    pub const fn new(module_path: &'static str, name: &'static str) -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+        Histogram {
+            module_path,
+            name,
+            buckets: [ZERO; HISTOGRAM_BUCKETS],
+            count: ZERO,
+            sum: ZERO,
+            registered: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record(&'static self, v: u64) {
+        register_once(&self.registered, Metric::Histogram(self));
+        let bucket = (64 - v.leading_zeros() as usize).min(HISTOGRAM_BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(v, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum.load(Ordering::Relaxed)
+    }
+
+    pub fn bucket(&self, i: usize) -> u64 {
+        self.buckets[i].load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        for b in &self.buckets {
+            b.store(0, Ordering::Relaxed);
+        }
+        self.count.store(0, Ordering::Relaxed);
+        self.sum.store(0, Ordering::Relaxed);
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum Metric {
+    Counter(&'static Counter),
+    Histogram(&'static Histogram),
+}
+
+impl Metric {
+    /// The module that declared the metric, as used by `rust_log`.
+    pub fn module(&self) -> &'static str {
+        match self {
+            Metric::Counter(c) => module_name(c.module_path),
+            Metric::Histogram(h) => module_name(h.module_path),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Counter(c) => c.name,
+            Metric::Histogram(h) => h.name,
+        }
+    }
+
+    pub fn reset(&self) {
+        match self {
+            Metric::Counter(c) => c.reset(),
+            Metric::Histogram(h) => h.reset(),
+        }
+    }
+}
+
+// metrics are listed here the first time they are updated. this is a
+// fixed array so updates from interrupt handlers never allocate.
+static METRICS: IRQLock<[Option<Metric>; MAX_METRICS]> = IRQLock::new([None; MAX_METRICS]);
+
+fn register_once(registered: &AtomicBool, metric: Metric) {
+    if registered.load(Ordering::Relaxed) || registered.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    // past `MAX_METRICS`, metrics still count but are not listed
+    if let Some(slot) = METRICS.lock().iter_mut().find(|m| m.is_none()) {
+        *slot = Some(metric);
+    }
+}
+
+/// Every metric updated so far.
+pub fn metrics() -> Vec<Metric> {
+    METRICS.lock().iter().flatten().copied().collect()
+}
+
+pub fn reset_all() {
+    for m in METRICS.lock().iter().flatten() {
+        m.reset();
+    }
+}
+
+/// Declares a `Counter` static: `counter!(IRQS, "irqs");` declares
+/// `IRQS`, listed by `rust_stats` as `<module>.irqs`.
+#[macro_export]
+macro_rules! counter {
+    ($vis:vis $ident:ident, $name:expr) => {
+        $vis static $ident: $crate::nk_metrics::Counter =
+            $crate::nk_metrics::Counter::new(module_path!(), $name);
+    };
+}
+
+/// Declares a `Histogram` static, like `counter!`.
+#[macro_export]
+macro_rules! histogram {
+    ($vis:vis $ident:ident, $name:expr) => {
+        $vis static $ident: $crate::nk_metrics::Histogram =
+            $crate::nk_metrics::Histogram::new(module_path!(), $name);
+    };
+}
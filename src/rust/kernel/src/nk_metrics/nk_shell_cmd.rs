@@ -0,0 +1,172 @@
+use alloc::boxed::Box;
+use alloc::{format, string::String};
+use core::ffi::{c_char, c_int, c_void, CStr};
+use core::fmt::Write;
+
+use super::bench::{Bench, Report};
+use super::profile::{profiles, reset_profiles};
+use super::{metrics, reset_all, Metric, HISTOGRAM_BUCKETS};
+use crate::nk_error::Result;
+use crate::nk_lock::IRQLock;
+use crate::nk_time::{tsc, Instant};
+use crate::utils::VcWriter;
+
+fn print_histogram(w: &mut VcWriter, module: &str, name: &str, h: &super::Histogram) {
+    let count = h.count();
+    let avg = h.sum().checked_div(count).unwrap_or(0);
+    let _ = writeln!(w, "{}.{}: count {} avg {}", module, name, count, avg);
+    print_buckets(w, h);
+}
+
+fn print_buckets(w: &mut VcWriter, h: &super::Histogram) {
+    for i in 0..HISTOGRAM_BUCKETS {
+        let n = h.bucket(i);
+        if n == 0 {
+            continue;
+        }
+        let bound = if i == HISTOGRAM_BUCKETS - 1 {
+            String::from("inf")
+        } else {
+            format!("{}", 1u64 << i)
+        };
+        let _ = writeln!(w, "  < {:>10}: {}", bound, n);
+    }
+}
+
+// `rust_stats` prints all metrics, `rust_stats <module>` those of one
+// module, `rust_stats reset` zeroes them
+#[no_mangle]
+pub unsafe extern "C" fn rust_stats_shell_entry(
+    buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let mut args = line.split_whitespace().skip(1);
+
+    let module = match args.next() {
+        Some("reset") => {
+            reset_all();
+            return 0;
+        }
+        m => m,
+    };
+
+    let mut w = VcWriter::new();
+    for m in metrics() {
+        if matches!(module, Some(name) if name != m.module()) {
+            continue;
+        }
+        match m {
+            Metric::Counter(c) => {
+                let _ = writeln!(w, "{}.{}: {}", m.module(), m.name(), c.get());
+            }
+            Metric::Histogram(h) => print_histogram(&mut w, m.module(), m.name(), h),
+        }
+    }
+
+    0
+}
+
+// cycles as ns, or as cycles if the TSC isn't calibrated
+fn fmt_cycles(cycles: u64) -> String {
+    match tsc::cycles_to_ns(cycles) {
+        Some(ns) => format!("{} ns", ns),
+        None => format!("{} cycles", cycles),
+    }
+}
+
+// `rust_profile` prints every profiled scope, `rust_profile reset`
+// zeroes them
+#[no_mangle]
+pub unsafe extern "C" fn rust_profile_shell_entry(
+    buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    if matches!(line.split_whitespace().nth(1), Some("reset")) {
+        reset_profiles();
+        return 0;
+    }
+
+    let mut w = VcWriter::new();
+    for p in profiles() {
+        let h = p.cycles();
+        let avg = h.sum().checked_div(h.count()).unwrap_or(0);
+        let _ = writeln!(
+            w,
+            "{}.{}: count {} total {} avg {} max {}",
+            p.module(),
+            p.name(),
+            h.count(),
+            fmt_cycles(h.sum()),
+            fmt_cycles(avg),
+            fmt_cycles(p.max())
+        );
+        // buckets are in cycles
+        print_buckets(&mut w, h);
+    }
+
+    0
+}
+
+// the built-in benchmarks: the primitives other code is built from
+fn run_bench(name: &'static str, iterations: u32) -> Option<Result<Report>> {
+    static LOCK: IRQLock<u64> = IRQLock::new(0);
+    // `counter!` is defined after this module, so only by path
+    crate::counter!(BENCH_COUNTER, "bench");
+    let b = Bench::new(name).iterations(iterations);
+    Some(match name {
+        "tsc" => b.run(tsc::read),
+        "realtime" => b.run(Instant::now),
+        "irqlock" => b.run(|| *LOCK.lock() += 1),
+        "alloc" => b.run(|| Box::new(0u64)),
+        "counter" => b.run(|| BENCH_COUNTER.inc()),
+        _ => return None,
+    })
+}
+
+const BENCHES: [&str; 5] = ["tsc", "realtime", "irqlock", "alloc", "counter"];
+
+// `rust_bench` runs every built-in benchmark, `rust_bench <name> [n]`
+// one of them, timing `n` calls
+#[no_mangle]
+pub unsafe extern "C" fn rust_bench_shell_entry(
+    buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let mut args = line.split_whitespace().skip(1);
+    let names = match args.next() {
+        None => &BENCHES[..],
+        Some(name) => match BENCHES.iter().position(|&b| b == name) {
+            Some(i) => &BENCHES[i..=i],
+            None => {
+                error_print!("unknown benchmark {}, try one of {:?}", name, BENCHES);
+                return 0;
+            }
+        },
+    };
+    let iterations = match args.next().map(str::parse::<u32>) {
+        None => 10_000,
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            error_print!("usage: rust_bench [<name> [iterations]]");
+            return 0;
+        }
+    };
+
+    let mut w = VcWriter::new();
+    for &name in names {
+        match run_bench(name, iterations) {
+            Some(Ok(r)) => {
+                let _ = writeln!(w, "{}", r);
+            }
+            Some(Err(e)) => error_print!("{}: {}", name, e),
+            None => {}
+        }
+    }
+    0
+}
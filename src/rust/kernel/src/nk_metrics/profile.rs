@@ -0,0 +1,119 @@
+// scoped profiling: `profile_scope!("name")` times the rest of the
+// enclosing scope with the TSC, into a histogram of cycles per name.
+// `rust_profile` prints them, with times in ns once the TSC is
+// calibrated; `rust_stats` lists the histograms too.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::Histogram;
+use crate::nk_lock::IRQLock;
+use crate::nk_log::module_name;
+use crate::nk_time::tsc;
+
+const MAX_PROFILES: usize = 64;
+
+/// Where the time of one named scope goes. Declared by `profile_scope!`.
+pub struct Profile {
+    module_path: &'static str,
+    name: &'static str,
+    cycles: Histogram,
+    max: AtomicU64,
+    registered: AtomicBool,
+}
+
+impl Profile {
+    pub const fn new(module_path: &'static str, name: &'static str) -> Self {
+        Profile {
+            module_path,
+            name,
+            cycles: Histogram::new(module_path, name),
+            max: AtomicU64::new(0),
+            registered: AtomicBool::new(false),
+        }
+    }
+
+    /// Starts timing; the time is recorded when the guard is dropped.
+    pub fn enter(&'static self) -> ProfileGuard {
+        ProfileGuard {
+            profile: self,
+            start: tsc::read(),
+        }
+    }
+
+    pub fn record(&'static self, cycles: u64) {
+        if !self.registered.load(Ordering::Relaxed)
+            && !self.registered.swap(true, Ordering::Relaxed)
+        {
+            // past `MAX_PROFILES`, scopes are still timed but not listed
+            if let Some(slot) = PROFILES.lock().iter_mut().find(|p| p.is_none()) {
+                *slot = Some(self);
+            }
+        }
+        self.cycles.record(cycles);
+        self.max.fetch_max(cycles, Ordering::Relaxed);
+    }
+
+    /// The module that declared the scope, as used by `rust_log`.
+    pub fn module(&self) -> &'static str {
+        module_name(self.module_path)
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Cycles spent in the scope, per visit.
+    pub fn cycles(&self) -> &Histogram {
+        &self.cycles
+    }
+
+    /// The longest visit, in cycles.
+    pub fn max(&self) -> u64 {
+        self.max.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.cycles.reset();
+        self.max.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Times a scope, from `Profile::enter` until dropped.
+pub struct ProfileGuard {
+    profile: &'static Profile,
+    start: u64,
+}
+
+impl Drop for ProfileGuard {
+    fn drop(&mut self) {
+        self.profile.record(tsc::read().wrapping_sub(self.start));
+    }
+}
+
+static PROFILES: IRQLock<[Option<&'static Profile>; MAX_PROFILES]> =
+    IRQLock::new([None; MAX_PROFILES]);
+
+/// Every scope profiled so far.
+pub fn profiles() -> Vec<&'static Profile> {
+    PROFILES.lock().iter().flatten().copied().collect()
+}
+
+pub fn reset_profiles() {
+    for p in PROFILES.lock().iter().flatten() {
+        p.reset();
+    }
+}
+
+/// Times the rest of the enclosing scope: `profile_scope!("flush");`
+/// is listed by `rust_profile` as `<module>.flush`.
+#[macro_export]
+macro_rules! profile_scope {
+    ($name:expr) => {
+        let _profile_guard = {
+            static PROFILE: $crate::nk_metrics::profile::Profile =
+                $crate::nk_metrics::profile::Profile::new(module_path!(), $name);
+            PROFILE.enter()
+        };
+    };
+}
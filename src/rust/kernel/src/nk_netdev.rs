@@ -0,0 +1,348 @@
+// network device drivers' side of NK's network device interface
+// (nautilus/netdev.h): a driver implements `NetDev`, and `Registration`
+// registers it, and hands NK's calls on to it with the device locked.
+//
+// posts are asynchronous: a driver queues each packet, and completes it
+// once the device is done, usually from its interrupt handler. a caller
+// that wants to wait goes through nk_net_dev_receive/send_packet, which
+// do the waiting.
+
+use alloc::boxed::Box;
+use alloc::ffi::CString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ffi::{c_char, c_int, c_void};
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+use crate::nk_crash;
+use crate::nk_error::{KError, Result};
+use crate::nk_lock::IRQLock;
+use crate::nk_raw;
+
+counter!(POSTS, "posts");
+counter!(FAILED, "failed");
+fault_point!(REGISTER_FAULT, "netdev_register");
+
+type RawCallback = unsafe extern "C" fn(nk_raw::nk_net_dev_status_t, *mut c_void);
+
+pub const MAC_LEN: usize = 6;
+
+/// A device's address, and the sizes of the packets it takes, headers
+/// included.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Characteristics {
+    pub mac: [u8; MAC_LEN],
+    pub min_tu: u64,
+    pub max_tu: u64,
+}
+
+/// Where to report a packet's result, once the device is done with it.
+/// A packet the driver refuses is not completed; one it queues must be,
+/// or whoever posted it waits for good.
+#[must_use]
+pub struct Completion {
+    callback: Option<RawCallback>,
+    context: *mut c_void,
+}
+
+// the callback is NK's or the caller's, and may be called from any
+// context, interrupts included
+unsafe impl Send for Completion {}
+
+impl Completion {
+    pub fn complete(self, result: Result<()>) {
+        let status = match result {
+            Ok(()) => nk_raw::nk_net_dev_status_t_NK_NET_DEV_STATUS_SUCCESS,
+            Err(_) => {
+                FAILED.inc();
+                nk_raw::nk_net_dev_status_t_NK_NET_DEV_STATUS_ERROR
+            }
+        };
+        if let Some(callback) = self.callback {
+            // the caller passed them together with the packet
+            unsafe { callback(status, self.context) };
+        }
+    }
+}
+
+/// A network device driver.
+pub trait NetDev: Send {
+    fn get_characteristics(&self) -> Characteristics;
+
+    /// How big a buffer a packet of `packet_size` bytes needs.
+    fn buffer_size(packet_size: u64) -> u64 {
+        packet_size
+    }
+
+    /// Queues `dest` for the device to receive a packet of up to `len`
+    /// bytes into, and completes `done` once it has. If it cannot be
+    /// queued, `done` is dropped instead.
+    ///
+    /// # Safety
+    ///
+    /// `dest` must be valid for writes of `len` bytes, and stay so, and
+    /// not be otherwise used, until the packet completes.
+    unsafe fn post_receive(&mut self, dest: *mut u8, len: u64, done: Completion) -> Result<()>;
+
+    /// Queues the `len` byte packet in `src` for the device to send.
+    /// See `post_receive`.
+    ///
+    /// # Safety
+    ///
+    /// `src` must be valid for reads of `len` bytes, and stay so until
+    /// the packet completes.
+    unsafe fn post_send(&mut self, src: *const u8, len: u64, done: Completion) -> Result<()>;
+}
+
+/// `T` registered as a network device, unregistered when dropped.
+pub struct Registration<T: NetDev> {
+    dev: NonNull<nk_raw::nk_net_dev>,
+    // NK keeps a pointer to it until the device is unregistered
+    _interface: Box<nk_raw::nk_net_dev_int>,
+    _driver: PhantomData<Arc<IRQLock<T>>>,
+}
+
+// only handed back to nk_net_dev_unregister
+unsafe impl<T: NetDev> Send for Registration<T> {}
+
+impl<T: NetDev> Registration<T> {
+    pub fn register(name: &str, driver: Arc<IRQLock<T>>) -> Result<Self> {
+        REGISTER_FAULT.check(KError::FAILED)?;
+        let interface = Box::new(nk_raw::nk_net_dev_int {
+            get_characteristics: Some(get_characteristics::<T>),
+            post_receive: Some(post_receive::<T>),
+            post_send: Some(post_send::<T>),
+            dev_int: nk_raw::nk_dev_int {
+                open: None,
+                close: None,
+            },
+        });
+        // NK copies the name, so it is ours again once registered
+        let name_bytes = CString::new(name).map_err(|_| KError::INVALID_ARG)?;
+        let driver_ptr = Arc::into_raw(driver);
+        // not actually mutable, but C code had no `const` qualifier
+        let r = unsafe {
+            nk_raw::nk_net_dev_register(
+                name_bytes.as_ptr() as *mut c_char,
+                0,
+                &*interface as *const _ as *mut nk_raw::nk_net_dev_int,
+                driver_ptr as *mut c_void,
+            )
+        };
+        let dev = match NonNull::new(r) {
+            Some(d) => d,
+            None => {
+                // not registered, so the reference is still ours
+                drop(unsafe { Arc::from_raw(driver_ptr) });
+                return Err(KError::FAILED);
+            }
+        };
+        nk_crash::devices::register(r as *const u8, "netdev", name);
+        Ok(Registration {
+            dev,
+            _interface: interface,
+            _driver: PhantomData,
+        })
+    }
+}
+
+impl<T: NetDev> Drop for Registration<T> {
+    fn drop(&mut self) {
+        let ptr = self.dev.as_ptr();
+        nk_crash::devices::unregister(ptr as *const u8);
+        unsafe {
+            let state = (*ptr).dev.state as *const IRQLock<T>;
+            // NK calls into `state` until it is unregistered
+            nk_raw::nk_net_dev_unregister(ptr);
+            // taking back `Arc` is safe from any `netdev` we registered
+            drop(Arc::from_raw(state));
+        }
+    }
+}
+
+unsafe fn deref_locked_state<'a, T>(state: *mut c_void) -> &'a IRQLock<T> {
+    // caller must guarantee `state` is what `register` passed, and that
+    // the device is still registered
+    let l = state as *const IRQLock<T>;
+    unsafe { l.as_ref() }.unwrap()
+}
+
+unsafe extern "C" fn packet_size_to_buffer_size<T: NetDev>(packet_size: u64) -> u64 {
+    T::buffer_size(packet_size)
+}
+
+unsafe extern "C" fn get_characteristics<T: NetDev>(
+    state: *mut c_void,
+    c: *mut nk_raw::nk_net_dev_characteristics,
+) -> c_int {
+    let d = unsafe { deref_locked_state::<T>(state) };
+    let chars = d.lock().get_characteristics();
+    unsafe {
+        (*c).mac = chars.mac;
+        (*c).min_tu = chars.min_tu;
+        (*c).max_tu = chars.max_tu;
+        (*c).packet_size_to_buffer_size = Some(packet_size_to_buffer_size::<T>);
+    }
+    0
+}
+
+fn post_ret(r: Result<()>) -> c_int {
+    POSTS.inc();
+    match r {
+        Ok(()) => 0,
+        Err(e) => {
+            FAILED.inc();
+            debug_print!("netdev post failed: {}", e.name());
+            -1
+        }
+    }
+}
+
+unsafe extern "C" fn post_receive<T: NetDev>(
+    state: *mut c_void,
+    dest: *mut u8,
+    len: u64,
+    callback: Option<RawCallback>,
+    context: *mut c_void,
+) -> c_int {
+    let d = unsafe { deref_locked_state::<T>(state) };
+    let done = Completion { callback, context };
+    let r = unsafe { d.lock().post_receive(dest, len, done) };
+    post_ret(r)
+}
+
+unsafe extern "C" fn post_send<T: NetDev>(
+    state: *mut c_void,
+    src: *mut u8,
+    len: u64,
+    callback: Option<RawCallback>,
+    context: *mut c_void,
+) -> c_int {
+    let d = unsafe { deref_locked_state::<T>(state) };
+    let done = Completion { callback, context };
+    let r = unsafe { d.lock().post_send(src, len, done) };
+    post_ret(r)
+}
+
+// sends loop back to the next receive, as soon as both are posted
+#[derive(Default)]
+struct TestLoopback {
+    sent: Vec<u8>,
+    // completes packets with an error
+    failing: bool,
+}
+
+impl NetDev for TestLoopback {
+    fn get_characteristics(&self) -> Characteristics {
+        Characteristics {
+            mac: [0x02, 0, 0, 0, 0, 1],
+            min_tu: 1,
+            max_tu: 8,
+        }
+    }
+
+    fn buffer_size(packet_size: u64) -> u64 {
+        packet_size + 4
+    }
+
+    unsafe fn post_receive(&mut self, dest: *mut u8, len: u64, done: Completion) -> Result<()> {
+        if (len as usize) < self.sent.len() {
+            return Err(KError::INVALID_ARG);
+        }
+        unsafe { core::ptr::copy_nonoverlapping(self.sent.as_ptr(), dest, self.sent.len()) };
+        self.sent.clear();
+        done.complete(if self.failing {
+            Err(KError::IO)
+        } else {
+            Ok(())
+        });
+        Ok(())
+    }
+
+    unsafe fn post_send(&mut self, src: *const u8, len: u64, done: Completion) -> Result<()> {
+        if len > 8 {
+            return Err(KError::INVALID_ARG);
+        }
+        let packet = unsafe { core::slice::from_raw_parts(src, len as usize) };
+        self.sent.extend_from_slice(packet);
+        done.complete(if self.failing {
+            Err(KError::IO)
+        } else {
+            Ok(())
+        });
+        Ok(())
+    }
+}
+
+kernel_test!(
+    fn netdev_registration_lasts_until_dropped() {
+        use crate::nk_test::fixture::{registered, Driver};
+
+        let lo = Driver::new(TestLoopback::default());
+        let reg = Registration::register("rust-test-net", lo.driver.clone()).unwrap();
+        kassert!(registered("rust-test-net"));
+        kassert_eq!(lo.shared(), 1);
+        drop(reg);
+        kassert!(!registered("rust-test-net"));
+        kassert_eq!(lo.shared(), 0);
+    }
+);
+
+kernel_test!(
+    fn netdev_characteristics_size_buffers() {
+        use crate::nk_test::fixture::Driver;
+
+        let lo = Driver::new(TestLoopback::default());
+        let mut chars = nk_raw::nk_net_dev_characteristics {
+            mac: [0; MAC_LEN],
+            min_tu: 0,
+            max_tu: 0,
+            packet_size_to_buffer_size: None,
+        };
+        let r = unsafe { get_characteristics::<TestLoopback>(lo.state(), &mut chars) };
+        kassert_eq!(r, 0);
+        kassert_eq!(chars.mac, [0x02, 0, 0, 0, 0, 1]);
+        kassert_eq!((chars.min_tu, chars.max_tu), (1, 8));
+        // the driver's, not the default
+        let to_buffer = chars.packet_size_to_buffer_size.unwrap();
+        kassert_eq!(unsafe { to_buffer(4) }, 8);
+    }
+);
+
+kernel_test!(
+    fn netdev_errors_reach_the_caller() {
+        use crate::nk_test::fixture::{Done, Driver};
+
+        let lo = Driver::new(TestLoopback::default());
+        let state = lo.state();
+        let done = Done::default();
+        let (callback, context) = done.callback();
+        let mut packet = *b"ping";
+        let r =
+            unsafe { post_send::<TestLoopback>(state, packet.as_mut_ptr(), 4, callback, context) };
+        kassert_eq!(r, 0);
+        kassert_eq!(
+            done.take(),
+            Some(nk_raw::nk_net_dev_status_t_NK_NET_DEV_STATUS_SUCCESS)
+        );
+
+        // refused, so not completed
+        let mut big = [0u8; 9];
+        let r = unsafe { post_send::<TestLoopback>(state, big.as_mut_ptr(), 9, callback, context) };
+        kassert_eq!(r, -1);
+        kassert_eq!(done.take(), None);
+
+        // queued, then failed, though the packet came through
+        lo.driver.lock().failing = true;
+        let mut buf = [0u8; 8];
+        let r =
+            unsafe { post_receive::<TestLoopback>(state, buf.as_mut_ptr(), 8, callback, context) };
+        kassert_eq!(r, 0);
+        kassert_eq!(
+            done.take(),
+            Some(nk_raw::nk_net_dev_status_t_NK_NET_DEV_STATUS_ERROR)
+        );
+        kassert_eq!(&buf[..4], b"ping");
+    }
+);
@@ -0,0 +1,94 @@
+use core::cmp::min;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{
+    nk_backtrace, nk_crash,
+    nk_error::{KError, Result},
+    nk_lock::IRQLock,
+    nk_raw, nk_test,
+};
+
+const MAX_HOOKS: usize = 8;
+
+/// Called on panic, before the message is handed to NK's `panic`.
+///
+/// Hooks run in whatever context panicked, possibly with interrupts
+/// off or locks held, so they should do as little as possible and must
+/// not allocate.
+pub type PanicHook = fn(&PanicInfo);
+
+static HOOKS: IRQLock<[Option<PanicHook>; MAX_HOOKS]> = IRQLock::new([None; MAX_HOOKS]);
+
+// set on the first panic, so a hook that panics does not recurse
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Registers `hook` to run when Rust code panics, e.g. to restore a
+/// text console so the message is visible. Hooks run in the order they
+/// were registered and cannot be removed; `BUSY` once `MAX_HOOKS` are.
+pub fn set_hook(hook: PanicHook) -> Result<()> {
+    let mut hooks = HOOKS.lock();
+    let slot = hooks.iter_mut().find(|h| h.is_none()).ok_or(KError::BUSY)?;
+    *slot = Some(hook);
+    Ok(())
+}
+
+fn run_hooks(info: &PanicInfo) {
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    // the panic may have happened with the lock held
+    if let Some(hooks) = HOOKS.try_lock() {
+        for hook in hooks.iter().flatten() {
+            hook(info);
+        }
+    }
+}
+
+#[cfg(not(test))]
+#[panic_handler]
+fn nk_rust_panic(info: &PanicInfo) -> ! {
+    // a panicking kernel test only ends its own thread
+    nk_test::contain_panic(info);
+
+    let panic_msg = match info.message() {
+        Some(m) => match m.as_str() {
+            Some(s) if !s.contains('\0') => m
+                .as_str()
+                .unwrap_or("Panic occurred in Rust (unable to format panic message)."),
+            _ => "Panic occurred in Rust (invalid panic message).",
+        },
+        None => "Panic occurred in Rust.",
+    }
+    .as_bytes();
+
+    // we should not allocate memory here, since this function might be
+    // called as a result of a failure to allocate memory.
+    //
+    // make a buffer of known size, copy the message, and add text
+    // indicating the message was truncated if it's too long.
+    let mut msg_buf: [u8; 8192] = [0; 8192];
+    let copy_len = min(panic_msg.len(), msg_buf.len());
+    msg_buf[..copy_len].clone_from_slice(&panic_msg[..copy_len]);
+    // all bytes after the message - a minimum of 1 - should be 0x0 (nul terminator).
+    if panic_msg.len() >= msg_buf.len() {
+        let truncation_msg = "...(trunc)\0".as_bytes();
+        let mlen = msg_buf.len();
+        msg_buf[(mlen - truncation_msg.len())..].copy_from_slice(truncation_msg);
+    }
+
+    // do everything else first, since NK's panic does not return
+    run_hooks(info);
+    nk_crash::report(info);
+    nk_backtrace::print_backtrace();
+
+    let buf_ptr = msg_buf.as_ptr() as *const i8;
+    unsafe {
+        // this is fine because this function never returns;
+        // it might not be okay otherwise
+        nk_raw::panic(buf_ptr);
+    }
+
+    // should never get here - NK's panic handler should diverge
+    loop {}
+}
@@ -0,0 +1,81 @@
+// idling a CPU until something happens: `halt` until the next
+// interrupt, or `wait_for_change` of a word in memory, which uses
+// MONITOR/MWAIT where the CPU has them and can be told how deep a
+// sleep is worth it.
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::instructions::interrupts;
+
+// CPUID.01H:ECX[3]
+const MONITOR_FEATURE: u32 = 1 << 3;
+// CPUID leaf with MWAIT's C-states
+const MWAIT_LEAF: u32 = 0x5;
+
+/// How long the caller expects to wait, so how deep a C-state is
+/// worth entering.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hint {
+    /// A short wait: C1, which wakes quickly.
+    Short,
+    /// A long wait: the deepest C-state the CPU reports.
+    Long,
+}
+
+/// Whether this CPU has MONITOR/MWAIT.
+pub fn mwait_supported() -> bool {
+    __cpuid(1).ecx & MONITOR_FEATURE != 0
+}
+
+// MWAIT's EAX for the deepest C-state CPUID lists sub-states for;
+// bits 7:4 are the C-state minus one
+fn deepest_hint() -> u32 {
+    if __cpuid(0).eax < MWAIT_LEAF {
+        return 0;
+    }
+    // EDX[4n+3:4n] is the number of sub-states of Cn
+    let substates = __cpuid(MWAIT_LEAF).edx;
+    (1..8)
+        .rev()
+        .find(|n| (substates >> (4 * n)) & 0xf != 0)
+        .map_or(0, |n| (n - 1) << 4)
+}
+
+/// Waits for the next interrupt. With interrupts off, there would be
+/// none to wake the CPU, so this only pauses.
+pub fn halt() {
+    if interrupts::are_enabled() {
+        x86_64::instructions::hlt();
+    } else {
+        core::hint::spin_loop();
+    }
+}
+
+/// Waits until `word` may no longer be `old`: until another CPU writes
+/// it, or an interrupt, or some other wakeup MWAIT allows. Returns at
+/// once if it already isn't `old`; callers recheck, as wakeups can be
+/// spurious. Without MWAIT, this only pauses.
+pub fn wait_for_change(word: &AtomicU64, old: u64, hint: Hint) {
+    if !mwait_supported() {
+        core::hint::spin_loop();
+        return;
+    }
+    let eax = match hint {
+        Hint::Short => 0,
+        Hint::Long => deepest_hint(),
+    };
+    unsafe {
+        asm!("monitor", in("rax") word.as_ptr(), in("ecx") 0, in("edx") 0,
+             options(nostack, preserves_flags));
+    }
+    // a write between the load and the monitor would otherwise be missed
+    if word.load(Ordering::SeqCst) != old {
+        return;
+    }
+    // ECX[0]: interrupts wake the CPU even while they are off
+    unsafe {
+        asm!("mwait", in("eax") eax, in("ecx") 1, options(nostack, preserves_flags));
+    }
+}
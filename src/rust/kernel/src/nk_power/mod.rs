@@ -0,0 +1,87 @@
+// rebooting and powering off the machine, and idling a CPU until there
+// is something to do.
+//
+// NK's own `reboot()` tries the ACPI reset register first, but that
+// path halts before it writes the register, so rebooting is done here,
+// with the resets QEMU and real chipsets honor, ending in a triple
+// fault that no machine survives.
+
+use core::arch::asm;
+
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::PortWrite;
+
+use crate::nk_raw;
+
+pub mod idle;
+mod nk_shell_cmd;
+
+// the chipset's reset control register: writing 0x02 then 0x06 asks
+// for a hard reset
+const RESET_CONTROL_PORT: u16 = 0xcf9;
+// bit 0 of system control port A resets the CPU
+const SYSTEM_CONTROL_PORT_A: u16 = 0x92;
+// the 8042 keyboard controller, whose 0xfe command pulses the reset line
+const KBD_STATUS_PORT: u16 = 0x64;
+const KBD_INPUT_FULL: u8 = 0x02;
+const KBD_PULSE_RESET: u8 = 0xfe;
+
+// each reset gets this long to take effect before the next is tried
+const RESET_SPINS: u32 = 1_000_000;
+
+fn give_reset_time() {
+    for _ in 0..RESET_SPINS {
+        core::hint::spin_loop();
+    }
+}
+
+fn kbd_reset() {
+    use x86_64::instructions::port::PortRead;
+
+    for _ in 0..RESET_SPINS {
+        if unsafe { u8::read_from_port(KBD_STATUS_PORT) } & KBD_INPUT_FULL == 0 {
+            break;
+        }
+        core::hint::spin_loop();
+    }
+    unsafe { u8::write_to_port(KBD_STATUS_PORT, KBD_PULSE_RESET) };
+}
+
+fn triple_fault() -> ! {
+    // with an empty IDT, the breakpoint's exception can't be delivered,
+    // nor can the double fault that follows
+    let idt = x86_64::structures::DescriptorTablePointer {
+        limit: 0,
+        base: x86_64::VirtAddr::zero(),
+    };
+    unsafe {
+        x86_64::instructions::tables::lidt(&idt);
+        asm!("int3", options(noreturn));
+    }
+}
+
+/// Resets the machine. Tries, in order, the chipset's reset control
+/// register, the fast reset in system control port A, and the keyboard
+/// controller's reset line, and triple faults if none of them took.
+pub fn reboot() -> ! {
+    info_print!("rebooting");
+    interrupts::disable();
+    unsafe {
+        u8::write_to_port(RESET_CONTROL_PORT, 0x02);
+        u8::write_to_port(RESET_CONTROL_PORT, 0x06);
+    }
+    give_reset_time();
+    unsafe { u8::write_to_port(SYSTEM_CONTROL_PORT_A, 0x01) };
+    give_reset_time();
+    kbd_reset();
+    give_reset_time();
+    triple_fault()
+}
+
+/// Powers the machine off through ACPI's S5 state, which also ends a
+/// QEMU run. Without ACPI support for it, halts the calling CPU
+/// instead; the other CPUs keep running.
+pub fn poweroff() -> ! {
+    info_print!("powering off");
+    unsafe { nk_raw::acpi_shutdown() }
+}
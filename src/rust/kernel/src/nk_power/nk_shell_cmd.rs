@@ -0,0 +1,19 @@
+use core::ffi::{c_char, c_int, c_void};
+
+// `reboot` resets the machine
+#[no_mangle]
+pub unsafe extern "C" fn rust_reboot_shell_entry(
+    _buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    super::reboot()
+}
+
+// `poweroff` turns the machine off, ending a QEMU run
+#[no_mangle]
+pub unsafe extern "C" fn rust_poweroff_shell_entry(
+    _buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    super::poweroff()
+}
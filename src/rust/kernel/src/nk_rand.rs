@@ -0,0 +1,283 @@
+// random numbers for Rust code, in place of libccompat's `rand()`.
+//
+// `Rng` is xoshiro256**: fast and statistically good, but predictable
+// from its output, so not for anything secret. the shared generator
+// behind the free functions is seeded at boot from the TSC, the RTC,
+// and RDSEED or RDRAND where the CPU has them; drivers for entropy
+// sources mix in more with `add_entropy`. `Rng::seed_from_u64` gives a
+// reproducible sequence, for tests and demos that want one.
+//
+// `random::<T>()` is a whole value of any `Random` type: every value of
+// an integer type equally likely, and either `bool`.
+
+use core::arch::x86_64::{__cpuid, _rdrand64_step, _rdseed64_step};
+use core::ops::Range;
+
+use crate::nk_error::Result;
+use crate::nk_lock::IRQLock;
+use crate::nk_time::{rtc, tsc};
+
+// CPUID.01H:ECX[30] and CPUID.(EAX=07H,ECX=0):EBX[18]
+const RDRAND_FEATURE: u32 = 1 << 30;
+const RDSEED_FEATURE: u32 = 1 << 18;
+// words of hardware randomness mixed in at boot
+const HW_SEED_WORDS: usize = 4;
+// both instructions can fail when their source is drained
+const HW_RETRIES: usize = 10;
+
+// the shared generator; until it is seeded at boot, the same sequence
+// every time
+static SHARED: IRQLock<Rng> = IRQLock::new(Rng::from_splitmix(0));
+
+/// A type a uniformly distributed value of which an `Rng` can give.
+pub trait Random {
+    fn random(rng: &mut Rng) -> Self;
+}
+
+macro_rules! random_from_bits {
+    ($($t:ty),*) => {
+        $(
+            impl Random for $t {
+                fn random(rng: &mut Rng) -> Self {
+                    // the high bits are the better ones
+                    (rng.next_u64() >> (64 - <$t>::BITS)) as $t
+                }
+            }
+        )*
+    };
+}
+
+random_from_bits!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl Random for bool {
+    fn random(rng: &mut Rng) -> Self {
+        rng.next_u64() >> 63 == 1
+    }
+}
+
+impl<T: Random, const N: usize> Random for [T; N] {
+    fn random(rng: &mut Rng) -> Self {
+        core::array::from_fn(|_| T::random(rng))
+    }
+}
+
+/// A xoshiro256** pseudo-random number generator.
+#[derive(Clone)]
+pub struct Rng {
+    s: [u64; 4],
+}
+
+// the SplitMix64 step xoshiro's authors recommend for seeding: every
+// seed, even 0, gives a state that isn't all zeroes
+const fn splitmix64(x: &mut u64) -> u64 {
+    *x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl Rng {
+    const fn from_splitmix(mut seed: u64) -> Self {
+        Rng {
+            s: [
+                splitmix64(&mut seed),
+                splitmix64(&mut seed),
+                splitmix64(&mut seed),
+                splitmix64(&mut seed),
+            ],
+        }
+    }
+
+    /// A generator whose sequence depends only on `seed`.
+    pub fn seed_from_u64(seed: u64) -> Self {
+        Self::from_splitmix(seed)
+    }
+
+    /// A generator seeded from the shared one, so with the boot
+    /// entropy, for a thread or driver that wants its own.
+    pub fn from_entropy() -> Self {
+        Self::from_splitmix(next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.s;
+        let ret = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        ret
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        // the high bits are the better ones
+        (self.next_u64() >> 32) as u32
+    }
+
+    pub fn random<T: Random>(&mut self) -> T {
+        T::random(self)
+    }
+
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    /// A uniformly distributed number in `range`, which must not be
+    /// empty.
+    pub fn gen_range(&mut self, range: Range<u64>) -> u64 {
+        kassert!(range.start < range.end, "{:?}", range);
+        range.start + self.below(range.end - range.start)
+    }
+
+    // Lemire's multiply-and-reject: uniform in 0..n without a division
+    // in the common case
+    fn below(&mut self, n: u64) -> u64 {
+        let mut m = self.next_u64() as u128 * n as u128;
+        if (m as u64) < n {
+            let threshold = n.wrapping_neg() % n;
+            while (m as u64) < threshold {
+                m = self.next_u64() as u128 * n as u128;
+            }
+        }
+        (m >> 64) as u64
+    }
+
+    /// Mixes `data` into the state. Doesn't make the output any worse,
+    /// however predictable `data` is.
+    pub fn add_entropy(&mut self, data: &[u8]) {
+        for (i, chunk) in data.chunks(8).enumerate() {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            let mut x = u64::from_le_bytes(word);
+            self.s[i % 4] ^= splitmix64(&mut x);
+        }
+        if self.s == [0; 4] {
+            *self = Self::from_splitmix(0);
+        }
+        // spread what was mixed into one word across all of them
+        for _ in 0..4 {
+            self.next_u64();
+        }
+    }
+}
+
+/// A number from the shared generator.
+pub fn next_u64() -> u64 {
+    SHARED.lock().next_u64()
+}
+
+pub fn next_u32() -> u32 {
+    SHARED.lock().next_u32()
+}
+
+pub fn fill_bytes(buf: &mut [u8]) {
+    SHARED.lock().fill_bytes(buf)
+}
+
+/// A value from the shared generator, e.g. `random::<bool>()`.
+pub fn random<T: Random>() -> T {
+    SHARED.lock().random()
+}
+
+/// A uniformly distributed number in `range` from the shared
+/// generator; `range` must not be empty.
+pub fn gen_range(range: Range<u64>) -> u64 {
+    SHARED.lock().gen_range(range)
+}
+
+/// Mixes `data` into the shared generator, for drivers of entropy
+/// sources.
+pub fn add_entropy(data: &[u8]) {
+    SHARED.lock().add_entropy(data)
+}
+
+#[target_feature(enable = "rdseed")]
+unsafe fn rdseed() -> Option<u64> {
+    let mut v = 0;
+    (0..HW_RETRIES).find(|_| _rdseed64_step(&mut v) == 1)?;
+    Some(v)
+}
+
+#[target_feature(enable = "rdrand")]
+unsafe fn rdrand() -> Option<u64> {
+    let mut v = 0;
+    (0..HW_RETRIES).find(|_| _rdrand64_step(&mut v) == 1)?;
+    Some(v)
+}
+
+// a word from the CPU's hardware generator, if it has one
+fn hw_random() -> Option<u64> {
+    if __cpuid(0).eax >= 7 && __cpuid(7).ebx & RDSEED_FEATURE != 0 {
+        if let Some(v) = unsafe { rdseed() } {
+            return Some(v);
+        }
+    }
+    if __cpuid(1).ecx & RDRAND_FEATURE != 0 {
+        return unsafe { rdrand() };
+    }
+    None
+}
+
+nk_init!(
+    Core,
+    fn seed_from_boot() -> Result<()> {
+        let t = rtc::read();
+        let mut rng = SHARED.lock();
+        rng.add_entropy(&tsc::read().to_le_bytes());
+        rng.add_entropy(&[t.year as u8, t.month, t.day, t.hour, t.minute, t.second]);
+        let mut hw = 0;
+        for v in (0..HW_SEED_WORDS).map_while(|_| hw_random()) {
+            rng.add_entropy(&v.to_le_bytes());
+            hw += 1;
+        }
+        // the TSC again, after however long the RTC and CPU took
+        rng.add_entropy(&tsc::read().to_le_bytes());
+        if hw == 0 {
+            warn_print!("no hardware randomness; seeded from the clocks only");
+        }
+        Ok(())
+    }
+);
+
+kernel_test!(
+    fn xoshiro_matches_reference() {
+        let mut rng = Rng::seed_from_u64(0);
+        kassert_eq!(rng.next_u64(), 0x99ec_5f36_cb75_f2b4);
+        kassert_eq!(rng.next_u64(), 0xbf6e_1f78_4956_452a);
+        kassert_eq!(rng.next_u64(), 0x1a5f_849d_4933_e6e0);
+    }
+);
+
+kernel_test!(
+    fn ranges_are_respected() {
+        let mut rng = Rng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let v = rng.gen_range(10..13);
+            kassert!((10..13).contains(&v), "{}", v);
+        }
+        kassert_eq!(rng.gen_range(7..8), 7);
+        let mut buf = [0u8; 13];
+        rng.fill_bytes(&mut buf);
+        kassert!(buf.iter().any(|&b| b != 0));
+    }
+);
+
+kernel_test!(
+    fn random_values_use_the_high_bits() {
+        let mut a = Rng::seed_from_u64(2);
+        let mut b = a.clone();
+        let word = b.next_u64();
+        kassert_eq!(a.random::<u8>(), (word >> 56) as u8);
+        let word = b.next_u64();
+        kassert_eq!(a.random::<bool>(), word >> 63 == 1);
+        let words = [b.next_u64(), b.next_u64()];
+        kassert_eq!(a.random::<[u32; 2]>(), words.map(|w| (w >> 32) as u32));
+    }
+);
@@ -0,0 +1,131 @@
+// the C interface Rust code may call, re-exported from the private
+// bindgen output in `nk_bindings`. build.rs only generates bindings for
+// the items allowed there, and only those listed here are reachable,
+// so a new C function or type has to be added to both before any
+// module can use it - which is where it gets reviewed.
+//
+// prefer the safe wrappers (nk_alloc, nk_sched, nk_time, ...) where
+// they exist; this is for writing those.
+
+// errors
+pub use crate::nk_bindings::{EBUSY, EEXIST, EINVAL, EIO, ENODEV, ENOENT, ENOMEM, ENOSPC};
+
+// console, panic, and power
+pub use crate::nk_bindings::{
+    acpi_shutdown, nk_vc_log, nk_vc_print, nk_vc_start_chardev_console, nk_vc_stop_chardev_console,
+    panic, printk, qemu_shutdown_with_code,
+};
+
+// shell
+pub use crate::nk_bindings::shell_cmd_impl;
+
+// memory
+pub use crate::nk_bindings::{
+    kmem_find_block, kmem_free, kmem_malloc, kmem_malloc_specific, kmem_mallocz, nk_map_page,
+    page_size_t_PS_2M,
+};
+
+// CPUs and NUMA
+pub use crate::nk_bindings::{nk_get_num_cpus, nk_get_num_domains, nk_my_numa_node, smp_xcall};
+
+// locks
+pub use crate::nk_bindings::spinlock_t;
+
+// interrupts
+pub use crate::nk_bindings::{
+    apic_do_eoi, excp_entry_t, excp_vec_t, idt_find_and_reserve_range, nk_irq_is_assigned,
+    nk_mask_irq, nk_unmask_irq, register_int_handler, register_irq_handler,
+};
+
+// threads
+pub use crate::nk_bindings::{
+    nk_join, nk_sleep, nk_thread, nk_thread_exit, nk_thread_id_t, nk_thread_name, nk_thread_start,
+    nk_yield, MAX_THREAD_NAME,
+};
+
+// scheduler
+pub use crate::nk_bindings::{
+    nk_sched_aperiodic_constraints, nk_sched_constraint_type_t_APERIODIC,
+    nk_sched_constraint_type_t_PERIODIC, nk_sched_constraint_type_t_SPORADIC, nk_sched_constraints,
+    nk_sched_constraints__bindgen_ty_1, nk_sched_cpu_stats, nk_sched_get_cpu_stats,
+    nk_sched_get_realtime, nk_sched_get_thread_stats, nk_sched_map_threads,
+    nk_sched_periodic_constraints, nk_sched_sporadic_constraints,
+    nk_sched_thread_change_constraints, nk_sched_thread_stats,
+};
+
+// timers
+pub use crate::nk_bindings::{
+    nk_timer_cancel, nk_timer_create, nk_timer_destroy, nk_timer_dump_timers, nk_timer_set,
+    nk_timer_start, nk_timer_t, nk_timer_wait, NK_TIMER_CALLBACK, NK_TIMER_CALLBACK_LOCAL_SYNC,
+    NK_TIMER_WAIT_ALL,
+};
+
+// devices
+pub use crate::nk_bindings::{
+    nk_char_dev, nk_char_dev_characteristics, nk_char_dev_int, nk_char_dev_register,
+    nk_char_dev_unregister, nk_char_dev_write, nk_dev, nk_dev_find, nk_dev_int,
+    nk_dev_request_type_t_NK_DEV_REQ_NONBLOCKING, nk_dev_signal, nk_dev_type_t_NK_DEV_CHAR,
+    NK_CHARDEV_ERROR, NK_CHARDEV_READABLE, NK_CHARDEV_WRITEABLE,
+};
+
+// block devices
+pub use crate::nk_bindings::{
+    nk_block_dev, nk_block_dev_characteristics, nk_block_dev_int, nk_block_dev_register,
+    nk_block_dev_status_t, nk_block_dev_status_t_NK_BLOCK_DEV_STATUS_ERROR,
+    nk_block_dev_status_t_NK_BLOCK_DEV_STATUS_SUCCESS, nk_block_dev_unregister,
+};
+
+// network devices
+pub use crate::nk_bindings::{
+    nk_net_dev, nk_net_dev_characteristics, nk_net_dev_int, nk_net_dev_register,
+    nk_net_dev_status_t, nk_net_dev_status_t_NK_NET_DEV_STATUS_ERROR,
+    nk_net_dev_status_t_NK_NET_DEV_STATUS_SUCCESS, nk_net_dev_unregister,
+};
+
+// PCI
+pub use crate::nk_bindings::{
+    pci_dev, pci_dev_cfg_readw, pci_dev_cfg_writew, pci_dev_mask_msi_x_all,
+    pci_dev_set_msi_x_entry, pci_dev_unmask_msi_x_all, pci_dev_unmask_msi_x_entry,
+};
+
+// virtio
+pub use crate::nk_bindings::{
+    virtio_pci_ack_device, virtio_pci_common_cfg, virtio_pci_desc_chain_alloc,
+    virtio_pci_desc_chain_free, virtio_pci_dev, virtio_pci_dev_model_VIRTIO_PCI_LEGACY_MODEL,
+    virtio_pci_dev_model_VIRTIO_PCI_MODERN_MODEL, virtio_pci_int_type_VIRTIO_PCI_MSI_X_INTERRUPT,
+    virtio_pci_read_features, virtio_pci_read_regb, virtio_pci_read_regl, virtio_pci_reset_device,
+    virtio_pci_start_device, virtio_pci_virtq, virtio_pci_virtqueue_deinit,
+    virtio_pci_virtqueue_init, virtio_pci_virtqueue_notify, virtio_pci_write_features, virtq,
+    virtq_avail, virtq_desc, virtq_used, DEVICE_REGS_START_LEGACY, DEVICE_REGS_START_MSI_X,
+    ISR_STATUS, VIRTQ_DESC_F_NEXT, VIRTQ_DESC_F_WRITE,
+};
+
+// graphics
+pub use crate::nk_bindings::{
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_AND,
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_COPY,
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_DIVIDE,
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_MINUS,
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_MULTIPLY,
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_NAND,
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_NOR,
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_NOT,
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_OR,
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_PLUS,
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_XNOR,
+    nk_gpu_dev_bit_blit_op_NK_GPU_DEV_BIT_BLIT_OP_XOR, nk_gpu_dev_bit_blit_op_t,
+    nk_gpu_dev_bitmap_create, nk_gpu_dev_bitmap_destroy, nk_gpu_dev_bitmap_t, nk_gpu_dev_box_t,
+    nk_gpu_dev_coordinate_t, nk_gpu_dev_find, nk_gpu_dev_flush, nk_gpu_dev_get_available_modes,
+    nk_gpu_dev_get_mode, nk_gpu_dev_graphics_copy_box, nk_gpu_dev_graphics_draw_line,
+    nk_gpu_dev_graphics_draw_pixel, nk_gpu_dev_graphics_draw_poly,
+    nk_gpu_dev_graphics_fill_box_with_pixel, nk_gpu_dev_graphics_set_clipping_box,
+    nk_gpu_dev_graphics_set_cursor, nk_gpu_dev_graphics_set_cursor_bitmap, nk_gpu_dev_pixel_t,
+    nk_gpu_dev_set_mode, nk_gpu_dev_t, nk_gpu_dev_video_mode_NK_GPU_DEV_MODE_TYPE_GRAPHICS_2D,
+    nk_gpu_dev_video_mode_t, NK_GPU_DEV_HAS_CLIPPING, NK_GPU_DEV_HAS_MOUSE_CURSOR,
+};
+
+// keyboard and mouse
+pub use crate::nk_bindings::{
+    nk_keycode_t, nk_mouse_event_t, nk_scancode_t, nk_vc_get_keycode, nk_vc_handle_keyboard,
+    nk_vc_handle_mouse,
+};
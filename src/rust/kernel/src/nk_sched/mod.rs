@@ -0,0 +1,165 @@
+// the scheduler's accounting: per-CPU queue lengths and per-thread run
+// time and switch counts, as plain copies taken when asked for.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ffi::{c_char, c_int, c_void, CStr};
+
+use crate::nk_error::{KError, Result};
+use crate::nk_raw;
+use crate::nk_time::Duration;
+
+mod nk_shell_cmd;
+pub mod rt;
+
+extern "C" {
+    // glue.c
+    fn nk_rust_thread_tid(t: *mut nk_raw::nk_thread) -> u64;
+    fn nk_rust_thread_cpu(t: *mut nk_raw::nk_thread) -> c_int;
+    fn nk_rust_thread_is_idle(t: *mut nk_raw::nk_thread) -> c_int;
+    fn nk_rust_thread_name(t: *mut nk_raw::nk_thread) -> *const c_char;
+}
+
+// most threads `threads` lists
+const MAX_THREADS: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Constraints {
+    Aperiodic,
+    Sporadic,
+    Periodic,
+}
+
+/// One CPU's scheduler.
+#[derive(Clone, Copy, Debug)]
+pub struct CpuStats {
+    pub cpu: u32,
+    /// Periodic and sporadic threads that have not arrived yet.
+    pub pending: u64,
+    /// Periodic and sporadic threads that have arrived.
+    pub runnable: u64,
+    /// Runnable aperiodic threads.
+    pub aperiodic: u64,
+    /// Threads stolen from other CPUs.
+    pub thefts: u64,
+    pub interrupts: u64,
+    /// Timer and kick interrupts that had to be reinjected.
+    pub reinjections: u64,
+    pub current_tid: u64,
+}
+
+impl CpuStats {
+    /// Threads waiting to run.
+    pub fn queued(&self) -> u64 {
+        self.runnable + self.aperiodic
+    }
+}
+
+/// One thread, since it was created or its constraints last changed.
+#[derive(Clone, Debug)]
+pub struct ThreadStats {
+    pub tid: u64,
+    pub name: String,
+    pub cpu: u32,
+    pub idle: bool,
+    pub constraints: Constraints,
+    pub run_time: Duration,
+    /// Times it was switched to.
+    pub switches: u64,
+    pub arrivals: u64,
+    pub rescheds: u64,
+    /// Rescheds that took the scheduler's slow path.
+    pub long_rescheds: u64,
+    pub deadline_misses: u64,
+    pub missed_time: Duration,
+}
+
+pub fn num_cpus() -> u32 {
+    unsafe { nk_raw::nk_get_num_cpus() }
+}
+
+pub fn cpu_stats(cpu: u32) -> Result<CpuStats> {
+    let mut s = nk_raw::nk_sched_cpu_stats::default();
+    KError::from_ret(unsafe { nk_raw::nk_sched_get_cpu_stats(cpu as c_int, &mut s) })?;
+    Ok(CpuStats {
+        cpu,
+        pending: s.pending,
+        runnable: s.runnable,
+        aperiodic: s.aperiodic,
+        thefts: s.num_thefts,
+        interrupts: s.interrupt_count,
+        reinjections: s.reinject_count,
+        current_tid: s.current_tid,
+    })
+}
+
+// a thread as copied while the scheduler's thread list is locked
+struct RawThread {
+    tid: u64,
+    cpu: c_int,
+    idle: bool,
+    name: [u8; nk_raw::MAX_THREAD_NAME as usize],
+    stats: nk_raw::nk_sched_thread_stats,
+}
+
+// with the list locked, so it mustn't allocate or keep `t`
+unsafe extern "C" fn collect_thread(t: *mut nk_raw::nk_thread, state: *mut c_void) {
+    let threads = unsafe { &mut *(state as *mut Vec<RawThread>) };
+    if threads.len() == threads.capacity() {
+        return;
+    }
+    let mut raw = RawThread {
+        tid: unsafe { nk_rust_thread_tid(t) },
+        cpu: unsafe { nk_rust_thread_cpu(t) },
+        idle: unsafe { nk_rust_thread_is_idle(t) } != 0,
+        name: [0; nk_raw::MAX_THREAD_NAME as usize],
+        stats: Default::default(),
+    };
+    // nul-terminated within `MAX_THREAD_NAME`
+    let name = unsafe { CStr::from_ptr(nk_rust_thread_name(t)) }.to_bytes();
+    let len = name.len().min(raw.name.len());
+    raw.name[..len].copy_from_slice(&name[..len]);
+    unsafe { nk_raw::nk_sched_get_thread_stats(t, &mut raw.stats) };
+    threads.push(raw);
+}
+
+impl From<&RawThread> for ThreadStats {
+    fn from(t: &RawThread) -> Self {
+        let name = t.name.split(|&b| b == 0).next().unwrap_or(&[]);
+        let s = &t.stats;
+        ThreadStats {
+            tid: t.tid,
+            name: String::from_utf8_lossy(name).into_owned(),
+            cpu: t.cpu as u32,
+            idle: t.idle,
+            constraints: match s.type_ {
+                nk_raw::nk_sched_constraint_type_t_PERIODIC => Constraints::Periodic,
+                nk_raw::nk_sched_constraint_type_t_SPORADIC => Constraints::Sporadic,
+                _ => Constraints::Aperiodic,
+            },
+            run_time: Duration::from_nanos(s.run_time),
+            switches: s.switch_in_count,
+            arrivals: s.arrival_count,
+            rescheds: s.resched_count,
+            long_rescheds: s.resched_long_count,
+            deadline_misses: s.miss_count,
+            missed_time: Duration::from_nanos(s.miss_time_sum),
+        }
+    }
+}
+
+/// Threads on `cpu`, or on every CPU, up to 256 of them.
+pub fn threads(cpu: Option<u32>) -> Result<Vec<ThreadStats>> {
+    let mut raw: Vec<RawThread> = Vec::new();
+    raw.try_reserve_exact(MAX_THREADS)
+        .map_err(|_| KError::NO_MEM)?;
+    let cpu = cpu.map_or(-1, |c| c as c_int);
+    unsafe {
+        nk_raw::nk_sched_map_threads(
+            cpu,
+            Some(collect_thread),
+            &mut raw as *mut Vec<RawThread> as *mut c_void,
+        )
+    };
+    Ok(raw.iter().map(ThreadStats::from).collect())
+}
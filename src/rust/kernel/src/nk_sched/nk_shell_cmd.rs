@@ -0,0 +1,74 @@
+use alloc::format;
+use core::ffi::{c_char, c_int, c_void, CStr};
+use core::fmt::Write;
+
+use super::{cpu_stats, num_cpus, threads};
+use crate::utils::VcWriter;
+
+// `rust_sched` prints every CPU's queues and every thread's accounting,
+// `rust_sched <cpu>` those of one CPU
+#[no_mangle]
+pub unsafe extern "C" fn rust_sched_shell_entry(
+    buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let cpu = match line.split_whitespace().nth(1).map(str::parse::<u32>) {
+        None => None,
+        Some(Ok(cpu)) if cpu < num_cpus() => Some(cpu),
+        Some(_) => {
+            error_print!("usage: rust_sched [cpu]");
+            return 0;
+        }
+    };
+
+    let mut w = VcWriter::new();
+    let _ = writeln!(
+        w,
+        "cpu {:>8} {:>8} {:>8} {:>8} {:>10} {:>8}",
+        "runnable", "aperiod", "pending", "thefts", "interrupts", "current"
+    );
+    for c in (0..num_cpus()).filter(|&c| cpu.is_none() || cpu == Some(c)) {
+        match cpu_stats(c) {
+            Ok(s) => {
+                let _ = writeln!(
+                    w,
+                    "{:>3} {:>8} {:>8} {:>8} {:>8} {:>10} {:>8}",
+                    c, s.runnable, s.aperiodic, s.pending, s.thefts, s.interrupts, s.current_tid
+                );
+            }
+            Err(e) => error_print!("cpu {}: {}", c, e),
+        }
+    }
+
+    let threads = match threads(cpu) {
+        Ok(t) => t,
+        Err(e) => {
+            error_print!("cannot list threads: {}", e);
+            return 0;
+        }
+    };
+    let _ = writeln!(
+        w,
+        "\n{:>6} {:>3} {:<20} {:>10} {:>12} {:>8} {:>8}",
+        "tid", "cpu", "name", "type", "run ms", "switches", "misses"
+    );
+    for t in &threads {
+        let name = if t.idle { "(idle)" } else { t.name.as_str() };
+        let _ = writeln!(
+            w,
+            "{:>6} {:>3} {:<20} {:>10} {:>12} {:>8} {:>8}",
+            t.tid,
+            t.cpu,
+            name,
+            // `{:?}` ignores width
+            format!("{:?}", t.constraints),
+            t.run_time.as_millis(),
+            t.switches,
+            t.deadline_misses
+        );
+    }
+
+    0
+}
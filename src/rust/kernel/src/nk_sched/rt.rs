@@ -0,0 +1,288 @@
+// real-time scheduling: the constraints a thread runs under (aperiodic
+// with a priority, periodic with a slice of every period, or sporadic
+// with one burst before a deadline), built and checked here before the
+// scheduler's admission control sees them.
+//
+// only threads can carry constraints; there are no async tasks to
+// attach them to yet.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::ffi::c_void;
+use core::mem::ManuallyDrop;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::nk_error::{KError, Result};
+use crate::nk_raw;
+use crate::nk_time::units::saturating_nanos as nanos;
+use crate::nk_time::Duration;
+
+extern "C" {
+    // glue.c
+    fn nk_rust_thread_detach(t: nk_raw::nk_thread_id_t);
+}
+
+const RT_STACK_SIZE: u64 = 64 * 1024;
+// the utilization unit of `nk_sched_config`
+const PPM: u128 = 1_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Runs when no real-time thread needs to; lower numbers first.
+    Aperiodic { priority: u64 },
+    /// Arrives every `period`, and runs for `slice` of it.
+    Periodic {
+        phase: Duration,
+        period: Duration,
+        slice: Duration,
+    },
+    /// Arrives once, runs for `size` before `deadline`, and is then
+    /// aperiodic with `priority`.
+    Sporadic {
+        phase: Duration,
+        size: Duration,
+        deadline: Duration,
+        priority: u64,
+    },
+}
+
+/// How a thread is scheduled: built with `aperiodic`, `periodic` or
+/// `sporadic`, then adjusted with the other methods.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RtConstraints {
+    kind: Kind,
+    interrupt_priority_class: u8,
+}
+
+impl RtConstraints {
+    pub fn aperiodic(priority: u64) -> Self {
+        RtConstraints {
+            kind: Kind::Aperiodic { priority },
+            interrupt_priority_class: 0,
+        }
+    }
+
+    pub fn periodic(period: Duration, slice: Duration) -> Self {
+        RtConstraints {
+            kind: Kind::Periodic {
+                phase: Duration::ZERO,
+                period,
+                slice,
+            },
+            interrupt_priority_class: 0,
+        }
+    }
+
+    pub fn sporadic(size: Duration, deadline: Duration, then_priority: u64) -> Self {
+        RtConstraints {
+            kind: Kind::Sporadic {
+                phase: Duration::ZERO,
+                size,
+                deadline,
+                priority: then_priority,
+            },
+            interrupt_priority_class: 0,
+        }
+    }
+
+    /// Delays the first arrival by `phase` after admission. Aperiodic
+    /// threads have no arrivals, and ignore it.
+    pub fn phase(mut self, phase: Duration) -> Self {
+        match &mut self.kind {
+            Kind::Periodic { phase: p, .. } | Kind::Sporadic { phase: p, .. } => *p = phase,
+            Kind::Aperiodic { .. } => {}
+        }
+        self
+    }
+
+    /// Only interrupts above `class` (0 to 0xf) preempt the thread.
+    /// Ignored when interrupts run in threads.
+    pub fn interrupt_priority_class(mut self, class: u8) -> Self {
+        self.interrupt_priority_class = class;
+        self
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// The share of a CPU the constraints reserve, in millionths, as
+    /// admission control counts it.
+    pub fn utilization_ppm(&self) -> u64 {
+        let (run, per) = match self.kind {
+            Kind::Aperiodic { .. } => return 0,
+            Kind::Periodic { period, slice, .. } => (slice, period),
+            Kind::Sporadic { size, deadline, .. } => (size, deadline),
+        };
+        let ppm = run.as_nanos() * PPM / per.as_nanos().max(1);
+        u64::try_from(ppm).unwrap_or(u64::MAX)
+    }
+
+    /// Rejects constraints no scheduler could meet, before asking.
+    pub fn validate(&self) -> Result<()> {
+        let ok = match self.kind {
+            Kind::Aperiodic { .. } => true,
+            Kind::Periodic { period, slice, .. } => !slice.is_zero() && slice <= period,
+            Kind::Sporadic { size, deadline, .. } => !size.is_zero() && size <= deadline,
+        };
+        if ok && self.interrupt_priority_class <= 0xf {
+            Ok(())
+        } else {
+            Err(KError::INVALID_ARG)
+        }
+    }
+
+    fn to_raw(self) -> nk_raw::nk_sched_constraints {
+        let (type_, anon) = match self.kind {
+            Kind::Aperiodic { priority } => (
+                nk_raw::nk_sched_constraint_type_t_APERIODIC,
+                nk_raw::nk_sched_constraints__bindgen_ty_1 {
+                    aperiodic: nk_raw::nk_sched_aperiodic_constraints { priority },
+                },
+            ),
+            Kind::Periodic {
+                phase,
+                period,
+                slice,
+            } => (
+                nk_raw::nk_sched_constraint_type_t_PERIODIC,
+                nk_raw::nk_sched_constraints__bindgen_ty_1 {
+                    periodic: nk_raw::nk_sched_periodic_constraints {
+                        phase: nanos(phase),
+                        period: nanos(period),
+                        slice: nanos(slice),
+                    },
+                },
+            ),
+            Kind::Sporadic {
+                phase,
+                size,
+                deadline,
+                priority,
+            } => (
+                nk_raw::nk_sched_constraint_type_t_SPORADIC,
+                nk_raw::nk_sched_constraints__bindgen_ty_1 {
+                    sporadic: nk_raw::nk_sched_sporadic_constraints {
+                        phase: nanos(phase),
+                        size: nanos(size),
+                        deadline: nanos(deadline),
+                        aperiodic_priority: priority,
+                    },
+                },
+            ),
+        };
+        nk_raw::nk_sched_constraints {
+            type_,
+            interrupt_priority_class: self.interrupt_priority_class,
+            __bindgen_anon_1: anon,
+        }
+    }
+}
+
+/// Puts the calling thread under `c`. `NOT_ADMITTED` if the scheduler
+/// can't fit it on this CPU, and the thread keeps running aperiodic.
+pub fn set_current(c: RtConstraints) -> Result<()> {
+    c.validate()?;
+    let mut raw = c.to_raw();
+    if unsafe { nk_raw::nk_sched_thread_change_constraints(&mut raw) } != 0 {
+        warn_print!(
+            "{:?} not admitted ({} ppm of a CPU)",
+            c.kind,
+            c.utilization_ppm()
+        );
+        return Err(KError::NOT_ADMITTED);
+    }
+    Ok(())
+}
+
+const PENDING: u8 = 0;
+const ADMITTED: u8 = 1;
+const REJECTED: u8 = 2;
+
+struct Start {
+    constraints: RtConstraints,
+    f: Box<dyn FnOnce() + Send>,
+    admission: Arc<AtomicU8>,
+}
+
+unsafe extern "C" fn rt_thread_entry(input: *mut c_void, _output: *mut *mut c_void) {
+    // leaked by `RtThread::spawn` for us
+    let start = unsafe { Box::from_raw(input as *mut Start) };
+    let admitted = set_current(start.constraints).is_ok();
+    let state = if admitted { ADMITTED } else { REJECTED };
+    start.admission.store(state, Ordering::Release);
+    if admitted {
+        (start.f)();
+    }
+}
+
+/// A thread that runs under real-time constraints from its first
+/// instruction of user code on. Dropping it without joining detaches
+/// the thread, which NK then reaps once it exits.
+pub struct RtThread {
+    tid: nk_raw::nk_thread_id_t,
+}
+
+impl RtThread {
+    /// Starts `f` on a new thread bound to `cpu`, once the thread has
+    /// been admitted under `constraints`. If it isn't, `f` never runs,
+    /// the thread exits, and this returns `NOT_ADMITTED`.
+    pub fn spawn<F>(cpu: u32, constraints: RtConstraints, f: F) -> Result<RtThread>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        constraints.validate()?;
+        let admission = Arc::new(AtomicU8::new(PENDING));
+        let start = Box::into_raw(Box::new(Start {
+            constraints,
+            f: Box::new(f),
+            admission: admission.clone(),
+        }));
+
+        let mut tid = null_mut();
+        let r = unsafe {
+            nk_raw::nk_thread_start(
+                Some(rt_thread_entry),
+                start as *mut c_void,
+                null_mut(),
+                0,
+                RT_STACK_SIZE,
+                &mut tid,
+                cpu as i32,
+            )
+        };
+        if let Err(e) = KError::from_ret(r) {
+            // the thread never started, so `start` is still ours
+            drop(unsafe { Box::from_raw(start) });
+            return Err(e);
+        }
+
+        let thread = RtThread { tid };
+        loop {
+            match admission.load(Ordering::Acquire) {
+                PENDING => unsafe { nk_raw::nk_yield() },
+                ADMITTED => return Ok(thread),
+                _ => {
+                    thread.join();
+                    return Err(KError::NOT_ADMITTED);
+                }
+            }
+        }
+    }
+
+    /// Waits for the thread to exit.
+    pub fn join(self) {
+        // `nk_join` detaches the thread itself
+        let thread = ManuallyDrop::new(self);
+        unsafe { nk_raw::nk_join(thread.tid, null_mut()) };
+    }
+}
+
+impl Drop for RtThread {
+    fn drop(&mut self) {
+        // not `Send`, so this runs on the thread that started it, which is
+        // the only one that may take it off its list of children
+        unsafe { nk_rust_thread_detach(self.tid) };
+    }
+}
@@ -2,7 +2,7 @@ use core::ffi::{c_char, c_int, c_void};
 
 use crate::{
     example::example::nk_rust_example,
-    nk_bindings,
+    nk_raw,
     utils::{print_to_vc, to_c_string},
 };
 
@@ -25,7 +25,7 @@ pub fn register_nk_shell_cmd(
 ) {
     let cmd_c = to_c_string(name);
     let help_c = to_c_string(help);
-    let _cmd_impl = nk_bindings::shell_cmd_impl {
+    let _cmd_impl = nk_raw::shell_cmd_impl {
         cmd: cmd_c,
         help_str: help_c,
         handler: Some(func),
@@ -0,0 +1,121 @@
+// which CPU we are on, and running code on another one.
+//
+// `call_on` is NK's `smp_xcall`: the closure runs on the target CPU in
+// its xcall interrupt handler, with interrupts off, while the caller
+// spins until it is done. Each CPU has room for one xcall at a time, so
+// a call to a CPU that is already taking one retries until it is free.
+
+use core::ffi::{c_int, c_void};
+
+use x86_64::instructions::interrupts;
+
+use crate::nk_error::{KError, Result};
+use crate::nk_raw;
+use crate::nk_time::{timeout::spin_with_timeout, Duration};
+
+pub use crate::nk_sched::num_cpus;
+pub use stop::run_on_all_cpus;
+
+mod stop;
+
+// wrappers around inline functions and macros in glue.c
+extern "C" {
+    fn nk_rust_cpu_state_get_cpu() -> *mut c_void;
+    fn nk_rust_my_cpu_id() -> c_int;
+    fn nk_rust_preempt_disable();
+    fn nk_rust_preempt_enable();
+    fn nk_rust_xcall_pending(cpu: c_int) -> c_int;
+}
+
+// how long to keep retrying a CPU whose xcall slot is taken
+const XCALL_BUSY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The CPU this runs on: 0, the boot CPU, before per-CPU state is set
+/// up. Unless preemption or interrupts are off, the thread may have
+/// moved by the time the caller looks at it.
+pub fn current_cpu() -> u32 {
+    unsafe {
+        if nk_rust_cpu_state_get_cpu().is_null() {
+            return 0;
+        }
+        nk_rust_my_cpu_id() as u32
+    }
+}
+
+// a call in flight: the closure until it has run, then what it returned
+struct Call<F, R> {
+    f: Option<F>,
+    ret: Option<R>,
+}
+
+unsafe extern "C" fn run_call<F: FnOnce() -> R, R>(arg: *mut c_void) {
+    // the caller waits for this to return, so `arg` is still its `Call`
+    let call = unsafe { &mut *(arg as *mut Call<F, R>) };
+    if let Some(f) = call.f.take() {
+        call.ret = Some(f());
+    }
+}
+
+/// Runs `f` on `cpu` and waits for it, returning what it returned. On
+/// the current CPU, it runs directly. `f` runs in interrupt context
+/// with interrupts off, so it must not sleep, block, or allocate.
+///
+/// A CPU spinning with interrupts off can't take another's call, so
+/// calling another CPU from interrupt context, or with interrupts off,
+/// could deadlock and is a bug.
+pub fn call_on<F, R>(cpu: u32, f: F) -> Result<R>
+where
+    F: FnOnce() -> R + Send,
+    R: Send,
+{
+    if cpu >= num_cpus() {
+        return Err(KError::INVALID_ARG);
+    }
+    let mut call = Call {
+        f: Some(f),
+        ret: None,
+    };
+    let arg = &mut call as *mut Call<F, R> as *mut c_void;
+    if cpu != current_cpu() {
+        kassert!(interrupts::are_enabled(), "call to cpu {}", cpu);
+    }
+    xcall(cpu, run_call::<F, R>, arg, true)?;
+    call.ret.ok_or(KError::FAILED)
+}
+
+// sends `fun(arg)` to `cpu`, retrying while its xcall slot is taken.
+// `smp_xcall` prints an error every time it finds the slot taken, so
+// this waits for the slot to look free before each try; only losing a
+// race for it with another sender costs an error line.
+fn xcall(
+    cpu: u32,
+    fun: unsafe extern "C" fn(*mut c_void),
+    arg: *mut c_void,
+    wait: bool,
+) -> Result<()> {
+    spin_with_timeout(
+        || unsafe {
+            nk_rust_xcall_pending(cpu as c_int) == 0
+                && nk_raw::smp_xcall(cpu, Some(fun), arg, wait as u8) == 0
+        },
+        XCALL_BUSY_TIMEOUT,
+    )
+}
+
+// sends `fun(arg)` to `cpu` without waiting for it, if its xcall slot
+// is free; for interrupt context, where retrying could wait on a CPU
+// that is itself waiting for this one
+pub(crate) fn try_send(cpu: u32, fun: unsafe extern "C" fn(*mut c_void), arg: *mut c_void) -> bool {
+    unsafe {
+        nk_rust_xcall_pending(cpu as c_int) == 0 && nk_raw::smp_xcall(cpu, Some(fun), arg, 0) == 0
+    }
+}
+
+kernel_test!(
+    fn calls_run_on_their_cpu() {
+        for cpu in 0..num_cpus() {
+            kassert_eq!(call_on(cpu, current_cpu), Ok(cpu));
+        }
+        kassert_eq!(call_on(num_cpus(), || ()), Err(KError::INVALID_ARG));
+    }
+);
@@ -0,0 +1,142 @@
+// running a closure on every CPU at once, with all of them held until
+// each is done, so that none of them can be in the middle of using
+// whatever the closure changes.
+//
+// every other CPU gets an xcall that doesn't wait, in which it checks
+// in and spins with interrupts off; once all have, the caller turns
+// its own interrupts off and lets them go. each runs the closure, then
+// waits for the rest before returning to what it was doing.
+
+use core::ffi::c_void;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use x86_64::instructions::interrupts;
+
+use super::{current_cpu, nk_rust_preempt_disable, nk_rust_preempt_enable, num_cpus, xcall};
+use crate::nk_error::Result;
+
+// phases of a stop, set by the CPU that started it
+const GATHER: u8 = 0;
+const RUN: u8 = 1;
+// not every CPU could be sent its xcall; those that were return at once
+const ABORT: u8 = 2;
+
+// a CPU spinning in a stop can't take the xcall of a second one, so
+// only one runs at a time. it's taken with interrupts on, so that its
+// waiters can still be stopped by its holder.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+struct Stop<F> {
+    f: F,
+    phase: AtomicU8,
+    // CPUs that have checked in, that have run `f`, and that are done
+    // with this `Stop`
+    arrived: AtomicU32,
+    done: AtomicU32,
+    left: AtomicU32,
+    cpus: u32,
+}
+
+impl<F: Fn() + Sync> Stop<F> {
+    fn wait_for(count: &AtomicU32, n: u32) {
+        while count.load(Ordering::Acquire) < n {
+            spin_loop();
+        }
+    }
+
+    fn run(&self) {
+        (self.f)();
+        self.done.fetch_add(1, Ordering::AcqRel);
+        Self::wait_for(&self.done, self.cpus);
+    }
+}
+
+unsafe extern "C" fn stopped_cpu<F: Fn() + Sync>(arg: *mut c_void) {
+    // the CPU that started the stop waits for every CPU it sent this to
+    // to leave before `stop` goes away
+    let stop = unsafe { &*(arg as *const Stop<F>) };
+    stop.arrived.fetch_add(1, Ordering::AcqRel);
+    let phase = loop {
+        match stop.phase.load(Ordering::Acquire) {
+            GATHER => spin_loop(),
+            phase => break phase,
+        }
+    };
+    if phase == RUN {
+        stop.run();
+    }
+    stop.left.fetch_add(1, Ordering::Release);
+}
+
+/// Runs `f` on every CPU at the same time, with interrupts off, and
+/// returns once all of them have. No CPU runs anything else from when
+/// the first starts `f` to when the last finishes it, so `f` can
+/// change state every CPU reads without locks, such as a global log
+/// sink or a shared device table.
+///
+/// `f` must be short, and must not sleep, block, allocate, or start
+/// another stop. As with `call_on`, this must be called with
+/// interrupts on. If not every CPU can be reached, `f` runs nowhere.
+pub fn run_on_all_cpus<F: Fn() + Sync>(f: F) -> Result<()> {
+    kassert!(interrupts::are_enabled());
+    while STOPPING
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        spin_loop();
+    }
+    // the starting CPU must stay put while it picks the others
+    unsafe { nk_rust_preempt_disable() };
+
+    let stop = Stop {
+        f,
+        phase: AtomicU8::new(GATHER),
+        arrived: AtomicU32::new(0),
+        done: AtomicU32::new(0),
+        left: AtomicU32::new(0),
+        cpus: num_cpus(),
+    };
+    let arg = &stop as *const Stop<F> as *mut c_void;
+    let me = current_cpu();
+    let mut sent = 0;
+    let mut ret = Ok(());
+    for cpu in (0..stop.cpus).filter(|&c| c != me) {
+        ret = xcall(cpu, stopped_cpu::<F>, arg, false);
+        if ret.is_err() {
+            break;
+        }
+        sent += 1;
+    }
+    Stop::<F>::wait_for(&stop.arrived, sent);
+    match ret {
+        Ok(()) => interrupts::without_interrupts(|| {
+            stop.phase.store(RUN, Ordering::Release);
+            stop.run();
+        }),
+        Err(e) => {
+            warn_print!("could not stop every cpu: {}", e);
+            stop.phase.store(ABORT, Ordering::Release);
+        }
+    }
+    // `stop` lives on this stack, so every CPU sent the xcall must be
+    // done with it before this returns
+    Stop::<F>::wait_for(&stop.left, sent);
+
+    unsafe { nk_rust_preempt_enable() };
+    STOPPING.store(false, Ordering::Release);
+    ret
+}
+
+kernel_test!(
+    fn every_cpu_runs_once() {
+        let ran = AtomicU32::new(0);
+        kassert_eq!(
+            run_on_all_cpus(|| {
+                ran.fetch_add(1, Ordering::Relaxed);
+            }),
+            Ok(())
+        );
+        kassert_eq!(ran.load(Ordering::Relaxed), num_cpus());
+    }
+);
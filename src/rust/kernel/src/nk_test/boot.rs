@@ -0,0 +1,55 @@
+// running the tests at the end of boot (RUST_BOOT_TESTS), and telling
+// whoever started QEMU how they went (RUST_BOOT_TESTS_QEMU_EXIT).
+
+use core::ffi::c_void;
+
+use super::run;
+use crate::nk_raw;
+
+/// What QEMU is told through its isa-debug-exit device; it exits with
+/// `(code << 1) | 1`. The codes are those of the C test harness, so
+/// scripts can treat both alike.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u16)]
+pub enum QemuExit {
+    Passed = 0x31,
+    Failed = 0xff,
+}
+
+/// Exits QEMU with `code`. QEMU must have been started with `-device
+/// isa-debug-exit`; without it, this CPU just halts.
+pub fn exit_qemu(code: QemuExit) -> ! {
+    unsafe { nk_raw::qemu_shutdown_with_code(code as u16) }
+}
+
+/// Runs every kernel test, logging each result, then exits QEMU with
+/// the outcome if that is configured. As the tests block, boot starts
+/// this as a thread of its own.
+#[no_mangle]
+pub extern "C" fn nk_rust_run_boot_tests(_input: *mut c_void, _output: *mut *mut c_void) {
+    info_print!("running kernel tests");
+    let r = run("", |r| {
+        if r.passed() {
+            info_print!("{}", r);
+        } else {
+            error_print!("{}", r);
+        }
+    });
+    let passed = match r {
+        Ok(s) => {
+            info_print!("{} passed, {} failed", s.passed, s.failed);
+            s.failed == 0
+        }
+        Err(e) => {
+            error_print!("cannot run tests: {}", e);
+            false
+        }
+    };
+    if nk_config_enabled!(RUST_BOOT_TESTS_QEMU_EXIT) {
+        exit_qemu(if passed {
+            QemuExit::Passed
+        } else {
+            QemuExit::Failed
+        });
+    }
+}
@@ -0,0 +1,73 @@
+// what the tests of the device wrappers (`nk_blkdev`, `nk_netdev`, and
+// `nk_chardev`) share: a driver as NK hands it back to their callbacks,
+// and a request's callback as a caller passes it
+
+use alloc::ffi::CString;
+use alloc::sync::Arc;
+use core::cell::Cell;
+use core::ffi::{c_char, c_void};
+
+use crate::nk_lock::IRQLock;
+use crate::nk_raw;
+
+/// A driver, shared as `register` shares it with NK.
+pub struct Driver<T> {
+    pub driver: Arc<IRQLock<T>>,
+}
+
+impl<T> Driver<T> {
+    pub fn new(driver: T) -> Self {
+        Driver {
+            driver: Arc::new(IRQLock::new(driver)),
+        }
+    }
+
+    /// What NK passes the wrapper's callbacks.
+    pub fn state(&self) -> *mut c_void {
+        Arc::as_ptr(&self.driver) as *mut c_void
+    }
+
+    /// How many references there are besides the test's, e.g. a
+    /// registration's.
+    pub fn shared(&self) -> usize {
+        Arc::strong_count(&self.driver) - 1
+    }
+}
+
+/// Whether NK has a device named `name`.
+pub fn registered(name: &str) -> bool {
+    let name = CString::new(name).unwrap();
+    // not actually mutable, but C code had no `const` qualifier
+    !unsafe { nk_raw::nk_dev_find(name.as_ptr() as *mut c_char) }.is_null()
+}
+
+/// Where a request's status lands once it completes.
+pub struct Done<S> {
+    status: Cell<Option<S>>,
+}
+
+impl<S> Default for Done<S> {
+    fn default() -> Self {
+        Done {
+            status: Cell::new(None),
+        }
+    }
+}
+
+impl<S: Copy> Done<S> {
+    /// The callback and context to pass with a request.
+    pub fn callback(&self) -> (Option<unsafe extern "C" fn(S, *mut c_void)>, *mut c_void) {
+        let context = &self.status as *const Cell<Option<S>> as *mut c_void;
+        (Some(record::<S>), context)
+    }
+
+    /// The status, if the request completed since the last `take`.
+    pub fn take(&self) -> Option<S> {
+        self.status.take()
+    }
+}
+
+unsafe extern "C" fn record<S: Copy>(status: S, context: *mut c_void) {
+    // passed by `Done::callback`, which outlives the request
+    unsafe { (*(context as *const Cell<Option<S>>)).set(Some(status)) };
+}
@@ -0,0 +1,290 @@
+// in-kernel tests: `kernel_test!` wraps a function and puts a pointer
+// to it in the `.rust_tests` link section (see link/nautilus.ld), the
+// way `nk_register_shell_cmd` collects shell commands. `rust_test` runs
+// them, each on a thread of its own so that a panic only ends the test
+// and not the kernel, and so can the end of boot, for automated runs
+// under QEMU.
+
+use core::ffi::c_void;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use crate::nk_error::{KError, Result};
+use crate::nk_log::module_name;
+use crate::nk_raw;
+use crate::nk_time::{Duration, Instant};
+
+mod boot;
+pub(crate) mod fixture;
+mod nk_shell_cmd;
+
+pub use boot::{exit_qemu, QemuExit};
+
+extern "C" {
+    // link/nautilus.ld
+    static __start_rust_tests: [u8; 0];
+    static __stop_rust_tests: [u8; 0];
+    // glue.c
+    fn nk_rust_get_cur_thread() -> *mut nk_raw::nk_thread;
+}
+
+// tests run on the stack of a fresh thread, and debug builds are
+// generous with it
+const TEST_STACK_SIZE: u64 = 1 << 20;
+const PANIC_MSG_LEN: usize = 256;
+
+/// A test, as collected by `kernel_test!`.
+pub struct KernelTest {
+    pub module_path: &'static str,
+    pub name: &'static str,
+    pub run: fn() -> Result<()>,
+}
+
+impl KernelTest {
+    /// The module that declared the test, as used by `rust_log`.
+    pub fn module(&self) -> &'static str {
+        module_name(self.module_path)
+    }
+}
+
+/// Every test linked into the kernel.
+pub fn tests() -> &'static [&'static KernelTest] {
+    // the linker puts nothing but `kernel_test!`'s pointers between
+    // the two symbols
+    unsafe {
+        let start = __start_rust_tests.as_ptr() as *const &'static KernelTest;
+        let stop = __stop_rust_tests.as_ptr() as *const &'static KernelTest;
+        core::slice::from_raw_parts(start, stop.offset_from(start) as usize)
+    }
+}
+
+/// A panic message, cut short to fit without allocating.
+pub struct PanicMessage {
+    buf: [u8; PANIC_MSG_LEN],
+    len: usize,
+}
+
+impl PanicMessage {
+    const fn new() -> Self {
+        PanicMessage {
+            buf: [0; PANIC_MSG_LEN],
+            len: 0,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole `str`s are written
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl Write for PanicMessage {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(PANIC_MSG_LEN - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+// `Panicked` is built in the panic handler, which mustn't allocate
+#[allow(clippy::large_enum_variant)]
+pub enum Outcome {
+    Passed,
+    Failed(KError),
+    Panicked(PanicMessage),
+}
+
+/// How one test went.
+pub struct TestResult {
+    pub test: &'static KernelTest,
+    pub outcome: Outcome,
+    pub time: Duration,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, Outcome::Passed)
+    }
+}
+
+impl fmt::Display for TestResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{} ... ", self.test.module(), self.test.name)?;
+        match &self.outcome {
+            Outcome::Passed => write!(f, "ok")?,
+            Outcome::Failed(e) => write!(f, "FAILED: {}", e)?,
+            Outcome::Panicked(msg) => write!(f, "PANICKED: {}", msg.as_str())?,
+        }
+        write!(f, " ({} us)", self.time.as_micros())
+    }
+}
+
+/// How many tests a `run` ran, and how many of those failed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Summary {
+    pub passed: u32,
+    pub failed: u32,
+}
+
+// the test being run, shared by the runner, the test's thread, and the
+// panic handler
+struct Run {
+    test: &'static KernelTest,
+    thread: AtomicPtr<nk_raw::nk_thread>,
+    outcome: Outcome,
+}
+
+static CURRENT: AtomicPtr<Run> = AtomicPtr::new(null_mut());
+// one `run` at a time, since there is one `CURRENT`
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" fn test_thread(input: *mut c_void, _output: *mut *mut c_void) {
+    // the runner joins us before `input` goes away
+    let run = unsafe { &mut *(input as *mut Run) };
+    run.thread
+        .store(unsafe { nk_rust_get_cur_thread() }, Ordering::SeqCst);
+    run.outcome = match (run.test.run)() {
+        Ok(()) => Outcome::Passed,
+        Err(e) => Outcome::Failed(e),
+    };
+}
+
+/// Called first thing on every panic. If a test's thread panicked,
+/// records the message as the test's outcome and ends the thread;
+/// otherwise returns, and the kernel panics as usual.
+///
+/// Locks the test held stay held, so a test that panics with a lock
+/// other code needs can still hang the kernel later.
+pub(crate) fn contain_panic(info: &PanicInfo) {
+    let run = CURRENT.load(Ordering::SeqCst);
+    if run.is_null() {
+        return;
+    }
+    // `CURRENT` is only set while the runner waits for the test
+    let run = unsafe { &mut *run };
+    if run.thread.load(Ordering::SeqCst) != unsafe { nk_rust_get_cur_thread() } {
+        return;
+    }
+    let mut msg = PanicMessage::new();
+    let _ = write!(msg, "{}", info);
+    run.outcome = Outcome::Panicked(msg);
+    unsafe { nk_raw::nk_thread_exit(null_mut()) };
+}
+
+/// Runs `test` on a thread of its own and waits for it.
+fn run_one(test: &'static KernelTest) -> Result<TestResult> {
+    let mut run = Run {
+        test,
+        thread: AtomicPtr::new(null_mut()),
+        outcome: Outcome::Passed,
+    };
+    CURRENT.store(&mut run, Ordering::SeqCst);
+
+    let start = Instant::now();
+    let mut tid = null_mut();
+    let r = unsafe {
+        nk_raw::nk_thread_start(
+            Some(test_thread),
+            &mut run as *mut Run as *mut c_void,
+            null_mut(),
+            0,
+            TEST_STACK_SIZE,
+            &mut tid,
+            -1,
+        )
+    };
+    if r == 0 {
+        unsafe { nk_raw::nk_join(tid, null_mut()) };
+    }
+    let time = start.elapsed();
+
+    CURRENT.store(null_mut(), Ordering::SeqCst);
+    KError::from_ret(r)?;
+    Ok(TestResult {
+        test,
+        outcome: run.outcome,
+        time,
+    })
+}
+
+/// Runs every test whose `module::name` contains `filter`, one at a
+/// time, handing each result to `report` as it comes. Stops at the
+/// first test that can't be started. `BUSY` if tests are already
+/// running.
+pub fn run(filter: &str, mut report: impl FnMut(&TestResult)) -> Result<Summary> {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return Err(KError::BUSY);
+    }
+    let mut summary = Summary::default();
+    let mut result = Ok(());
+    for &test in tests() {
+        let matches = test.module_path.contains(filter)
+            || test.name.contains(filter)
+            || filter
+                .split_once("::")
+                .is_some_and(|(m, n)| test.module() == m && test.name.contains(n));
+        if !matches {
+            continue;
+        }
+        match run_one(test) {
+            Ok(r) => {
+                if r.passed() {
+                    summary.passed += 1;
+                } else {
+                    summary.failed += 1;
+                }
+                report(&r);
+            }
+            Err(e) => {
+                result = Err(e);
+                break;
+            }
+        }
+    }
+    RUNNING.store(false, Ordering::SeqCst);
+    result.map(|_| summary)
+}
+
+/// Declares a kernel test, run by `rust_test`.
+///
+/// The test fails if it panics (so `assert!` and `kassert!` work) or
+/// if it returns an error:
+///
+/// ```ignore
+/// kernel_test!(fn adds() {
+///     assert_eq!(1 + 1, 2);
+/// });
+///
+/// kernel_test!(fn allocates() -> Result<()> {
+///     let _v: Vec<u8> = Vec::try_with_capacity(64).map_err(|_| KError::NO_MEM)?;
+///     Ok(())
+/// });
+/// ```
+#[macro_export]
+macro_rules! kernel_test {
+    (fn $name:ident() $body:block) => {
+        $crate::kernel_test!(fn $name() -> $crate::nk_error::Result<()> {
+            $body;
+            Ok(())
+        });
+    };
+    (fn $name:ident() -> $ret:ty $body:block) => {
+        fn $name() -> $ret $body
+
+        const _: () = {
+            #[used]
+            #[link_section = ".rust_tests"]
+            static TEST: &$crate::nk_test::KernelTest = &$crate::nk_test::KernelTest {
+                module_path: module_path!(),
+                name: stringify!($name),
+                run: $name,
+            };
+        };
+    };
+}
@@ -0,0 +1,27 @@
+use core::ffi::{c_char, c_int, c_void, CStr};
+use core::fmt::Write;
+
+use super::run;
+use crate::utils::VcWriter;
+
+// `rust_test` runs every kernel test, `rust_test <filter>` those whose
+// module or name contains the filter, or `module::name`
+#[no_mangle]
+pub unsafe extern "C" fn rust_test_shell_entry(buf: *const c_char, _priv_: *const c_void) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let filter = line.split_whitespace().nth(1).unwrap_or("");
+
+    let r = run(filter, |r| {
+        let mut w = VcWriter::new();
+        let _ = writeln!(w, "{}", r);
+    });
+    match r {
+        Ok(s) => {
+            let mut w = VcWriter::new();
+            let _ = writeln!(w, "{} passed, {} failed", s.passed, s.failed);
+        }
+        Err(e) => error_print!("cannot run tests: {}", e),
+    }
+    0
+}
@@ -0,0 +1,61 @@
+// deadlines for waits that give up: a timeout turned into the instant
+// it runs out, which loops can check and re-arm timers against.
+
+use super::{units, Duration, Instant};
+use crate::nk_error::{KError, Result};
+
+/// An instant by which something has to happen.
+///
+/// A deadline after a timeout too long to represent is never reached,
+/// rather than overflowing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// The deadline `timeout` from now.
+    pub fn after(timeout: Duration) -> Self {
+        Deadline(Instant::now().saturating_add(timeout))
+    }
+
+    pub const fn at(instant: Instant) -> Self {
+        Deadline(instant)
+    }
+
+    /// A deadline that is never reached.
+    pub const fn never() -> Self {
+        Deadline(Instant::MAX)
+    }
+
+    pub fn instant(self) -> Instant {
+        self.0
+    }
+
+    /// Time left until the deadline, zero once it has passed.
+    pub fn remaining(self) -> Duration {
+        self.0.duration_since(Instant::now())
+    }
+
+    /// `remaining` in nanoseconds, as the C timer calls take it.
+    pub fn remaining_nanos(self) -> u64 {
+        units::saturating_nanos(self.remaining())
+    }
+
+    pub fn is_expired(self) -> bool {
+        Instant::now() >= self.0
+    }
+
+    /// `TIMEOUT` once the deadline has passed, for `?` in wait loops.
+    pub fn check(self) -> Result<()> {
+        if self.is_expired() {
+            Err(KError::TIMEOUT)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl From<Instant> for Deadline {
+    fn from(instant: Instant) -> Self {
+        Deadline(instant)
+    }
+}
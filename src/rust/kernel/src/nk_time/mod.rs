@@ -0,0 +1,147 @@
+// monotonic time: `Instant`s on the scheduler's clock (nanoseconds
+// since boot), `core::time::Duration`s between them, and the CPU's
+// cycle counter for finer measurements. wall-clock time is in
+// `SystemTime`.
+
+use core::arch::x86_64::_rdtsc;
+use core::ffi::c_ulong;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+pub use core::time::Duration;
+
+use crate::nk_raw;
+
+mod deadline;
+mod nk_shell_cmd;
+pub(crate) mod registry;
+pub mod rtc;
+mod system;
+pub mod timeout;
+pub mod timer;
+pub mod tsc;
+pub mod units;
+
+pub use deadline::Deadline;
+pub use system::{DateTime, SystemTime, UNIX_EPOCH};
+
+extern "C" {
+    // glue.c; this CPU's frequency as measured at boot, 0 if unknown
+    fn nk_rust_cpu_khz() -> c_ulong;
+}
+
+/// A point in time since boot. Never goes backwards.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// The last instant there is, which is never reached.
+    pub const MAX: Instant = Instant(u64::MAX);
+
+    pub fn now() -> Self {
+        Instant(unsafe { nk_raw::nk_sched_get_realtime() })
+    }
+
+    /// The instant `ns` nanoseconds after boot.
+    pub const fn from_nanos(ns: u64) -> Self {
+        Instant(ns)
+    }
+
+    /// Nanoseconds since boot.
+    pub const fn as_nanos(self) -> u64 {
+        self.0
+    }
+
+    /// Time from `earlier` to `self`, zero if `earlier` is later.
+    pub fn duration_since(self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    pub fn checked_duration_since(self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_nanos)
+    }
+
+    pub fn elapsed(self) -> Duration {
+        Instant::now().duration_since(self)
+    }
+
+    pub fn checked_add(self, d: Duration) -> Option<Instant> {
+        self.0.checked_add(units::nanos(d)?).map(Instant)
+    }
+
+    /// `self + d`, or `Instant::MAX` if that is too late to represent.
+    pub fn saturating_add(self, d: Duration) -> Instant {
+        self.checked_add(d).unwrap_or(Instant::MAX)
+    }
+
+    pub fn checked_sub(self, d: Duration) -> Option<Instant> {
+        self.0.checked_sub(units::nanos(d)?).map(Instant)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, d: Duration) -> Instant {
+        self.checked_add(d)
+            .expect("overflow adding a duration to an instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, d: Duration) {
+        *self = *self + d;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, d: Duration) -> Instant {
+        self.checked_sub(d)
+            .expect("overflow subtracting a duration from an instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, d: Duration) {
+        *self = *self - d;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Blocks the calling thread for at least `d`.
+pub fn sleep(d: Duration) {
+    unsafe { nk_raw::nk_sleep(units::saturating_nanos(d)) };
+}
+
+/// The cycle counter of the CPU we are running on. Counters of
+/// different CPUs need not agree.
+pub fn cycles() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+// cycles per second of this CPU: the calibrated TSC rate, or the rate
+// measured at boot if the TSC wasn't calibrated
+fn cycles_per_sec() -> Option<u64> {
+    tsc::hz().or(match unsafe { nk_rust_cpu_khz() } {
+        0 => None,
+        khz => khz.checked_mul(1000),
+    })
+}
+
+/// How long `cycles` cycles of this CPU take, if its frequency is known.
+pub fn cycles_to_duration(cycles: u64) -> Option<Duration> {
+    units::ticks_to_duration(cycles, cycles_per_sec()?)
+}
+
+/// How many cycles of this CPU `d` takes, if its frequency is known and
+/// the count fits.
+pub fn duration_to_cycles(d: Duration) -> Option<u64> {
+    units::duration_to_ticks(d, cycles_per_sec()?)
+}
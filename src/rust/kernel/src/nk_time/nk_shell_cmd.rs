@@ -0,0 +1,39 @@
+use core::ffi::{c_char, c_int, c_void};
+use core::fmt::Write;
+
+use super::registry::{timers, Kind};
+use super::Instant;
+use crate::nk_raw;
+use crate::utils::VcWriter;
+
+// `rust_timers` lists the timers created from Rust, then every kernel
+// timer as the C side sees it
+#[no_mangle]
+pub unsafe extern "C" fn rust_timers_shell_entry(
+    _buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    let now = Instant::now();
+    let mut w = VcWriter::new();
+    for t in timers() {
+        let _ = write!(w, "{:<32} ", t.name());
+        let _ = match t.kind {
+            Kind::OneShot => write!(w, "one-shot "),
+            Kind::Periodic(p) => write!(w, "every {:?} ", p),
+            Kind::Interval(p) => write!(w, "interval {:?} ", p),
+        };
+        let _ = match t.deadline {
+            None => write!(w, "unset"),
+            Some(d) if d <= now => write!(w, "expired"),
+            Some(d) => write!(w, "in {:?}", d - now),
+        };
+        if let Some(cpu) = t.cpu {
+            let _ = write!(w, " on cpu {}", cpu);
+        }
+        let _ = writeln!(w, " ({}:{})", t.owner.file(), t.owner.line());
+    }
+    w.flush();
+
+    unsafe { nk_raw::nk_timer_dump_timers() };
+    0
+}
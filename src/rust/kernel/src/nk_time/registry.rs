@@ -0,0 +1,111 @@
+// every timer created from Rust, with where it was created and when it
+// next fires, for `rust_timers`. a fixed table, since periodic timers
+// update their deadline from interrupt context.
+
+use alloc::vec::Vec;
+use core::panic::Location;
+
+use super::{Duration, Instant};
+use crate::nk_lock::IRQLock;
+
+const MAX_TIMERS: usize = 64;
+const NAME_LEN: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    OneShot,
+    Periodic(Duration),
+    Interval(Duration),
+}
+
+#[derive(Clone, Copy)]
+pub struct Entry {
+    // the `nk_timer_t`, which identifies the entry
+    timer: usize,
+    name: [u8; NAME_LEN],
+    pub kind: Kind,
+    /// When it next fires, if it is set.
+    pub deadline: Option<Instant>,
+    /// The CPU its callback runs on, for timers with one.
+    pub cpu: Option<u32>,
+    /// Where it was created.
+    pub owner: &'static Location<'static>,
+}
+
+impl Entry {
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+        // copied from a `str`, cut at a char boundary
+        unsafe { core::str::from_utf8_unchecked(&self.name[..len]) }
+    }
+}
+
+static TIMERS: IRQLock<[Option<Entry>; MAX_TIMERS]> = IRQLock::new([None; MAX_TIMERS]);
+
+fn truncated(name: &str) -> [u8; NAME_LEN] {
+    let mut len = name.len().min(NAME_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    let mut buf = [0; NAME_LEN];
+    buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+    buf
+}
+
+// past `MAX_TIMERS`, timers still work but are not listed
+pub(super) fn register(
+    timer: *const u8,
+    name: &str,
+    kind: Kind,
+    owner: &'static Location<'static>,
+) {
+    if let Some(slot) = TIMERS.lock().iter_mut().find(|e| e.is_none()) {
+        *slot = Some(Entry {
+            timer: timer as usize,
+            name: truncated(name),
+            kind,
+            deadline: None,
+            cpu: None,
+            owner,
+        });
+    }
+}
+
+fn update(timer: *const u8, f: impl FnOnce(&mut Entry)) {
+    let mut timers = TIMERS.lock();
+    if let Some(e) = timers
+        .iter_mut()
+        .flatten()
+        .find(|e| e.timer == timer as usize)
+    {
+        f(e);
+    }
+}
+
+pub(super) fn set_deadline(timer: *const u8, deadline: Option<Instant>) {
+    update(timer, |e| e.deadline = deadline);
+}
+
+pub(super) fn set_cpu(timer: *const u8, cpu: u32) {
+    update(timer, |e| e.cpu = Some(cpu));
+}
+
+pub(super) fn unregister(timer: *const u8) {
+    let mut timers = TIMERS.lock();
+    if let Some(slot) = timers
+        .iter_mut()
+        .find(|e| matches!(e, Some(e) if e.timer == timer as usize))
+    {
+        *slot = None;
+    }
+}
+
+// for crash reports
+pub(crate) fn locked() -> bool {
+    TIMERS.is_locked()
+}
+
+/// Every listed timer.
+pub fn timers() -> Vec<Entry> {
+    TIMERS.lock().iter().flatten().copied().collect()
+}
@@ -0,0 +1,115 @@
+// the CMOS real-time clock: the date and time kept by the battery
+// backed clock, to one second, in whatever zone the firmware set it to
+// (UTC, we assume).
+
+use x86_64::instructions::port::{PortRead, PortWrite};
+
+use crate::nk_lock::IRQLock;
+
+// bit 7 of the index disables NMIs, which all registers below leave clear
+const INDEX_PORT: u16 = 0x70;
+const DATA_PORT: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+
+// status A: the clock is updating, and its registers may be torn
+const UPDATE_IN_PROGRESS: u8 = 0x80;
+// status B: values are binary rather than BCD, hours are 0-23
+const BINARY: u8 = 0x04;
+const HOURS_24: u8 = 0x02;
+// in 12 hour mode, set in the hours register for PM
+const HOUR_PM: u8 = 0x80;
+
+// the index and data ports are one register pair for everyone
+static CMOS: IRQLock<()> = IRQLock::new(());
+
+/// A reading of the RTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RtcTime {
+    pub year: u16,
+    /// 1-12
+    pub month: u8,
+    /// 1-31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn read_reg(reg: u8) -> u8 {
+    unsafe {
+        u8::write_to_port(INDEX_PORT, reg);
+        u8::read_from_port(DATA_PORT)
+    }
+}
+
+// the raw time registers, read between updates
+fn read_raw() -> [u8; 6] {
+    while read_reg(REG_STATUS_A) & UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    [
+        read_reg(REG_SECONDS),
+        read_reg(REG_MINUTES),
+        read_reg(REG_HOURS),
+        read_reg(REG_DAY),
+        read_reg(REG_MONTH),
+        read_reg(REG_YEAR),
+    ]
+}
+
+fn from_bcd(v: u8) -> u8 {
+    (v >> 4) * 10 + (v & 0xf)
+}
+
+/// Reads the date and time from the RTC.
+pub fn read() -> RtcTime {
+    let (raw, status) = {
+        let _guard = CMOS.lock();
+        // an update can still start while we read, so read until two
+        // readings agree
+        let mut raw = read_raw();
+        loop {
+            let again = read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        (raw, read_reg(REG_STATUS_B))
+    };
+
+    let [mut second, mut minute, hour_reg, mut day, mut month, mut year] = raw;
+    let pm = hour_reg & HOUR_PM != 0;
+    let mut hour = hour_reg & !HOUR_PM;
+    if status & BINARY == 0 {
+        second = from_bcd(second);
+        minute = from_bcd(minute);
+        hour = from_bcd(hour);
+        day = from_bcd(day);
+        month = from_bcd(month);
+        year = from_bcd(year);
+    }
+    if status & HOURS_24 == 0 {
+        // 12 is midnight or noon
+        hour = (hour % 12) + if pm { 12 } else { 0 };
+    }
+
+    RtcTime {
+        // the century register isn't at a fixed place, and the clock
+        // won't be this old
+        year: 2000 + year as u16,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    }
+}
@@ -0,0 +1,208 @@
+// wall-clock time: `SystemTime`s since the Unix epoch, and their
+// calendar dates as `DateTime`s.
+//
+// the RTC is read once, the first time it's needed, and the time since
+// then comes from `Instant`, so the wall clock ticks smoothly at the
+// scheduler clock's resolution but is only as accurate as the RTC's
+// one second.
+
+use core::fmt;
+use core::ops::{Add, Sub};
+
+use super::rtc::{self, RtcTime};
+use super::{Duration, Instant};
+use crate::nk_lock::IRQLock;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+// days from 0000-03-01 to 1970-01-01 in the proleptic Gregorian calendar
+const EPOCH_DAYS_FROM_MARCH_0: u64 = 719_468;
+const DAYS_PER_ERA: u64 = 146_097;
+
+/// A point in wall-clock time, at or after the Unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime(Duration);
+
+pub const UNIX_EPOCH: SystemTime = SystemTime(Duration::ZERO);
+
+// the wall-clock time at an instant, from the RTC
+static BASE: IRQLock<Option<(SystemTime, Instant)>> = IRQLock::new(None);
+
+impl SystemTime {
+    pub fn now() -> Self {
+        let (wall, at) = *BASE
+            .lock()
+            .get_or_insert_with(|| (DateTime::from(rtc::read()).to_system_time(), Instant::now()));
+        wall + at.elapsed()
+    }
+
+    pub const fn from_unix(since_epoch: Duration) -> Self {
+        SystemTime(since_epoch)
+    }
+
+    /// Time since the Unix epoch.
+    pub const fn since_epoch(self) -> Duration {
+        self.0
+    }
+
+    pub const fn unix_secs(self) -> u64 {
+        self.0.as_secs()
+    }
+
+    /// Time from `earlier` to `self`, zero if `earlier` is later.
+    pub fn duration_since(self, earlier: SystemTime) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or_default()
+    }
+
+    pub fn checked_duration_since(self, earlier: SystemTime) -> Option<Duration> {
+        self.0.checked_sub(earlier.0)
+    }
+
+    /// Time since `self`, zero if it is in the future.
+    pub fn elapsed(self) -> Duration {
+        SystemTime::now().duration_since(self)
+    }
+
+    pub fn date_time(self) -> DateTime {
+        DateTime::from_system_time(self)
+    }
+}
+
+impl Add<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn add(self, d: Duration) -> SystemTime {
+        SystemTime(self.0 + d)
+    }
+}
+
+impl Sub<Duration> for SystemTime {
+    type Output = SystemTime;
+
+    fn sub(self, d: Duration) -> SystemTime {
+        SystemTime(
+            self.0
+                .checked_sub(d)
+                .expect("subtracting a duration went before the epoch"),
+        )
+    }
+}
+
+/// As RFC 3339, in UTC, to the millisecond.
+impl fmt::Display for SystemTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.date_time().fmt(f)
+    }
+}
+
+/// A UTC calendar date and time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u32,
+    /// 1-12
+    pub month: u8,
+    /// 1-31
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub nanos: u32,
+}
+
+impl DateTime {
+    pub fn from_system_time(t: SystemTime) -> Self {
+        let secs = t.unix_secs();
+        let (year, month, day) = civil_from_days(secs / SECS_PER_DAY);
+        let secs_of_day = secs % SECS_PER_DAY;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (secs_of_day / 3600) as u8,
+            minute: (secs_of_day / 60 % 60) as u8,
+            second: (secs_of_day % 60) as u8,
+            nanos: t.0.subsec_nanos(),
+        }
+    }
+
+    /// The time this names. Dates before the epoch give the epoch.
+    pub fn to_system_time(&self) -> SystemTime {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let secs = self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        match days {
+            Some(days) => SystemTime(Duration::new(days * SECS_PER_DAY + secs, self.nanos)),
+            None => UNIX_EPOCH,
+        }
+    }
+}
+
+impl From<RtcTime> for DateTime {
+    fn from(t: RtcTime) -> Self {
+        DateTime {
+            year: t.year as u32,
+            month: t.month,
+            day: t.day,
+            hour: t.hour,
+            minute: t.minute,
+            second: t.second,
+            nanos: 0,
+        }
+    }
+}
+
+/// `YYYY-MM-DDTHH:MM:SS.mmmZ`
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.nanos / 1_000_000
+        )
+    }
+}
+
+// the calendar conversions count days in 400-year eras starting on
+// March 1st, so the leap day is the last day of each year
+
+// days since the epoch of a date, None before it
+fn days_from_civil(year: u32, month: u8, day: u8) -> Option<u64> {
+    let (month, day) = (month as u64, day as u64);
+    let y = (year as u64).checked_sub((month <= 2) as u64)?;
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day.saturating_sub(1);
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    (era * DAYS_PER_ERA + doe).checked_sub(EPOCH_DAYS_FROM_MARCH_0)
+}
+
+// the date `days` after the epoch
+fn civil_from_days(days: u64) -> (u32, u8, u8) {
+    let z = days + EPOCH_DAYS_FROM_MARCH_0;
+    let era = z / DAYS_PER_ERA;
+    let doe = z - era * DAYS_PER_ERA;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+    let year = (era * 400 + yoe) as u32 + (month <= 2) as u32;
+    (year, month, day)
+}
+
+kernel_test!(
+    fn calendar_round_trip() {
+        kassert_eq!(days_from_civil(1970, 1, 1), Some(0));
+        kassert_eq!(days_from_civil(2000, 3, 1), Some(11_017));
+        kassert_eq!(days_from_civil(1969, 12, 31), None);
+        for days in [0, 59, 365, 11_016, 11_017, 19_723] {
+            let (y, m, d) = civil_from_days(days);
+            kassert_eq!(days_from_civil(y, m, d), Some(days));
+        }
+    }
+);
@@ -0,0 +1,20 @@
+// waiting for a condition with a deadline: `spin_with_timeout` busy-waits,
+// for code that can't sleep (interrupts off, a spinlock held).
+
+use core::hint::spin_loop;
+
+use super::{Deadline, Duration};
+use crate::nk_error::Result;
+
+/// Busy-waits until `pred` holds, or until `timeout` has passed, which
+/// gives `TIMEOUT`.
+pub fn spin_with_timeout(mut pred: impl FnMut() -> bool, timeout: Duration) -> Result<()> {
+    let deadline = Deadline::after(timeout);
+    loop {
+        if pred() {
+            return Ok(());
+        }
+        deadline.check()?;
+        spin_loop();
+    }
+}
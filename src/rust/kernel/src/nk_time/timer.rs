@@ -0,0 +1,571 @@
+// kernel timers (nautilus/timer.h). expired timers are handled on CPU
+// 0 from the timer interrupt, which wakes waiting threads, or runs
+// callbacks there or sends them to the CPU they were placed on.
+//
+// a `Timer` is set, then waited on or cancelled. `PeriodicTimer` runs
+// a callback every interval and `Interval` lets a thread wait for each
+// tick; both re-arm themselves against a fixed schedule, so ticks
+// don't drift by however late each one ran.
+
+use alloc::boxed::Box;
+use alloc::ffi::CString;
+use core::cell::UnsafeCell;
+use core::ffi::{c_int, c_void};
+use core::hint::spin_loop;
+use core::panic::Location;
+use core::ptr::{null_mut, NonNull};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+
+use super::registry::{self, Kind};
+use super::units::saturating_nanos as nanos;
+use super::{Duration, Instant};
+use crate::nk_error::{KError, Result};
+use crate::{nk_raw, nk_smp};
+
+extern "C" {
+    // glue.c
+    fn nk_rust_my_cpu_id() -> c_int;
+    fn nk_rust_timer_cancel(t: *mut nk_raw::nk_timer_t) -> c_int;
+}
+
+// the handler runs on CPU 0. with LOCAL_SYNC, it calls callbacks placed
+// there directly; those placed elsewhere go by an xcall that doesn't
+// wait, which C drops if the CPU's xcall slot is taken, so periodic
+// timers stay on CPU 0 and forward their callbacks themselves
+const HANDLER_CPU: u32 = 0;
+const CALLBACK_FLAGS: u32 = nk_raw::NK_TIMER_CALLBACK | nk_raw::NK_TIMER_CALLBACK_LOCAL_SYNC;
+
+/// The CPU a timer's callback runs on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+    Cpu(u32),
+    /// The CPU the timer is started or moved from.
+    Current,
+}
+
+impl Placement {
+    fn resolve(self) -> Result<u32> {
+        let cpu = match self {
+            Placement::Cpu(cpu) => cpu,
+            Placement::Current => (unsafe { nk_rust_my_cpu_id() }) as u32,
+        };
+        if cpu < unsafe { nk_raw::nk_get_num_cpus() } {
+            Ok(cpu)
+        } else {
+            Err(KError::INVALID_ARG)
+        }
+    }
+}
+
+impl Default for Placement {
+    /// Where the handler runs, so no xcall is needed.
+    fn default() -> Self {
+        Placement::Cpu(HANDLER_CPU)
+    }
+}
+
+// an `nk_timer_t`, listed in the registry, and destroyed (and so
+// cancelled) on drop
+struct RawTimer(NonNull<nk_raw::nk_timer_t>);
+
+unsafe impl Send for RawTimer {}
+unsafe impl Sync for RawTimer {}
+
+impl RawTimer {
+    #[track_caller]
+    fn new(name: &str, kind: Kind) -> Result<Self> {
+        let c_name = CString::new(name).map_err(|_| KError::INVALID_ARG)?;
+        // the name is copied into the timer
+        let t = unsafe { nk_raw::nk_timer_create(c_name.as_ptr() as *mut _) };
+        let t = NonNull::new(t).map(RawTimer).ok_or(KError::NO_MEM)?;
+        registry::register(t.id(), name, kind, Location::caller());
+        Ok(t)
+    }
+
+    // the timer's registry key
+    fn id(&self) -> *const u8 {
+        self.as_ptr() as *const u8
+    }
+
+    fn as_ptr(&self) -> *mut nk_raw::nk_timer_t {
+        self.0.as_ptr()
+    }
+
+    // arms it to wake its waiters `ns` from now
+    fn start_wait(&self, ns: u64) -> Result<()> {
+        let t = self.as_ptr();
+        let flags = nk_raw::NK_TIMER_WAIT_ALL as u64;
+        KError::from_ret(unsafe { nk_raw::nk_timer_set(t, ns, flags, None, null_mut(), 0) })?;
+        KError::from_ret(unsafe { nk_raw::nk_timer_start(t) }).map(|_| ())
+    }
+
+    // blocks until it expires or is cancelled
+    fn wait(&self) -> Result<()> {
+        KError::from_ret(unsafe { nk_raw::nk_timer_wait(self.as_ptr()) }).map(|_| ())
+    }
+
+    // arms it to call `callback(p)` in the handler `ns` from now
+    fn start_callback(
+        &self,
+        ns: u64,
+        callback: unsafe extern "C" fn(*mut c_void),
+        p: *mut c_void,
+    ) -> Result<()> {
+        let t = self.as_ptr();
+        let flags = CALLBACK_FLAGS as u64;
+        let cpu = HANDLER_CPU;
+        KError::from_ret(unsafe { nk_raw::nk_timer_set(t, ns, flags, Some(callback), p, cpu) })?;
+        KError::from_ret(unsafe { nk_raw::nk_timer_start(t) }).map(|_| ())
+    }
+
+    // false if it wasn't armed. unlike `nk_timer_cancel`, leaves one
+    // that already expired signalled, for its waiters to see
+    fn cancel(&self) -> bool {
+        unsafe { nk_rust_timer_cancel(self.as_ptr()) == 0 }
+    }
+}
+
+impl Drop for RawTimer {
+    fn drop(&mut self) {
+        registry::unregister(self.id());
+        unsafe { nk_raw::nk_timer_destroy(self.as_ptr()) };
+    }
+}
+
+// the next deadline on `interval`'s schedule after `now`, and how many
+// deadlines were skipped to get there. one too late to represent is
+// `Instant::MAX`
+fn next_deadline(deadline: Instant, interval: Duration, now: Instant) -> (Instant, u64) {
+    let next = deadline.saturating_add(interval);
+    if next > now {
+        return (next, 0);
+    }
+    let behind = now.duration_since(next).as_nanos() / interval.as_nanos();
+    let skipped = u64::try_from(behind).unwrap_or(u64::MAX).saturating_add(1);
+    let ahead = u32::try_from(skipped).unwrap_or(u32::MAX);
+    let next = interval
+        .checked_mul(ahead)
+        .map_or(Instant::MAX, |d| next.saturating_add(d));
+    (next, skipped)
+}
+
+type Callback = Box<dyn FnMut() + Send>;
+
+// shared by a `PeriodicTimer` and its callback trampoline
+struct Periodic {
+    timer: RawTimer,
+    interval: Duration,
+    // only the trampoline touches the deadline, and it never runs twice
+    // at once since it re-arms the timer on its way out; the callback is
+    // also run by `periodic_remote`, but only while `running` is set
+    deadline: UnsafeCell<Instant>,
+    callback: UnsafeCell<Callback>,
+    // set by drop: don't re-arm
+    stop: AtomicBool,
+    // the trampoline is running
+    busy: AtomicBool,
+    // the trampoline saw `stop`, and is done with us
+    done: AtomicBool,
+    // the callback is running, here or on the CPU it was forwarded to
+    running: AtomicBool,
+    // where the next tick's callback runs
+    cpu: AtomicU32,
+    ticks: AtomicU64,
+    missed: AtomicU64,
+}
+
+unsafe impl Sync for Periodic {}
+
+impl Periodic {
+    // runs the callback for a tick that set `running`, and clears it
+    fn run_callback(&self) {
+        unsafe { (*self.callback.get())() };
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+        // once clear, drop may free us
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    fn this(&self) -> *mut c_void {
+        self as *const Periodic as *mut c_void
+    }
+}
+
+// a tick's callback, forwarded to the CPU it is placed on
+unsafe extern "C" fn periodic_remote(p: *mut c_void) {
+    // the trampoline set `running`, so drop waits for us
+    let p = unsafe { &*(p as *const Periodic) };
+    p.run_callback();
+}
+
+unsafe extern "C" fn periodic_trampoline(p: *mut c_void) {
+    // `p` is the `Periodic` of a live `PeriodicTimer`; its drop waits
+    // for us to be done with it
+    let p = unsafe { &*(p as *const Periodic) };
+    p.busy.store(true, Ordering::SeqCst);
+
+    let cpu = p.cpu.load(Ordering::SeqCst);
+    if p.running.swap(true, Ordering::SeqCst) {
+        // the last tick's callback is still running elsewhere
+        p.missed.fetch_add(1, Ordering::Relaxed);
+    } else if cpu == HANDLER_CPU {
+        p.run_callback();
+    } else if !nk_smp::try_send(cpu, periodic_remote, p.this()) {
+        p.running.store(false, Ordering::SeqCst);
+        p.missed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    if p.stop.load(Ordering::SeqCst) {
+        // our last access to `p`
+        p.done.store(true, Ordering::SeqCst);
+        return;
+    }
+
+    let now = Instant::now();
+    let deadline = unsafe { &mut *p.deadline.get() };
+    let (next, skipped) = next_deadline(*deadline, p.interval, now);
+    p.missed.fetch_add(skipped, Ordering::Relaxed);
+    *deadline = next;
+    registry::set_deadline(p.timer.id(), Some(next));
+    let _ = p
+        .timer
+        .start_callback(nanos(next - now), periodic_trampoline, p.this());
+    p.busy.store(false, Ordering::SeqCst);
+}
+
+/// Runs a callback every `interval` until dropped.
+///
+/// The callback runs in interrupt context, on CPU 0 unless placed
+/// elsewhere, so it must not block, and should be short. Ticks follow
+/// a fixed schedule from when the timer was started; if a callback runs
+/// so late that whole ticks were missed, they are skipped (and counted)
+/// rather than run back to back.
+pub struct PeriodicTimer {
+    inner: Box<Periodic>,
+}
+
+impl PeriodicTimer {
+    #[track_caller]
+    pub fn start(
+        name: &str,
+        interval: Duration,
+        callback: impl FnMut() + Send + 'static,
+    ) -> Result<Self> {
+        Self::start_on(name, interval, Placement::default(), callback)
+    }
+
+    /// Like `start`, with the callback run on the CPU `placement` names.
+    /// Placing it off CPU 0 costs an xcall every tick, and a tick is
+    /// skipped (and counted as missed) if that CPU's xcall slot is taken
+    /// or the last tick's callback is still running.
+    #[track_caller]
+    pub fn start_on(
+        name: &str,
+        interval: Duration,
+        placement: Placement,
+        callback: impl FnMut() + Send + 'static,
+    ) -> Result<Self> {
+        if interval.is_zero() {
+            return Err(KError::INVALID_ARG);
+        }
+        let cpu = placement.resolve()?;
+        let first = Instant::now().saturating_add(interval);
+        let inner = Box::new(Periodic {
+            timer: RawTimer::new(name, Kind::Periodic(interval))?,
+            interval,
+            deadline: UnsafeCell::new(first),
+            callback: UnsafeCell::new(Box::new(callback)),
+            stop: AtomicBool::new(false),
+            busy: AtomicBool::new(false),
+            done: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+            cpu: AtomicU32::new(cpu),
+            ticks: AtomicU64::new(0),
+            missed: AtomicU64::new(0),
+        });
+        // the box doesn't move, and outlives the timer's last callback
+        inner
+            .timer
+            .start_callback(nanos(interval), periodic_trampoline, inner.this())?;
+        registry::set_deadline(inner.timer.id(), Some(first));
+        registry::set_cpu(inner.timer.id(), cpu);
+        Ok(PeriodicTimer { inner })
+    }
+
+    /// The CPU the callback runs on.
+    pub fn cpu(&self) -> u32 {
+        self.inner.cpu.load(Ordering::SeqCst)
+    }
+
+    /// Moves the callback to the CPU `placement` names, from the next
+    /// tick on. A tick whose callback is running still finishes where
+    /// it was placed.
+    pub fn move_to(&self, placement: Placement) -> Result<()> {
+        let cpu = placement.resolve()?;
+        let p = &*self.inner;
+        // the trampoline reads it on every tick
+        p.cpu.store(cpu, Ordering::SeqCst);
+        registry::set_cpu(p.timer.id(), cpu);
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.inner.interval
+    }
+
+    /// How many times the callback has run.
+    pub fn ticks(&self) -> u64 {
+        self.inner.ticks.load(Ordering::Relaxed)
+    }
+
+    /// How many ticks were skipped: because the callback ran too late,
+    /// or, placed off CPU 0, could not be sent there.
+    pub fn missed(&self) -> u64 {
+        self.inner.missed.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for PeriodicTimer {
+    fn drop(&mut self) {
+        let p = &*self.inner;
+        p.stop.store(true, Ordering::SeqCst);
+        loop {
+            if p.timer.cancel() {
+                // it was armed, so no callback is pending, but the
+                // trampoline may still be on its way out after arming it
+                while p.busy.load(Ordering::SeqCst) {
+                    spin_loop();
+                }
+                break;
+            }
+            // it expired, so the trampoline is pending or running (the
+            // handler calls it directly, so it can't be lost), and will
+            // either see `stop` or re-arm it for us to cancel
+            if p.done.load(Ordering::SeqCst) {
+                break;
+            }
+            spin_loop();
+        }
+        // a callback forwarded to another CPU may still be running
+        while p.running.load(Ordering::SeqCst) {
+            spin_loop();
+        }
+    }
+}
+
+/// How a wait on a `SetTimer` ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wake {
+    Expired,
+    Cancelled,
+}
+
+/// A one-shot timer that isn't set. Setting it gives a `SetTimer`,
+/// which can be waited on or cancelled, and turned back into a `Timer`
+/// to be set again.
+pub struct Timer {
+    raw: RawTimer,
+}
+
+impl Timer {
+    #[track_caller]
+    pub fn new(name: &str) -> Result<Self> {
+        Ok(Timer {
+            raw: RawTimer::new(name, Kind::OneShot)?,
+        })
+    }
+
+    /// Sets the timer to expire `after` from now, or never if that is
+    /// too late to represent.
+    pub fn set(self, after: Duration) -> Result<SetTimer> {
+        // no later than the deadline the C side computes, which `wait`
+        // relies on
+        let now = Instant::now();
+        let deadline = now.saturating_add(after);
+        // the C side adds it to its own now, wrapping around past the end
+        self.raw.start_wait(nanos(deadline.duration_since(now)))?;
+        registry::set_deadline(self.raw.id(), Some(deadline));
+        Ok(SetTimer {
+            raw: self.raw,
+            deadline,
+        })
+    }
+
+    /// Sets the timer to expire at `deadline`, at once if it has passed.
+    pub fn set_at(self, deadline: Instant) -> Result<SetTimer> {
+        let after = deadline.duration_since(Instant::now());
+        let mut set = self.set(after)?;
+        set.deadline = set.deadline.min(deadline);
+        registry::set_deadline(set.raw.id(), Some(set.deadline));
+        Ok(set)
+    }
+}
+
+/// A `Timer` that has been set, and may or may not have expired or been
+/// cancelled yet. Any number of threads can wait on it at once.
+pub struct SetTimer {
+    raw: RawTimer,
+    deadline: Instant,
+}
+
+impl SetTimer {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Time left until the deadline, zero once it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.deadline.duration_since(Instant::now())
+    }
+
+    /// Blocks until the timer expires or is cancelled, and says which;
+    /// returns at once if either already happened.
+    pub fn wait(&self) -> Result<Wake> {
+        self.raw.wait()?;
+        // the C side can't tell the two apart, but it never expires a
+        // timer before its deadline
+        if Instant::now() < self.deadline {
+            Ok(Wake::Cancelled)
+        } else {
+            Ok(Wake::Expired)
+        }
+    }
+
+    /// Cancels the timer, waking its waiters with `Wake::Cancelled`.
+    /// `NOT_FOUND` if it already expired or was cancelled.
+    pub fn cancel(&self) -> Result<()> {
+        if self.raw.cancel() {
+            Ok(())
+        } else {
+            Err(KError::NOT_FOUND)
+        }
+    }
+
+    /// Cancels the timer if it is still pending, and gives it back to
+    /// be set again.
+    pub fn into_timer(self) -> Timer {
+        self.raw.cancel();
+        registry::set_deadline(self.raw.id(), None);
+        Timer { raw: self.raw }
+    }
+}
+
+/// Ticks every `period` for a thread to wait on, starting one period
+/// from now. As an iterator, yields the deadline of each tick.
+pub struct Interval {
+    timer: RawTimer,
+    period: Duration,
+    deadline: Instant,
+    missed: u64,
+}
+
+/// An `Interval` ticking every `period`.
+#[track_caller]
+pub fn interval(period: Duration) -> Result<Interval> {
+    if period.is_zero() {
+        return Err(KError::INVALID_ARG);
+    }
+    let timer = RawTimer::new("rust-interval", Kind::Interval(period))?;
+    let deadline = Instant::now().saturating_add(period);
+    registry::set_deadline(timer.id(), Some(deadline));
+    Ok(Interval {
+        timer,
+        period,
+        deadline,
+        missed: 0,
+    })
+}
+
+impl Interval {
+    /// Blocks until the next tick, and returns its deadline. If whole
+    /// ticks have passed since the last call, they are skipped (and
+    /// counted) and this returns at once.
+    pub fn tick(&mut self) -> Result<Instant> {
+        let now = Instant::now();
+        if self.deadline > now {
+            self.timer.start_wait(nanos(self.deadline - now))?;
+            self.timer.wait()?;
+        }
+        let tick = self.deadline;
+        let (next, skipped) = next_deadline(tick, self.period, Instant::now());
+        self.missed += skipped;
+        self.deadline = next;
+        registry::set_deadline(self.timer.id(), Some(next));
+        Ok(tick)
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// How many ticks were skipped because `tick` was called too late.
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+impl Iterator for Interval {
+    type Item = Instant;
+
+    fn next(&mut self) -> Option<Instant> {
+        self.tick().ok()
+    }
+}
+
+kernel_test!(
+    fn next_deadline_skips_missed_ticks() {
+        let ms = Duration::from_millis(1);
+        let start = Instant::from_nanos(0);
+        kassert_eq!(next_deadline(start, ms, start), (start + ms, 0));
+        // 3.5 intervals in: the ticks at 1, 2 and 3 ms were missed
+        let now = start + ms * 7 / 2;
+        kassert_eq!(next_deadline(start, ms, now), (start + ms * 4, 3));
+        // too late to represent, however far behind
+        let end = Instant::MAX - ms;
+        kassert_eq!(next_deadline(end, ms * 2, end), (Instant::MAX, 0));
+        kassert_eq!(next_deadline(end, ms, Instant::MAX).0, Instant::MAX);
+        kassert_eq!(
+            next_deadline(start, Duration::MAX, start),
+            (Instant::MAX, 0)
+        );
+    }
+);
+
+kernel_test!(
+    fn cancel_after_expiry_still_waits() {
+        let timer = Timer::new("rust-test-timer").unwrap();
+        let set = timer.set(Duration::from_millis(1)).unwrap();
+        kassert_eq!(set.wait(), Ok(Wake::Expired));
+        kassert_eq!(set.cancel(), Err(KError::NOT_FOUND));
+        kassert_eq!(set.wait(), Ok(Wake::Expired));
+    }
+);
+
+kernel_test!(
+    fn timers_too_late_to_represent_never_expire() {
+        let timer = Timer::new("rust-test-timer").unwrap();
+        let set = timer.set(Duration::MAX).unwrap();
+        kassert_eq!(set.deadline(), Instant::MAX);
+        // still armed
+        kassert_eq!(set.cancel(), Ok(()));
+    }
+);
+
+kernel_test!(
+    fn periodic_callbacks_run_on_their_cpu() {
+        let cpu = nk_smp::num_cpus() - 1;
+        let seen = alloc::sync::Arc::new(AtomicU32::new(u32::MAX));
+        let ran_on = seen.clone();
+        let timer = PeriodicTimer::start_on(
+            "rust-test-periodic",
+            Duration::from_millis(1),
+            Placement::Cpu(cpu),
+            move || ran_on.store(nk_smp::current_cpu(), Ordering::SeqCst),
+        )
+        .unwrap();
+        super::sleep(Duration::from_millis(20));
+        // ticks that found the CPU's xcall slot taken are missed, not lost
+        kassert!(timer.ticks() > 0);
+        drop(timer);
+        kassert_eq!(seen.load(Ordering::SeqCst), cpu);
+    }
+);
@@ -0,0 +1,144 @@
+// the time stamp counter as a clock: calibrated once at boot against
+// the HPET, or the PIT if there is no HPET, and read with `rdtsc` for
+// timing that costs a few cycles instead of a call into the scheduler.
+//
+// only an invariant TSC (one that ticks at a constant rate whatever
+// the CPU's power state, and the same on every CPU) is a clock; without
+// one, nanosecond reads are unavailable and only raw cycles are.
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::ffi::{c_int, c_ulong};
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+use super::units;
+
+// how long each HPET calibration round spins
+const HPET_ROUND_NS: u64 = 10_000_000;
+const HPET_ROUNDS: usize = 3;
+
+extern "C" {
+    // glue.c
+    fn nk_rust_hpet_freq() -> u64;
+    fn nk_rust_hpet_counter() -> u64;
+    // dev/i8254.c; kHz, ULONG_MAX if it was disturbed
+    fn i8254_calib_tsc() -> c_ulong;
+}
+
+/// What the TSC was calibrated against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Source {
+    Uncalibrated = 0,
+    Hpet = 1,
+    Pit = 2,
+}
+
+static HZ: AtomicU64 = AtomicU64::new(0);
+static SOURCE: AtomicU8 = AtomicU8::new(Source::Uncalibrated as u8);
+// `cpuid` is slow (and traps under a hypervisor), so ask once
+static INVARIANT: AtomicBool = AtomicBool::new(false);
+
+/// The TSC of the CPU we are running on.
+#[inline]
+pub fn read() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Whether the CPU says its TSC is invariant.
+pub fn invariant() -> bool {
+    INVARIANT.load(Ordering::Relaxed)
+}
+
+fn cpuid_invariant() -> bool {
+    let max_ext = __cpuid(0x8000_0000).eax;
+    // CPUID.80000007H:EDX[8]
+    max_ext >= 0x8000_0007 && __cpuid(0x8000_0007).edx & (1 << 8) != 0
+}
+
+/// TSC ticks per second, once calibrated.
+pub fn hz() -> Option<u64> {
+    match HZ.load(Ordering::Relaxed) {
+        0 => None,
+        hz => Some(hz),
+    }
+}
+
+pub fn source() -> Source {
+    match SOURCE.load(Ordering::Relaxed) {
+        1 => Source::Hpet,
+        2 => Source::Pit,
+        _ => Source::Uncalibrated,
+    }
+}
+
+/// The TSC in nanoseconds, if it is invariant and calibrated.
+pub fn now_ns() -> Option<u64> {
+    if !invariant() {
+        return None;
+    }
+    cycles_to_ns(read())
+}
+
+pub fn cycles_to_ns(cycles: u64) -> Option<u64> {
+    units::ticks_to_ns(cycles, hz()?)
+}
+
+pub fn ns_to_cycles(ns: u64) -> Option<u64> {
+    units::ns_to_ticks(ns, hz()?)
+}
+
+// TSC ticks per second measured over one round against the HPET
+fn hpet_round(hpet_hz: u64) -> u64 {
+    let ticks = units::ns_to_ticks(HPET_ROUND_NS, hpet_hz).unwrap_or(u64::MAX);
+    let h0 = unsafe { nk_rust_hpet_counter() };
+    let t0 = read();
+    let mut h1 = h0;
+    while h1.wrapping_sub(h0) < ticks {
+        core::hint::spin_loop();
+        h1 = unsafe { nk_rust_hpet_counter() };
+    }
+    let t1 = read();
+    let elapsed = h1.wrapping_sub(h0) as u128;
+    ((t1 - t0) as u128 * hpet_hz as u128 / elapsed) as u64
+}
+
+fn calibrate() -> Option<(u64, Source)> {
+    let hpet_hz = unsafe { nk_rust_hpet_freq() };
+    if hpet_hz != 0 {
+        // an interrupt can land on either end of a round, and skew it
+        // either way, so take the median
+        let mut rounds = [0; HPET_ROUNDS];
+        rounds.iter_mut().for_each(|r| *r = hpet_round(hpet_hz));
+        rounds.sort_unstable();
+        return Some((rounds[HPET_ROUNDS / 2], Source::Hpet));
+    }
+    match unsafe { i8254_calib_tsc() } {
+        0 | c_ulong::MAX => None,
+        khz => Some((khz * 1000, Source::Pit)),
+    }
+}
+
+/// Calibrates the TSC. Called once at boot, on the boot CPU.
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn nk_rust_tsc_init() -> c_int {
+    INVARIANT.store(cpuid_invariant(), Ordering::Relaxed);
+    match calibrate() {
+        Some((hz, source)) => {
+            HZ.store(hz, Ordering::Relaxed);
+            SOURCE.store(source as u8, Ordering::Relaxed);
+            info_print!(
+                "TSC is {}.{:03} MHz against the {:?}, {}invariant",
+                hz / 1_000_000,
+                hz / 1000 % 1000,
+                source,
+                if invariant() { "" } else { "not " }
+            );
+            0
+        }
+        None => {
+            warn_print!("TSC calibration failed");
+            -1
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// conversions between `Duration`s, nanoseconds and ticks of a clock
+// running at some frequency (TSC cycles, HPET ticks). all of the time
+// code converts through these, so rounding and overflow behave the same
+// everywhere: ticks round down, and anything that doesn't fit is `None`
+// rather than wrapped or clamped.
+
+use super::Duration;
+
+pub const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// `d` in whole nanoseconds, if that fits in a `u64` (about 584 years).
+pub fn nanos(d: Duration) -> Option<u64> {
+    u64::try_from(d.as_nanos()).ok()
+}
+
+/// `d` in whole nanoseconds, or `u64::MAX` if it is longer; for the C
+/// timer calls, where that is as good as forever.
+pub fn saturating_nanos(d: Duration) -> u64 {
+    nanos(d).unwrap_or(u64::MAX)
+}
+
+/// How long `ticks` ticks of a `hz` clock take. `None` for a 0 Hz clock.
+pub fn ticks_to_duration(ticks: u64, hz: u64) -> Option<Duration> {
+    if hz == 0 {
+        return None;
+    }
+    let nanos = (ticks % hz) as u128 * NANOS_PER_SEC as u128 / hz as u128;
+    Some(Duration::new(ticks / hz, nanos as u32))
+}
+
+/// How many whole ticks of a `hz` clock `d` takes.
+pub fn duration_to_ticks(d: Duration, hz: u64) -> Option<u64> {
+    let ticks = d.as_nanos().checked_mul(hz as u128)? / NANOS_PER_SEC as u128;
+    u64::try_from(ticks).ok()
+}
+
+/// How many nanoseconds `ticks` ticks of a `hz` clock take.
+pub fn ticks_to_ns(ticks: u64, hz: u64) -> Option<u64> {
+    ticks_to_duration(ticks, hz).and_then(nanos)
+}
+
+/// How many whole ticks of a `hz` clock `ns` nanoseconds take.
+pub fn ns_to_ticks(ns: u64, hz: u64) -> Option<u64> {
+    duration_to_ticks(Duration::from_nanos(ns), hz)
+}
+
+kernel_test!(
+    fn ticks_round_trip() {
+        let hz = 2_400_000_000;
+        kassert_eq!(ticks_to_duration(hz, hz), Some(Duration::from_secs(1)));
+        kassert_eq!(
+            duration_to_ticks(Duration::from_millis(1), hz),
+            Some(2_400_000)
+        );
+        kassert_eq!(ticks_to_ns(3, 1_000), Some(3_000_000));
+        kassert_eq!(ns_to_ticks(999, 1_000_000), Some(0));
+    }
+);
+
+kernel_test!(
+    fn conversions_are_checked() {
+        kassert_eq!(ticks_to_duration(1, 0), None);
+        kassert_eq!(duration_to_ticks(Duration::MAX, 1_000), None);
+        kassert_eq!(nanos(Duration::from_secs(u64::MAX)), None);
+        kassert_eq!(saturating_nanos(Duration::MAX), u64::MAX);
+    }
+);
@@ -0,0 +1,566 @@
+// virtio device drivers' side of NK's virtio PCI transport
+// (dev/virtio_pci.h). virtio_pci finds the devices, and a driver brings
+// one up: negotiates features, has its virtqueues set up, routes its
+// interrupts, and hands buffers to the device through its queues.
+//
+// both models are supported: the legacy (transitional) one, whose
+// registers virtio_pci reads and writes for us, and the modern one,
+// whose register blocks it maps, but only with MSI-X, as it does not map
+// a modern device's interrupt status. buffers handed to a device are
+// identity mapped kernel memory, so their addresses are what the device
+// is given; see `nk_alloc::dma`.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ffi::{c_int, c_void};
+use core::ptr::{self, addr_of, addr_of_mut, NonNull};
+use core::sync::atomic::{fence, Ordering};
+
+use crate::nk_error::{KError, Result};
+use crate::nk_lock::IRQLock;
+use crate::nk_log::fast::Hex;
+use crate::nk_raw;
+
+counter!(BUFFERS, "buffers");
+counter!(USED, "used");
+counter!(INTERRUPTS, "interrupts");
+
+// the interrupt disable bit of the PCI command register
+const PCI_COMMAND: u8 = 0x4;
+const PCI_COMMAND_INTX_DISABLE: u16 = 0x400;
+// the interrupt line, and above it the pin, 1 to 4 for INTA to INTD
+const PCI_INTERRUPT: u8 = 0x3c;
+
+// a modern device refuses any features without it
+const F_VERSION_1: u64 = 1 << 32;
+
+// a device's interrupt handler, called with the state it was routed
+// with; the end of the interrupt is signalled for it
+type Handler = unsafe fn(*mut c_void);
+
+// a handler and the state it expects
+struct Route {
+    handler: Handler,
+    state: *mut c_void,
+}
+
+// routes are kept for good, and their state is only the handler's
+unsafe impl Send for Route {}
+
+impl Route {
+    unsafe fn run(&self) {
+        INTERRUPTS.inc();
+        // routed together
+        unsafe { (self.handler)(self.state) }
+    }
+}
+
+// the devices on each legacy line, by IRQ. lines may be shared, and NK
+// takes one handler for each, which runs every device's
+static LEGACY_ROUTES: IRQLock<Vec<(u8, Route)>> = IRQLock::new(Vec::new());
+
+// a driver, and what its device's interrupts have it do
+struct DriverRoute<T> {
+    driver: Arc<IRQLock<T>>,
+    on_interrupt: fn(&IRQLock<T>),
+}
+
+// the `Handler` for a `DriverRoute<T>`
+unsafe fn run_driver<T>(state: *mut c_void) {
+    // leaked by `route_to`
+    let r = unsafe { &*(state as *const DriverRoute<T>) };
+    (r.on_interrupt)(&r.driver);
+}
+
+// NK's handler for a legacy line, whose state is its IRQ
+unsafe extern "C" fn legacy_interrupt(
+    _excp: *mut nk_raw::excp_entry_t,
+    vec: nk_raw::excp_vec_t,
+    state: *mut c_void,
+) -> c_int {
+    debug_fast!("interrupt on vector ", Hex(vec));
+    let irq = state as usize as u8;
+    for (_, route) in LEGACY_ROUTES.lock().iter().filter(|(i, _)| *i == irq) {
+        // each device tells whether it interrupted
+        unsafe { route.run() };
+    }
+    // IRQ_HANDLER_END
+    unsafe { nk_raw::apic_do_eoi() };
+    0
+}
+
+// NK's handler for an MSI-X vector, whose state is its device's route
+unsafe extern "C" fn msi_x_interrupt(
+    _excp: *mut nk_raw::excp_entry_t,
+    vec: nk_raw::excp_vec_t,
+    state: *mut c_void,
+) -> c_int {
+    debug_fast!("interrupt on vector ", Hex(vec));
+    // leaked by `route_interrupts`
+    unsafe { (*(state as *const Route)).run() };
+    // IRQ_HANDLER_END
+    unsafe { nk_raw::apic_do_eoi() };
+    0
+}
+
+/// A device virtio_pci found, for a driver to bring up.
+#[derive(Copy, Clone)]
+pub struct VirtioDev {
+    ptr: NonNull<nk_raw::virtio_pci_dev>,
+}
+
+// virtio_pci's devices live for good, and the transport locks its
+// descriptor allocator itself; everything else is the driver's to lock
+unsafe impl Send for VirtioDev {}
+
+impl VirtioDev {
+    /// # Safety
+    ///
+    /// `ptr` must be a device virtio_pci found, with only one driver.
+    pub unsafe fn from_raw(ptr: *mut nk_raw::virtio_pci_dev) -> Option<Self> {
+        NonNull::new(ptr).map(|ptr| VirtioDev { ptr })
+    }
+
+    pub fn as_ptr(&self) -> *mut nk_raw::virtio_pci_dev {
+        self.ptr.as_ptr()
+    }
+
+    fn is_msi_x(&self) -> bool {
+        unsafe { (*self.as_ptr()).itype == nk_raw::virtio_pci_int_type_VIRTIO_PCI_MSI_X_INTERRUPT }
+    }
+
+    fn is_modern(&self) -> bool {
+        unsafe { (*self.as_ptr()).model != nk_raw::virtio_pci_dev_model_VIRTIO_PCI_LEGACY_MODEL }
+    }
+
+    /// Acknowledges the device, accepts the features `select` picks
+    /// from those it offers, and sets up its virtqueues. Returns the
+    /// accepted features.
+    pub fn negotiate(&self, select: impl FnOnce(u64) -> u64) -> Result<u64> {
+        let d = self.as_ptr();
+        if self.is_modern() && !self.is_msi_x() {
+            error_print!("virtio: modern devices are only supported with MSI-X");
+            return Err(KError::NO_DEVICE);
+        }
+        KError::from_ret(unsafe { nk_raw::virtio_pci_ack_device(d) })?;
+        KError::from_ret(unsafe { nk_raw::virtio_pci_read_features(d) })?;
+        let offered = unsafe { (*d).feat_offered };
+        let mut accepted = select(offered);
+        if self.is_modern() {
+            accepted |= offered & F_VERSION_1;
+        }
+        KError::from_ret(unsafe { nk_raw::virtio_pci_write_features(d, accepted) })?;
+        KError::from_ret(unsafe { nk_raw::virtio_pci_virtqueue_init(d) })?;
+        Ok(accepted)
+    }
+
+    // frees the virtqueues `negotiate` set up, which the device must not
+    // be using
+    fn release_queues(&self) {
+        unsafe { nk_raw::virtio_pci_virtqueue_deinit(self.as_ptr()) };
+    }
+
+    /// Undoes `negotiate`, for a bring-up that failed before routing
+    /// interrupts: resets the device, so that it uses the queues no
+    /// more, and releases them.
+    pub fn abandon(&self) {
+        unsafe { nk_raw::virtio_pci_reset_device(self.as_ptr()) };
+        self.release_queues();
+    }
+
+    /// Brings the device down once interrupts may have been routed to
+    /// the handler for `driver`, whether its bring-up failed or it is
+    /// torn down: masks them and resets the device, then, with no
+    /// handler running, has `stop` stop the driver, and releases the
+    /// queues. NK cannot take the handler back, so `stop` must leave the
+    /// driver ignoring interrupts, and touching the queues no more.
+    /// Returns what `stop` does, e.g. requests to fail.
+    pub fn shut_down<T, R>(&self, driver: &IRQLock<T>, stop: impl FnOnce(&mut T) -> R) -> R {
+        // handlers run with `driver` locked
+        let mut held = driver.lock();
+        let p = unsafe { (*self.as_ptr()).pci_dev };
+        unsafe {
+            if self.is_msi_x() {
+                nk_raw::pci_dev_mask_msi_x_all(p);
+            } else {
+                // the line may be another device's as well
+                let cmd = nk_raw::pci_dev_cfg_readw(p, PCI_COMMAND);
+                nk_raw::pci_dev_cfg_writew(p, PCI_COMMAND, cmd | PCI_COMMAND_INTX_DISABLE);
+            }
+            nk_raw::virtio_pci_reset_device(self.as_ptr());
+        }
+        let stopped = stop(&mut *held);
+        self.release_queues();
+        stopped
+    }
+
+    // the IRQ NK's IOAPIC setup gives a device's legacy interrupt pin:
+    // INTA to INTD are IRQs 16 to 19, whichever the device
+    fn legacy_irq(&self) -> Result<u8> {
+        let p = unsafe { (*self.as_ptr()).pci_dev };
+        let pin = unsafe { nk_raw::pci_dev_cfg_readw(p, PCI_INTERRUPT) } >> 8;
+        if !(1..=4).contains(&pin) {
+            error_print!("virtio: device has no interrupt pin");
+            return Err(KError::NO_DEVICE);
+        }
+        let irq = 16 + (pin - 1) as u8;
+        if unsafe { nk_raw::nk_irq_is_assigned(irq) } == 0 {
+            error_print!("virtio: IRQ {} is not routed", irq);
+            return Err(KError::NO_DEVICE);
+        }
+        Ok(irq)
+    }
+
+    // where a legacy device's own registers start, after the common
+    // ones, which are longer with MSI-X
+    fn config_offset(&self) -> u32 {
+        if self.is_msi_x() {
+            nk_raw::DEVICE_REGS_START_MSI_X
+        } else {
+            nk_raw::DEVICE_REGS_START_LEGACY
+        }
+    }
+
+    // a modern device's own registers, mapped
+    fn config_ptr(&self, offset: u32) -> *const u8 {
+        unsafe { (*self.as_ptr()).device_specific.add(offset as usize) }
+    }
+
+    /// The byte at `offset` in the device's own registers.
+    pub fn config_u8(&self, offset: u32) -> u8 {
+        if self.is_modern() {
+            return unsafe { ptr::read_volatile(self.config_ptr(offset)) };
+        }
+        unsafe { nk_raw::virtio_pci_read_regb(self.as_ptr(), self.config_offset() + offset) }
+    }
+
+    /// The 32 bits at `offset` in the device's own registers, which
+    /// must be aligned.
+    pub fn config_u32(&self, offset: u32) -> u32 {
+        if self.is_modern() {
+            return unsafe { ptr::read_volatile(self.config_ptr(offset) as *const u32) };
+        }
+        unsafe { nk_raw::virtio_pci_read_regl(self.as_ptr(), self.config_offset() + offset) }
+    }
+
+    pub fn num_queues(&self) -> u16 {
+        unsafe { (*self.as_ptr()).num_virtqs as u16 }
+    }
+
+    /// Queue `idx`, once `negotiate` set it up.
+    ///
+    /// # Safety
+    ///
+    /// A queue's buffers are only handed out and taken back through the
+    /// one `Queue`, so each may be taken only once while it is set up.
+    pub unsafe fn queue(&self, idx: u16) -> Result<Queue> {
+        if idx >= self.num_queues() {
+            return Err(KError::NOT_FOUND);
+        }
+        Ok(Queue { dev: *self, idx })
+    }
+
+    /// Routes the device's interrupts to `on_interrupt`, called with
+    /// `driver` in interrupt context; the end of the interrupt is
+    /// signalled for it. NK cannot take an interrupt handler back, so
+    /// `driver` is kept for good.
+    ///
+    /// Without MSI-X, the device's legacy line may be shared with other
+    /// devices, whose handlers run as well; see `take_interrupt`.
+    pub fn route_to<T>(
+        &self,
+        driver: Arc<IRQLock<T>>,
+        on_interrupt: fn(&IRQLock<T>),
+    ) -> Result<()> {
+        let route = Box::into_raw(Box::new(DriverRoute {
+            driver,
+            on_interrupt,
+        }));
+        // as `run_driver` expects
+        unsafe { self.route_interrupts(run_driver::<T>, route as *mut c_void) }
+    }
+
+    // the caller guarantees `handler` expects `state`, which is valid for
+    // good. virtio_pci maps queue `i` to MSI-X table entry `i`
+    unsafe fn route_interrupts(&self, handler: Handler, state: *mut c_void) -> Result<()> {
+        let p = unsafe { (*self.as_ptr()).pci_dev };
+        let route = Route { handler, state };
+        if !self.is_msi_x() {
+            let irq = self.legacy_irq()?;
+            let mut routes = LEGACY_ROUTES.lock();
+            if !routes.iter().any(|(i, _)| *i == irq) {
+                KError::from_ret(unsafe {
+                    nk_raw::register_irq_handler(
+                        irq as u16,
+                        Some(legacy_interrupt),
+                        irq as usize as *mut c_void,
+                    )
+                })?;
+                unsafe { nk_raw::nk_unmask_irq(irq) };
+            }
+            routes.push((irq, route));
+            drop(routes);
+            unsafe {
+                let cmd = nk_raw::pci_dev_cfg_readw(p, PCI_COMMAND);
+                nk_raw::pci_dev_cfg_writew(p, PCI_COMMAND, cmd & !PCI_COMMAND_INTX_DISABLE);
+            }
+            debug_print!("virtio: legacy interrupt on IRQ {}", irq);
+            return Ok(());
+        }
+
+        // MSI-X is on, with the whole function masked until the end
+        let route = Box::into_raw(Box::new(route)) as *mut c_void;
+        for i in 0..self.num_queues() as c_int {
+            let mut vec = 0;
+            KError::from_ret(unsafe { nk_raw::idt_find_and_reserve_range(1, 0, &mut vec) })?;
+            unsafe {
+                KError::from_ret(nk_raw::register_int_handler(
+                    vec as u16,
+                    Some(msi_x_interrupt),
+                    route,
+                ))?;
+                KError::from_ret(nk_raw::pci_dev_set_msi_x_entry(p, i, vec as c_int, 0))?;
+                KError::from_ret(nk_raw::pci_dev_unmask_msi_x_entry(p, i))?;
+            }
+            debug_print!("virtio: queue {} on vector {}", i, vec);
+        }
+        KError::from_ret(unsafe { nk_raw::pci_dev_unmask_msi_x_all(p) }).map(|_| ())
+    }
+
+    /// Whether the device interrupted. A legacy interrupt may be
+    /// shared; reading the status clears it.
+    pub fn take_interrupt(&self) -> bool {
+        self.is_msi_x()
+            || unsafe { nk_raw::virtio_pci_read_regb(self.as_ptr(), nk_raw::ISR_STATUS) } & 1 != 0
+    }
+
+    /// Tells the device the driver is ready.
+    pub fn start(&self) -> Result<()> {
+        KError::from_ret(unsafe { nk_raw::virtio_pci_start_device(self.as_ptr()) }).map(|_| ())
+    }
+
+    /// Gives virtio_pci the driver's `state`, and the `teardown` that
+    /// frees it once the device is brought down.
+    ///
+    /// # Safety
+    ///
+    /// `teardown` must expect `state`.
+    pub unsafe fn set_state(
+        &self,
+        state: *mut c_void,
+        teardown: unsafe extern "C" fn(*mut nk_raw::virtio_pci_dev),
+    ) {
+        unsafe {
+            (*self.as_ptr()).state = state;
+            (*self.as_ptr()).teardown = Some(teardown);
+        }
+    }
+}
+
+/// A buffer handed to the device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Buf {
+    pub addr: u64,
+    pub len: u32,
+    /// Whether the device writes it, rather than reads it.
+    pub device_writes: bool,
+}
+
+impl Buf {
+    /// A buffer the device reads.
+    pub fn readable(addr: u64, len: u32) -> Self {
+        Buf {
+            addr,
+            len,
+            device_writes: false,
+        }
+    }
+
+    /// A buffer the device writes.
+    pub fn writable(addr: u64, len: u32) -> Self {
+        Buf {
+            addr,
+            len,
+            device_writes: true,
+        }
+    }
+}
+
+/// One of a device's virtqueues.
+pub struct Queue {
+    dev: VirtioDev,
+    idx: u16,
+}
+
+impl Queue {
+    fn virtq(&self) -> *mut nk_raw::virtio_pci_virtq {
+        // `VirtioDev::queue` checked there is one
+        unsafe { addr_of_mut!((*self.dev.as_ptr()).virtq[self.idx as usize]) }
+    }
+
+    /// How many descriptors it has. A chain's first descriptor, which
+    /// identifies it, is below this.
+    pub fn size(&self) -> u16 {
+        unsafe { (*self.virtq()).vq.qsz }
+    }
+
+    /// Hands the device a chain of `bufs` and notifies it. Returns the
+    /// first descriptor, which `next_used` returns once the device is
+    /// done with the chain. Fails with `BUSY` when there are not enough
+    /// free descriptors.
+    ///
+    /// # Safety
+    ///
+    /// The buffers must be valid, and stay so and not be otherwise
+    /// used, until the device is done with them.
+    pub unsafe fn submit(&mut self, bufs: &[Buf]) -> Result<u16> {
+        let count = u16::try_from(bufs.len()).map_err(|_| KError::INVALID_ARG)?;
+        if count == 0 || count > self.size() {
+            return Err(KError::INVALID_ARG);
+        }
+        let d = self.dev.as_ptr();
+        let mut chain = vec![0u16; bufs.len()];
+        let r =
+            unsafe { nk_raw::virtio_pci_desc_chain_alloc(d, self.idx, chain.as_mut_ptr(), count) };
+        if r != 0 {
+            return Err(KError::BUSY);
+        }
+
+        let virtq = self.virtq();
+        unsafe {
+            let vq = &mut (*virtq).vq;
+            for (i, b) in bufs.iter().enumerate() {
+                // the descriptors are ours until the device uses them
+                let desc = vq.desc.add(chain[i] as usize);
+                let mut flags = if b.device_writes {
+                    nk_raw::VIRTQ_DESC_F_WRITE
+                } else {
+                    0
+                };
+                if i + 1 < chain.len() {
+                    flags |= nk_raw::VIRTQ_DESC_F_NEXT;
+                }
+                (*desc).addr = b.addr;
+                (*desc).len = b.len;
+                (*desc).flags = flags as u16;
+                (*desc).next = chain.get(i + 1).copied().unwrap_or(0);
+            }
+
+            // the device reads the ring, so it must see the descriptors
+            // before the index that hands them over
+            let avail = vq.avail;
+            let idx = ptr::read_volatile(addr_of!((*avail).idx));
+            let slot = (*avail).ring.as_mut_ptr().add((idx % vq.qsz) as usize);
+            ptr::write_volatile(slot, chain[0]);
+            fence(Ordering::SeqCst);
+            ptr::write_volatile(addr_of_mut!((*avail).idx), idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+            nk_raw::virtio_pci_virtqueue_notify(d, self.idx);
+        }
+        BUFFERS.add(bufs.len() as u64);
+        Ok(chain[0])
+    }
+
+    /// The next chain the device is done with, if any: its first
+    /// descriptor, and how many bytes the device wrote into it. Its
+    /// descriptors are freed for the next.
+    pub fn next_used(&mut self) -> Option<(u16, u32)> {
+        let virtq = self.virtq();
+        let (head, len) = unsafe {
+            let vq = &(*virtq).vq;
+            let used = ptr::read_volatile(addr_of!((*vq.used).idx));
+            let seen = (*virtq).last_seen_used;
+            if seen == used {
+                return None;
+            }
+            // and the element it wrote before the index
+            fence(Ordering::SeqCst);
+            let elem = (*vq.used).ring.as_ptr().add((seen % vq.qsz) as usize);
+            (*virtq).last_seen_used = seen.wrapping_add(1);
+            (
+                ptr::read_volatile(addr_of!((*elem).id)) as u16,
+                ptr::read_volatile(addr_of!((*elem).len)),
+            )
+        };
+        let r = unsafe { nk_raw::virtio_pci_desc_chain_free(self.dev.as_ptr(), self.idx, head) };
+        if r != 0 {
+            error_print!("virtio: cannot free descriptor {}", head);
+        }
+        USED.inc();
+        Some((head, len))
+    }
+}
+
+kernel_test!(
+    fn virtio_queue_rings() {
+        const QSZ: u16 = 4;
+        // every descriptor free, linked as virtio_pci links them
+        let mut desc: [nk_raw::virtq_desc; QSZ as usize] = unsafe { core::mem::zeroed() };
+        for (i, d) in desc.iter_mut().enumerate() {
+            d.next = i as u16 + 1;
+        }
+        // each ring's flags, index, and slots, with room to spare
+        let mut avail = [0u64; 4];
+        let mut used = [0u64; 8];
+        // notified as a modern device, through plain memory
+        let mut common: nk_raw::virtio_pci_common_cfg = unsafe { core::mem::zeroed() };
+        let mut doorbell = 0u32;
+
+        let mut dev: Box<nk_raw::virtio_pci_dev> = Box::new(unsafe { core::mem::zeroed() });
+        dev.model = nk_raw::virtio_pci_dev_model_VIRTIO_PCI_MODERN_MODEL;
+        dev.common = &mut common;
+        dev.notify_base_addr = &mut doorbell;
+        dev.num_virtqs = 1;
+        let desc = desc.as_mut_ptr();
+        let avail = avail.as_mut_ptr() as *mut nk_raw::virtq_avail;
+        let used = used.as_mut_ptr() as *mut nk_raw::virtq_used;
+        let virtq = &mut dev.virtq[0];
+        virtq.vq.qsz = QSZ;
+        virtq.vq.desc = desc;
+        virtq.vq.avail = avail;
+        virtq.vq.used = used;
+        virtq.nfree = QSZ;
+
+        // only the test's, with the one queue taken once
+        let vdev = unsafe { VirtioDev::from_raw(&mut *dev) }.unwrap();
+        let mut q = unsafe { vdev.queue(0) }.unwrap();
+        kassert!(unsafe { vdev.queue(1) }.is_err());
+        kassert_eq!(q.size(), QSZ);
+        kassert!(q.next_used().is_none());
+
+        // the addresses are never used, as there is no device
+        let chain = [Buf::readable(0x1000, 16), Buf::writable(0x2000, 1)];
+        let head = unsafe { q.submit(&chain) }.unwrap();
+        unsafe {
+            // chained in order, and handed over in the next slot
+            let first = &*desc.add(head as usize);
+            kassert_eq!((first.addr, first.len), (0x1000, 16));
+            kassert_eq!(first.flags, nk_raw::VIRTQ_DESC_F_NEXT as u16);
+            let second = &*desc.add(first.next as usize);
+            kassert_eq!((second.addr, second.len), (0x2000, 1));
+            kassert_eq!(second.flags, nk_raw::VIRTQ_DESC_F_WRITE as u16);
+            kassert_eq!((*avail).idx, 1);
+            kassert_eq!(*(*avail).ring.as_ptr(), head);
+            kassert_eq!(ptr::read_volatile(&doorbell), u32::MAX);
+        }
+        // two descriptors are left
+        let three = [Buf::readable(0x1000, 1); 3];
+        kassert_eq!(unsafe { q.submit(&three) }, Err(KError::BUSY));
+        kassert_eq!(unsafe { q.submit(&[]) }, Err(KError::INVALID_ARG));
+
+        // the device is done with the chain, having written a byte
+        unsafe {
+            let elem = (*used).ring.as_mut_ptr();
+            (*elem).id = head as u32;
+            (*elem).len = 1;
+            (*used).idx = 1;
+        }
+        kassert_eq!(q.next_used(), Some((head, 1)));
+        kassert!(q.next_used().is_none());
+        // and its descriptors are free again
+        let all = [Buf::readable(0x1000, 1); QSZ as usize];
+        kassert!(unsafe { q.submit(&all) }.is_ok());
+    }
+);
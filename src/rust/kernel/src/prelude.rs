@@ -0,0 +1,72 @@
+// what a Rust driver or subsystem can rely on, in one import:
+//
+// ```ignore
+// use kernel::prelude::*;
+// ```
+//
+// everything here is the supported kernel API: its names and meaning
+// only change along with every user in the tree. modules not
+// re-exported here are still public, for the shell commands and tools
+// built on them, but are internals that may change with the code
+// around them. that includes `nk_raw`, which is for writing wrappers,
+// and the `#[no_mangle]` entry points (`nk_rust_*`, `*_shell_entry`),
+// which are only for C to call, and are hidden from the docs. glue
+// that nothing outside the kernel crate needs is `pub(crate)`.
+//
+// the macros are also in scope by name everywhere in the crate.
+
+// the heap
+pub use alloc::borrow::ToOwned;
+pub use alloc::boxed::Box;
+pub use alloc::format;
+pub use alloc::string::{String, ToString};
+pub use alloc::sync::Arc;
+pub use alloc::vec::Vec;
+
+// errors
+pub use crate::nk_error::{KError, Result};
+
+// locking
+pub use crate::nk_lock::IRQLock;
+
+// logging and assertions
+pub use crate::{debug_fast, error_fast, info_fast, warn_fast};
+pub use crate::{debug_kassert, debug_kassert_eq, kassert, kassert_eq};
+pub use crate::{debug_print, error_print, info_print, warn_print};
+
+// configuration and boot
+pub use crate::nk_cmdline;
+pub use crate::{nk_config_enabled, nk_init};
+
+// power
+pub use crate::nk_power::{poweroff, reboot};
+
+// testing and fault injection
+pub use crate::{fault_point, kernel_test};
+
+// metrics
+pub use crate::{counter, histogram};
+
+// randomness
+pub use crate::nk_rand;
+
+// time
+pub use crate::nk_time::timer::{PeriodicTimer, Timer};
+pub use crate::nk_time::{sleep, Deadline, Duration, Instant};
+
+// CPUs, threads, and the scheduler
+pub use crate::nk_sched::rt::{RtConstraints, RtThread};
+pub use crate::nk_smp::{call_on, current_cpu, num_cpus, run_on_all_cpus};
+
+// memory-mapped I/O and DMA
+pub use crate::nk_alloc::dma::{DmaBox, DmaSlice};
+pub use crate::nk_aspace::{map_phys, Caching, Mmio, PhysAddr};
+
+// devices: the trait a driver implements, with the rest of what it
+// registers (`Registration`, `Characteristics`, ...) under the module
+pub use crate::nk_blkdev::{self, BlkDev};
+pub use crate::nk_chardev::{self, CharDev};
+pub use crate::nk_gpudev::{self, GpuDev};
+pub use crate::nk_netdev::{self, NetDev};
+#[cfg(NAUT_CONFIG_VIRTIO_PCI)]
+pub use crate::nk_virtio::{self, VirtioDev};
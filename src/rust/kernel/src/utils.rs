@@ -0,0 +1,93 @@
+use alloc::ffi::CString;
+use core::fmt;
+
+use crate::nk_raw;
+
+/// Takes a `&str` and provides a C-flavored string that can be passed via FFI.
+/// Unless followed by a call to `CString::from_raw` on the returned pointer,
+/// this function will leak memory.
+pub fn to_c_string(s: &str) -> *mut i8 {
+    let c_str = CString::new(s).expect("CString::new failed - did you include a nul byte?");
+    c_str.into_raw()
+}
+
+pub fn print_to_vc(s: &str) {
+    let c_str = to_c_string(s);
+    unsafe {
+        // c_str is safe to pass to nk_vc_print;
+        // it is a nul-terminated C string.
+        // (not nk_vc_printf, which would interpret any '%' in `s`)
+        nk_raw::nk_vc_print(c_str);
+        // nk_vc_print obeys the invariant required for `from_raw`
+        // (it does not mutate or free the string).
+        // We are free to "take back" the memory associated with the string.
+        _ = CString::from_raw(c_str);
+        // memory allocated for the string should be freed here.
+    }
+}
+
+// output is printed in chunks of at most this many bytes
+const VC_WRITER_LEN: usize = 256;
+
+/// `core::fmt::Write` for the virtual console, for printing output
+/// piece by piece (tables, progress bars, ...) without allocating.
+///
+/// Output is buffered, and printed when the buffer fills, on a
+/// newline, on `flush`, or when the writer is dropped.
+pub struct VcWriter {
+    buf: [u8; VC_WRITER_LEN],
+    len: usize,
+}
+
+impl VcWriter {
+    pub const fn new() -> Self {
+        VcWriter {
+            buf: [0; VC_WRITER_LEN],
+            len: 0,
+        }
+    }
+
+    /// Prints anything buffered, e.g. to update a progress bar
+    /// that has no newline.
+    pub fn flush(&mut self) {
+        if self.len == 0 {
+            return;
+        }
+        self.buf[self.len] = 0;
+        unsafe {
+            // `buf` is nul-terminated, and `write_str` replaced
+            // any interior nul bytes
+            nk_raw::nk_vc_print(self.buf.as_mut_ptr() as *mut i8);
+        }
+        self.len = 0;
+    }
+}
+
+impl Default for VcWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Write for VcWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            // always leave room for the nul terminator
+            if self.len == VC_WRITER_LEN - 1 {
+                self.flush();
+            }
+            self.buf[self.len] = if b == 0 { b'?' } else { b };
+            self.len += 1;
+            if b == b'\n' {
+                self.flush();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for VcWriter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
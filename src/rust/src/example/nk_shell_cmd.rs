@@ -1,6 +1,6 @@
-use core::ffi::{c_char, c_int, c_void};
-use crate::utils::print_to_vc;
 use super::nk_rust_example;
+use core::ffi::{c_char, c_int, c_void};
+use kernel::utils::print_to_vc;
 
 // this handler function can be called from the shell after registering it
 // unsure whether `buf` and `priv` can be `mut`, keeping `const` to be safe
@@ -0,0 +1,404 @@
+// a text console drawn on a gpudev device in graphics mode. it is a
+// chardev that the VC mirrors the default console's output into, so
+// everything printed there shows up on screen, drawn by Rust: glyphs
+// from a bitmap font, a cursor, and a scrollback to look back through.
+//
+// text is kept as rows of screen width, so long lines are wrapped as
+// they come in, and a row never needs to be laid out again.
+use alloc::collections::VecDeque;
+use alloc::vec;
+use core::ops::Range;
+
+use kernel::nk_chardev::{Registration, Status};
+use kernel::nk_gpudev::font::{self, Font};
+use kernel::nk_gpudev::{BlitOp, Pixel, Rect, SavedMode};
+use kernel::nk_raw;
+use kernel::prelude::*;
+
+pub mod nk_shell_cmd;
+
+/// The name of the chardev the console registers.
+pub const NAME: &str = "fbcon";
+// rows kept once they scroll off the top of the screen
+const SCROLLBACK: usize = 500;
+const TAB: u32 = 8;
+// the cursor is a bar under the character it is on, this many pixels
+// high
+const CURSOR_HEIGHT: u32 = 2;
+
+const FOREGROUND: (u8, u8, u8) = (0xaa, 0xaa, 0xaa);
+const BACKGROUND: (u8, u8, u8) = (0, 0, 0);
+
+/// The rows of text, the last of them the one being written.
+struct Text {
+    rows: VecDeque<Vec<u8>>,
+    // the most rows kept
+    max: usize,
+}
+
+impl Text {
+    fn new(max: usize) -> Self {
+        let mut rows = VecDeque::new();
+        rows.push_back(Vec::new());
+        Text { rows, max }
+    }
+
+    fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn push_row(&mut self) {
+        self.rows.push_back(Vec::new());
+        if self.rows.len() > self.max {
+            self.rows.pop_front();
+        }
+    }
+
+    /// Puts `c` at `col` of the last row.
+    fn set(&mut self, col: u32, c: u8) {
+        let row = self.rows.back_mut().unwrap();
+        let col = col as usize;
+        if row.len() <= col {
+            row.resize(col + 1, b' ');
+        }
+        row[col] = c;
+    }
+
+    fn get(&self, row: usize, col: u32) -> u8 {
+        self.rows[row].get(col as usize).copied().unwrap_or(b' ')
+    }
+
+    /// Which rows a screen `height` rows high shows when scrolled back
+    /// `back` rows, and how far back that is once limited to the rows
+    /// there are.
+    fn window(&self, height: u32, back: usize) -> (Range<usize>, usize) {
+        let back = back.min(self.len().saturating_sub(height as usize));
+        let end = self.len() - back;
+        (end.saturating_sub(height as usize)..end, back)
+    }
+}
+
+// a glyph as spans to fill, see `Font::spans`
+type Glyph = Vec<Rect>;
+
+pub struct Console {
+    dev: GpuDev,
+    // put back when the console is dropped
+    _saved: SavedMode,
+    font: &'static Font,
+    // the size of the screen in characters
+    cols: u32,
+    rows: u32,
+    fg: Pixel,
+    bg: Pixel,
+    text: Text,
+    // where the cursor is on the last row; it can be one past the
+    // end, until the next character wraps it onto a new row
+    col: u32,
+    // how many rows the screen is scrolled back
+    back: usize,
+    // by character code, filled in as they are first drawn
+    glyphs: Vec<Option<Glyph>>,
+    // set once the console is being taken down, see its `CharDev`
+    closing: bool,
+}
+
+impl Console {
+    /// Switches `device` to its first graphics mode, and clears it.
+    /// The mode it was in is restored when the console is dropped.
+    pub fn open(device: &str, font: &'static Font) -> Result<Self> {
+        let dev = GpuDev::find(device)?;
+        let saved = dev.save_mode()?;
+        let mode = dev
+            .available_modes()?
+            .into_iter()
+            .find(|m| m.is_graphics())
+            .ok_or(KError::NOT_FOUND)?;
+        let (cols, rows) = (mode.width() / font.width(), mode.height() / font.height());
+        if cols == 0 || rows == 0 {
+            return Err(KError::INVALID_ARG);
+        }
+        dev.set_mode(&mode)?;
+
+        let (r, g, b) = FOREGROUND;
+        let fg = mode.rgb(r, g, b);
+        let (r, g, b) = BACKGROUND;
+        let bg = mode.rgb(r, g, b);
+        let mut console = Console {
+            dev,
+            _saved: saved,
+            font,
+            cols,
+            rows,
+            fg,
+            bg,
+            text: Text::new(rows as usize + SCROLLBACK),
+            col: 0,
+            back: 0,
+            glyphs: vec![None; 256],
+            closing: false,
+        };
+        console.redraw()?;
+        console.dev.flush()?;
+        Ok(console)
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.cols, self.rows)
+    }
+
+    fn cell(&self, col: u32, row: u32) -> Rect {
+        let (w, h) = (self.font.width(), self.font.height());
+        Rect::new(col * w, row * h, w, h)
+    }
+
+    fn draw_char(&mut self, col: u32, row: u32, c: u8) -> Result<()> {
+        let cell = self.cell(col, row);
+        self.dev.fill_box(cell, self.bg, BlitOp::Copy)?;
+        let font = self.font;
+        let glyph = self.glyphs[c as usize].get_or_insert_with(|| font.spans(c));
+        for span in glyph.iter() {
+            let at = Rect::new(cell.x + span.x, cell.y + span.y, span.width, span.height);
+            self.dev.fill_box(at, self.fg, BlitOp::Copy)?;
+        }
+        Ok(())
+    }
+
+    // the screen row the last row of text is on, when it is on screen
+    fn last_row(&self) -> Option<u32> {
+        let (shown, _) = self.text.window(self.rows, self.back);
+        (self.back == 0).then_some((shown.end - 1 - shown.start) as u32)
+    }
+
+    // draws the cursor, or the character under it to hide it
+    fn draw_cursor(&mut self, show: bool) -> Result<()> {
+        let row = match self.last_row() {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let col = self.col.min(self.cols - 1);
+        if show {
+            let cell = self.cell(col, row);
+            let bar = Rect::new(
+                cell.x,
+                cell.y + cell.height - CURSOR_HEIGHT,
+                cell.width,
+                CURSOR_HEIGHT,
+            );
+            self.dev.fill_box(bar, self.fg, BlitOp::Copy)
+        } else {
+            let c = self.text.get(self.text.len() - 1, col);
+            self.draw_char(col, row, c)
+        }
+    }
+
+    fn redraw(&mut self) -> Result<()> {
+        let whole = Rect::new(
+            0,
+            0,
+            self.cols * self.font.width(),
+            self.rows * self.font.height(),
+        );
+        self.dev.fill_box(whole, self.bg, BlitOp::Copy)?;
+        let (shown, _) = self.text.window(self.rows, self.back);
+        for (row, i) in shown.enumerate() {
+            for col in 0..self.cols {
+                let c = self.text.get(i, col);
+                if c != b' ' {
+                    self.draw_char(col, row as u32, c)?;
+                }
+            }
+        }
+        self.draw_cursor(true)
+    }
+
+    fn new_row(&mut self) -> Result<()> {
+        self.text.push_row();
+        self.col = 0;
+        if self.text.len() <= self.rows as usize {
+            // the row below was already blank
+            return Ok(());
+        }
+        // everything moves up a row, which the device does for us
+        let (w, h) = (self.cols * self.font.width(), self.font.height());
+        let below = Rect::new(0, h, w, (self.rows - 1) * h);
+        self.dev
+            .copy_box(below, Rect::new(0, 0, w, below.height), BlitOp::Copy)?;
+        let last = Rect::new(0, (self.rows - 1) * h, w, h);
+        self.dev.fill_box(last, self.bg, BlitOp::Copy)
+    }
+
+    fn put(&mut self, c: u8) -> Result<()> {
+        if self.col >= self.cols {
+            self.new_row()?;
+        }
+        self.text.set(self.col, c);
+        let row = self.last_row().unwrap();
+        self.draw_char(self.col, row, c)?;
+        self.col += 1;
+        Ok(())
+    }
+
+    fn write_byte(&mut self, b: u8) -> Result<()> {
+        match b {
+            b'\n' => self.new_row(),
+            b'\r' => {
+                self.col = 0;
+                Ok(())
+            }
+            // backspace only moves back; the shell overwrites what it
+            // erases with a space
+            0x08 => {
+                self.col = self.col.min(self.cols).saturating_sub(1);
+                Ok(())
+            }
+            b'\t' => {
+                self.put(b' ')?;
+                while self.col % TAB != 0 && self.col < self.cols {
+                    self.put(b' ')?;
+                }
+                Ok(())
+            }
+            // other control characters, including escape sequences'
+            // escapes, are not drawn
+            0..=0x1f | 0x7f => Ok(()),
+            c => self.put(c),
+        }
+    }
+
+    /// Draws `bytes` at the cursor. Anything written brings a screen
+    /// scrolled back down to the bottom.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        if self.back != 0 {
+            self.back = 0;
+            self.redraw()?;
+        }
+        self.draw_cursor(false)?;
+        for &b in bytes {
+            self.write_byte(b)?;
+        }
+        self.draw_cursor(true)?;
+        self.dev.flush()
+    }
+
+    /// Shows the screen as it was `rows` rows ago, or as far back as
+    /// the scrollback goes. Returns how far back that is.
+    pub fn scroll_back(&mut self, rows: usize) -> Result<usize> {
+        let (_, back) = self.text.window(self.rows, rows);
+        self.back = back;
+        self.redraw()?;
+        self.dev.flush()?;
+        Ok(back)
+    }
+}
+
+// the console as a write-only chardev, for the VC to print into.
+//
+// the VC's chardev console thread also reads from the device, for
+// keyboard input, and blocks until it is readable. to stop that thread
+// it signals the device and waits for the read to fail, so once the
+// console is closing it reports itself readable, and reads fail.
+impl CharDev for Console {
+    fn read(&mut self) -> Result<Option<u8>> {
+        if self.closing {
+            Err(KError::FAILED)
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn write(&mut self, byte: u8) -> Result<bool> {
+        Console::write(self, &[byte]).map(|_| true)
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            readable: self.closing,
+            writeable: true,
+            error: false,
+        }
+    }
+}
+
+struct Active {
+    console: Arc<IRQLock<Console>>,
+    // unregisters the chardev when dropped, before the console's mode
+    // is restored
+    _chardev: Registration<Console>,
+}
+
+static ACTIVE: IRQLock<Option<Active>> = IRQLock::new(None);
+
+/// Starts the console on the gpudev device `device`. Returns its size
+/// in characters. There can only be one.
+pub fn start(device: &str) -> Result<(u32, u32)> {
+    if ACTIVE.lock().is_some() {
+        return Err(KError::EXISTS);
+    }
+    let console = Console::open(device, font::selected())?;
+    let size = console.size();
+    let console = Arc::new(IRQLock::new(console));
+    let chardev = Registration::register(NAME, console.clone())?;
+    {
+        let mut active = ACTIVE.lock();
+        if active.is_some() {
+            return Err(KError::EXISTS);
+        }
+        *active = Some(Active {
+            console,
+            _chardev: chardev,
+        });
+    }
+
+    let name = format!("{}\0", NAME);
+    // only reads the name, and copies it
+    let r = unsafe { nk_raw::nk_vc_start_chardev_console(name.as_ptr() as *mut i8) };
+    if let Err(e) = KError::from_ret(r) {
+        ACTIVE.lock().take();
+        return Err(e);
+    }
+    Ok(size)
+}
+
+/// Stops the console, and puts its device back in the mode it was in.
+pub fn stop() -> Result<()> {
+    let active = ACTIVE.lock().take().ok_or(KError::NOT_FOUND)?;
+    active.console.lock().closing = true;
+    let name = format!("{}\0", NAME);
+    // waits for the VC's thread reading from the chardev to see
+    // `closing` and exit
+    unsafe { nk_raw::nk_vc_stop_chardev_console(name.as_ptr() as *mut i8) };
+    drop(active);
+    Ok(())
+}
+
+/// See `Console::scroll_back`.
+pub fn scroll_back(rows: usize) -> Result<usize> {
+    let console = ACTIVE
+        .lock()
+        .as_ref()
+        .map(|a| a.console.clone())
+        .ok_or(KError::NOT_FOUND)?;
+    let mut console = console.lock();
+    console.scroll_back(rows)
+}
+
+kernel_test!(
+    fn fbcon_text_window() {
+        let mut text = Text::new(5);
+        for c in b"abcdef" {
+            text.set(0, *c);
+            text.push_row();
+        }
+        // "a" fell off the top, and the last row is still empty
+        kassert_eq!(text.len(), 5);
+        kassert_eq!(text.get(0, 0), b'c');
+        kassert_eq!(text.get(4, 0), b' ');
+
+        kassert_eq!(text.window(3, 0), (2..5, 0));
+        kassert_eq!(text.window(3, 1), (1..4, 1));
+        // no further back than the first row
+        kassert_eq!(text.window(3, 10), (0..3, 2));
+        kassert_eq!(text.window(8, 1), (0..5, 0));
+    }
+);
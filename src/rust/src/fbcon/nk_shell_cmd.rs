@@ -0,0 +1,44 @@
+use core::ffi::{c_char, c_int, c_void, CStr};
+
+use super::{scroll_back, start, stop, NAME};
+
+const USAGE: &str = "usage: rust_fbcon <device> | off | scroll <rows>";
+
+// `rust_fbcon <device>` starts the framebuffer console on a gpudev device,
+// `rust_fbcon off` stops it, and `rust_fbcon scroll <rows>` looks back
+// through its scrollback (anything printed scrolls it back down)
+#[no_mangle]
+pub unsafe extern "C" fn rust_fbcon_shell_entry(
+    buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let mut args = line.split_whitespace().skip(1);
+
+    let result = match (args.next(), args.next()) {
+        (Some("off"), None) => stop().map(|_| info_print!("rust_fbcon: stopped")),
+        (Some("scroll"), Some(n)) => match n.parse::<usize>() {
+            Ok(n) => scroll_back(n).map(|back| info_print!("rust_fbcon: {} rows back", back)),
+            Err(_) => {
+                error_print!("{}", USAGE);
+                return -1;
+            }
+        },
+        (Some(device), None) if device != "scroll" => start(device).map(|(cols, rows)| {
+            info_print!("rust_fbcon: {} on {}, {}x{}", NAME, device, cols, rows)
+        }),
+        _ => {
+            error_print!("{}", USAGE);
+            return -1;
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            error_print!("rust_fbcon: {}", e.name());
+            e.code()
+        }
+    }
+}
@@ -1,12 +1,7 @@
-// unstable feature core::ffi
-#![feature(panic_info_message)]
-#![feature(alloc_error_handler)]
-#![feature(alloc_c_string)]
-#![feature(core_ffi_c)]
-#![feature(core_c_str)]
-#![feature(c_size_t)]
-#![feature(lang_items)]
-// no stdlib
+// the in-tree Rust drivers, built on the `kernel` crate like an
+// out-of-tree module would be. this is the staticlib NK links, so it
+// also pulls in `kernel`, with the allocator, panic handler, and the
+// C entry points that live there.
 #![no_std]
 #![no_builtins]
 // use saner, more strict interpretation of `unsafe fn`
@@ -17,10 +12,16 @@
 #![deny(unsafe_op_in_unsafe_fn)]
 
 extern crate alloc;
+// the logging, assertion, and registration macros
+#[macro_use]
+extern crate kernel;
+
 mod example;
-mod parport;
-pub mod nk_alloc;
-pub mod nk_bindings;
-pub mod nk_panic;
-//pub mod nk_shell_cmd;
-pub mod utils;
+config_module!(NAUT_CONFIG_RUST_PARPORT, mod parport, stubs: [parport_shell_entry]);
+config_module!(NAUT_CONFIG_RUST_VIRTIO_BLK, mod virtio_blk);
+config_module!(NAUT_CONFIG_RUST_VIRTIO_NET, mod virtio_net);
+config_module!(NAUT_CONFIG_RUST_VIRTIO_CONSOLE, mod virtio_console);
+config_module!(NAUT_CONFIG_RUST_VIRTIO_RNG, mod virtio_rng);
+config_module!(NAUT_CONFIG_RUST_VIRTIO_INPUT, mod virtio_input, stubs: [rust_vinput_shell_entry]);
+config_module!(NAUT_CONFIG_RUST_FBCON, mod fbcon, stubs: [rust_fbcon_shell_entry]);
+config_module!(NAUT_CONFIG_RUST_SNAKE, mod snake, stubs: [rust_snake_shell_entry]);
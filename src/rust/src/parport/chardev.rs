@@ -1,22 +1,23 @@
-const CHARDEV_RW: c_int =
-    (nk_bindings::NK_CHARDEV_READABLE | nk_bindings::NK_CHARDEV_WRITEABLE) as c_int;
+const CHARDEV_RW: c_int = (nk_raw::NK_CHARDEV_READABLE | nk_raw::NK_CHARDEV_WRITEABLE) as c_int;
 
 use core::{
     ffi::{c_int, c_void},
-    fmt::Error,
     intrinsics::write_bytes,
     ptr::null_mut,
 };
 
-use alloc::{borrow::ToOwned, string::String, sync::Arc};
+use kernel::{
+    nk_crash, nk_raw,
+    prelude::*,
+    utils::{print_to_vc, to_c_string},
+};
 
-use crate::utils::print_to_vc;
-use crate::{nk_bindings, utils::to_c_string};
+use super::Parport;
 
-use super::{lock::IRQLock, Parport};
+fault_point!(REGISTER_FAULT, "chardev_register");
 
 pub struct NkCharDev {
-    dev: *mut nk_bindings::nk_char_dev,
+    dev: *mut nk_raw::nk_char_dev,
     name: String,
 }
 
@@ -33,51 +34,57 @@ impl NkCharDev {
     }
 
     pub fn signal(&mut self) {
-        if self.dev.is_null() {
-            panic!("not registered");
-        }
+        kassert!(!self.dev.is_null(), "{} not registered", self.name);
 
-        let d = self.dev as *mut nk_bindings::nk_dev;
+        let d = self.dev as *mut nk_raw::nk_dev;
         unsafe {
-            nk_bindings::nk_dev_signal(d);
+            nk_raw::nk_dev_signal(d);
         }
     }
 
-    pub fn register(&mut self, parport: Arc<IRQLock<Parport>>) -> Result<(), Error> {
+    pub fn register(&mut self, parport: Arc<IRQLock<Parport>>) -> Result<()> {
         print_to_vc("register device\n");
 
-        if !self.dev.is_null() {
-            panic!("attempted to register NkCharDev twice");
-        }
+        kassert!(
+            self.dev.is_null(),
+            "attempted to register NkCharDev {} twice",
+            self.name
+        );
+        REGISTER_FAULT.check(KError::FAILED)?;
 
         // TODO: fix leak of this C string on unregistration
         let name_bytes = to_c_string(&self.name);
         let parport_ptr = Arc::into_raw(parport);
-        let cd = &CHARDEV_INTERFACE as *const nk_bindings::nk_char_dev_int;
+        let cd = &CHARDEV_INTERFACE as *const nk_raw::nk_char_dev_int;
         let r;
         unsafe {
-            r = nk_bindings::nk_char_dev_register(
+            r = nk_raw::nk_char_dev_register(
                 name_bytes,
                 0,
                 // not actually mutable, but C code had no `const` qualifier
-                cd as *mut nk_bindings::nk_char_dev_int,
+                cd as *mut nk_raw::nk_char_dev_int,
                 // not actually mutable, but C code had no `const` qualifier
                 parport_ptr as *mut c_void,
             );
         }
 
         self.dev = r;
-        (!r.is_null()).then(|| ()).ok_or(Error)
+        if r.is_null() {
+            return Err(KError::FAILED);
+        }
+        nk_crash::devices::register(r as *const u8, "chardev", &self.name);
+        Ok(())
     }
 }
 
 impl Drop for NkCharDev {
     fn drop(&mut self) {
         if let Some(ptr) = unsafe { self.dev.as_mut() } {
+            nk_crash::devices::unregister(ptr as *const _ as *const u8);
             unsafe {
                 // taking back `Arc` is safe from any non-null `chardev` we registered
                 let _ = Arc::from_raw(ptr.dev.state as *const IRQLock<Parport>);
-                nk_bindings::nk_char_dev_unregister(ptr);
+                nk_raw::nk_char_dev_unregister(ptr);
             }
         }
     }
@@ -133,7 +140,7 @@ pub unsafe extern "C" fn write(state: *mut c_void, src: *mut u8) -> c_int {
 
 pub unsafe extern "C" fn get_characteristics(
     _state: *mut c_void,
-    c: *mut nk_bindings::nk_char_dev_characteristics,
+    c: *mut nk_raw::nk_char_dev_characteristics,
 ) -> c_int {
     unsafe {
         // memset the (single) struct to bytes of 0
@@ -142,12 +149,12 @@ pub unsafe extern "C" fn get_characteristics(
     0
 }
 
-const CHARDEV_INTERFACE: nk_bindings::nk_char_dev_int = nk_bindings::nk_char_dev_int {
+const CHARDEV_INTERFACE: nk_raw::nk_char_dev_int = nk_raw::nk_char_dev_int {
     get_characteristics: Some(get_characteristics),
     read: Some(read),
     write: Some(write),
     status: Some(status),
-    dev_int: nk_bindings::nk_dev_int {
+    dev_int: nk_raw::nk_dev_int {
         open: None,
         close: None,
     },
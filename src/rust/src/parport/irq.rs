@@ -1,14 +1,14 @@
 use core::{
     ffi::{c_int, c_void},
-    fmt::Error,
     ptr::null,
 };
 
-use alloc::sync::Arc;
+use kernel::{nk_crash, nk_log::fast::Hex, nk_raw, prelude::*};
 
-use crate::nk_bindings;
+use super::Parport;
 
-use super::{lock::IRQLock, Parport};
+counter!(IRQS, "irqs");
+fault_point!(REGISTER_FAULT, "irq_register");
 
 pub struct Irq {
     num: u8,
@@ -25,16 +25,17 @@ impl Irq {
         }
     }
 
-    pub unsafe fn register(&mut self, parport: Arc<IRQLock<Parport>>) -> Result<(), Error> {
+    pub unsafe fn register(&mut self, parport: Arc<IRQLock<Parport>>) -> Result<()> {
         if self.registered {
-            return Err(Error);
+            return Err(KError::EXISTS);
         }
+        REGISTER_FAULT.check(KError::FAILED)?;
 
         let handler = interrupt_handler;
         self.arc_ptr = Arc::into_raw(parport);
         let result;
         unsafe {
-            result = nk_bindings::register_irq_handler(
+            result = nk_raw::register_irq_handler(
                 self.num.into(),
                 Some(handler),
                 self.arc_ptr as *mut c_void,
@@ -43,14 +44,15 @@ impl Irq {
 
         if result == 0 {
             unsafe {
-                nk_bindings::nk_unmask_irq(self.num);
+                nk_raw::nk_unmask_irq(self.num);
             }
             self.registered = true;
+            nk_crash::devices::register(self.arc_ptr as *const u8, "irq", &format!("{}", self.num));
             Ok(())
         } else {
             // taking back `Arc` is safe if handler registration never succeeded
             let _ = unsafe { Arc::from_raw(self.arc_ptr) };
-            Err(Error)
+            Err(KError::from_code(result))
         }
     }
 }
@@ -58,8 +60,9 @@ impl Irq {
 impl Drop for Irq {
     fn drop(&mut self) {
         if self.registered {
+            nk_crash::devices::unregister(self.arc_ptr as *const u8);
             unsafe {
-                nk_bindings::nk_mask_irq(self.num);
+                nk_raw::nk_mask_irq(self.num);
                 Arc::from_raw(self.arc_ptr);
             }
         }
@@ -76,17 +79,20 @@ unsafe fn deref_locked_state<'a>(state: *mut c_void) -> &'a IRQLock<Parport> {
 }
 
 pub unsafe extern "C" fn interrupt_handler(
-    _excp: *mut nk_bindings::excp_entry_t,
-    _vec: nk_bindings::excp_vec_t,
+    _excp: *mut nk_raw::excp_entry_t,
+    vec: nk_raw::excp_vec_t,
     state: *mut c_void,
 ) -> c_int {
+    debug_fast!("interrupt on vector ", Hex(vec));
+    IRQS.inc();
+
     let p = unsafe { deref_locked_state(state) };
     let mut l = p.lock();
     l.set_ready();
 
     // IRQ_HANDLER_END
     unsafe {
-        nk_bindings::apic_do_eoi();
+        nk_raw::apic_do_eoi();
     }
     0
     // l falls out of scope here, releasing the lock and reenabling interrupts after
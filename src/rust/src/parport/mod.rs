@@ -1,25 +1,28 @@
 use core::ffi::c_int;
-use core::fmt::Error;
 
-use alloc::{string::String, sync::Arc};
 use bitfield::bitfield;
 
-use crate::utils::print_to_vc;
+use kernel::nk_error;
+use kernel::nk_time::timeout::spin_with_timeout;
+use kernel::prelude::*;
+use kernel::utils::print_to_vc;
 use chardev::NkCharDev;
 use irq::Irq;
 use portio::ParportIO;
 
-use self::{lock::IRQLock, portio::io_delay};
+use self::portio::io_delay;
 
 pub mod nk_shell_cmd;
 
 mod chardev;
 mod irq;
-mod lock;
 mod portio;
 
 const PARPORT0_BASE: u16 = 0x378;
 const PARPORT0_IRQ: u8 = 7;
+// how long the attached device can take to become ready; we spin for
+// it with the port locked, so keep it short
+const DEVICE_TIMEOUT: Duration = Duration::from_millis(100);
 
 bitfield! {
     pub struct StatReg(u8);
@@ -61,7 +64,7 @@ pub struct Parport {
 }
 
 impl Parport {
-    pub fn new(dev: NkCharDev, port: ParportIO, irq: Irq) -> Result<Arc<IRQLock<Parport>>, Error> {
+    pub fn new(dev: NkCharDev, port: ParportIO, irq: Irq) -> Result<Arc<IRQLock<Parport>>> {
         let p = Parport {
             dev,
             port,
@@ -91,21 +94,26 @@ impl Parport {
         self.port.write_ctrl(&ctrl);
     }
 
-    fn wait_for_attached_device(&mut self) {
-        //let mut count = 0;
-        loop {
-            io_delay();
-            let stat = self.port.read_stat();
-            //count += 1;
-            if stat.busy() {
-                break;
-            }
+    fn wait_for_attached_device(&mut self) -> Result<()> {
+        let port = &mut self.port;
+        let ready = spin_with_timeout(
+            || {
+                io_delay();
+                port.read_stat().busy()
+            },
+            DEVICE_TIMEOUT,
+        );
+        if ready.is_err() {
+            warn_print!("attached device did not become ready");
+            // nothing was sent, so the port is free again
+            self.state = ParportStatus::Ready;
         }
+        ready
     }
 
-    pub fn write(&mut self, data: u8) -> Result<(), Error> {
+    pub fn write(&mut self, data: u8) -> Result<()> {
         if !self.is_ready() {
-            return Err(Error);
+            return Err(KError::BUSY);
         }
         self.state = ParportStatus::Busy;
 
@@ -115,7 +123,7 @@ impl Parport {
         stat.set_busy(false); // stat.busy = 0
         self.port.write_stat(&stat);
 
-        self.wait_for_attached_device();
+        self.wait_for_attached_device()?;
 
         // set device to output mode
         print_to_vc("setting device to output mode\n");
@@ -139,9 +147,9 @@ impl Parport {
         Ok(())
     }
 
-    fn read(&mut self) -> Result<u8, Error> {
+    fn read(&mut self) -> Result<u8> {
         if !self.is_ready() {
-            return Err(Error);
+            return Err(KError::BUSY);
         }
         self.state = ParportStatus::Busy;
 
@@ -151,7 +159,7 @@ impl Parport {
         stat.set_busy(false); // stat.busy = 0
         self.port.write_stat(&stat);
 
-        self.wait_for_attached_device();
+        self.wait_for_attached_device()?;
 
         // disable output drivers for reading so no fire happens
         let mut ctrl = self.port.read_ctrl();
@@ -180,7 +188,7 @@ impl Parport {
     }
 }
 
-unsafe fn bringup_device(name: &str, port: u16, irq: u8) -> Result<(), Error> {
+unsafe fn bringup_device(name: &str, port: u16, irq: u8) -> Result<()> {
     let port = unsafe { ParportIO::new(port) };
     let irq = Irq::new(irq);
     let dev = NkCharDev::new(name);
@@ -190,7 +198,7 @@ unsafe fn bringup_device(name: &str, port: u16, irq: u8) -> Result<(), Error> {
     Ok(())
 }
 
-fn discover_and_bringup_devices() -> Result<(), Error> {
+fn discover_and_bringup_devices() -> Result<()> {
     unsafe {
         // PARPORT0_BASE and PARPORT0_IRQ are valid and correct
         bringup_device("parport0", PARPORT0_BASE, PARPORT0_IRQ)?;
@@ -199,12 +207,17 @@ fn discover_and_bringup_devices() -> Result<(), Error> {
     Ok(())
 }
 
+// otherwise the `parport` shell command brings the port up
+#[cfg(nk_config = "RUST_PARPORT_AT_BOOT")]
+nk_init!(
+    Device,
+    fn parport_init() -> Result<()> {
+        discover_and_bringup_devices()
+    }
+);
+
 #[no_mangle]
 pub extern "C" fn nk_parport_init() -> c_int {
     print_to_vc("partport init\n");
-    if discover_and_bringup_devices().is_err() {
-        -1
-    } else {
-        0
-    }
+    nk_error::to_c_ret(discover_and_bringup_devices())
 }
@@ -0,0 +1,28 @@
+use kernel::nk_gpudev::{SavedMode, VideoMode};
+use kernel::prelude::*;
+
+/// A gpudev device switched to its first graphics mode. The mode it
+/// was in before is restored when this is dropped.
+pub struct Screen {
+    pub dev: GpuDev,
+    pub mode: VideoMode,
+    _saved: SavedMode,
+}
+
+impl Screen {
+    pub fn open(name: &str) -> Result<Self> {
+        let dev = GpuDev::find(name)?;
+        let saved = dev.save_mode()?;
+        let mode = dev
+            .available_modes()?
+            .into_iter()
+            .find(|m| m.is_graphics())
+            .ok_or(KError::NOT_FOUND)?;
+        dev.set_mode(&mode)?;
+        Ok(Screen {
+            dev,
+            mode,
+            _saved: saved,
+        })
+    }
+}
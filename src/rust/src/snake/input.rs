@@ -0,0 +1,61 @@
+use kernel::nk_raw;
+
+use super::Direction;
+
+// keycodes from dev/ps2.h, which are built by macros bindgen can't
+// expand. the arrow keys come through as the keypad's.
+const NO_KEY: nk_raw::nk_keycode_t = 0xffff;
+const KEY_ESC: nk_raw::nk_keycode_t = 0x1b;
+const KEY_KPUP: nk_raw::nk_keycode_t = 0x0381;
+const KEY_KPLEFT: nk_raw::nk_keycode_t = 0x0384;
+const KEY_KPRIGHT: nk_raw::nk_keycode_t = 0x0386;
+const KEY_KPDOWN: nk_raw::nk_keycode_t = 0x0389;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Key {
+    Turn(Direction),
+    Quit,
+}
+
+fn translate(key: nk_raw::nk_keycode_t) -> Option<Key> {
+    let key = match key {
+        KEY_KPUP => Key::Turn(Direction::Up),
+        KEY_KPDOWN => Key::Turn(Direction::Down),
+        KEY_KPLEFT => Key::Turn(Direction::Left),
+        KEY_KPRIGHT => Key::Turn(Direction::Right),
+        KEY_ESC => Key::Quit,
+        k if k > 0x7f => return None,
+        k => match (k as u8).to_ascii_lowercase() {
+            b'w' => Key::Turn(Direction::Up),
+            b's' => Key::Turn(Direction::Down),
+            b'a' => Key::Turn(Direction::Left),
+            b'd' => Key::Turn(Direction::Right),
+            b'q' => Key::Quit,
+            _ => return None,
+        },
+    };
+    Some(key)
+}
+
+/// The next key the game cares about that was pressed on the current
+/// thread's virtual console, without waiting for one.
+pub fn poll() -> Option<Key> {
+    loop {
+        let key = unsafe { nk_raw::nk_vc_get_keycode(0) };
+        if key == NO_KEY {
+            return None;
+        }
+        if let Some(k) = translate(key) {
+            return Some(k);
+        }
+    }
+}
+
+kernel_test!(
+    fn snake_keys() {
+        kassert_eq!(translate(KEY_KPLEFT), Some(Key::Turn(Direction::Left)));
+        kassert_eq!(translate(b'W' as u16), Some(Key::Turn(Direction::Up)));
+        kassert_eq!(translate(b'q' as u16), Some(Key::Quit));
+        kassert_eq!(translate(b'x' as u16), None);
+    }
+);
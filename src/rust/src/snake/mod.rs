@@ -0,0 +1,279 @@
+// snake, played on a gpudev device with the keyboard: an end-to-end
+// exercise of the graphics modes, fill and copy operations, timers,
+// and keyboard input of the virtual console
+use alloc::collections::VecDeque;
+
+use kernel::nk_gpudev::{BlitOp, Rect};
+use kernel::nk_time::timer;
+use kernel::prelude::*;
+
+use gpu::Screen;
+use input::Key;
+
+pub mod nk_shell_cmd;
+
+mod gpu;
+mod input;
+
+// size of a grid cell on screen, in pixels
+const CELL: u32 = 16;
+const START_LEN: usize = 4;
+pub const DEFAULT_TICK: Duration = Duration::from_millis(120);
+// how long the board stays up after the game is lost
+const GAME_OVER_PAUSE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    fn opposite(self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+}
+
+type Cell = (u32, u32);
+
+/// What happened in one step of the game.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Step {
+    /// The head moved to `head`, and the tail left `tail`.
+    Moved { head: Cell, tail: Cell },
+    /// The head moved onto the food at `head`, and the snake grew.
+    Ate { head: Cell },
+    /// The head hit a wall or the snake.
+    Crashed,
+}
+
+struct Game {
+    width: u32,
+    height: u32,
+    // head first
+    snake: VecDeque<Cell>,
+    heading: Direction,
+    // where the snake heads next, set by the keyboard between steps
+    turn: Direction,
+    food: Cell,
+    score: u32,
+}
+
+impl Game {
+    /// A snake in the middle of a `width` x `height` board, heading
+    /// right. The board must be at least twice as wide as the snake is
+    /// long.
+    fn new(width: u32, height: u32) -> Self {
+        let (x, y) = (width / 2, height / 2);
+        let snake = (0..START_LEN as u32).map(|i| (x - i, y)).collect();
+        let mut game = Game {
+            width,
+            height,
+            snake,
+            heading: Direction::Right,
+            turn: Direction::Right,
+            food: (0, 0),
+            score: 0,
+        };
+        game.food = game.place_food();
+        game
+    }
+
+    fn head(&self) -> Cell {
+        self.snake[0]
+    }
+
+    // a random free cell; there always is one, as the game ends when
+    // the snake runs into itself
+    fn place_food(&self) -> Cell {
+        loop {
+            let cell = (
+                nk_rand::gen_range(0..self.width as u64) as u32,
+                nk_rand::gen_range(0..self.height as u64) as u32,
+            );
+            if !self.snake.contains(&cell) {
+                return cell;
+            }
+        }
+    }
+
+    /// Heads towards `d` on the next step, unless that would turn the
+    /// snake back onto itself.
+    fn steer(&mut self, d: Direction) {
+        if d != self.heading.opposite() {
+            self.turn = d;
+        }
+    }
+
+    fn step(&mut self) -> Step {
+        self.heading = self.turn;
+        let (x, y) = self.head();
+        let head = match self.heading {
+            Direction::Up => y.checked_sub(1).map(|y| (x, y)),
+            Direction::Down => Some((x, y + 1)).filter(|&(_, y)| y < self.height),
+            Direction::Left => x.checked_sub(1).map(|x| (x, y)),
+            Direction::Right => Some((x + 1, y)).filter(|&(x, _)| x < self.width),
+        };
+        let head = match head {
+            Some(h) => h,
+            None => return Step::Crashed,
+        };
+
+        if head == self.food {
+            self.snake.push_front(head);
+            self.score += 1;
+            self.food = self.place_food();
+            return Step::Ate { head };
+        }
+        // the tail moves out of the way of the head
+        let tail = self.snake.pop_back().unwrap();
+        if self.snake.contains(&head) {
+            self.snake.push_back(tail);
+            return Step::Crashed;
+        }
+        self.snake.push_front(head);
+        Step::Moved { head, tail }
+    }
+}
+
+const BACKGROUND: (u8, u8, u8) = (0, 0, 0);
+const SNAKE: (u8, u8, u8) = (0x40, 0xd0, 0x40);
+const FOOD: (u8, u8, u8) = (0xe0, 0x30, 0x30);
+const CRASHED: (u8, u8, u8) = (0x80, 0x80, 0x80);
+
+struct Board {
+    screen: Screen,
+    // where the grid starts on screen, so that it is centered
+    origin: (u32, u32),
+}
+
+impl Board {
+    fn rect(&self, (x, y): Cell) -> Rect {
+        Rect::new(
+            self.origin.0 + x * CELL,
+            self.origin.1 + y * CELL,
+            CELL,
+            CELL,
+        )
+    }
+
+    fn draw_cell(&self, cell: Cell, (r, g, b): (u8, u8, u8)) -> Result<()> {
+        let rect = self.rect(cell);
+        let pixel = self.screen.mode.rgb(r, g, b);
+        self.screen.dev.fill_box(rect, pixel, BlitOp::Copy)
+    }
+
+    fn copy_cell(&self, from: Cell, to: Cell) -> Result<()> {
+        let (from, to) = (self.rect(from), self.rect(to));
+        self.screen.dev.copy_box(from, to, BlitOp::Copy)
+    }
+
+    fn draw_all(&self, game: &Game) -> Result<()> {
+        let whole = Rect::new(0, 0, self.screen.mode.width(), self.screen.mode.height());
+        let border = self.screen.mode.rgb(0x30, 0x30, 0x30);
+        self.screen.dev.fill_box(whole, border, BlitOp::Copy)?;
+        let (x, y) = self.origin;
+        let grid = Rect::new(x, y, game.width * CELL, game.height * CELL);
+        let (r, g, b) = BACKGROUND;
+        let background = self.screen.mode.rgb(r, g, b);
+        self.screen.dev.fill_box(grid, background, BlitOp::Copy)?;
+
+        for &cell in &game.snake {
+            self.draw_cell(cell, SNAKE)?;
+        }
+        self.draw_cell(game.food, FOOD)?;
+        self.screen.dev.flush()
+    }
+
+    // only the cells that changed are redrawn. the new head is a copy
+    // of the old one, which is already on screen, so the device does
+    // the work
+    fn draw_step(&self, game: &Game, step: Step) -> Result<()> {
+        match step {
+            Step::Moved { head, tail } => {
+                self.draw_cell(tail, BACKGROUND)?;
+                self.copy_cell(game.snake[1], head)?;
+            }
+            Step::Ate { head } => {
+                self.copy_cell(game.snake[1], head)?;
+                self.draw_cell(game.food, FOOD)?;
+            }
+            Step::Crashed => {
+                for &cell in &game.snake {
+                    self.draw_cell(cell, CRASHED)?;
+                }
+            }
+        }
+        self.screen.dev.flush()
+    }
+}
+
+/// Plays a game on the gpudev device `device`, moving the snake once
+/// every `tick`, until it crashes or the player quits. The device is
+/// put back in the mode it was in either way. Returns the score.
+pub fn play(device: &str, tick: Duration) -> Result<u32> {
+    let screen = Screen::open(device)?;
+    let (width, height) = (screen.mode.width() / CELL, screen.mode.height() / CELL);
+    if width < 2 * START_LEN as u32 || height == 0 {
+        return Err(KError::INVALID_ARG);
+    }
+    let origin = (
+        (screen.mode.width() - width * CELL) / 2,
+        (screen.mode.height() - height * CELL) / 2,
+    );
+    let board = Board { screen, origin };
+    let mut game = Game::new(width, height);
+    info_print!("snake on {}: {}x{} cells", device, game.width, game.height);
+
+    board.draw_all(&game)?;
+    for _ in timer::interval(tick)? {
+        while let Some(key) = input::poll() {
+            match key {
+                Key::Turn(d) => game.steer(d),
+                Key::Quit => return Ok(game.score),
+            }
+        }
+        let step = game.step();
+        board.draw_step(&game, step)?;
+        if step == Step::Crashed {
+            sleep(GAME_OVER_PAUSE);
+            break;
+        }
+    }
+    Ok(game.score)
+}
+
+kernel_test!(
+    fn snake_grows_and_crashes() {
+        let mut game = Game::new(8, 1);
+        game.food = (6, 0);
+        kassert_eq!(game.head(), (4, 0));
+        // turning back onto itself is ignored
+        game.steer(Direction::Left);
+        kassert_eq!(
+            game.step(),
+            Step::Moved {
+                head: (5, 0),
+                tail: (1, 0)
+            }
+        );
+        kassert_eq!(game.step(), Step::Ate { head: (6, 0) });
+        kassert_eq!((game.score, game.snake.len()), (1, START_LEN + 1));
+        game.food = (0, 0);
+        kassert_eq!(
+            game.step(),
+            Step::Moved {
+                head: (7, 0),
+                tail: (2, 0)
+            }
+        );
+        kassert_eq!(game.step(), Step::Crashed);
+    }
+);
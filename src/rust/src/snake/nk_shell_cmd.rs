@@ -0,0 +1,44 @@
+use core::ffi::{c_char, c_int, c_void, CStr};
+
+use kernel::prelude::*;
+
+use super::{play, DEFAULT_TICK};
+
+// `rust_snake <device> [tick_ms]` plays on a gpudev device until the snake crashes or q
+// or escape is pressed; steer with the arrow keys or wasd
+#[no_mangle]
+pub unsafe extern "C" fn rust_snake_shell_entry(
+    buf: *const c_char,
+    _priv_: *const c_void,
+) -> c_int {
+    // caller (the shell) passes the full nul-terminated command line
+    let line = unsafe { CStr::from_ptr(buf) }.to_str().unwrap_or("");
+    let mut args = line.split_whitespace().skip(1);
+
+    let device = match args.next() {
+        Some(d) => d,
+        None => {
+            error_print!("usage: rust_snake <device> [tick_ms]");
+            return -1;
+        }
+    };
+    let tick = match args.next().map(str::parse::<u64>) {
+        None => DEFAULT_TICK,
+        Some(Ok(ms)) if ms > 0 => Duration::from_millis(ms),
+        Some(_) => {
+            error_print!("rust_snake: tick must be a positive number of ms");
+            return -1;
+        }
+    };
+
+    match play(device, tick) {
+        Ok(score) => {
+            info_print!("rust_snake: score {}", score);
+            0
+        }
+        Err(e) => {
+            error_print!("rust_snake: cannot play on {}: {}", device, e.name());
+            e.code()
+        }
+    }
+}
@@ -0,0 +1,332 @@
+// a virtio block device, driven in Rust over the C virtio_pci transport
+// (see `kernel::nk_virtio`), which finds the device, and lays out and
+// hands out the virtqueue's descriptors. virtio_pci brings the device up with
+// `nk_rust_virtio_blk_init` instead of the C driver's `virtio_blk_init`.
+//
+// like the C driver, it only supports the legacy (transitional) model.
+// requests are asynchronous: each is a chain of three descriptors on the
+// one request queue, a header, the caller's buffer, and a status byte
+// for the device to write, and it completes in the interrupt handler,
+// which calls the caller's callback.
+use core::ffi::{c_int, c_void};
+use core::ptr::{self, addr_of};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use kernel::nk_blkdev::{Characteristics, Completion, Registration};
+use kernel::nk_raw;
+use kernel::nk_virtio::{Buf, Queue};
+use kernel::prelude::*;
+
+counter!(READS, "reads");
+counter!(WRITES, "writes");
+counter!(ERRORS, "errors");
+
+/// The size of a virtio block device's sectors, whatever block size it
+/// prefers.
+pub const SECTOR_SIZE: u32 = 512;
+
+const REQUEST_QUEUE: u16 = 0;
+
+// feature bits
+const F_RO: u32 = 5;
+
+// request types, and the status the device writes back
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const S_OK: u8 = 0;
+
+static NUM_DEVS: AtomicU32 = AtomicU32::new(0);
+
+// a request as the device reads it: the header, then, after the
+// caller's buffer, the status byte it writes
+#[repr(C)]
+struct Header {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+    status: u8,
+}
+
+// the length of the header proper, without the status
+const HEADER_LEN: u32 = 16;
+
+// a request the device has yet to complete
+struct Pending {
+    header: DmaBox<Header>,
+    done: Completion,
+}
+
+// the features to accept of those `offered`
+fn select_features(offered: u64) -> u64 {
+    offered & (1 << F_RO)
+}
+
+pub struct VirtioBlk {
+    vdev: VirtioDev,
+    requests: Queue,
+    // in sectors
+    capacity: u64,
+    read_only: bool,
+    // by the index of their first descriptor
+    pending: Vec<Option<Pending>>,
+    // for good, once the device is shut down
+    stopped: bool,
+}
+
+impl VirtioBlk {
+    /// Negotiates features with `vdev` and sets up its request queue.
+    fn new(vdev: VirtioDev) -> Result<Self> {
+        let accepted = vdev.negotiate(select_features)?;
+        // a failure from here on leaves the device as it was found
+        Self::set_up(vdev, accepted).inspect_err(|_| vdev.abandon())
+    }
+
+    // reads the capacity, and takes the queue `negotiate` set up
+    fn set_up(vdev: VirtioDev, accepted: u64) -> Result<Self> {
+        let capacity = (vdev.config_u32(4) as u64) << 32 | vdev.config_u32(0) as u64;
+        // the one queue, taken once
+        let requests = unsafe { vdev.queue(REQUEST_QUEUE) }?;
+        let mut pending = Vec::new();
+        pending.resize_with(requests.size() as usize, || None);
+        Ok(VirtioBlk {
+            vdev,
+            requests,
+            capacity,
+            read_only: accepted & (1 << F_RO) != 0,
+            pending,
+            stopped: false,
+        })
+    }
+
+    /// The device's size in sectors.
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    // asks the device to read `count` sectors from `sector` on into
+    // `buf`, or write them from it, and to complete `done` once it has.
+    // the caller guarantees `buf` as `BlkDev`'s does
+    unsafe fn submit(
+        &mut self,
+        sector: u64,
+        count: u64,
+        buf: *mut u8,
+        write: bool,
+        done: Completion,
+    ) -> Result<()> {
+        if self.stopped {
+            return Err(KError::NO_DEVICE);
+        }
+        if count == 0
+            || sector
+                .checked_add(count)
+                .is_none_or(|end| end > self.capacity)
+        {
+            return Err(KError::INVALID_ARG);
+        }
+        if write && self.read_only {
+            return Err(KError::INVALID_ARG);
+        }
+        // a descriptor's length is 32 bits
+        let len = count
+            .checked_mul(SECTOR_SIZE as u64)
+            .and_then(|l| u32::try_from(l).ok())
+            .ok_or(KError::INVALID_ARG)?;
+
+        let header = DmaBox::new(Header {
+            kind: if write { T_OUT } else { T_IN },
+            reserved: 0,
+            sector,
+            status: 0xff,
+        })?;
+        let buf = if write {
+            Buf::readable(buf as u64, len)
+        } else {
+            Buf::writable(buf as u64, len)
+        };
+        let chain = [
+            Buf::readable(header.phys_addr(), HEADER_LEN),
+            buf,
+            Buf::writable(header.phys_addr() + HEADER_LEN as u64, 1),
+        ];
+        // ours until the device is done, which it can only report with
+        // the driver locked, so once `pending` has it
+        let head = unsafe { self.requests.submit(&chain) }?;
+        self.pending[head as usize] = Some(Pending { header, done });
+        if write {
+            WRITES.inc();
+        } else {
+            READS.inc();
+        }
+        Ok(())
+    }
+
+    /// Whether the device interrupted; see `VirtioDev::take_interrupt`.
+    pub fn take_interrupt(&mut self) -> bool {
+        !self.stopped && self.vdev.take_interrupt()
+    }
+
+    /// Stops the driver, once the device is reset: it ignores
+    /// interrupts and refuses requests from then on. Returns the
+    /// requests the device had yet to complete, to fail.
+    pub fn stop(&mut self) -> Vec<Completion> {
+        self.stopped = true;
+        self.pending
+            .iter_mut()
+            .filter_map(Option::take)
+            .map(|p| p.done)
+            .collect()
+    }
+
+    /// The next request the device has completed, if any, and how it
+    /// went. Its descriptors are freed for the next.
+    pub fn next_completed(&mut self) -> Option<(Completion, Result<()>)> {
+        loop {
+            let (head, _) = self.requests.next_used()?;
+            let pending = self.pending.get_mut(head as usize).and_then(Option::take);
+            let p = match pending {
+                Some(p) => p,
+                None => {
+                    // not a request we made; nothing to complete
+                    ERRORS.inc();
+                    warn_print!("virtio_blk: used descriptor {} was not pending", head);
+                    continue;
+                }
+            };
+            let status = unsafe { ptr::read_volatile(addr_of!((*p.header.as_ptr()).status)) };
+            if status != S_OK {
+                ERRORS.inc();
+                return Some((p.done, Err(KError::IO)));
+            }
+            return Some((p.done, Ok(())));
+        }
+    }
+}
+
+impl BlkDev for VirtioBlk {
+    fn get_characteristics(&self) -> Characteristics {
+        Characteristics {
+            block_size: SECTOR_SIZE as u64,
+            num_blocks: self.capacity,
+        }
+    }
+
+    unsafe fn read_blocks(
+        &mut self,
+        blocknum: u64,
+        count: u64,
+        dest: *mut u8,
+        done: Completion,
+    ) -> Result<()> {
+        unsafe { self.submit(blocknum, count, dest, false, done) }
+    }
+
+    unsafe fn write_blocks(
+        &mut self,
+        blocknum: u64,
+        count: u64,
+        src: *const u8,
+        done: Completion,
+    ) -> Result<()> {
+        // only read by the device
+        unsafe { self.submit(blocknum, count, src as *mut u8, true, done) }
+    }
+}
+
+// the interrupt handler, which completes requests one at a time,
+// unlocked, so a callback can make another
+fn interrupt_handler(d: &IRQLock<VirtioBlk>) {
+    if d.lock().take_interrupt() {
+        let mut next = || d.lock().next_completed();
+        while let Some((done, result)) = next() {
+            done.complete(result);
+        }
+    }
+}
+
+// what virtio_pci keeps for the device, in its `state`
+struct Device {
+    vdev: VirtioDev,
+    driver: Arc<IRQLock<VirtioBlk>>,
+    blkdev: Registration<VirtioBlk>,
+}
+
+// virtio_pci's `teardown`, once the device is brought down
+unsafe extern "C" fn teardown(vdev: *mut nk_raw::virtio_pci_dev) {
+    // set with `teardown` in `bringup`
+    let device = unsafe { Box::from_raw((*vdev).state as *mut Device) };
+    let Device {
+        vdev: v,
+        driver,
+        blkdev,
+    } = *device;
+    // the requests it will not complete fail, unlocked, as the interrupt
+    // handler completes them
+    for done in v.shut_down(&driver, VirtioBlk::stop) {
+        done.complete(Err(KError::IO));
+    }
+    drop(blkdev);
+    unsafe { (*vdev).state = ptr::null_mut() };
+}
+
+fn bringup(vdev: VirtioDev) -> Result<()> {
+    let driver = VirtioBlk::new(vdev)?;
+    let (capacity, read_only) = (driver.capacity(), driver.is_read_only());
+    let driver = Arc::new(IRQLock::new(driver));
+    let started = vdev
+        .route_to(driver.clone(), interrupt_handler)
+        .and_then(|_| vdev.start());
+    if let Err(e) = started {
+        vdev.shut_down(&driver, VirtioBlk::stop);
+        return Err(e);
+    }
+
+    let name = format!("virtio-blk{}", NUM_DEVS.fetch_add(1, Ordering::Relaxed));
+    // nothing is asked of the device until it is registered
+    let blkdev = Registration::register(&name, driver.clone()).inspect_err(|_| {
+        vdev.shut_down(&driver, VirtioBlk::stop);
+    })?;
+    info_print!(
+        "virtio_blk: {}, {} sectors{}",
+        name,
+        capacity,
+        if read_only { ", read-only" } else { "" }
+    );
+    let device = Box::new(Device {
+        vdev,
+        driver,
+        blkdev,
+    });
+    unsafe { vdev.set_state(Box::into_raw(device) as *mut c_void, teardown) };
+    Ok(())
+}
+
+/// Brings up a virtio block device, for virtio_pci.
+#[no_mangle]
+pub unsafe extern "C" fn nk_rust_virtio_blk_init(vdev: *mut nk_raw::virtio_pci_dev) -> c_int {
+    // virtio_pci calls this once for each block device it found
+    let vdev = match unsafe { VirtioDev::from_raw(vdev) } {
+        Some(v) => v,
+        None => return KError::INVALID_ARG.code(),
+    };
+    match bringup(vdev) {
+        Ok(()) => 0,
+        Err(e) => {
+            error_print!("virtio_blk: cannot bring up device: {}", e.name());
+            e.code()
+        }
+    }
+}
+
+kernel_test!(
+    fn virtio_blk_requests() {
+        // the header proper, as the device reads it, then the status
+        kassert_eq!(core::mem::offset_of!(Header, status), HEADER_LEN as usize);
+        kassert_eq!(select_features(u64::MAX), 1 << F_RO);
+        kassert_eq!(select_features(0), 0);
+    }
+);
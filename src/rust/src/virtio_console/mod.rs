@@ -0,0 +1,312 @@
+// a virtio console's first port, driven in Rust over the C virtio_pci
+// transport (see `kernel::nk_virtio`), and registered as a chardev, a
+// paravirtual serial line to the host. virtio_pci brings the device up
+// with `nk_rust_virtio_console_init`; there is no C driver.
+//
+// it only supports the legacy (transitional) model, and accepts no
+// features, so there is the one port, on the first two queues. the
+// receive queue is kept full of small buffers, whose bytes the interrupt
+// handler moves to the input the chardev reads, before posting them
+// again. chardevs write a byte at a time, and each byte goes out in a
+// one-descriptor chain of its own, whose slot is free again once the
+// device has sent it.
+use core::ffi::{c_int, c_void};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use alloc::collections::VecDeque;
+
+use kernel::nk_chardev::{Registration, Signal, Status};
+use kernel::nk_raw;
+use kernel::nk_virtio::{Buf, Queue};
+use kernel::prelude::*;
+
+counter!(RECEIVED, "received");
+counter!(SENT, "sent");
+counter!(DROPPED, "dropped");
+counter!(ERRORS, "errors");
+
+// port 0's queues
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+
+// the size of each receive buffer
+const RECEIVE_BUF_LEN: usize = 64;
+// how much input is kept for readers, beyond which it is dropped
+const INPUT_LIMIT: usize = 4096;
+
+static NUM_DEVS: AtomicU32 = AtomicU32::new(0);
+
+// adds what of `bytes` fits to `input`, returning how many did not
+fn take_input(input: &mut VecDeque<u8>, bytes: &[u8]) -> usize {
+    let room = INPUT_LIMIT.saturating_sub(input.len());
+    let n = bytes.len().min(room);
+    input.extend(&bytes[..n]);
+    bytes.len() - n
+}
+
+pub struct VirtioConsole {
+    vdev: VirtioDev,
+    receive: Queue,
+    // posted in full, by index, and the index of each by the index of
+    // its descriptor
+    receive_bufs: DmaSlice<[u8; RECEIVE_BUF_LEN]>,
+    receive_posted: Vec<Option<usize>>,
+    input: VecDeque<u8>,
+    transmit: Queue,
+    // a byte each, the free ones by index, and the index of each posted
+    // one by the index of its descriptor
+    transmit_bufs: DmaSlice<u8>,
+    transmit_free: Vec<usize>,
+    transmit_posted: Vec<Option<usize>>,
+    // once registered, to wake readers and writers
+    signal: Option<Signal>,
+    // for good, once the device is shut down
+    stopped: bool,
+}
+
+impl VirtioConsole {
+    /// Negotiates features with `vdev`, sets up its queues, and fills
+    /// its receive queue.
+    fn new(vdev: VirtioDev) -> Result<Self> {
+        vdev.negotiate(|_| 0)?;
+        // a failure from here on leaves the device as it was found
+        Self::set_up(vdev).inspect_err(|_| vdev.abandon())
+    }
+
+    // takes the queues `negotiate` set up, and fills the receive one
+    fn set_up(vdev: VirtioDev) -> Result<Self> {
+        if vdev.num_queues() <= TRANSMIT_QUEUE {
+            error_print!("virtio_console: device has no transmit queue");
+            return Err(KError::NO_DEVICE);
+        }
+        // each taken once
+        let receive = unsafe { vdev.queue(RECEIVE_QUEUE) }?;
+        let transmit = unsafe { vdev.queue(TRANSMIT_QUEUE) }?;
+        let (rsz, tsz) = (receive.size() as usize, transmit.size() as usize);
+        let mut receive_posted = Vec::new();
+        receive_posted.resize(rsz, None);
+        let mut transmit_posted = Vec::new();
+        transmit_posted.resize(tsz, None);
+        let mut c = VirtioConsole {
+            vdev,
+            receive,
+            receive_bufs: DmaSlice::filled(rsz, [0; RECEIVE_BUF_LEN])?,
+            receive_posted,
+            input: VecDeque::new(),
+            transmit,
+            transmit_bufs: DmaSlice::filled(tsz, 0)?,
+            transmit_free: (0..tsz).rev().collect(),
+            transmit_posted,
+            signal: None,
+            stopped: false,
+        };
+        for i in 0..rsz {
+            c.post_receive(i)?;
+        }
+        Ok(c)
+    }
+
+    // hands receive buffer `i` to the device
+    fn post_receive(&mut self, i: usize) -> Result<()> {
+        let buf = Buf::writable(self.receive_bufs.phys_addr_of(i), RECEIVE_BUF_LEN as u32);
+        // ours, and only ever posted once at a time
+        let head = unsafe { self.receive.submit(&[buf]) }?;
+        self.receive_posted[head as usize] = Some(i);
+        Ok(())
+    }
+
+    /// Lets the interrupt handler wake readers and writers.
+    pub fn set_signal(&mut self, signal: Option<Signal>) {
+        self.signal = signal;
+    }
+
+    /// Whether the device interrupted; see `VirtioDev::take_interrupt`.
+    pub fn take_interrupt(&mut self) -> bool {
+        !self.stopped && self.vdev.take_interrupt()
+    }
+
+    /// Stops the driver, once the device is reset: it ignores
+    /// interrupts, wakes no one, and writes no more. What it received
+    /// can still be read.
+    pub fn stop(&mut self) {
+        self.stopped = true;
+        self.signal = None;
+    }
+
+    /// Takes in what the device received, and frees what it sent.
+    /// Returns whether anything changed, and whom to tell if so.
+    pub fn service(&mut self) -> Option<Signal> {
+        let mut changed = false;
+        while let Some((head, len)) = self.receive.next_used() {
+            let i = match self
+                .receive_posted
+                .get_mut(head as usize)
+                .and_then(Option::take)
+            {
+                Some(i) => i,
+                None => {
+                    ERRORS.inc();
+                    warn_print!("virtio_console: used descriptor {} was not posted", head);
+                    continue;
+                }
+            };
+            let len = (len as usize).min(RECEIVE_BUF_LEN);
+            let dropped = take_input(&mut self.input, &self.receive_bufs[i][..len]);
+            RECEIVED.add(len as u64);
+            DROPPED.add(dropped as u64);
+            changed = true;
+            // a descriptor was just freed for it
+            if let Err(e) = self.post_receive(i) {
+                ERRORS.inc();
+                error_print!("virtio_console: cannot post receive buffer: {}", e.name());
+            }
+        }
+        while let Some((head, _)) = self.transmit.next_used() {
+            match self
+                .transmit_posted
+                .get_mut(head as usize)
+                .and_then(Option::take)
+            {
+                Some(i) => self.transmit_free.push(i),
+                None => {
+                    ERRORS.inc();
+                    warn_print!("virtio_console: used descriptor {} was not posted", head);
+                }
+            }
+            changed = true;
+        }
+        self.signal.filter(|_| changed)
+    }
+}
+
+impl CharDev for VirtioConsole {
+    fn read(&mut self) -> Result<Option<u8>> {
+        Ok(self.input.pop_front())
+    }
+
+    fn write(&mut self, byte: u8) -> Result<bool> {
+        if self.stopped {
+            return Err(KError::NO_DEVICE);
+        }
+        let i = match self.transmit_free.pop() {
+            Some(i) => i,
+            None => return Ok(false),
+        };
+        self.transmit_bufs[i] = byte;
+        let buf = Buf::readable(self.transmit_bufs.phys_addr_of(i), 1);
+        // free, so not the device's until now
+        match unsafe { self.transmit.submit(&[buf]) } {
+            Ok(head) => {
+                self.transmit_posted[head as usize] = Some(i);
+                SENT.inc();
+                Ok(true)
+            }
+            Err(e) => {
+                self.transmit_free.push(i);
+                if e == KError::BUSY {
+                    Ok(false)
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            readable: !self.input.is_empty(),
+            writeable: !self.stopped && !self.transmit_free.is_empty(),
+            error: self.stopped,
+        }
+    }
+}
+
+// the interrupt handler, which takes in what the console received, frees
+// what it sent, and wakes whoever waits on the chardev
+fn interrupt_handler(d: &IRQLock<VirtioConsole>) {
+    let mut c = d.lock();
+    if c.take_interrupt() {
+        // locked, so teardown, which stops the driver under the lock
+        // before unregistering the chardev, cannot come between
+        if let Some(s) = c.service() {
+            unsafe { s.signal() };
+        }
+    }
+}
+
+// what virtio_pci keeps for the device, in its `state`
+struct Device {
+    vdev: VirtioDev,
+    driver: Arc<IRQLock<VirtioConsole>>,
+    chardev: Registration<VirtioConsole>,
+}
+
+// virtio_pci's `teardown`, once the device is brought down
+unsafe extern "C" fn teardown(vdev: *mut nk_raw::virtio_pci_dev) {
+    // set with `teardown` in `bringup`
+    let device = unsafe { Box::from_raw((*vdev).state as *mut Device) };
+    let Device {
+        vdev: v,
+        driver,
+        chardev,
+    } = *device;
+    // the interrupt handler outlives the chardev, so stops signalling
+    // it first
+    v.shut_down(&driver, VirtioConsole::stop);
+    drop(chardev);
+    unsafe { (*vdev).state = core::ptr::null_mut() };
+}
+
+fn bringup(vdev: VirtioDev) -> Result<()> {
+    let driver = Arc::new(IRQLock::new(VirtioConsole::new(vdev)?));
+    let started = vdev
+        .route_to(driver.clone(), interrupt_handler)
+        .and_then(|_| vdev.start());
+    if let Err(e) = started {
+        vdev.shut_down(&driver, VirtioConsole::stop);
+        return Err(e);
+    }
+
+    let name = format!("virtio-cons{}", NUM_DEVS.fetch_add(1, Ordering::Relaxed));
+    let chardev = Registration::register(&name, driver.clone()).inspect_err(|_| {
+        vdev.shut_down(&driver, VirtioConsole::stop);
+    })?;
+    driver.lock().set_signal(Some(chardev.signal()));
+    info_print!("virtio_console: {}", name);
+    let device = Box::new(Device {
+        vdev,
+        driver,
+        chardev,
+    });
+    unsafe { vdev.set_state(Box::into_raw(device) as *mut c_void, teardown) };
+    Ok(())
+}
+
+/// Brings up a virtio console device, for virtio_pci.
+#[no_mangle]
+pub unsafe extern "C" fn nk_rust_virtio_console_init(vdev: *mut nk_raw::virtio_pci_dev) -> c_int {
+    // virtio_pci calls this once for each console device it found
+    let vdev = match unsafe { VirtioDev::from_raw(vdev) } {
+        Some(v) => v,
+        None => return KError::INVALID_ARG.code(),
+    };
+    match bringup(vdev) {
+        Ok(()) => 0,
+        Err(e) => {
+            error_print!("virtio_console: cannot bring up device: {}", e.name());
+            e.code()
+        }
+    }
+}
+
+kernel_test!(
+    fn virtio_console_input() {
+        let mut input = VecDeque::new();
+        kassert_eq!(take_input(&mut input, b"abc"), 0);
+        kassert_eq!(input.len(), 3);
+        let full = [0u8; INPUT_LIMIT];
+        kassert_eq!(take_input(&mut input, &full), 3);
+        kassert_eq!(input.len(), INPUT_LIMIT);
+        kassert_eq!(input.pop_front(), Some(b'a'));
+    }
+);
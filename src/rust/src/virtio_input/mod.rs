@@ -0,0 +1,443 @@
+// a virtio input device (a keyboard, mouse, or tablet), driven in Rust
+// over the C virtio_pci transport (see `kernel::nk_virtio`). virtio_pci
+// brings the device up with `nk_rust_virtio_input_init`; there is no C
+// driver.
+//
+// QEMU's virtio-keyboard, -mouse, and -tablet are modern devices, so
+// need MSI-X (see `kernel::nk_virtio`); legacy ones work as well. the
+// event queue is kept full of buffers, each of which the device fills
+// with one evdev event. the interrupt handler queues each for Rust code
+// to `poll`, and hands keys and relative motion on to the current
+// virtual console, as the ps2 driver does, so the shell and the snake
+// demo can be played from them. the status queue, for LEDs and the
+// like, is left alone.
+use core::ffi::{c_int, c_void};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use alloc::collections::VecDeque;
+
+use kernel::nk_raw;
+use kernel::nk_virtio::{Buf, Queue};
+use kernel::prelude::*;
+
+mod nk_shell_cmd;
+
+counter!(EVENTS, "events");
+counter!(DROPPED, "dropped");
+counter!(ERRORS, "errors");
+
+const EVENT_QUEUE: u16 = 0;
+
+// how many events are kept for `poll`, beyond which they are dropped
+const EVENT_LIMIT: usize = 256;
+
+// evdev event types and codes (linux/input-event-codes.h)
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+
+// from the ps2 driver, which reports motion at its default
+const MOUSE_RES: u8 = 4;
+
+static NUM_DEVS: AtomicU32 = AtomicU32::new(0);
+
+// every device's events, oldest first
+static QUEUE: IRQLock<VecDeque<InputEvent>> = IRQLock::new(VecDeque::new());
+
+// an event as the device writes it, little-endian like us
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+struct RawEvent {
+    kind: u16,
+    code: u16,
+    value: u32,
+}
+
+/// An input event, with evdev's codes and axes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    /// A key or button was pressed (or repeated), or released.
+    Key { code: u16, pressed: bool },
+    /// A relative axis, like a mouse's, moved by `delta`.
+    Relative { axis: u16, delta: i32 },
+    /// An absolute axis, like a tablet's, moved to `value`.
+    Absolute { axis: u16, value: u32 },
+    /// The events before it, since the last, happened together.
+    Sync,
+}
+
+impl InputEvent {
+    // `None` for the kinds nothing here uses, e.g. LEDs or misc
+    fn parse(e: RawEvent) -> Option<Self> {
+        let event = match e.kind {
+            EV_SYN => InputEvent::Sync,
+            EV_KEY => InputEvent::Key {
+                code: e.code,
+                pressed: e.value != 0,
+            },
+            EV_REL => InputEvent::Relative {
+                axis: e.code,
+                delta: e.value as i32,
+            },
+            EV_ABS => InputEvent::Absolute {
+                axis: e.code,
+                value: e.value,
+            },
+            _ => return None,
+        };
+        Some(event)
+    }
+}
+
+/// The oldest input event from any device that is yet to be polled,
+/// without waiting for one.
+pub fn poll() -> Option<InputEvent> {
+    QUEUE.lock().pop_front()
+}
+
+// adds `event` for `poll`, unless there are too many already
+fn enqueue(events: &mut VecDeque<InputEvent>, event: InputEvent) -> bool {
+    if events.len() >= EVENT_LIMIT {
+        return false;
+    }
+    events.push_back(event);
+    true
+}
+
+// the PC (set 1) scancode for an evdev key, which evdev's codes match up
+// to F12; the cursor block comes through as the keypad's, as the ps2
+// driver sends it without the extended prefix
+fn scancode(code: u16) -> Option<u8> {
+    let scan = match code {
+        1..=88 => code as u8,
+        96 => 0x1c,  // keypad enter
+        97 => 0x1d,  // right ctrl
+        98 => 0x35,  // keypad slash
+        100 => 0x38, // right alt
+        102 => 0x47, // home
+        103 => 0x48, // up
+        104 => 0x49, // page up
+        105 => 0x4b, // left
+        106 => 0x4d, // right
+        107 => 0x4f, // end
+        108 => 0x50, // down
+        109 => 0x51, // page down
+        110 => 0x52, // insert
+        111 => 0x53, // delete
+        _ => return None,
+    };
+    Some(scan)
+}
+
+// a mouse's buttons and motion, gathered up to each sync
+#[derive(Debug, Default)]
+struct Pointer {
+    // left, middle, right
+    buttons: [bool; 3],
+    dx: i32,
+    dy: i32,
+    changed: bool,
+}
+
+impl Pointer {
+    fn update(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::Key { code, pressed } => {
+                let i = match code {
+                    BTN_LEFT => 0,
+                    BTN_MIDDLE => 1,
+                    BTN_RIGHT => 2,
+                    _ => return,
+                };
+                self.buttons[i] = pressed;
+            }
+            InputEvent::Relative { axis: REL_X, delta } => self.dx += delta,
+            InputEvent::Relative { axis: REL_Y, delta } => self.dy += delta,
+            _ => return,
+        }
+        self.changed = true;
+    }
+
+    // what changed since the last sync, if anything did
+    fn take(&mut self) -> Option<nk_raw::nk_mouse_event_t> {
+        if !core::mem::take(&mut self.changed) {
+            return None;
+        }
+        let [left, middle, right] = self.buttons;
+        Some(nk_raw::nk_mouse_event_t {
+            left: left as u8,
+            middle: middle as u8,
+            right: right as u8,
+            res: MOUSE_RES,
+            dx: core::mem::take(&mut self.dx),
+            dy: core::mem::take(&mut self.dy),
+        })
+    }
+}
+
+pub struct VirtioInput {
+    vdev: VirtioDev,
+    events: Queue,
+    // posted in full, by index, and the index of each by the index of
+    // its descriptor
+    bufs: DmaSlice<RawEvent>,
+    posted: Vec<Option<usize>>,
+    pointer: Pointer,
+    // for good, once the device is shut down
+    stopped: bool,
+}
+
+impl VirtioInput {
+    /// Negotiates features with `vdev`, sets up its event queue, and
+    /// fills it.
+    fn new(vdev: VirtioDev) -> Result<Self> {
+        vdev.negotiate(|_| 0)?;
+        // a failure from here on leaves the device as it was found
+        Self::set_up(vdev).inspect_err(|_| vdev.abandon())
+    }
+
+    // takes the event queue `negotiate` set up, and fills it
+    fn set_up(vdev: VirtioDev) -> Result<Self> {
+        // the only queue taken, once
+        let events = unsafe { vdev.queue(EVENT_QUEUE) }?;
+        let size = events.size() as usize;
+        let mut posted = Vec::new();
+        posted.resize(size, None);
+        let mut d = VirtioInput {
+            vdev,
+            events,
+            bufs: DmaSlice::filled(size, RawEvent::default())?,
+            posted,
+            pointer: Pointer::default(),
+            stopped: false,
+        };
+        for i in 0..size {
+            d.post(i)?;
+        }
+        Ok(d)
+    }
+
+    // hands event buffer `i` to the device
+    fn post(&mut self, i: usize) -> Result<()> {
+        let len = core::mem::size_of::<RawEvent>() as u32;
+        let buf = Buf::writable(self.bufs.phys_addr_of(i), len);
+        // ours, and only ever posted once at a time
+        let head = unsafe { self.events.submit(&[buf]) }?;
+        self.posted[head as usize] = Some(i);
+        Ok(())
+    }
+
+    /// Whether the device interrupted; see `VirtioDev::take_interrupt`.
+    pub fn take_interrupt(&mut self) -> bool {
+        !self.stopped && self.vdev.take_interrupt()
+    }
+
+    /// Stops the driver, once the device is reset: it ignores
+    /// interrupts from then on.
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    /// Takes in the events the device wrote, queueing them for `poll`
+    /// and handing them on to the virtual console, and posts their
+    /// buffers again.
+    pub fn service(&mut self) {
+        while let Some((head, _)) = self.events.next_used() {
+            let i = match self.posted.get_mut(head as usize).and_then(Option::take) {
+                Some(i) => i,
+                None => {
+                    ERRORS.inc();
+                    warn_print!("virtio_input: used descriptor {} was not posted", head);
+                    continue;
+                }
+            };
+            let raw = self.bufs[i];
+            // a descriptor was just freed for it
+            if let Err(e) = self.post(i) {
+                ERRORS.inc();
+                error_print!("virtio_input: cannot post event buffer: {}", e.name());
+            }
+
+            let event = match InputEvent::parse(raw) {
+                Some(e) => e,
+                None => continue,
+            };
+            EVENTS.inc();
+            if !enqueue(&mut QUEUE.lock(), event) {
+                DROPPED.inc();
+            }
+            self.forward(event);
+        }
+    }
+
+    // hands `event` on to the virtual console, as the ps2 driver would
+    fn forward(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::Key { code, pressed } => {
+                if let Some(scan) = scancode(code) {
+                    let scan = if pressed { scan } else { scan | 0x80 };
+                    unsafe { nk_raw::nk_vc_handle_keyboard(scan as nk_raw::nk_scancode_t) };
+                } else {
+                    self.pointer.update(event);
+                }
+            }
+            InputEvent::Sync => {
+                if let Some(mut m) = self.pointer.take() {
+                    unsafe { nk_raw::nk_vc_handle_mouse(&mut m) };
+                }
+            }
+            e => self.pointer.update(e),
+        }
+    }
+}
+
+// the interrupt handler, which takes in the events the device wrote
+fn interrupt_handler(d: &IRQLock<VirtioInput>) {
+    let mut i = d.lock();
+    if i.take_interrupt() {
+        i.service();
+    }
+}
+
+// what virtio_pci keeps for the device, in its `state`
+struct Device {
+    vdev: VirtioDev,
+    driver: Arc<IRQLock<VirtioInput>>,
+}
+
+// virtio_pci's `teardown`, once the device is brought down
+unsafe extern "C" fn teardown(vdev: *mut nk_raw::virtio_pci_dev) {
+    // set with `teardown` in `bringup`
+    let device = unsafe { Box::from_raw((*vdev).state as *mut Device) };
+    device.vdev.shut_down(&device.driver, VirtioInput::stop);
+    drop(device);
+    unsafe { (*vdev).state = core::ptr::null_mut() };
+}
+
+fn bringup(vdev: VirtioDev) -> Result<()> {
+    let driver = Arc::new(IRQLock::new(VirtioInput::new(vdev)?));
+    let started = vdev
+        .route_to(driver.clone(), interrupt_handler)
+        .and_then(|_| vdev.start());
+    if let Err(e) = started {
+        vdev.shut_down(&driver, VirtioInput::stop);
+        return Err(e);
+    }
+
+    let n = NUM_DEVS.fetch_add(1, Ordering::Relaxed);
+    info_print!("virtio_input: device {}", n);
+    let device = Box::new(Device { vdev, driver });
+    unsafe { vdev.set_state(Box::into_raw(device) as *mut c_void, teardown) };
+    Ok(())
+}
+
+/// Brings up a virtio input device, for virtio_pci.
+#[no_mangle]
+pub unsafe extern "C" fn nk_rust_virtio_input_init(vdev: *mut nk_raw::virtio_pci_dev) -> c_int {
+    // virtio_pci calls this once for each input device it found
+    let vdev = match unsafe { VirtioDev::from_raw(vdev) } {
+        Some(v) => v,
+        None => return KError::INVALID_ARG.code(),
+    };
+    match bringup(vdev) {
+        Ok(()) => 0,
+        Err(e) => {
+            error_print!("virtio_input: cannot bring up device: {}", e.name());
+            e.code()
+        }
+    }
+}
+
+kernel_test!(
+    fn virtio_input_events() {
+        let key = |code, value| RawEvent {
+            kind: EV_KEY,
+            code,
+            value,
+        };
+        kassert_eq!(
+            InputEvent::parse(key(30, 1)),
+            Some(InputEvent::Key {
+                code: 30,
+                pressed: true
+            })
+        );
+        // a repeat is still pressed
+        kassert_eq!(
+            InputEvent::parse(key(30, 2)),
+            Some(InputEvent::Key {
+                code: 30,
+                pressed: true
+            })
+        );
+        let rel = RawEvent {
+            kind: EV_REL,
+            code: REL_X,
+            value: -3i32 as u32,
+        };
+        kassert_eq!(
+            InputEvent::parse(rel),
+            Some(InputEvent::Relative {
+                axis: REL_X,
+                delta: -3
+            })
+        );
+        let led = RawEvent {
+            kind: 0x11,
+            code: 0,
+            value: 1,
+        };
+        kassert_eq!(InputEvent::parse(led), None);
+
+        kassert_eq!(scancode(30), Some(0x1e));
+        kassert_eq!(scancode(103), Some(0x48));
+        kassert_eq!(scancode(BTN_LEFT), None);
+
+        let mut events = VecDeque::new();
+        for _ in 0..EVENT_LIMIT {
+            kassert!(enqueue(&mut events, InputEvent::Sync));
+        }
+        kassert!(!enqueue(&mut events, InputEvent::Sync));
+    }
+);
+
+kernel_test!(
+    fn virtio_input_pointer() {
+        let mut p = Pointer::default();
+        kassert!(p.take().is_none());
+        p.update(InputEvent::Relative {
+            axis: REL_X,
+            delta: 2,
+        });
+        p.update(InputEvent::Relative {
+            axis: REL_Y,
+            delta: -1,
+        });
+        p.update(InputEvent::Key {
+            code: BTN_RIGHT,
+            pressed: true,
+        });
+        let m = p.take().unwrap();
+        kassert_eq!((m.left, m.middle, m.right), (0, 0, 1));
+        kassert_eq!((m.dx, m.dy), (2, -1));
+        // motion is since the last, buttons are as they are
+        p.update(InputEvent::Relative {
+            axis: REL_X,
+            delta: 1,
+        });
+        let m = p.take().unwrap();
+        kassert_eq!((m.right, m.dx, m.dy), (1, 1, 0));
+        // a key is not the pointer's
+        p.update(InputEvent::Key {
+            code: 30,
+            pressed: true,
+        });
+        kassert!(p.take().is_none());
+    }
+);
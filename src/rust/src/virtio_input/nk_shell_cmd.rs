@@ -0,0 +1,16 @@
+use core::ffi::{c_char, c_int, c_void};
+
+use super::poll;
+
+// `rust_vinput` prints the virtio input events that are yet to be
+// polled, taking them as it goes
+#[no_mangle]
+pub extern "C" fn rust_vinput_shell_entry(_buf: *const c_char, _priv_: *const c_void) -> c_int {
+    let mut n = 0;
+    while let Some(e) = poll() {
+        info_print!("rust_vinput: {:?}", e);
+        n += 1;
+    }
+    info_print!("rust_vinput: {} events", n);
+    0
+}
@@ -0,0 +1,335 @@
+// a virtio network device, driven in Rust over the C virtio_pci transport
+// (see `kernel::nk_virtio`). virtio_pci brings the device up with
+// `nk_rust_virtio_net_init` instead of the C driver's `virtio_net_init`.
+//
+// like the C driver, it only supports the legacy (transitional) model,
+// and accepts no offloads. each packet is a chain of two descriptors, a
+// header and the caller's buffer, posted on the receive queue for the
+// device to fill, or on the transmit queue for it to send. either way it
+// completes in the interrupt handler, which calls the caller's callback.
+use core::ffi::{c_int, c_void};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use kernel::nk_netdev::{Characteristics, Completion, Registration, MAC_LEN};
+use kernel::nk_raw;
+use kernel::nk_virtio::{Buf, Queue};
+use kernel::prelude::*;
+
+counter!(RECEIVES, "receives");
+counter!(SENDS, "sends");
+counter!(ERRORS, "errors");
+
+// the smallest and largest packets the device takes, headers included
+const MIN_TU: u64 = 48;
+const MAX_TU: u64 = 1522;
+
+// the queues, by index
+const RECEIVE_QUEUE: u16 = 0;
+const TRANSMIT_QUEUE: u16 = 1;
+
+// feature bits
+const F_MAC: u32 = 5;
+
+// where the MAC address is in the device's own registers
+const CONFIG_MAC: u32 = 0;
+
+static NUM_DEVS: AtomicU32 = AtomicU32::new(0);
+
+// what precedes each packet on either queue; with no offloads accepted,
+// all zeroes
+#[repr(C, packed)]
+#[derive(Default)]
+struct Header {
+    flags: u8,
+    gso_type: u8,
+    hdr_len: u16,
+    gso_size: u16,
+    csum_start: u16,
+    csum_offset: u16,
+}
+
+const HEADER_LEN: u32 = 10;
+
+// a packet the device has yet to receive or send
+struct Pending {
+    // the device reads or writes it until then
+    _header: DmaBox<Header>,
+    done: Completion,
+}
+
+// the features to accept of those `offered`
+fn select_features(offered: u64) -> u64 {
+    offered & (1 << F_MAC)
+}
+
+// a queue and the packets posted on it, by the index of their first
+// descriptor
+struct PacketQueue {
+    queue: Queue,
+    pending: Vec<Option<Pending>>,
+}
+
+impl PacketQueue {
+    // the caller guarantees queue `idx` is taken once, as
+    // `VirtioDev::queue`'s does
+    unsafe fn new(vdev: VirtioDev, idx: u16) -> Result<Self> {
+        let queue = unsafe { vdev.queue(idx) }?;
+        let mut pending = Vec::new();
+        pending.resize_with(queue.size() as usize, || None);
+        Ok(PacketQueue { queue, pending })
+    }
+
+    // the caller guarantees `buf` as `NetDev::post_receive`'s does
+    unsafe fn post(
+        &mut self,
+        buf: *mut u8,
+        len: u64,
+        receive: bool,
+        done: Completion,
+    ) -> Result<()> {
+        let len = u32::try_from(len).map_err(|_| KError::INVALID_ARG)?;
+        if len == 0 {
+            return Err(KError::INVALID_ARG);
+        }
+        let header = DmaBox::new(Header::default())?;
+        let chain = if receive {
+            [
+                Buf::writable(header.phys_addr(), HEADER_LEN),
+                Buf::writable(buf as u64, len),
+            ]
+        } else {
+            [
+                Buf::readable(header.phys_addr(), HEADER_LEN),
+                Buf::readable(buf as u64, len),
+            ]
+        };
+        // ours until the device is done, which it can only report with
+        // the driver locked, so once `pending` has it
+        let head = unsafe { self.queue.submit(&chain) }?;
+        self.pending[head as usize] = Some(Pending {
+            _header: header,
+            done,
+        });
+        Ok(())
+    }
+
+    fn next_done(&mut self) -> Option<Completion> {
+        loop {
+            let (head, _) = self.queue.next_used()?;
+            match self.pending.get_mut(head as usize).and_then(Option::take) {
+                Some(p) => return Some(p.done),
+                None => {
+                    // not a packet we posted; nothing to complete
+                    ERRORS.inc();
+                    warn_print!("virtio_net: used descriptor {} was not pending", head);
+                }
+            }
+        }
+    }
+
+    // takes back the packets posted, once the device is reset
+    fn take_pending(&mut self) -> impl Iterator<Item = Completion> + '_ {
+        self.pending
+            .iter_mut()
+            .filter_map(Option::take)
+            .map(|p| p.done)
+    }
+}
+
+pub struct VirtioNet {
+    vdev: VirtioDev,
+    mac: [u8; MAC_LEN],
+    receive: PacketQueue,
+    transmit: PacketQueue,
+    // for good, once the device is shut down
+    stopped: bool,
+}
+
+impl VirtioNet {
+    /// Negotiates features with `vdev` and sets up its receive and
+    /// transmit queues.
+    fn new(vdev: VirtioDev) -> Result<Self> {
+        let accepted = vdev.negotiate(select_features)?;
+        // a failure from here on leaves the device as it was found
+        Self::set_up(vdev, accepted).inspect_err(|_| vdev.abandon())
+    }
+
+    // reads the MAC address, if `accepted`, and takes the queues
+    // `negotiate` set up
+    fn set_up(vdev: VirtioDev, accepted: u64) -> Result<Self> {
+        // the device makes one up without the feature, as the C driver
+        // leaves it zero
+        let mut mac = [0; MAC_LEN];
+        if accepted & (1 << F_MAC) != 0 {
+            for (i, b) in mac.iter_mut().enumerate() {
+                *b = vdev.config_u8(CONFIG_MAC + i as u32);
+            }
+        }
+        if vdev.num_queues() <= TRANSMIT_QUEUE {
+            error_print!("virtio_net: device has no transmit queue");
+            return Err(KError::NO_DEVICE);
+        }
+        Ok(VirtioNet {
+            vdev,
+            mac,
+            // each taken once
+            receive: unsafe { PacketQueue::new(vdev, RECEIVE_QUEUE) }?,
+            transmit: unsafe { PacketQueue::new(vdev, TRANSMIT_QUEUE) }?,
+            stopped: false,
+        })
+    }
+
+    pub fn mac(&self) -> [u8; MAC_LEN] {
+        self.mac
+    }
+
+    /// Whether the device interrupted; see `VirtioDev::take_interrupt`.
+    pub fn take_interrupt(&mut self) -> bool {
+        !self.stopped && self.vdev.take_interrupt()
+    }
+
+    /// Stops the driver, once the device is reset: it ignores
+    /// interrupts and refuses packets from then on. Returns the packets
+    /// the device had yet to receive or send, to fail.
+    pub fn stop(&mut self) -> Vec<Completion> {
+        self.stopped = true;
+        let mut unfinished: Vec<_> = self.receive.take_pending().collect();
+        unfinished.extend(self.transmit.take_pending());
+        unfinished
+    }
+
+    /// The next packet the device is done receiving or sending, if
+    /// any. Its descriptors are freed for the next.
+    pub fn next_done(&mut self) -> Option<Completion> {
+        self.receive
+            .next_done()
+            .or_else(|| self.transmit.next_done())
+    }
+}
+
+impl NetDev for VirtioNet {
+    fn get_characteristics(&self) -> Characteristics {
+        Characteristics {
+            mac: self.mac,
+            min_tu: MIN_TU,
+            max_tu: MAX_TU,
+        }
+    }
+
+    unsafe fn post_receive(&mut self, dest: *mut u8, len: u64, done: Completion) -> Result<()> {
+        if self.stopped {
+            return Err(KError::NO_DEVICE);
+        }
+        unsafe { self.receive.post(dest, len, true, done) }?;
+        RECEIVES.inc();
+        Ok(())
+    }
+
+    unsafe fn post_send(&mut self, src: *const u8, len: u64, done: Completion) -> Result<()> {
+        if self.stopped {
+            return Err(KError::NO_DEVICE);
+        }
+        // only read by the device
+        unsafe { self.transmit.post(src as *mut u8, len, false, done) }?;
+        SENDS.inc();
+        Ok(())
+    }
+}
+
+// the interrupt handler, which completes received and sent packets one
+// at a time, unlocked, so a callback can post another
+fn interrupt_handler(d: &IRQLock<VirtioNet>) {
+    if d.lock().take_interrupt() {
+        let mut next = || d.lock().next_done();
+        // the legacy device reports no errors
+        while let Some(done) = next() {
+            done.complete(Ok(()));
+        }
+    }
+}
+
+// what virtio_pci keeps for the device, in its `state`
+struct Device {
+    vdev: VirtioDev,
+    driver: Arc<IRQLock<VirtioNet>>,
+    netdev: Registration<VirtioNet>,
+}
+
+// virtio_pci's `teardown`, once the device is brought down
+unsafe extern "C" fn teardown(vdev: *mut nk_raw::virtio_pci_dev) {
+    // set with `teardown` in `bringup`
+    let device = unsafe { Box::from_raw((*vdev).state as *mut Device) };
+    let Device {
+        vdev: v,
+        driver,
+        netdev,
+    } = *device;
+    // the packets it will not receive or send fail, unlocked, as the
+    // interrupt handler completes them
+    for done in v.shut_down(&driver, VirtioNet::stop) {
+        done.complete(Err(KError::IO));
+    }
+    drop(netdev);
+    unsafe { (*vdev).state = core::ptr::null_mut() };
+}
+
+fn bringup(vdev: VirtioDev) -> Result<()> {
+    let driver = VirtioNet::new(vdev)?;
+    let mac = driver.mac();
+    let driver = Arc::new(IRQLock::new(driver));
+    let started = vdev
+        .route_to(driver.clone(), interrupt_handler)
+        .and_then(|_| vdev.start());
+    if let Err(e) = started {
+        vdev.shut_down(&driver, VirtioNet::stop);
+        return Err(e);
+    }
+
+    let name = format!("virtio-net{}", NUM_DEVS.fetch_add(1, Ordering::Relaxed));
+    // nothing is posted to the device until it is registered
+    let netdev = Registration::register(&name, driver.clone()).inspect_err(|_| {
+        vdev.shut_down(&driver, VirtioNet::stop);
+    })?;
+    info_print!(
+        "virtio_net: {}, MAC {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        name,
+        mac[0],
+        mac[1],
+        mac[2],
+        mac[3],
+        mac[4],
+        mac[5]
+    );
+    let device = Box::new(Device {
+        vdev,
+        driver,
+        netdev,
+    });
+    unsafe { vdev.set_state(Box::into_raw(device) as *mut c_void, teardown) };
+    Ok(())
+}
+
+/// Brings up a virtio network device, for virtio_pci.
+#[no_mangle]
+pub unsafe extern "C" fn nk_rust_virtio_net_init(vdev: *mut nk_raw::virtio_pci_dev) -> c_int {
+    // virtio_pci calls this once for each network device it found
+    let vdev = match unsafe { VirtioDev::from_raw(vdev) } {
+        Some(v) => v,
+        None => return KError::INVALID_ARG.code(),
+    };
+    match bringup(vdev) {
+        Ok(()) => 0,
+        Err(e) => {
+            error_print!("virtio_net: cannot bring up device: {}", e.name());
+            e.code()
+        }
+    }
+}
+
+kernel_test!(
+    fn virtio_net_packets() {
+        kassert_eq!(core::mem::size_of::<Header>(), HEADER_LEN as usize);
+        kassert_eq!(select_features(u64::MAX), 1 << F_MAC);
+        kassert_eq!(select_features(0), 0);
+    }
+);
@@ -0,0 +1,181 @@
+// a virtio entropy device, driven in Rust over the C virtio_pci transport
+// (see `kernel::nk_virtio`), and mixed into `nk_rand`'s shared generator.
+// virtio_pci brings the device up with `nk_rust_virtio_rng_init`; there
+// is no C driver.
+//
+// it only supports the legacy (transitional) model. the device fills
+// the buffers posted on its one queue with random bytes, which could go
+// on as fast as it has them; the driver asks for a buffer's worth at
+// bring-up, then again every so often from a timer, and the interrupt
+// handler mixes each into the generator.
+use core::ffi::{c_int, c_void};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use kernel::nk_raw;
+use kernel::nk_virtio::{Buf, Queue};
+use kernel::prelude::*;
+
+counter!(REQUESTS, "requests");
+counter!(BYTES, "bytes");
+
+const REQUEST_QUEUE: u16 = 0;
+
+// how much to ask for at a time, and how often
+const BUF_LEN: usize = 64;
+const RESEED_INTERVAL: Duration = Duration::from_secs(10);
+
+static NUM_DEVS: AtomicU32 = AtomicU32::new(0);
+
+pub struct VirtioRng {
+    vdev: VirtioDev,
+    requests: Queue,
+    buf: DmaBox<[u8; BUF_LEN]>,
+    // whether the device has `buf`
+    posted: bool,
+    // for good, once the device is shut down
+    stopped: bool,
+}
+
+impl VirtioRng {
+    /// Negotiates features with `vdev` and sets up its request queue.
+    fn new(vdev: VirtioDev) -> Result<Self> {
+        vdev.negotiate(|_| 0)?;
+        // a failure from here on leaves the device as it was found
+        Self::set_up(vdev).inspect_err(|_| vdev.abandon())
+    }
+
+    // takes the queue `negotiate` set up
+    fn set_up(vdev: VirtioDev) -> Result<Self> {
+        Ok(VirtioRng {
+            vdev,
+            // the one queue, taken once
+            requests: unsafe { vdev.queue(REQUEST_QUEUE) }?,
+            buf: DmaBox::new([0; BUF_LEN])?,
+            posted: false,
+            stopped: false,
+        })
+    }
+
+    /// Asks the device for a buffer of random bytes, unless it is
+    /// filling one already.
+    pub fn request(&mut self) -> Result<()> {
+        if self.stopped {
+            return Err(KError::NO_DEVICE);
+        }
+        if self.posted {
+            return Ok(());
+        }
+        let buf = Buf::writable(self.buf.phys_addr(), BUF_LEN as u32);
+        // only ever posted once at a time
+        unsafe { self.requests.submit(&[buf]) }?;
+        self.posted = true;
+        REQUESTS.inc();
+        Ok(())
+    }
+
+    /// Whether the device interrupted; see `VirtioDev::take_interrupt`.
+    pub fn take_interrupt(&mut self) -> bool {
+        !self.stopped && self.vdev.take_interrupt()
+    }
+
+    /// Stops the driver, once the device is reset: it ignores
+    /// interrupts and asks for nothing from then on.
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    /// Mixes whatever the device has filled into the shared generator.
+    pub fn collect(&mut self) {
+        while let Some((_, len)) = self.requests.next_used() {
+            let len = (len as usize).min(BUF_LEN);
+            nk_rand::add_entropy(&self.buf[..len]);
+            BYTES.add(len as u64);
+            self.posted = false;
+        }
+    }
+}
+
+// the interrupt handler, which mixes what the device filled into the
+// shared generator
+fn interrupt_handler(d: &IRQLock<VirtioRng>) {
+    let mut rng = d.lock();
+    if rng.take_interrupt() {
+        rng.collect();
+    }
+}
+
+// what virtio_pci keeps for the device, in its `state`
+struct Device {
+    vdev: VirtioDev,
+    driver: Arc<IRQLock<VirtioRng>>,
+    // stopped when dropped
+    reseed: PeriodicTimer,
+}
+
+// virtio_pci's `teardown`, once the device is brought down
+unsafe extern "C" fn teardown(vdev: *mut nk_raw::virtio_pci_dev) {
+    // set with `teardown` in `bringup`
+    let device = unsafe { Box::from_raw((*vdev).state as *mut Device) };
+    let Device {
+        vdev: v,
+        driver,
+        reseed,
+    } = *device;
+    drop(reseed);
+    v.shut_down(&driver, VirtioRng::stop);
+    unsafe { (*vdev).state = core::ptr::null_mut() };
+}
+
+fn bringup(vdev: VirtioDev) -> Result<()> {
+    let driver = Arc::new(IRQLock::new(VirtioRng::new(vdev)?));
+    let started = vdev
+        .route_to(driver.clone(), interrupt_handler)
+        .and_then(|_| vdev.start());
+    if let Err(e) = started {
+        vdev.shut_down(&driver, VirtioRng::stop);
+        return Err(e);
+    }
+    // the timer asks again otherwise
+    if let Err(e) = driver.lock().request() {
+        warn_print!("virtio_rng: cannot ask for entropy: {}", e.name());
+    }
+
+    let n = NUM_DEVS.fetch_add(1, Ordering::Relaxed);
+    let d = driver.clone();
+    let reseed = PeriodicTimer::start("virtio-rng", RESEED_INTERVAL, move || {
+        if let Err(e) = d.lock().request() {
+            warn_print!("virtio_rng: cannot ask for entropy: {}", e.name());
+        }
+    })
+    .inspect_err(|_| vdev.shut_down(&driver, VirtioRng::stop))?;
+    info_print!(
+        "virtio_rng: device {}, {} bytes every {:?}",
+        n,
+        BUF_LEN,
+        RESEED_INTERVAL
+    );
+    let device = Box::new(Device {
+        vdev,
+        driver,
+        reseed,
+    });
+    unsafe { vdev.set_state(Box::into_raw(device) as *mut c_void, teardown) };
+    Ok(())
+}
+
+/// Brings up a virtio entropy device, for virtio_pci.
+#[no_mangle]
+pub unsafe extern "C" fn nk_rust_virtio_rng_init(vdev: *mut nk_raw::virtio_pci_dev) -> c_int {
+    // virtio_pci calls this once for each entropy device it found
+    let vdev = match unsafe { VirtioDev::from_raw(vdev) } {
+        Some(v) => v,
+        None => return KError::INVALID_ARG.code(),
+    };
+    match bringup(vdev) {
+        Ok(()) => 0,
+        Err(e) => {
+            error_print!("virtio_rng: cannot bring up device: {}", e.name());
+            e.code()
+        }
+    }
+}